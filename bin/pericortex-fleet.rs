@@ -0,0 +1,145 @@
+//! The `pericortex-fleet` command-line interface for managing CorTeX workers across many hosts
+use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use pericortex::fleet::{self, Inventory, RemoteAction};
+
+// Sample runs:
+// 1. The state of every worker of the fleet, on one screen refreshed every 10s
+// cargo run --bin pericortex-fleet -- --inventory fleet.toml status --watch 10
+// 2. Start the Engrafo workers of one host, then drain all of them
+// cargo run --bin pericortex-fleet -- --inventory fleet.toml start --host gpu-01 --service engrafo
+// cargo run --bin pericortex-fleet -- --inventory fleet.toml stop --service engrafo
+// 3. After installing a new binary on every host, upgrade the workers in place
+// cargo run --bin pericortex-fleet -- --inventory fleet.toml upgrade
+
+#[derive(Parser)]
+#[command(name = "pericortex-fleet", version, about = "Manage CorTeX workers across many hosts")]
+struct Cli {
+  /// The inventory of hosts and their workers, a TOML file
+  #[arg(long, default_value = "fleet.toml")]
+  inventory: PathBuf,
+  #[command(subcommand)]
+  command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+  /// Summarize the states of the workers, polled from their `run --health` endpoints
+  Status {
+    /// Print the states as JSON
+    #[arg(long)]
+    json: bool,
+    /// Refresh the summary every this many seconds, until interrupted
+    #[arg(long)]
+    watch: Option<u64>,
+    #[command(flatten)]
+    selection: Selection,
+  },
+  /// Start the workers with their commands, over ssh
+  Start {
+    #[command(flatten)]
+    selection: Selection,
+  },
+  /// Let the workers finish their tasks and exit (SIGTERM)
+  Stop {
+    #[command(flatten)]
+    selection: Selection,
+  },
+  /// Let the workers finish their tasks and restart as the installed binary (SIGUSR2)
+  Upgrade {
+    #[command(flatten)]
+    selection: Selection,
+  },
+  /// Send the workers a signal, e.g. INT
+  Signal {
+    /// The signal, by name or number, without the SIG prefix
+    signal: String,
+    #[command(flatten)]
+    selection: Selection,
+  },
+}
+
+/// The workers a command applies to, all of them by default
+#[derive(Args)]
+struct Selection {
+  /// Only the workers of this host
+  #[arg(long)]
+  host: Option<String>,
+  /// Only the workers of this service
+  #[arg(long)]
+  service: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+  let cli = Cli::parse();
+  let inventory = Inventory::load(&cli.inventory)?;
+  let (selection, action) = match cli.command {
+    Commands::Status {
+      json,
+      watch,
+      selection,
+    } => {
+      let workers = select(&inventory, &selection)?;
+      loop {
+        let status = fleet::status(&workers);
+        if json {
+          println!("{}", serde_json::to_string_pretty(&status)?);
+        } else {
+          if watch.is_some() {
+            // clear the screen, as `watch` does
+            print!("\x1B[2J\x1B[H");
+          }
+          print!("{}", status);
+        }
+        match watch {
+          Some(secs) => thread::sleep(Duration::from_secs(secs.max(1))),
+          None => return Ok(()),
+        }
+      }
+    }
+    Commands::Start { selection } => (selection, RemoteAction::Start),
+    Commands::Stop { selection } => (selection, RemoteAction::Signal("TERM".to_string())),
+    Commands::Upgrade { selection } => (selection, RemoteAction::Signal("USR2".to_string())),
+    Commands::Signal { signal, selection } => {
+      let signal = signal.trim_start_matches("SIG").to_string();
+      (selection, RemoteAction::Signal(signal))
+    }
+  };
+  let workers = select(&inventory, &selection)?;
+  let mut failed = 0;
+  for ((host, worker), outcome) in workers.iter().zip(fleet::perform(&inventory, &workers, &action)) {
+    match outcome {
+      Ok(output) => {
+        println!("{} {}: ok", host.name, worker.service);
+        for line in output.lines() {
+          println!("  {}", line);
+        }
+      }
+      Err(e) => {
+        failed += 1;
+        eprintln!("{} {}: {}", host.name, worker.service, e);
+      }
+    }
+  }
+  if failed > 0 {
+    eprintln!("{} of {} workers failed", failed, workers.len());
+    process::exit(1);
+  }
+  Ok(())
+}
+
+fn select<'a>(
+  inventory: &'a Inventory,
+  selection: &Selection,
+) -> Result<Vec<(&'a fleet::Host, &'a fleet::FleetWorker)>, Box<dyn Error>> {
+  let workers = inventory.select(selection.host.as_deref(), selection.service.as_deref());
+  if workers.is_empty() {
+    return Err(From::from("no worker of the inventory matches"));
+  }
+  Ok(workers)
+}
@@ -0,0 +1,1309 @@
+//! The `pericortex` command-line interface for running CorTeX workers
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::LevelFilter;
+use pericortex::announcements;
+use pericortex::bench;
+use pericortex::capabilities;
+use pericortex::check;
+use pericortex::config::{self, WorkerConfig};
+use pericortex::daemon;
+#[cfg(feature = "tui")]
+use pericortex::dashboard;
+use pericortex::diff;
+use pericortex::endpoint::Endpoint;
+use pericortex::dryrun;
+use pericortex::filters::{self, AnonymizePaths, StripLargeFiles};
+use pericortex::golden::{self, GoldenStatus};
+use pericortex::identity;
+use pericortex::import;
+use pericortex::journal::{self, JournalEvent};
+use pericortex::loadtest::{self, LatencyDistribution, LoadTest};
+use pericortex::local::{BatchObserver, LocalRunner};
+use pericortex::logger;
+use pericortex::mirror;
+use pericortex::policy::{self, EmptyInput, InputAttachment, OversizedOutput};
+use pericortex::pressure;
+use pericortex::progress;
+use pericortex::protocol::{self, Compression};
+use pericortex::quarantine::{self, QUARANTINE_STRIKES};
+use pericortex::quota::{self, Quota};
+use pericortex::random;
+use pericortex::ratelimit;
+use pericortex::registration;
+use pericortex::replay::{self, ReplayVerdict};
+use pericortex::report::{Report, TaskReport};
+use pericortex::schedule::{self, QuietWindow};
+use pericortex::scratch;
+use pericortex::secrets::{self, Secrets};
+use pericortex::selftest;
+use pericortex::shutdown;
+#[cfg(feature = "signing")]
+use pericortex::signing;
+#[cfg(feature = "object-storage")]
+use pericortex::storage::{self, ObjectStore};
+use pericortex::submit::{self, SubmitOptions};
+use pericortex::triage::{self, TriageOptions};
+use pericortex::tuning::{self, FrameSizeBounds};
+use pericortex::upgrade;
+use pericortex::watchdog::{self, Watchdog};
+#[cfg(feature = "engrafo")]
+use pericortex::worker::{EngrafoWorker, GpuAssignment};
+use pericortex::worker::{CommandSpec, CommandWorker, EchoWorker, TexToHtmlWorker, Worker};
+
+// Sample runs:
+// 1. Simple localhost test
+// cargo run --features=engrafo -- run engrafo --source tcp://127.0.0.1:51695 --sink tcp://127.0.0.1:51696
+// 2. 16 workers pointed at the live CorTeX endpoint
+// cargo run --features=engrafo -- run engrafo --pool 16
+// 3. As above, also serving /healthz and /readyz probes on port 8080
+// cargo run --features=engrafo -- run engrafo --pool 16 --health 0.0.0.0:8080
+// 4. As a background daemon, without systemd
+// cargo run --features=engrafo -- run engrafo --daemon --pid-file engrafo.pid --log-file engrafo.log
+// 5. A custom executable, described in a job specification
+// cargo run -- run command --spec job.toml --pool 4
+// 6. Diagnose a worker's setup before starting it
+// cargo run --features=engrafo -- check engrafo
+// 7. Convert a local corpus entry, without a dispatcher
+// cargo run --features=engrafo -- convert-local tests/resources/1508.01222.zip engrafo
+// 8. Convert a local corpus with 8 threads
+// cargo run --features=engrafo -- batch corpus/ --output-dir results/ engrafo --pool 8
+// 9. Record live tasks, then replay them through an upgraded converter
+// cargo run --features=engrafo -- run engrafo --record replays/
+// cargo run --features=engrafo -- replay replays/ engrafo
+// 10. Compare the results of two converter versions
+// cargo run -- diff old/1508.01222.zip new/1508.01222.zip
+// 11. Measure Engrafo on sample documents, to size pools and memory limits
+// cargo run --features=engrafo -- bench samples/ --iterations 5 engrafo
+// 12. Journal every task, and find the one in flight when the worker crashed
+// cargo run --features=engrafo -- run engrafo --journal engrafo.journal
+// cargo run -- journal engrafo.journal
+// 13. Stop converting documents that crashed the worker twice
+// cargo run --features=engrafo -- run engrafo --quarantine engrafo.quarantine --quarantine-strikes 2
+// 14. Verify a freshly deployed node converts a sample document
+// cargo run --features=engrafo -- selftest engrafo
+// 15. Report the pericortex, worker and Engrafo image versions with every task request
+// cargo run --features=engrafo -- run engrafo --protocol-version 2
+// 16. Let every thread find a good message size between 64KB and 16MB
+// cargo run --features=engrafo -- run engrafo --auto-message-size 65536:16777216
+// 17. Refuse to start unless CorTeX knows the service
+// cargo run --features=engrafo -- run engrafo --register tcp://131.188.48.209:51697
+// 18. Follow the dispatcher's fleet-wide pause/resume/drain announcements
+// cargo run --features=engrafo -- run engrafo --announcements tcp://131.188.48.209:51698
+// 19. 32 conversion threads fed by 4 network threads
+// cargo run --features=engrafo -- run engrafo --pool 32 --io-threads 4
+// 20. Identify the threads of a cloned VM by its instance id, e.g. i-0abc:worker7:engrafo:01
+// cargo run --features=engrafo -- run engrafo --pool 16 --identity-prefix i-0abc
+// 21. After installing a new binary, let a running worker finish its tasks and restart as it
+// kill -USR2 $(cat engrafo.pid)
+// 22. Upload results over 32MB to a MinIO bucket, sending the sink their manifests
+// cargo run --features=engrafo,object-storage -- run engrafo --protocol-version 3 --object-store http://minio:9000/results --upload-threshold-mb 32
+// 23. Check a new host against live tasks, answering them without converting
+// cargo run --features=engrafo -- run engrafo --dry-run --limit 10
+// 24. Check how a dispatcher copes with slow, failing and corrupted results
+// cargo run -- run echo --delay-ms 500 --fail-every 10 --corrupt-every 7 --payload-size 1048576 --throttle-secs 0
+// 25. Put a dispatcher under the load of 500 workers, each taking 2s per task on average
+// cargo run -- loadtest --workers 500 --seconds 300 --latency exponential:2000
+// 26. Check Engrafo against the golden summaries of the bundled fixtures, then accept an upgrade's results
+// cargo run --features=engrafo -- golden tests/resources --golden-dir tests/golden engrafo
+// cargo run --features=engrafo -- golden tests/resources --golden-dir tests/golden --update engrafo
+// 27. Verify a binary was built with Engrafo support before rolling it out
+// pericortex capabilities --json | jq -e '.workers | index("engrafo")'
+// 28. Turn a directory of arXiv tarballs, .tex files and HTML bundles into a CorTeX corpus
+// cargo run -- import raw/ --output-dir corpus/
+// 29. Submit the imported corpus to a CorTeX ventilator, at most 5 entries per second (rerun to resume)
+// cargo run -- submit --manifest corpus/index.json --ventilator tcp://127.0.0.1:51694 --rate 5
+// 30. Keep results lean and free of host paths, whichever worker produced them
+// cargo run -- run command --spec job.toml --strip-files-over-mb 50 --anonymize-paths
+// 31. Engrafo behind an institutional proxy and resolver
+// cargo run --features=engrafo -- run engrafo --network host --dns 10.0.0.2 --docker-env HTTPS_PROXY --docker-env NO_PROXY=localhost
+// 32. Guarantee the whole fleet converts with the identical Engrafo build
+// cargo run --features=engrafo -- run engrafo --image-digest sha256:<digest from `docker image inspect`>
+// 33. Small documents: keep a container per thread, replacing it every 200 tasks
+// cargo run --features=engrafo -- run engrafo --pool 16 --reuse-containers 200
+// 34. Four threads, each converting on its own GPU
+// cargo run --features=engrafo -- run engrafo --pool 4 --gpus 0,1,2,3
+// 35. Per-corpus settings, for a dispatcher sending task metadata (a [corpora.longpapers] table in worker.toml)
+// cargo run -- run tex-to-html --config worker.toml --protocol-version 6
+// 36. Contribute a shared workstation to corpus runs only outside office hours
+// cargo run -- run command --spec job.toml --quiet-hours 08:00-20:00
+// 37. Ease a new converter release into the corpus, at most 30 tasks per minute
+// cargo run -- run command --spec job.toml --pool 8 --max-tasks-per-minute 30
+// 38. Try out an experimental converter on at most 1000 tasks, stopping early after 50 failures
+// cargo run -- run command --spec job.toml --max-tasks 1000 --max-failures 50
+// 39. Report conversions stuck for over an hour, and replace their threads
+// cargo run -- run command --spec job.toml --hang-threshold 3600 --respawn-wedged
+// 40. Watch the transfers of very large documents, logging their progress every 30 seconds
+// cargo run -- run tex-to-html --progress-interval 30
+// 41. Snapshot the input and output files of every task, then see which files two runs changed
+// cargo run --features=engrafo -- run engrafo --journal before.journal --snapshot
+// cargo run -- journal before.journal --compare after.journal
+// 42. Print the settings a worker would run with, from its configuration file, flags and environment
+// cargo run -- config show command --spec job.toml --config worker.toml --pool 8
+// 43. Send the TeX sources of failed tasks back with their reports, for triage by the dispatcher
+// cargo run -- run command --spec job.toml --attach-input-on-failure --attach-input-extensions tex,bib,sty --attach-input-max-mb 20
+// 44. Weeks-long runs: keep the scratch space of the worker under 20GB, deleting what wedged threads left behind
+// cargo run -- run command --spec job.toml --pool 16 --hang-threshold 3600 --respawn-wedged --scratch-budget-mb 20480
+// 45. Corpora mixing one-page notes with 500MB theses: frame every result by its own size
+// cargo run --features=engrafo -- run engrafo --message-size-bounds 65536:16777216
+// 46. Convert a local corpus, following its progress and failures on the terminal (--quiet for plain logs)
+// cargo run -- batch corpus/ --output-dir results/ command --spec job.toml --pool 8
+// 47. Evaluate a converter upgrade on recorded tasks, with a report to load into a notebook
+// cargo run -- replay recorded/ --report evaluation/ command --spec job.toml
+// 48. After a big rerun, bundle three failed inputs per log category for the converter developers
+// cargo run -- triage run.journal --recordings recorded/ --per-category 3 --max-mb 100
+// 49. Keep an archival copy of every result, without the archive ever slowing down the dispatcher
+// cargo run --features=engrafo -- run engrafo --mirror-sink tcp://archive.example.org:51696
+// 50. Sign every result for a tamper-evident corpus, then check stored results against the fleet's key
+// cargo run --features=signing -- keygen fleet.key
+// cargo run --features=engrafo,signing -- run engrafo --signing-key fleet.key
+// cargo run --features=signing -- verify results/*.zip --public-key <printed by keygen>
+// 51. Keep the S3 credentials and signing key out of the environment, in files only the worker's user can read
+// cargo run --features=engrafo,object-storage,signing -- run engrafo --protocol-version 3 --object-store http://minio:9000/results --secrets file:/etc/pericortex/secrets
+// cargo run --features=engrafo,object-storage -- run engrafo --protocol-version 3 --object-store http://minio:9000/results --secrets "command:vault-get --field value"
+// 52. Babysit a campaign from a terminal dashboard rather than the scrolling log
+// cargo run --features=engrafo,tui -- run engrafo --pool 16 --tui
+// 53. Keep pathological documents from clogging the sink: drop the largest assets of results over 2GB
+// cargo run --features=engrafo -- run engrafo --max-output-size-mb 2048 --oversized-output truncate-assets
+
+#[derive(Parser)]
+#[command(name = "pericortex", version, about = "Worker executables for CorTeX")]
+struct Cli {
+  #[command(subcommand)]
+  command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+  /// Start a worker for a CorTeX dispatcher
+  #[command(subcommand)]
+  Run(WorkerCommand),
+  /// Verify the endpoints, converter and scratch space of a worker, without starting it
+  Check {
+    /// Minimum free space in the temporary directory, in megabytes
+    #[arg(long, default_value_t = 1024)]
+    min_scratch_mb: u64,
+    #[command(subcommand)]
+    worker: WorkerCommand,
+  },
+  /// Convert a bundled sample document end-to-end, with per-stage timing
+  Selftest {
+    #[command(subcommand)]
+    worker: WorkerCommand,
+  },
+  /// Convert a local corpus ZIP with a worker, without a dispatcher
+  ConvertLocal {
+    /// The corpus entry ZIP to convert
+    input: PathBuf,
+    /// Where to write the result, defaults to `<input stem>.<service>.zip` next to the input
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Print log lines rather than a progress display
+    #[arg(long)]
+    quiet: bool,
+    #[command(subcommand)]
+    worker: WorkerCommand,
+  },
+  /// Convert every corpus entry ZIP under a local directory, without a dispatcher
+  Batch {
+    /// Directory tree containing corpus entry ZIPs
+    input_dir: PathBuf,
+    /// Directory receiving the results, at the same relative paths, a summary.csv and a report
+    /// (report.csv, report.json)
+    #[arg(long)]
+    output_dir: PathBuf,
+    /// Print log lines rather than a progress display
+    #[arg(long)]
+    quiet: bool,
+    #[command(subcommand)]
+    worker: WorkerCommand,
+  },
+  /// Measure conversion latency, output sizes and peak memory on sample documents
+  Bench {
+    /// Directory tree containing sample corpus entry ZIPs
+    input_dir: PathBuf,
+    /// Conversions per document
+    #[arg(long, default_value_t = 5)]
+    iterations: usize,
+    #[command(subcommand)]
+    worker: WorkerCommand,
+  },
+  /// Normalize raw documents (tarballs, .tex files, HTML bundles) into corpus entry ZIPs
+  Import {
+    /// Directory tree containing the raw documents
+    input_dir: PathBuf,
+    /// Directory receiving the corpus entries and their index.json
+    #[arg(long)]
+    output_dir: PathBuf,
+  },
+  /// Submit the entries of an import manifest to a CorTeX ventilator, resuming earlier runs
+  Submit {
+    /// The index.json written by `import`
+    #[arg(long)]
+    manifest: PathBuf,
+    /// URL to the CorTeX ventilator accepting submissions
+    #[arg(long)]
+    ventilator: String,
+    /// Name of the corpus, by default that of the manifest's directory
+    #[arg(long)]
+    corpus: Option<String>,
+    /// Entries submitted per second at most
+    #[arg(long)]
+    rate: Option<f64>,
+    /// Seconds to wait for every acknowledgement
+    #[arg(long, default_value_t = submit::SUBMISSION_TIMEOUT.as_secs())]
+    timeout: u64,
+  },
+  /// Compare the results of fixture documents against checked-in golden summaries
+  Golden {
+    /// Directory tree containing fixture corpus entry ZIPs
+    fixtures: PathBuf,
+    /// Directory holding the golden summaries, one subdirectory per service
+    #[arg(long, default_value = "tests/golden")]
+    golden_dir: PathBuf,
+    /// Overwrite the golden summaries with the current results
+    #[arg(long)]
+    update: bool,
+    #[command(subcommand)]
+    worker: WorkerCommand,
+  },
+  /// Simulate a fleet of echo workers against a dispatcher, reporting its throughput and fairness
+  Loadtest {
+    /// URL to the CorTeX dispatcher
+    #[arg(long, default_value = "tcp://127.0.0.1:51695")]
+    source: String,
+    /// URL to the CorTeX sink
+    #[arg(long, default_value = "tcp://127.0.0.1:51696")]
+    sink: String,
+    /// Service to ask tasks for
+    #[arg(long, default_value = "echo_service")]
+    service: String,
+    /// Number of simulated workers
+    #[arg(long, default_value_t = 100)]
+    workers: usize,
+    /// Seconds to keep asking for tasks
+    #[arg(long, default_value_t = 60)]
+    seconds: u64,
+    /// Time to answer a task: fixed:MS, uniform:MIN_MS:MAX_MS or exponential:MEAN_MS
+    #[arg(long, default_value = "fixed:0")]
+    latency: LatencyDistribution,
+    /// Seed for the simulated latencies, making the load reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+  },
+  /// Generate an ed25519 key for signing results, printing its public key
+  #[cfg(feature = "signing")]
+  Keygen {
+    /// The file to store the signing key in, readable by its owner alone
+    output: PathBuf,
+  },
+  /// Check the signatures embedded in result archives against the public keys of a fleet
+  #[cfg(feature = "signing")]
+  Verify {
+    /// The result archives
+    #[arg(required = true)]
+    results: Vec<PathBuf>,
+    /// A trusted public key, in hex (repeatable)
+    #[arg(long, required = true)]
+    public_key: Vec<String>,
+  },
+  /// Report the workers, transports and integrations compiled into this binary
+  Capabilities {
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+  },
+  /// Compare two result archives: file lists, normalized HTML and cortex.log severities
+  Diff {
+    /// The result archive of the old converter
+    old: PathBuf,
+    /// The result archive of the new converter
+    new: PathBuf,
+  },
+  /// Summarize a task journal written with `run --journal`, listing unfinished tasks
+  Journal {
+    /// The journal file
+    path: PathBuf,
+    /// Another journal written with `run --snapshot`, to list the files that differ per task
+    #[arg(long)]
+    compare: Option<PathBuf>,
+  },
+  /// Package representative failed tasks of a run, by log category, to share with converter developers
+  Triage {
+    /// The journal written with `run --journal`
+    journal: PathBuf,
+    /// The directory of tasks recorded with `run --record`, holding the failed inputs and results
+    #[arg(long)]
+    recordings: Option<PathBuf>,
+    /// The bundle to write
+    #[arg(long, default_value = "triage.tar.gz")]
+    output: PathBuf,
+    /// Failed tasks packaged per log category
+    #[arg(long, default_value_t = triage::TRIAGE_PER_CATEGORY)]
+    per_category: usize,
+    /// Megabytes of inputs and logs in the bundle at most
+    #[arg(long, default_value_t = triage::TRIAGE_MAX_BYTES / (1024 * 1024))]
+    max_mb: u64,
+  },
+  /// Inspect the configuration of a worker
+  #[command(subcommand)]
+  Config(ConfigCommand),
+  /// Re-run the tasks recorded with `run --record` and compare against the recorded results
+  Replay {
+    /// The directory of recorded tasks
+    dir: PathBuf,
+    /// Write a per-task report.csv and a report.json with the aggregate summary into this directory
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Print log lines rather than a progress display
+    #[arg(long)]
+    quiet: bool,
+    #[command(subcommand)]
+    worker: WorkerCommand,
+  },
+}
+
+/// Configuration inspection
+#[derive(Subcommand)]
+enum ConfigCommand {
+  /// Print the effective configuration of a worker, with secrets redacted
+  Show {
+    #[command(subcommand)]
+    worker: WorkerCommand,
+  },
+}
+
+/// The workers available in this build
+#[derive(Subcommand)]
+enum WorkerCommand {
+  /// Engrafo tex-to-html conversion, via a docker image
+  #[cfg(feature = "engrafo")]
+  Engrafo {
+    /// Address for serving the /healthz and /readyz probe endpoints, e.g. 0.0.0.0:8080
+    #[arg(long)]
+    health: Option<String>,
+    /// Docker network of the Engrafo containers, e.g. host
+    #[arg(long)]
+    network: Option<String>,
+    /// DNS server of the Engrafo containers, may be repeated
+    #[arg(long)]
+    dns: Vec<String>,
+    /// NAME=value, or NAME to pass on the worker's value, set in the Engrafo containers; may be repeated
+    #[arg(long = "docker-env", value_name = "NAME[=VALUE]")]
+    env: Vec<String>,
+    /// Pin the Engrafo image to this digest (sha256:...), pulled if absent and verified before the first task
+    #[arg(long)]
+    image_digest: Option<String>,
+    /// Keep a container per thread, running this many tasks in it before replacing it
+    #[arg(long, value_name = "TASKS")]
+    reuse_containers: Option<usize>,
+    /// GPUs of the Engrafo containers: all, or device ids assigned to the pool threads in turn, e.g. 0,1
+    #[arg(long)]
+    gpus: Option<GpuAssignment>,
+    #[command(flatten)]
+    run: RunArgs,
+  },
+  /// LaTeXML tex-to-html conversion (demonstration only)
+  TexToHtml(RunArgs),
+  /// Echo every task back, for testing dispatchers
+  Echo {
+    /// Milliseconds to pause before answering every task
+    #[arg(long, default_value_t = 0)]
+    delay_ms: u64,
+    /// Fail every Nth task
+    #[arg(long)]
+    fail_every: Option<usize>,
+    /// Answer with a generated result of this many bytes instead of the payload
+    #[arg(long)]
+    payload_size: Option<usize>,
+    /// Flip the bits of the first frame of every Nth result
+    #[arg(long)]
+    corrupt_every: Option<usize>,
+    /// Seconds to pause after a failed task
+    #[arg(long, default_value_t = 60)]
+    throttle_secs: u64,
+    #[command(flatten)]
+    run: RunArgs,
+  },
+  /// Run an executable described by a TOML job specification
+  Command {
+    /// The job specification, with `service`, `program` and `args` entries
+    #[arg(long)]
+    spec: PathBuf,
+    #[command(flatten)]
+    run: RunArgs,
+  },
+}
+
+/// Options shared by all workers
+#[derive(Args)]
+struct RunArgs {
+  /// URL to the CorTeX dispatcher, e.g. tcp://127.0.0.1:51695
+  #[arg(long)]
+  source: Option<Endpoint>,
+  /// URL to the CorTeX sink, e.g. tcp://127.0.0.1:51696
+  #[arg(long)]
+  sink: Option<Endpoint>,
+  /// Number of worker threads
+  #[arg(long = "pool")]
+  pool_size: Option<usize>,
+  /// Number of threads receiving tasks and sending results, by default one per worker thread
+  #[arg(long)]
+  io_threads: Option<usize>,
+  /// Size of chunk for network communication
+  #[arg(long)]
+  message_size: Option<usize>,
+  /// Send every result in frames of a 64th of its size, within MIN:MAX bytes, instead of --message-size
+  #[arg(long, value_name = "MIN:MAX", conflicts_with = "auto_message_size")]
+  message_size_bounds: Option<FrameSizeBounds>,
+  /// Exit after each thread completed this many tasks
+  #[arg(long)]
+  limit: Option<usize>,
+  /// TOML configuration file, reloaded on SIGHUP; command-line flags take precedence over it
+  #[arg(long)]
+  config: Option<PathBuf>,
+  /// One of off, error, warn, info, debug, trace
+  #[arg(long)]
+  log_level: Option<String>,
+  /// Record every received task into this directory, for a later `pericortex replay`
+  #[arg(long)]
+  record: Option<PathBuf>,
+  /// Journal every task's id, outcome, duration and sizes into this SQLite database
+  #[arg(long)]
+  journal: Option<PathBuf>,
+  /// Also journal the path, hash and size of every file of each task's input and output
+  #[arg(long, requires = "journal")]
+  snapshot: bool,
+  /// Keep strikes against tasks interrupted by crashes or hangs in this file, quarantining repeat offenders
+  #[arg(long)]
+  quarantine: Option<PathBuf>,
+  /// Interrupted conversions before a task is quarantined
+  #[arg(long, default_value_t = QUARANTINE_STRIKES)]
+  quarantine_strikes: u32,
+  /// Remove the files of results larger than this many megabytes, except cortex.log
+  #[arg(long)]
+  strip_files_over_mb: Option<u64>,
+  /// Replace the temporary and home directories in the text files of results
+  #[arg(long)]
+  anonymize_paths: bool,
+  /// Receive, extract and answer tasks with a dry-run report, without converting them
+  #[arg(long)]
+  dry_run: bool,
+  /// Fork into the background and detach from the terminal
+  #[arg(long)]
+  daemon: bool,
+  /// Write the worker's process id to this file
+  #[arg(long)]
+  pid_file: Option<PathBuf>,
+  /// Append log messages to this file instead of STDERR
+  #[arg(long)]
+  log_file: Option<PathBuf>,
+  /// Compression of payloads on the wire, none or zstd (command workers)
+  #[arg(long)]
+  compression: Option<Compression>,
+  /// Tune message_size on the first tasks of every thread, within MIN:MAX bytes
+  #[arg(long, value_name = "MIN:MAX")]
+  auto_message_size: Option<FrameSizeBounds>,
+  /// Megabytes of results each IO thread may queue for a slow sink before it stops sending
+  #[arg(long)]
+  sink_queue_mb: Option<usize>,
+  /// Send a copy of every result to this sink as well, e.g. a mirror CorTeX instance (repeatable);
+  /// a slow or unreachable mirror misses results rather than holding back the sink
+  #[arg(long, value_name = "ADDRESS")]
+  mirror_sink: Vec<String>,
+  /// Fetch no tasks during this daily window of local time, e.g. 08:00-20:00 (repeatable)
+  #[arg(long, value_name = "HH:MM-HH:MM")]
+  quiet_hours: Vec<QuietWindow>,
+  /// Fetch at most this many tasks per minute across all threads, spread evenly over the minute
+  #[arg(long)]
+  max_tasks_per_minute: Option<f64>,
+  /// Answer a task whose input was empty with a report in its cortex.log, or skip it with an
+  /// empty result as for a failed conversion
+  #[arg(long, value_name = "report|skip")]
+  empty_input: Option<EmptyInput>,
+  /// Seconds to pause after an empty input, by default the throttle after a failure
+  #[arg(long)]
+  empty_input_throttle_secs: Option<u64>,
+  /// Hold result archives to this many megabytes, see --oversized-output
+  #[arg(long)]
+  max_output_size_mb: Option<u64>,
+  /// Answer a result over --max-output-size-mb with a report of the excess, drop its largest
+  /// assets until it fits, or upload it to the --object-store whatever the upload threshold
+  #[arg(long, value_name = "reject|truncate-assets|divert")]
+  oversized_output: Option<OversizedOutput>,
+  /// Answer a failed conversion with a report attaching its input, rather than an empty result
+  #[arg(long)]
+  attach_input_on_failure: bool,
+  /// Attach only the input files with these extensions, e.g. tex,bib,sty
+  #[arg(long, value_delimiter = ',', requires = "attach_input_on_failure")]
+  attach_input_extensions: Vec<String>,
+  /// Attach at most this many megabytes of input files to a failure report
+  #[arg(long, requires = "attach_input_on_failure")]
+  attach_input_max_mb: Option<u64>,
+  /// Drain and exit with status 3 after fetching this many tasks across all threads
+  #[arg(long)]
+  max_tasks: Option<u64>,
+  /// Drain and exit with status 3 after this many failed tasks
+  #[arg(long)]
+  max_failures: Option<u64>,
+  /// Drain and exit with status 3 after receiving and sending this many megabytes
+  #[arg(long)]
+  max_transfer_mb: Option<u64>,
+  /// Report threads whose task made no progress for this many seconds as wedged
+  #[arg(long)]
+  hang_threshold: Option<u64>,
+  /// Replace wedged threads with fresh ones, leaving them behind
+  #[arg(long, requires = "hang_threshold")]
+  respawn_wedged: bool,
+  /// Delete the scratch directories left behind by crashed or wedged threads, oldest first,
+  /// whenever those of this process take more than this many megabytes
+  #[arg(long)]
+  scratch_budget_mb: Option<u64>,
+  /// Seconds between two progress lines of a long transfer to or from CorTeX
+  #[arg(long, default_value_t = progress::DEFAULT_INTERVAL_SECS)]
+  progress_interval: u64,
+  /// Follow the pause, resume and drain announcements broadcast by the dispatcher at this endpoint
+  #[arg(long)]
+  announcements: Option<String>,
+  /// Register the service with this CorTeX registration endpoint before fetching tasks
+  #[arg(long)]
+  register: Option<String>,
+  /// Text before the hostname in every thread identity, e.g. a cloud instance id
+  #[arg(long)]
+  identity_prefix: Option<String>,
+  /// Text after the slot in every thread identity
+  #[arg(long)]
+  identity_suffix: Option<String>,
+  /// End every thread identity with a random tag, for hosts sharing a hostname
+  #[arg(long)]
+  random_identity: bool,
+  /// Protocol version spoken with the dispatcher, 2 reports the worker and converter versions,
+  /// 3 also allows manifests of results uploaded to object storage, 4 also precedes every result
+  /// with a status frame, 5 also with a stats frame, 6 also receives task metadata, 7 also sends
+  /// keepalives while streaming large results, 8 also reports empty inputs with their own status,
+  /// 9 also answers probe tasks without converting them, 10 also sends secondary artifacts in
+  /// frames of their own
+  #[arg(long, default_value_t = protocol::BASE_VERSION)]
+  protocol_version: u32,
+  /// Where the credentials and keys come from: env, file:<dir> of files named after them, readable
+  /// by their owner alone, or command:<command> printing the one named by its last argument
+  #[arg(long, value_name = "PROVIDER")]
+  secrets: Option<Secrets>,
+  /// Upload large results to this S3/MinIO bucket, e.g. http://minio:9000/results
+  #[cfg(feature = "object-storage")]
+  #[arg(long)]
+  object_store: Option<String>,
+  /// Results of at least this many megabytes are uploaded to the object store
+  #[cfg(feature = "object-storage")]
+  #[arg(long, default_value_t = storage::UPLOAD_THRESHOLD / 1_048_576)]
+  upload_threshold_mb: u64,
+  /// Sign every result archive with the ed25519 key stored in hex in this file, see `keygen`,
+  /// rather than with the PERICORTEX_SIGNING_KEY secret, if there is one
+  #[cfg(feature = "signing")]
+  #[arg(long)]
+  signing_key: Option<PathBuf>,
+  /// Show the threads, their tasks, the throughput and the latest log lines in a terminal
+  /// dashboard, rather than scrolling the log; `q` drains the worker
+  #[cfg(feature = "tui")]
+  #[arg(long, conflicts_with_all = ["daemon", "log_file"])]
+  tui: bool,
+}
+
+impl RunArgs {
+  /// The settings given on the command line
+  fn overrides(&self) -> WorkerConfig {
+    WorkerConfig {
+      source: self.source.clone(),
+      sink: self.sink.clone(),
+      pool_size: self.pool_size,
+      io_threads: self.io_threads,
+      message_size: self.message_size,
+      message_size_bounds: self.message_size_bounds,
+      log_level: self.log_level.clone(),
+      compression: self.compression,
+      quiet_hours: Some(self.quiet_hours.clone()).filter(|windows| !windows.is_empty()),
+      max_tasks_per_minute: self.max_tasks_per_minute,
+      empty_input: self.empty_input,
+      empty_input_throttle: self.empty_input_throttle_secs,
+      max_output_size: self.max_output_size_mb.map(|megabytes| megabytes * 1_048_576),
+      oversized_output: self.oversized_output,
+      ..WorkerConfig::default()
+    }
+  }
+
+  /// Set up logging and output filters, and configure `worker` from the configuration file and flags
+  fn prepare<W: Worker>(&self, worker: &mut W) -> Result<WorkerConfig, Box<dyn Error>> {
+    let overrides = self.overrides();
+    logger::init(overrides.log_level_filter()?.unwrap_or(LevelFilter::Info)).unwrap();
+    if let Some(megabytes) = self.strip_files_over_mb {
+      filters::register(Arc::new(StripLargeFiles {
+        max_bytes: megabytes * 1_048_576,
+      }));
+    }
+    if self.anonymize_paths {
+      filters::register(Arc::new(AnonymizePaths::default()));
+    }
+    let config = match self.config {
+      Some(ref path) => WorkerConfig::load(path)?.merged(&overrides),
+      None => overrides,
+    };
+    worker.configure(&config);
+    Ok(config)
+  }
+}
+
+/// What to do with the selected worker
+enum Action {
+  Run,
+  Check { min_scratch_space: u64 },
+  SelfTest,
+  ConvertLocal { input: PathBuf, output: Option<PathBuf>, quiet: bool },
+  Batch { input_dir: PathBuf, output_dir: PathBuf, quiet: bool },
+  Replay { dir: PathBuf, report: Option<PathBuf>, quiet: bool },
+  Bench { input_dir: PathBuf, iterations: usize },
+  Golden { fixtures: PathBuf, golden_dir: PathBuf, update: bool },
+  ShowConfig,
+}
+
+impl Action {
+  fn perform<W: Worker + 'static>(&self, args: RunArgs, mut worker: W) -> Result<(), Box<dyn Error>> {
+    let config = args.prepare(&mut worker)?;
+    match self {
+      Action::Run => {
+        // an upgraded process keeps the PID, detachment and pid file of its predecessor
+        let upgraded = upgrade::restore()?;
+        // detach before any threads are spawned
+        if args.daemon && !upgraded {
+          daemon::daemonize(args.pid_file.as_deref(), args.log_file.as_deref())?;
+        } else if let Some(pid_file) = args.pid_file.as_ref().filter(|_| !upgraded) {
+          daemon::write_pid_file(pid_file)?;
+        }
+        if let Some(ref log_file) = args.log_file {
+          logger::log_to_file(log_file)?;
+        }
+        shutdown::on_signals()?;
+        upgrade::on_signal()?;
+        protocol::set_version(args.protocol_version)?;
+        // before the object store and signing key, which need secrets
+        if let Some(ref provider) = args.secrets {
+          secrets::install(Box::new(provider.clone()));
+        }
+        identity::configure(
+          args.identity_prefix.clone(),
+          args.identity_suffix.clone(),
+          args.random_identity,
+        );
+        schedule::set_quiet_hours(config.quiet_hours.clone().unwrap_or_default());
+        ratelimit::set_max_tasks_per_minute(config.max_tasks_per_minute);
+        config.apply_empty_input();
+        if config.max_output_size.is_some() && config.oversized_output == Some(OversizedOutput::Divert) {
+          #[cfg(feature = "object-storage")]
+          let diverted = args.object_store.is_some();
+          #[cfg(not(feature = "object-storage"))]
+          let diverted = false;
+          if !diverted {
+            return Err(From::from("diverting oversized outputs requires --object-store"));
+          }
+        }
+        config.apply_output_size_limit();
+        if args.attach_input_on_failure {
+          policy::set_attach_input(Some(InputAttachment {
+            extensions: args.attach_input_extensions.clone(),
+            max_bytes: args.attach_input_max_mb.map(|megabytes| megabytes * 1_048_576),
+          }));
+        }
+        if args.max_tasks.is_some() || args.max_failures.is_some() || args.max_transfer_mb.is_some() {
+          quota::enable(Quota {
+            max_tasks: args.max_tasks,
+            max_failures: args.max_failures,
+            max_bytes: args.max_transfer_mb.map(|megabytes| megabytes * 1_048_576),
+          });
+        }
+        progress::set_interval(Duration::new(args.progress_interval, 0));
+        if let Some(seconds) = args.hang_threshold {
+          watchdog::enable(Watchdog {
+            hang_threshold: Duration::new(seconds, 0),
+            respawn: args.respawn_wedged,
+          });
+        }
+        if let Some(megabytes) = args.scratch_budget_mb {
+          scratch::spawn_sweeper(megabytes * 1_048_576, Duration::new(scratch::SWEEP_INTERVAL_SECS, 0));
+        }
+        if let Some(ref address) = args.announcements {
+          announcements::subscribe(address, worker.get_service())?;
+        }
+        if args.dry_run {
+          dryrun::enable();
+        }
+        if let Some(ref address) = args.register {
+          registration::enable(address);
+        }
+        #[cfg(feature = "object-storage")]
+        if let Some(ref url) = args.object_store {
+          if args.protocol_version < protocol::MANIFEST_VERSION {
+            return Err(From::from(format!(
+              "--object-store requires --protocol-version {}",
+              protocol::MANIFEST_VERSION
+            )));
+          }
+          storage::enable(ObjectStore::new(url)?, args.upload_threshold_mb * 1_048_576);
+        }
+        #[cfg(feature = "signing")]
+        {
+          let key = match args.signing_key {
+            Some(ref path) => Some(signing::load_key(path)?),
+            None => signing::provided_key()?,
+          };
+          if let Some(key) = key {
+            signing::enable(key);
+          }
+        }
+        if let Some(bounds) = args.auto_message_size {
+          tuning::enable(bounds);
+        }
+        if let Some(megabytes) = args.sink_queue_mb {
+          pressure::limit_sink_queue(megabytes * 1_048_576);
+        }
+        if !args.mirror_sink.is_empty() {
+          mirror::enable(&args.mirror_sink);
+        }
+        if let Some(ref dir) = args.record {
+          replay::record_into(dir)?;
+        }
+        if let Some(ref path) = args.journal {
+          journal::open(path)?;
+        }
+        if args.snapshot {
+          journal::enable_snapshots();
+        }
+        if let Some(ref path) = args.quarantine {
+          quarantine::open(path, args.quarantine_strikes)?;
+        }
+        // watched for reloads once the worker starts
+        if let Some(path) = args.config.clone() {
+          config::install_from(path, config, args.overrides());
+        }
+        #[cfg(feature = "tui")]
+        let dashboard = if args.tui {
+          Some(dashboard::show(worker.clone())?)
+        } else {
+          None
+        };
+        let result = worker.start(args.limit);
+        #[cfg(feature = "tui")]
+        if let Some(dashboard) = dashboard {
+          dashboard.close();
+        }
+        if result.is_ok() && upgrade::requested() {
+          return upgrade::exec();
+        }
+        if let Some(ref pid_file) = args.pid_file {
+          daemon::remove_pid_file(pid_file);
+        }
+        // the quota was logged when it was reached
+        if result.is_ok() && quota::exceeded().is_some() {
+          process::exit(quota::EXIT_STATUS);
+        }
+        result
+      }
+      Action::Check { min_scratch_space } => {
+        let report = check::diagnose(&worker, *min_scratch_space);
+        print!("{}", report);
+        if report.passed() {
+          Ok(())
+        } else {
+          Err(From::from("some checks failed"))
+        }
+      }
+      Action::ShowConfig => {
+        print!("{}", config::show(&worker, &config)?);
+        Ok(())
+      }
+      Action::SelfTest => {
+        let report = selftest::selftest(&worker)?;
+        print!("{}", report);
+        if report.passed() {
+          Ok(())
+        } else {
+          Err(From::from("the self-test failed"))
+        }
+      }
+      Action::ConvertLocal { input, output, quiet } => {
+        let output = output.clone().unwrap_or_else(|| {
+          let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+          input.with_file_name(format!("{}.{}.zip", stem, worker.get_service()))
+        });
+        let display = ProgressDisplay::show(*quiet, "documents");
+        if let Some(ref display) = display {
+          display.planned(1);
+          display.started(worker.get_identity(), &input.to_string_lossy());
+        }
+        let written = worker.convert_local(input, &output);
+        if let Some(display) = display {
+          let error = written.as_ref().err().map(|e| e.to_string());
+          display.finished(worker.get_identity(), &input.to_string_lossy(), error.as_deref());
+          display.finish();
+        }
+        let written = written?;
+        println!("wrote {} bytes to {}", written, output.display());
+        Ok(())
+      }
+      Action::Batch {
+        input_dir,
+        output_dir,
+        quiet,
+      } => {
+        let runner = LocalRunner {
+          worker,
+          input_dir: input_dir.clone(),
+          output_dir: output_dir.clone(),
+        };
+        let outcomes = match ProgressDisplay::show(*quiet, "entries") {
+          Some(display) => {
+            let outcomes = runner.run_observed(display.clone());
+            display.finish();
+            outcomes?
+          }
+          None => runner.run()?,
+        };
+        let failed = outcomes.iter().filter(|outcome| outcome.result.is_err()).count();
+        println!(
+          "converted {} of {} entries, summary in {}, report in {}",
+          outcomes.len() - failed,
+          outcomes.len(),
+          output_dir.join("summary.csv").display(),
+          output_dir.join("report.json").display()
+        );
+        Ok(())
+      }
+      Action::Replay { dir, report, quiet } => {
+        let outcomes = match ProgressDisplay::show(*quiet, "tasks") {
+          Some(display) => {
+            let outcomes = replay::replay_observed(&worker, dir, &*display);
+            display.finish();
+            outcomes?
+          }
+          None => replay::replay(&worker, dir)?,
+        };
+        let mut regressions = 0;
+        for outcome in &outcomes {
+          let verdict = match outcome.verdict {
+            ReplayVerdict::Identical => "identical".to_string(),
+            ReplayVerdict::NoRecordedResult => "converted, nothing recorded to compare".to_string(),
+            ReplayVerdict::Differs(ref files) => {
+              regressions += 1;
+              format!("differs in {}", files.join(", "))
+            }
+            ReplayVerdict::Failed(ref e) => {
+              regressions += 1;
+              format!("failed: {}", e)
+            }
+          };
+          println!("{}\t{}", outcome.record.taskid, verdict);
+        }
+        println!("replayed {} tasks, {} changed or failed", outcomes.len(), regressions);
+        if let Some(report_dir) = report {
+          Report::new(outcomes.iter().map(TaskReport::from).collect()).write(report_dir)?;
+          println!("report in {}", report_dir.join("report.json").display());
+        }
+        Ok(())
+      }
+      Action::Bench {
+        input_dir,
+        iterations,
+      } => {
+        print!("{}", bench::bench(&worker, input_dir, *iterations)?);
+        Ok(())
+      }
+      Action::Golden {
+        fixtures,
+        golden_dir,
+        update,
+      } => {
+        let outcomes = golden::check(&worker, fixtures, golden_dir, *update)?;
+        for outcome in &outcomes {
+          let fixture = outcome.fixture.display();
+          match outcome.status {
+            GoldenStatus::Matches => println!("{}\tmatches", fixture),
+            GoldenStatus::Updated => println!("{}\tupdated", fixture),
+            GoldenStatus::Missing => println!("{}\tno golden summary, run with --update", fixture),
+            GoldenStatus::Changed(ref differences) => {
+              println!("{}\tchanged", fixture);
+              for difference in differences {
+                println!("  {}", difference);
+              }
+            }
+            GoldenStatus::Failed(ref e) => println!("{}\tfailed: {}", fixture, e),
+          }
+        }
+        let regressions = outcomes.iter().filter(|outcome| outcome.is_regression()).count();
+        if regressions > 0 {
+          return Err(format!("{} of {} fixtures regressed", regressions, outcomes.len()).into());
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+impl WorkerCommand {
+  /// Build the selected worker and perform `action` with it
+  fn dispatch(self, action: Action) -> Result<(), Box<dyn Error>> {
+    match self {
+      #[cfg(feature = "engrafo")]
+      WorkerCommand::Engrafo {
+        health,
+        network,
+        dns,
+        env,
+        image_digest,
+        reuse_containers,
+        gpus,
+        run,
+      } => action.perform(
+        run,
+        EngrafoWorker {
+          source: Endpoint::tcp("131.188.48.209", 51695),
+          sink: Endpoint::tcp("131.188.48.209", 51696),
+          pool_size: num_cpus::get(),
+          health_address: health,
+          network_mode: network,
+          dns,
+          env,
+          image_digest,
+          reuse_containers,
+          gpus,
+          ..EngrafoWorker::default()
+        },
+      ),
+      WorkerCommand::TexToHtml(run) => action.perform(run, TexToHtmlWorker::default()),
+      WorkerCommand::Echo {
+        delay_ms,
+        fail_every,
+        payload_size,
+        corrupt_every,
+        throttle_secs,
+        run,
+      } => action.perform(
+        run,
+        EchoWorker {
+          delay: Duration::from_millis(delay_ms),
+          fail_every,
+          payload_size,
+          corrupt_every,
+          throttle: Duration::new(throttle_secs, 0),
+          ..EchoWorker::default()
+        },
+      ),
+      WorkerCommand::Command { spec, run } => {
+        action.perform(run, CommandWorker::from(CommandSpec::load(&spec)?))
+      }
+    }
+  }
+}
+
+/// The progress of a local run on the terminal: the tasks done with an ETA and the failures so
+/// far, above the task each conversion thread is working on. Log lines are printed above it.
+struct ProgressDisplay {
+  bars: MultiProgress,
+  overall: ProgressBar,
+  threads: Mutex<BTreeMap<String, ProgressBar>>,
+  failed: AtomicUsize,
+}
+
+impl ProgressDisplay {
+  /// A display counting `unit`, unless `quiet` or STDERR isn't a terminal
+  fn show(quiet: bool, unit: &str) -> Option<Arc<ProgressDisplay>> {
+    if quiet || !io::stderr().is_terminal() {
+      return None;
+    }
+    let bars = MultiProgress::new();
+    let overall = bars.add(ProgressBar::new(0));
+    overall.set_style(
+      ProgressStyle::with_template(&format!(
+        "{{wide_bar}} {{pos}}/{{len}} {} [{{elapsed_precise}}, ETA {{eta}}] {{msg}}",
+        unit
+      ))
+      .expect("a valid progress template"),
+    );
+    let printer = bars.clone();
+    logger::print_with(Some(Box::new(move |line| printer.println(line).unwrap_or(()))));
+    Some(Arc::new(ProgressDisplay {
+      bars,
+      overall,
+      threads: Mutex::new(BTreeMap::new()),
+      failed: AtomicUsize::new(0),
+    }))
+  }
+
+  /// Clear the display, which the summary of the run replaces, and print log lines as usual again
+  fn finish(&self) {
+    for bar in self.threads.lock().unwrap().values() {
+      bar.finish_and_clear();
+    }
+    self.overall.finish_and_clear();
+    logger::print_with(None);
+  }
+}
+
+impl BatchObserver for ProgressDisplay {
+  fn planned(&self, total: usize) { self.overall.set_length(total as u64); }
+
+  fn started(&self, identity: &str, task: &str) {
+    let mut threads = self.threads.lock().unwrap();
+    let bar = threads.entry(identity.to_string()).or_insert_with(|| {
+      let bar = self.bars.add(ProgressBar::new_spinner());
+      bar.set_style(ProgressStyle::with_template("{spinner} {prefix}: {msg} ({elapsed})").expect("a valid progress template"));
+      bar.set_prefix(identity.to_string());
+      bar.enable_steady_tick(Duration::from_millis(100));
+      bar
+    });
+    bar.reset_elapsed();
+    bar.set_message(task.to_string());
+  }
+
+  fn finished(&self, identity: &str, task: &str, error: Option<&str>) {
+    if let Some(bar) = self.threads.lock().unwrap().get(identity) {
+      bar.set_message("idle");
+    }
+    if let Some(error) = error {
+      let failed = self.failed.fetch_add(1, Ordering::SeqCst) + 1;
+      let error = error.lines().next().unwrap_or_default();
+      self.overall.set_message(format!("{} failed, last {}: {}", failed, task, error));
+    }
+    self.overall.inc(1);
+  }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+  let cli = Cli::parse();
+  match cli.command {
+    Commands::Run(worker) => worker.dispatch(Action::Run),
+    Commands::Check {
+      min_scratch_mb,
+      worker,
+    } => worker.dispatch(Action::Check {
+      min_scratch_space: min_scratch_mb * 1_048_576,
+    }),
+    Commands::Selftest { worker } => worker.dispatch(Action::SelfTest),
+    Commands::Config(ConfigCommand::Show { worker }) => worker.dispatch(Action::ShowConfig),
+    Commands::ConvertLocal {
+      input,
+      output,
+      quiet,
+      worker,
+    } => worker.dispatch(Action::ConvertLocal { input, output, quiet }),
+    Commands::Bench {
+      input_dir,
+      iterations,
+      worker,
+    } => worker.dispatch(Action::Bench {
+      input_dir,
+      iterations,
+    }),
+    Commands::Golden {
+      fixtures,
+      golden_dir,
+      update,
+      worker,
+    } => worker.dispatch(Action::Golden {
+      fixtures,
+      golden_dir,
+      update,
+    }),
+    Commands::Loadtest {
+      source,
+      sink,
+      service,
+      workers,
+      seconds,
+      latency,
+      seed,
+    } => {
+      if let Some(seed) = seed {
+        random::seed(seed);
+      }
+      print!(
+        "{}",
+        loadtest::run(LoadTest {
+          source,
+          sink,
+          service,
+          workers,
+          duration: Duration::from_secs(seconds),
+          latency,
+        })?
+      );
+      Ok(())
+    }
+    Commands::Import {
+      input_dir,
+      output_dir,
+    } => {
+      logger::init(LevelFilter::Info).unwrap();
+      let index = import::import(&input_dir, &output_dir)?;
+      for skipped in &index.skipped {
+        println!("skipped {}: {}", skipped.source.display(), skipped.reason);
+      }
+      println!(
+        "imported {} entries, skipped {}, index in {}",
+        index.entries.len(),
+        index.skipped.len(),
+        output_dir.join(import::INDEX_FILE).display()
+      );
+      Ok(())
+    }
+    Commands::Submit {
+      manifest,
+      ventilator,
+      corpus,
+      rate,
+      timeout,
+    } => {
+      logger::init(LevelFilter::Info).unwrap();
+      let corpus = match corpus {
+        Some(corpus) => corpus,
+        None => manifest
+          .canonicalize()?
+          .parent()
+          .and_then(|dir| dir.file_name())
+          .map(|name| name.to_string_lossy().into_owned())
+          .ok_or("cannot name the corpus after the manifest's directory, pass --corpus")?,
+      };
+      let options = SubmitOptions {
+        ventilator,
+        corpus,
+        rate,
+        timeout: Duration::from_secs(timeout),
+      };
+      print!("{}", submit::submit(&manifest, &options)?);
+      Ok(())
+    }
+    Commands::Capabilities { json } => {
+      let capabilities = capabilities::capabilities();
+      if json {
+        println!("{}", serde_json::to_string_pretty(&capabilities)?);
+      } else {
+        print!("{}", capabilities);
+      }
+      Ok(())
+    }
+    #[cfg(feature = "signing")]
+    Commands::Keygen { output } => {
+      let key = signing::generate_key();
+      signing::save_key(&output, &key)?;
+      println!("{}", signing::public_key(&key));
+      Ok(())
+    }
+    #[cfg(feature = "signing")]
+    Commands::Verify { results, public_key } => {
+      let trusted = public_key
+        .iter()
+        .map(|key| signing::parse_public_key(key))
+        .collect::<Result<Vec<_>, _>>()?;
+      let mut failures = 0;
+      for path in &results {
+        match signing::verify(path, &trusted) {
+          Ok(signature) => println!("{}: signed for task {}", path.display(), signature.taskid),
+          Err(e) => {
+            failures += 1;
+            println!("{}: {}", path.display(), e);
+          }
+        }
+      }
+      if failures > 0 {
+        return Err(From::from(format!("{} of {} results failed verification", failures, results.len())));
+      }
+      Ok(())
+    }
+    Commands::Diff { old, new } => {
+      print!("{}", diff::diff_archives(&old, &new)?);
+      Ok(())
+    }
+    Commands::Journal { path, compare } => {
+      let entries = journal::read(&path)?;
+      let count = |event| entries.iter().filter(|entry| entry.event == event).count();
+      println!(
+        "{} tasks started, {} completed, {} failed",
+        count(JournalEvent::Started),
+        count(JournalEvent::Completed),
+        count(JournalEvent::Failed)
+      );
+      for entry in journal::in_progress(&entries) {
+        println!(
+          "unfinished: task {} on {}, started {} ({} bytes)",
+          entry.taskid, entry.identity, entry.time, entry.input_bytes
+        );
+      }
+      if let Some(other_path) = compare {
+        let others = journal::read(&other_path)?;
+        let others = journal::snapshots_by_task(&others);
+        let mut snapshots: Vec<_> = journal::snapshots_by_task(&entries).into_iter().collect();
+        snapshots.sort_by_key(|(taskid, _)| *taskid);
+        for (taskid, ours) in snapshots {
+          let Some(theirs) = others.get(taskid) else {
+            continue;
+          };
+          for (tree, ours, theirs) in [("input", ours.input, theirs.input), ("output", ours.output, theirs.output)] {
+            if let (Some(ours), Some(theirs)) = (ours, theirs) {
+              for change in journal::manifest_changes(ours, theirs) {
+                println!("task {} {}: {}", taskid, tree, change);
+              }
+            }
+          }
+        }
+      }
+      Ok(())
+    }
+    Commands::Triage {
+      journal,
+      recordings,
+      output,
+      per_category,
+      max_mb,
+    } => {
+      logger::init(LevelFilter::Info).unwrap();
+      let options = TriageOptions {
+        per_category,
+        max_bytes: max_mb * 1024 * 1024,
+      };
+      let categories = triage::collect(&journal, recordings.as_deref(), options)?;
+      for category in &categories {
+        println!(
+          "{}: {} failed tasks, {} packaged",
+          category.category,
+          category.failures,
+          category.cases.len()
+        );
+      }
+      triage::write_bundle(&output, &categories, triage::environment(recordings.as_deref()))?;
+      println!("triage bundle in {}", output.display());
+      Ok(())
+    }
+    Commands::Replay {
+      dir,
+      report,
+      quiet,
+      worker,
+    } => worker.dispatch(Action::Replay { dir, report, quiet }),
+    Commands::Batch {
+      input_dir,
+      output_dir,
+      quiet,
+      worker,
+    } => worker.dispatch(Action::Batch {
+      input_dir,
+      output_dir,
+      quiet,
+    }),
+  }
+}
@@ -1,5 +1,8 @@
 //! Simple adaptors to relax the CorTeX conentions for agnostic third-party tooling
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fmt;
 use std::fs::{create_dir_all, File};
 use std::io::copy;
 use std::io::prelude::*;
@@ -7,70 +10,551 @@ use std::io::SeekFrom;
 use std::io::{Seek, Write};
 use std::iter::Iterator;
 use std::path::Path;
+use std::process::Output;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use tempdir::TempDir;
 use tempfile::tempfile;
 
 use walkdir::{DirEntry, WalkDir};
 use zip::write::FileOptions;
+use zip::result::ZipError;
 use zip::ZipArchive;
 
+use crate::policy::InputAttachment;
+use crate::scratch;
+
+/// Entry count, sizes and duration of extracting or creating a ZIP archive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    /// Files in the archive
+    pub entries: usize,
+    /// Total size of the files
+    pub uncompressed_bytes: u64,
+    /// Total size of the files in the archive, as compressed
+    pub compressed_bytes: u64,
+    /// Time spent (de)archiving
+    pub duration: Duration,
+}
+
+impl ArchiveStats {
+    const NONE: ArchiveStats = ArchiveStats {
+        entries: 0,
+        uncompressed_bytes: 0,
+        compressed_bytes: 0,
+        duration: Duration::ZERO,
+    };
+
+    fn add(&mut self, other: &ArchiveStats) {
+        self.entries += other.entries;
+        self.uncompressed_bytes += other.uncompressed_bytes;
+        self.compressed_bytes += other.compressed_bytes;
+        self.duration += other.duration;
+    }
+}
+
+impl fmt::Display for ArchiveStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} bytes ({} compressed) in {} ms",
+            self.entries,
+            self.uncompressed_bytes,
+            self.compressed_bytes,
+            self.duration.as_millis()
+        )
+    }
+}
+
+/// The extractions and archivings of a thread, e.g. for the task it is converting
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchivingTotals {
+    /// All ZIP archives extracted
+    pub extracted: ArchiveStats,
+    /// All ZIP archives created
+    pub archived: ArchiveStats,
+}
+
+thread_local! {
+    static ARCHIVING: Cell<ArchivingTotals> = const {
+        Cell::new(ArchivingTotals {
+            extracted: ArchiveStats::NONE,
+            archived: ArchiveStats::NONE,
+        })
+    };
+}
+
+/// The extractions and archivings of this thread since the previous call
+pub fn take_archiving() -> ArchivingTotals {
+    ARCHIVING.with(|archiving| archiving.take())
+}
+
+fn record_archiving(stats: ArchiveStats, extracted: bool) {
+    ARCHIVING.with(|archiving| {
+        let mut totals = archiving.get();
+        if extracted {
+            totals.extracted.add(&stats);
+        } else {
+            totals.archived.add(&stats);
+        }
+        archiving.set(totals);
+    });
+}
+
+/// Why an input was rejected before conversion
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The entry contains no `.tex` source
+    NoTexSource,
+    /// The entry is encrypted
+    Encrypted,
+    /// The entry is not in a format the converter understands
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RejectReason::NoTexSource => write!(f, "no_tex_source the entry contains no .tex file"),
+            RejectReason::Encrypted => write!(f, "encrypted the entry is password-protected"),
+            RejectReason::UnsupportedFormat(details) => write!(f, "unsupported_format {}", details),
+        }
+    }
+}
+
+impl Error for RejectReason {}
+
+/// Require at least one `.tex` file in an extracted corpus entry
+pub fn validate_tex_sources(dir: &Path) -> Result<(), RejectReason> {
+    let has_tex = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .any(|entry| {
+            entry.file_type().is_file() && entry.path().extension().map(|ext| ext == "tex").unwrap_or(false)
+        });
+    if has_tex {
+        Ok(())
+    } else {
+        Err(RejectReason::NoTexSource)
+    }
+}
+
+/// A result archive holding only a `cortex.log` that reports the rejection as fatal,
+/// so that CorTeX records a precise status instead of a generic failure
+pub fn rejection_archive(reason: &RejectReason) -> Result<File, Box<dyn Error>> {
+    fatal_archive("invalid_input", reason, None)
+}
+
+/// A result archive reporting an empty task input in its `cortex.log`, with a code of its own
+pub fn empty_input_archive() -> Result<File, Box<dyn Error>> {
+    fatal_archive("empty_input", &"empty the task's input was empty", None)
+}
+
+/// A result archive reporting a malformed dispatcher message in its `cortex.log`
+pub fn protocol_error_archive(error: &dyn fmt::Display) -> Result<File, Box<dyn Error>> {
+    fatal_archive("protocol", &format!("malformed_message {}", error), None)
+}
+
+/// The report of a task whose conversion failed with `error`, attaching the files of its `input`
+/// which `attachment` selects under `input/`. Selected files beyond the attachment's budget are
+/// listed in the `cortex.log` instead. An input which isn't a ZIP is attached as `input/payload`
+pub fn failure_with_input_archive<R: Read + Seek>(
+    error: &str,
+    input: &mut R,
+    attachment: &InputAttachment,
+) -> Result<File, Box<dyn Error>> {
+    let mut budget = attachment.max_bytes.unwrap_or(u64::MAX);
+    let mut file = tempfile()?;
+    {
+        let mut zip = zip::ZipWriter::new(&mut file);
+        let mut log = format!("Fatal:conversion:failed {}\n", error.replace('\n', " "));
+        input.seek(SeekFrom::Start(0))?;
+        match ZipArchive::new(&mut *input) {
+            Ok(mut archive) => {
+                for index in 0..archive.len() {
+                    let entry = archive.by_index_raw(index)?;
+                    if entry.is_dir() || !attachment.includes(entry.name()) {
+                        continue;
+                    }
+                    let name = entry.name().to_string();
+                    if entry.size() > budget {
+                        log.push_str(&format!("Info:triage:omitted {} ({} bytes)\n", name, entry.size()));
+                        continue;
+                    }
+                    budget -= entry.size();
+                    zip.raw_copy_file_rename(entry, format!("input/{}", name))?;
+                }
+            }
+            Err(_) => {
+                let size = input.seek(SeekFrom::End(0))?;
+                input.seek(SeekFrom::Start(0))?;
+                if size > budget {
+                    log.push_str(&format!("Info:triage:omitted payload ({} bytes)\n", size));
+                } else {
+                    zip.start_file("input/payload", FileOptions::default())?;
+                    copy(input, &mut zip)?;
+                }
+            }
+        }
+        zip.start_file("cortex.log", FileOptions::default())?;
+        zip.write_all(log.as_bytes())?;
+        zip.finish()?;
+    }
+    input.seek(SeekFrom::Start(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// Ways a result can break the CorTeX conventions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ContractViolation {
+    /// The converter produced no output
+    Empty,
+    /// The output is not a readable ZIP archive
+    NotAZip(String),
+    /// The archive has no `cortex.log` at its root
+    MissingLog,
+}
+
+impl fmt::Display for ContractViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContractViolation::Empty => write!(f, "empty_result the converter produced no output"),
+            ContractViolation::NotAZip(details) => write!(f, "not_a_zip {}", details),
+            ContractViolation::MissingLog => {
+                write!(f, "missing_log the archive has no cortex.log at its root")
+            }
+        }
+    }
+}
+
+impl Error for ContractViolation {}
+
+/// Check that a result is a non-empty ZIP with a `cortex.log` at its root, rewinding it afterwards
+pub fn verify_result<R: Read + Seek>(result: &mut R) -> Result<(), ContractViolation> {
+    let verdict = match result.seek(SeekFrom::End(0)) {
+        Ok(0) => Err(ContractViolation::Empty),
+        Ok(_) => match ZipArchive::new(&mut *result) {
+            Ok(archive) => {
+                if archive.file_names().any(|name| name == "cortex.log") {
+                    Ok(())
+                } else {
+                    Err(ContractViolation::MissingLog)
+                }
+            }
+            Err(e) => Err(ContractViolation::NotAZip(e.to_string())),
+        },
+        Err(e) => Err(ContractViolation::NotAZip(e.to_string())),
+    };
+    result.seek(SeekFrom::Start(0)).ok();
+    verdict
+}
+
+/// A failure archive reporting the violation in its `cortex.log`,
+/// with the malformed output attached as `malformed_result` for inspection
+pub fn failure_archive(violation: &ContractViolation, result: &mut File) -> Result<File, Box<dyn Error>> {
+    let attachment = if *violation == ContractViolation::Empty { None } else { Some(result) };
+    fatal_archive("result_contract", violation, attachment)
+}
+
+/// A `cortex.log` synthesized from a converter's captured output: its STDERR and STDOUT,
+/// followed by a status line for its exit code
+pub fn synthesize_log(tool: &str, output: &Output) -> String {
+    let mut log = String::from_utf8_lossy(&output.stderr).into_owned();
+    log.push_str(&String::from_utf8_lossy(&output.stdout));
+    if !log.is_empty() && !log.ends_with('\n') {
+        log.push('\n');
+    }
+    let status = if output.status.success() {
+        format!("Info:conversion:status {} completed ({})", tool, output.status)
+    } else {
+        format!("Fatal:conversion:status {} failed ({})", tool, output.status)
+    };
+    log.push_str(&status);
+    log.push('\n');
+    log
+}
+
+/// Add `contents` as the entry `name` of the ZIP `archive`, replacing an existing entry of that
+/// name; all other entries are copied over without recompressing
+pub fn upsert_zip_entry(archive: File, name: &str, contents: &[u8]) -> Result<File, Box<dyn Error>> {
+    let mut source = ZipArchive::new(archive)?;
+    let mut file = tempfile()?;
+    {
+        let mut zip = zip::ZipWriter::new(&mut file);
+        for i in 0..source.len() {
+            let entry = source.by_index_raw(i)?;
+            if entry.name() != name {
+                zip.raw_copy_file(entry)?;
+            }
+        }
+        zip.start_file(name, FileOptions::default().compression_method(METHOD_DEFLATED))?;
+        zip.write_all(contents)?;
+        zip.finish()?;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// Inject `log` as the `cortex.log` of a result archive which lacks one
+pub fn ensure_cortex_log(mut result: File, log: &str) -> Result<File, Box<dyn Error>> {
+    if verify_result(&mut result) == Err(ContractViolation::MissingLog) {
+        upsert_zip_entry(result, "cortex.log", log.as_bytes())
+    } else {
+        Ok(result)
+    }
+}
+
+/// The report of a task that interrupted the worker `strikes` times, and is no longer converted
+pub fn quarantine_archive(strikes: u32) -> Result<File, Box<dyn Error>> {
+    fatal_archive(
+        "quarantined",
+        &format!("poison_task interrupted the conversion {} times", strikes),
+        None,
+    )
+}
+
+/// A result archive holding only a `cortex.log` that reports a result of `size` bytes as rejected
+/// for exceeding the maximal output size of `max_size` bytes
+pub fn oversized_output_archive(size: u64, max_size: u64) -> Result<File, Box<dyn Error>> {
+    fatal_archive(
+        "output_size",
+        &format!("exceeded the result of {} bytes is over the limit of {} bytes", size, max_size),
+        None,
+    )
+}
+
+/// The ZIP `archive` without its entries named in `remove`, and with `log` appended to its
+/// `cortex.log`; all other entries are copied over without recompressing
+pub fn remove_zip_entries(archive: File, remove: &[String], log: &str) -> Result<File, Box<dyn Error>> {
+    let mut source = ZipArchive::new(archive)?;
+    let mut cortex_log = String::new();
+    if let Ok(mut existing) = source.by_name("cortex.log") {
+        existing.read_to_string(&mut cortex_log)?;
+    }
+    if !cortex_log.is_empty() && !cortex_log.ends_with('\n') {
+        cortex_log.push('\n');
+    }
+    cortex_log.push_str(log);
+    let mut file = tempfile()?;
+    {
+        let mut zip = zip::ZipWriter::new(&mut file);
+        for i in 0..source.len() {
+            let entry = source.by_index_raw(i)?;
+            if entry.name() != "cortex.log" && !remove.iter().any(|name| name == entry.name()) {
+                zip.raw_copy_file(entry)?;
+            }
+        }
+        zip.start_file("cortex.log", FileOptions::default().compression_method(METHOD_DEFLATED))?;
+        zip.write_all(cortex_log.as_bytes())?;
+        zip.finish()?;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// The report of a task whose conversion crashed the worker thread with `panic`
+pub fn crash_archive(panic: &dyn fmt::Display) -> Result<File, Box<dyn Error>> {
+    fatal_archive("workercrash", &format!("panic {}", panic), None)
+}
+
+/// The report of a task whose conversion wedged its thread, as described by `report`
+pub fn wedged_archive(report: &dyn fmt::Display) -> Result<File, Box<dyn Error>> {
+    fatal_archive("workercrash", &format!("wedged {}", report), None)
+}
+
+/// The report of a task received during a dry run, extracted into `files` files but not converted
+pub fn dry_run_archive(files: usize) -> Result<File, Box<dyn Error>> {
+    fatal_archive(
+        "dry_run",
+        &format!("not_converted received and extracted {} files, conversion skipped", files),
+        None,
+    )
+}
+
+fn fatal_archive(
+    category: &str,
+    message: &dyn fmt::Display,
+    attachment: Option<&mut File>,
+) -> Result<File, Box<dyn Error>> {
+    let mut file = tempfile()?;
+    {
+        let mut zip = zip::ZipWriter::new(&mut file);
+        zip.start_file("cortex.log", FileOptions::default())?;
+        writeln!(zip, "Fatal:{}:{}", category, message)?;
+        if let Some(attachment) = attachment {
+            zip.start_file("malformed_result", FileOptions::default())?;
+            copy(attachment, &mut zip)?;
+        }
+        zip.finish()?;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
 /// Transform the ZIP provided by cortex into a TempDir,
 /// for e.g. tools such as Engrafo that aren't ZIP-capable
 pub fn extract_zip_to_tmpdir(path: &Path, tmpdir_prefix: &str) -> Result<TempDir, Box<dyn Error>> {
-    let input_tmpdir = TempDir::new(tmpdir_prefix)?;
-    let unpacked_dir_path = input_tmpdir.path().to_str().unwrap().to_string() + "/";
+    extract_zip_reader_to_tmpdir(File::open(path)?, tmpdir_prefix)
+}
+
+/// Like `extract_zip_to_tmpdir`, reading the ZIP from memory or any other seekable stream,
+/// without writing it to disk first
+pub fn extract_zip_reader_to_tmpdir<R: Read + Seek>(
+    reader: R,
+    tmpdir_prefix: &str,
+) -> Result<TempDir, Box<dyn Error>> {
+    let start = Instant::now();
+    let input_tmpdir = scratch::tempdir(tmpdir_prefix)?;
 
     // unpack the Zip file for engrafo
-    let inputzip = File::open(path)?;
-    let mut input_archive = ZipArchive::new(inputzip)?;
+    let mut input_archive = ZipArchive::new(reader)
+        .map_err(|e| RejectReason::UnsupportedFormat(format!("not a ZIP archive: {}", e)))?;
+    let mut stats = ArchiveStats::default();
     for i in 0..input_archive.len() {
-        let mut file = input_archive.by_index(i)?;
-        let extract_path = file.mangled_name();
-        let extract_pathname = extract_path.as_path().display();
-        let full_pathname = format!("{}{}", unpacked_dir_path, extract_pathname);
+        let mut file = input_archive.by_index(i).map_err(|e| match e {
+            ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => {
+                Box::new(RejectReason::Encrypted)
+            }
+            e => Box::<dyn Error>::from(e),
+        })?;
+        let full_path = input_tmpdir.path().join(file.mangled_name());
         if (file.name()).ends_with('/') {
-            create_dir_all(&full_pathname)?;
+            create_dir_all(&full_path)?;
         } else {
-            if let Some(p) = extract_path.parent() {
-                if !p.exists() {
-                    let absolute_parent = format!("{}{}", unpacked_dir_path, p.display());
-                    create_dir_all(absolute_parent)?;
-                }
+            if let Some(p) = full_path.parent() {
+                create_dir_all(p)?;
             }
-            let mut extracted_file = File::create(&full_pathname)?;
+            let mut extracted_file = File::create(&full_path)?;
             copy(&mut file, &mut extracted_file)?;
+            stats.entries += 1;
+            stats.uncompressed_bytes += file.size();
+            stats.compressed_bytes += file.compressed_size();
         }
     }
+    stats.duration = start.elapsed();
+    debug!(target: "adaptor", "extracted {}", stats);
+    record_archiving(stats, true);
     Ok(input_tmpdir)
 }
 
+/// Size and CRC-32 of every file entry in a ZIP archive, keyed by entry name
+pub fn archive_manifest(path: &Path) -> Result<BTreeMap<String, (u64, u32)>, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut manifest = BTreeMap::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        if file.is_file() {
+            manifest.insert(file.name().to_string(), (file.size(), file.crc32()));
+        }
+    }
+    Ok(manifest)
+}
+
+/// Contents of the entry `name` of a ZIP archive, if present
+pub fn read_archive_entry(path: &Path, name: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    read_zip_entry(File::open(path)?, name)
+}
+
+/// Contents of the entry `name` of the ZIP archive read from `reader`, if present
+pub fn read_zip_entry<R: Read + Seek>(reader: R, name: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let result = match archive.by_name(name) {
+        Ok(mut file) => {
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            Ok(Some(contents))
+        }
+        Err(zip::result::ZipError::FileNotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    };
+    result
+}
+
 /// Adaptor that turns an output temporary directory (assuming the filnema conventions are _already_ ollowed)
 /// into a ZIP file transmittable back to Cortex
 pub fn archive_tmpdir_to_zip(tmpdir: TempDir) -> Result<File, Box<dyn Error>> {
-    let dir_path = tmpdir.path().to_str().unwrap();
-    archive_directory(dir_path)
+    archive_directory(tmpdir.path(), 1)
+}
+
+/// Like `archive_tmpdir_to_zip`, deflating the files on up to `threads` threads,
+/// which pays off for outputs with many assets
+pub fn archive_tmpdir_to_zip_parallel(tmpdir: TempDir, threads: usize) -> Result<File, Box<dyn Error>> {
+    archive_directory(tmpdir.path(), threads)
 }
 
 const METHOD_DEFLATED: zip::CompressionMethod = zip::CompressionMethod::Deflated;
 
-fn archive_directory(src_dir: &str) -> Result<File, Box<dyn Error>> {
+fn archive_directory(src_dir: &Path, threads: usize) -> Result<File, Box<dyn Error>> {
     let method = METHOD_DEFLATED;
+    let start = Instant::now();
 
     let mut file = tempfile()?;
 
     let walkdir = WalkDir::new(src_dir);
-    let it = walkdir.into_iter();
+    let entries: Vec<DirEntry> = walkdir
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .collect();
+    let mut stats = ArchiveStats {
+        entries: entries.len(),
+        uncompressed_bytes: entries
+            .iter()
+            .filter_map(|entry| entry.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum(),
+        ..ArchiveStats::default()
+    };
+    let threads = threads.clamp(1, entries.len().max(1));
 
-    zip_one_dir(&mut it.filter_map(Result::ok), src_dir, &mut file, method)?;
+    if threads == 1 {
+        zip_one_dir(&mut entries.into_iter(), src_dir, &mut file, method)?;
+    } else {
+        // Every thread deflates its share of the files into a partial archive,
+        // whose compressed entries are then copied over without recompressing
+        let mut shares = vec![Vec::new(); threads];
+        for (index, entry) in entries.into_iter().enumerate() {
+            shares[index % threads].push(entry);
+        }
+        let parts = thread::scope(|scope| {
+            let handles: Vec<_> = shares
+                .into_iter()
+                .map(|share| {
+                    scope.spawn(move || -> zip::result::ZipResult<File> {
+                        let mut part = tempfile()?;
+                        zip_one_dir(&mut share.into_iter(), src_dir, &mut part, method)?;
+                        Ok(part)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("a compression thread panicked"))
+                .collect::<zip::result::ZipResult<Vec<File>>>()
+        })?;
+        let mut zip = zip::ZipWriter::new(&mut file);
+        for part in parts {
+            let mut part_archive = ZipArchive::new(part)?;
+            for i in 0..part_archive.len() {
+                zip.raw_copy_file(part_archive.by_index(i)?)?;
+            }
+        }
+        zip.finish()?;
+    }
 
+    stats.compressed_bytes = file.seek(SeekFrom::End(0))?;
     file.seek(SeekFrom::Start(0))?;
+    stats.duration = start.elapsed();
+    debug!(target: "adaptor", "archived {}", stats);
+    record_archiving(stats, false);
     Ok(file)
 }
 
 fn zip_one_dir<T>(
     it: &mut dyn Iterator<Item = DirEntry>,
-    prefix: &str,
+    prefix: &Path,
     writer: &mut T,
     method: zip::CompressionMethod,
 ) -> zip::result::ZipResult<()>
@@ -78,20 +562,15 @@ where
     T: Write + Seek,
 {
     let mut zip = zip::ZipWriter::new(writer);
-    let options = FileOptions::default()
-        .compression_method(method)
-        .unix_permissions(0o755);
+    let options = FileOptions::default().compression_method(method);
+    #[cfg(unix)]
+    let options = options.unix_permissions(0o755);
 
     let mut buffer = Vec::new();
     for entry in it {
         let path = entry.path();
-        let name = path
-            .strip_prefix(Path::new(prefix))
-            .unwrap()
-            .to_str()
-            .unwrap();
-
         if path.is_file() {
+            let name = archive_entry_name(path.strip_prefix(prefix).unwrap());
             zip.start_file(name, options)?;
             let mut f = File::open(path)?;
 
@@ -103,3 +582,12 @@ where
     zip.finish()?;
     Result::Ok(())
 }
+
+/// ZIP entry names always use `/` as the separator, regardless of the host platform
+fn archive_entry_name(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,169 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Analysis of TeX source bundles, e.g. which of several `.tex` files is the main one.
+//!
+//! The main file is told apart in stages, each only narrowing the candidates left by the one
+//! before: files with a `\documentclass` (or `\documentstyle`) come first, then the files no
+//! other one `\input`s or `\include`s, and finally a file clearly larger than all the others.
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+/// How much larger than every other candidate a file must be, for its size to make it the main one
+pub const SIZE_MARGIN: f64 = 1.5;
+
+/// Commands whose argument names another source file of the document
+const INCLUSIONS: &[&str] = &["\\input", "\\include", "\\subfile"];
+
+/// Why no main `.tex` file could be chosen
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ambiguity {
+  /// The bundle contains no `.tex` file
+  NoTexFile,
+  /// The heuristics can't tell these candidates apart
+  Tied(Vec<PathBuf>),
+}
+
+impl fmt::Display for Ambiguity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Ambiguity::NoTexFile => f.write_str("no .tex file to convert"),
+      Ambiguity::Tied(candidates) => {
+        let names: Vec<String> = candidates.iter().map(|path| path.display().to_string()).collect();
+        write!(f, "cannot choose the main .tex file among {}", names.join(", "))
+      }
+    }
+  }
+}
+
+impl Error for Ambiguity {}
+
+/// A `.tex` file of a bundle, with its text stripped of comments
+struct Source {
+  path: PathBuf,
+  /// The path relative to the bundle, without the `.tex` extension and with `/` separators
+  name: String,
+  text: String,
+  size: u64,
+}
+
+/// The main `.tex` file of the sources under `dir`
+pub fn find_main_tex_file(dir: &Path) -> Result<PathBuf, Ambiguity> {
+  let sources = tex_sources(dir);
+  let mut candidates: Vec<&Source> = sources.iter().collect();
+  if candidates.is_empty() {
+    return Err(Ambiguity::NoTexFile);
+  }
+
+  let with_class: Vec<&Source> = candidates
+    .iter()
+    .copied()
+    .filter(|source| source.text.contains("\\documentclass") || source.text.contains("\\documentstyle"))
+    .collect();
+  if !with_class.is_empty() {
+    candidates = with_class;
+  }
+  if candidates.len() == 1 {
+    return Ok(candidates[0].path.clone());
+  }
+
+  let included: HashSet<String> = sources.iter().flat_map(|source| inclusions(&source.text)).collect();
+  let roots: Vec<&Source> = candidates
+    .iter()
+    .copied()
+    .filter(|source| !included.contains(&source.name))
+    .collect();
+  // every candidate being included means a cycle, which tells nothing
+  if !roots.is_empty() {
+    candidates = roots;
+  }
+  if candidates.len() == 1 {
+    return Ok(candidates[0].path.clone());
+  }
+
+  candidates.sort_by_key(|source| Reverse(source.size));
+  if candidates[0].size as f64 >= candidates[1].size as f64 * SIZE_MARGIN {
+    return Ok(candidates[0].path.clone());
+  }
+  let mut tied: Vec<PathBuf> = candidates.iter().map(|source| source.path.clone()).collect();
+  tied.sort();
+  Err(Ambiguity::Tied(tied))
+}
+
+/// The `.tex` files under `dir`, in path order
+fn tex_sources(dir: &Path) -> Vec<Source> {
+  let mut sources: Vec<Source> = WalkDir::new(dir)
+    .into_iter()
+    .filter_map(Result::ok)
+    .filter(|entry| entry.file_type().is_file())
+    .filter(|entry| entry.path().extension().map(|ext| ext == "tex").unwrap_or(false))
+    .filter_map(|entry| {
+      let bytes = fs::read(entry.path()).ok()?;
+      let relative = entry.path().strip_prefix(dir).ok()?.with_extension("");
+      let name: Vec<String> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+      Some(Source {
+        path: entry.path().to_path_buf(),
+        name: name.join("/"),
+        text: strip_comments(&String::from_utf8_lossy(&bytes)),
+        size: bytes.len() as u64,
+      })
+    })
+    .collect();
+  sources.sort_by(|a, b| a.path.cmp(&b.path));
+  sources
+}
+
+/// `text` without its `%` comments
+fn strip_comments(text: &str) -> String {
+  let mut stripped = String::with_capacity(text.len());
+  for line in text.lines() {
+    let mut escaped = false;
+    for c in line.chars() {
+      if c == '%' && !escaped {
+        break;
+      }
+      escaped = c == '\\' && !escaped;
+      stripped.push(c);
+    }
+    stripped.push('\n');
+  }
+  stripped
+}
+
+/// The files `text` includes, named as in [`Source::name`]
+fn inclusions(text: &str) -> Vec<String> {
+  let mut names = Vec::new();
+  for command in INCLUSIONS {
+    for (start, _) in text.match_indices(command) {
+      let rest = &text[start + command.len()..];
+      // `\input` but not `\inputencoding`
+      if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        continue;
+      }
+      let rest = rest.trim_start();
+      let argument = match rest.strip_prefix('{') {
+        Some(braced) => braced.split('}').next().unwrap_or(""),
+        None => rest.split(|c: char| c.is_whitespace() || c == '\\').next().unwrap_or(""),
+      };
+      let argument = argument.trim().trim_start_matches("./");
+      if !argument.is_empty() {
+        names.push(argument.strip_suffix(".tex").unwrap_or(argument).to_string());
+      }
+    }
+  }
+  names
+}
@@ -0,0 +1,157 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fleet-wide announcements broadcast by the dispatcher, received on a SUB socket.
+//!
+//! Every announcement is a single text frame: a verb, the service it targets (`*` for all
+//! services) and an optional free-form message, e.g. `pause engrafo upgrading the image`.
+//! `pause` stops fetching new tasks until `resume`, `drain` completes the tasks in flight and
+//! exits, and `notice` (new corpus priorities, upcoming maintenance, ...) is only logged.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::shutdown;
+
+/// What the dispatcher asks of the workers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Directive {
+  /// Stop fetching new tasks
+  Pause,
+  /// Fetch tasks again
+  Resume,
+  /// Complete the tasks in flight, then exit
+  Drain,
+  /// Nothing to do, log the message
+  Notice,
+}
+
+/// A single announcement
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Announcement {
+  /// What to do
+  pub directive: Directive,
+  /// The service addressed, `None` for all of them
+  pub service: Option<String>,
+  /// Explanation for the operators
+  pub message: String,
+}
+
+impl Announcement {
+  /// Is the announcement addressed to `service`?
+  pub fn concerns(&self, service: &str) -> bool {
+    self.service.as_deref().map(|target| target == service).unwrap_or(true)
+  }
+}
+
+impl FromStr for Announcement {
+  type Err = String;
+  fn from_str(frame: &str) -> Result<Announcement, String> {
+    let mut parts = frame.trim().splitn(3, char::is_whitespace);
+    let directive = match parts.next().unwrap_or_default() {
+      "pause" => Directive::Pause,
+      "resume" => Directive::Resume,
+      "drain" => Directive::Drain,
+      "notice" => Directive::Notice,
+      other => return Err(format!("unknown announcement {:?}", other)),
+    };
+    let service = match parts.next() {
+      None | Some("*") => None,
+      Some(service) => Some(service.to_string()),
+    };
+    Ok(Announcement {
+      directive,
+      service,
+      message: parts.next().unwrap_or_default().trim().to_string(),
+    })
+  }
+}
+
+impl fmt::Display for Announcement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let directive = match self.directive {
+      Directive::Pause => "pause",
+      Directive::Resume => "resume",
+      Directive::Drain => "drain",
+      Directive::Notice => "notice",
+    };
+    write!(f, "{} {}", directive, self.service.as_deref().unwrap_or("*"))?;
+    if !self.message.is_empty() {
+      write!(f, " {}", self.message)?;
+    }
+    Ok(())
+  }
+}
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Are task fetches paused by an announcement?
+pub fn paused() -> bool { PAUSED.load(Ordering::SeqCst) }
+
+/// Act on an announcement addressed to `service`
+pub fn apply(announcement: &Announcement, service: &str) {
+  if !announcement.concerns(service) {
+    return;
+  }
+  info!(target: "announcements", "{}", announcement);
+  match announcement.directive {
+    Directive::Pause => PAUSED.store(true, Ordering::SeqCst),
+    Directive::Resume => PAUSED.store(false, Ordering::SeqCst),
+    Directive::Drain => shutdown::request(),
+    Directive::Notice => {}
+  }
+}
+
+/// Blocks while task fetches are paused. Returns false if a shutdown was requested meanwhile
+pub fn await_resume(identity: &str) -> bool {
+  if !paused() {
+    return true;
+  }
+  info!(target: &format!("{}:announcements", identity), "paused by the dispatcher.");
+  while paused() {
+    if shutdown::requested() {
+      return false;
+    }
+    thread::sleep(Duration::new(1, 0));
+  }
+  info!(target: &format!("{}:announcements", identity), "resumed by the dispatcher.");
+  true
+}
+
+/// Subscribe to the announcements broadcast at `address` for `service`, in a background thread
+pub fn subscribe(address: &str, service: &str) -> Result<(), Box<dyn Error>> {
+  let context = zmq::Context::new();
+  let subscriber = context.socket(zmq::SUB)?;
+  subscriber.set_ipv6(true)?;
+  subscriber.set_subscribe(b"")?;
+  subscriber.connect(address)?;
+  let service = service.to_string();
+  info!(target: "announcements", "listening for announcements on {}", address);
+  thread::spawn(move || {
+    // the context lives as long as the subscription
+    let _context = context;
+    loop {
+      match subscriber.recv_string(0) {
+        Ok(Ok(frame)) => match frame.parse::<Announcement>() {
+          Ok(announcement) => apply(&announcement, &service),
+          Err(e) => warn!(target: "announcements", "{}", e),
+        },
+        Ok(Err(_)) => warn!(target: "announcements", "ignoring an announcement which isn't UTF-8"),
+        Err(zmq::Error::EINTR) => continue,
+        Err(e) => {
+          warn!(target: "announcements", "stopped listening for announcements: {}", e);
+          break;
+        }
+      }
+    }
+  });
+  Ok(())
+}
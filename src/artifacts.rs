@@ -0,0 +1,104 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Named secondary artifacts of a conversion, next to its main result archive, e.g. a standalone
+//! report or metrics alongside the HTML ZIP.
+//!
+//! A converter [`attach`]es them while converting a task, on the thread calling
+//! [`Worker::convert`](crate::worker::Worker::convert), and they are collected once the conversion
+//! returns, to go along with its result, see [`Conversion`](crate::policy::Conversion). Speaking
+//! protocol version
+//! [`ARTIFACTS_VERSION`](crate::protocol::ARTIFACTS_VERSION) or later, the worker sends each of
+//! them to the sink as an [`ArtifactHeader`] frame followed by a frame of its contents, ahead of
+//! the main result; with older dispatchers, they are [`fold`]ed into the result archive under the
+//! reserved [`ARTIFACTS_DIR`] instead. The artifacts of a failed conversion are discarded.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::adaptor;
+use crate::protocol::{ArtifactHeader, ZIP_MAGIC};
+
+/// Directory of the result archive holding the artifacts folded into it
+pub const ARTIFACTS_DIR: &str = "artifacts";
+
+/// A named secondary artifact of a conversion
+#[derive(Debug)]
+pub struct Artifact {
+  /// Name of the artifact, a plain file name such as `report.pdf`
+  pub name: String,
+  /// Contents of the artifact
+  pub file: File,
+}
+
+impl Artifact {
+  /// The frame announcing the artifact to the sink
+  pub fn header(&self) -> ArtifactHeader {
+    ArtifactHeader {
+      name: self.name.clone(),
+      size: self.file.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+    }
+  }
+
+  /// The contents of the artifact
+  pub fn contents(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut contents = Vec::new();
+    self.file.seek(SeekFrom::Start(0))?;
+    self.file.read_to_end(&mut contents)?;
+    Ok(contents)
+  }
+}
+
+thread_local! {
+  static PENDING: RefCell<Vec<Artifact>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Attach the artifact `name` to the result of the task converted on this thread, replacing an
+/// artifact attached under the same name
+pub fn attach(name: &str, file: File) -> Result<(), Box<dyn Error>> {
+  if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+    return Err(From::from(format!("invalid artifact name {:?}, expected a file name", name)));
+  }
+  PENDING.with(|pending| {
+    let mut pending = pending.borrow_mut();
+    pending.retain(|artifact| artifact.name != name);
+    pending.push(Artifact {
+      name: name.to_string(),
+      file,
+    });
+  });
+  Ok(())
+}
+
+/// Collect the artifacts attached on this thread since the last collection, as a conversion returns
+pub(crate) fn collect() -> Vec<Artifact> { PENDING.with(|pending| pending.take()) }
+
+/// Fold `artifacts` into the `result` archive, each under `artifacts/<name>`. The artifacts of a
+/// result which isn't a ZIP archive are dropped, with a warning
+pub fn fold(mut result: File, artifacts: Vec<Artifact>) -> Result<File, Box<dyn Error>> {
+  if artifacts.is_empty() {
+    return Ok(result);
+  }
+  let mut magic = [0; 4];
+  let is_zip = result.read_exact(&mut magic).is_ok() && magic == ZIP_MAGIC;
+  result.seek(SeekFrom::Start(0))?;
+  if !is_zip {
+    warn!(
+      target: "artifacts",
+      "dropping {} artifacts, the result isn't a ZIP archive to fold them into.",
+      artifacts.len()
+    );
+    return Ok(result);
+  }
+  for mut artifact in artifacts {
+    let contents = artifact.contents()?;
+    result = adaptor::upsert_zip_entry(result, &format!("{}/{}", ARTIFACTS_DIR, artifact.name), &contents)?;
+  }
+  Ok(result)
+}
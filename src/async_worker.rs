@@ -0,0 +1,362 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A tokio runtime for IO-bound services, e.g. link checkers or validators, whose conversions
+//! mostly wait on the network or on other processes.
+//!
+//! Where the [`Worker`](crate::worker::Worker) runtime dedicates a thread to every task it
+//! converts, an [`AsyncWorker`] converts up to [`AsyncWorker::concurrency`] tasks at once, each
+//! a tokio task. A task is requested whenever a slot frees up, and the results are sent with the
+//! status and stats frames of the protocol version in effect. A conversion running past
+//! [`AsyncWorker::timeout`] is dropped and its task answered as a transient failure; converters
+//! spawning processes with `tokio::process::Command::kill_on_drop` have them killed with it.
+//!
+//! The sockets are the libzmq ones of the threaded runtime, as ZeroMQ sockets can't be shared
+//! between threads: a DEALER socket fetching tasks and a PUSH socket sending results, each owned
+//! by a blocking thread of tokio's pool (`spawn_blocking`) which exchanges their messages with
+//! the conversions over channels.
+
+use std::error::Error;
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::spooled_tempfile;
+use tokio::sync::mpsc::{self, error::TryRecvError, Receiver, Sender, UnboundedReceiver};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinSet};
+use zmq::Socket;
+
+use crate::adaptor;
+use crate::endpoint::{self, Endpoint};
+use crate::error::WorkerError;
+use crate::identity::Identity;
+use crate::logcodes;
+use crate::protocol::{self, Compression, Handshake, ProbeReply, ResultStatus, TaskMetadata, TaskStats};
+use crate::shutdown;
+use crate::transfer::SPOOL_THRESHOLD;
+
+/// The frames of a ZeroMQ message
+type Frames = Vec<Vec<u8>>;
+
+/// The error of a conversion, which may cross threads
+pub type AsyncError = Box<dyn Error + Send + Sync>;
+
+/// A task as received from CorTeX
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsyncTask {
+  /// The CorTeX task id
+  pub taskid: String,
+  /// The payload, decompressed if zstd was negotiated
+  pub payload: Vec<u8>,
+  /// What the dispatcher told about the task, from protocol version 6 on
+  pub metadata: Option<TaskMetadata>,
+}
+
+/// Requirements for CorTeX workers converting their tasks as futures
+pub trait AsyncWorker: Send + Sync + 'static {
+  /// Convert the payload of a task into its result, usually a ZIP archive with a `cortex.log`
+  fn convert(&self, task: AsyncTask) -> impl Future<Output = Result<Vec<u8>, AsyncError>> + Send;
+  /// Name of the service, as registered in CorTeX
+  fn get_service(&self) -> &str;
+  /// Endpoint of the CorTeX dispatcher
+  fn get_source_address(&self) -> &Endpoint;
+  /// Endpoint of the CorTeX sink
+  fn get_sink_address(&self) -> &Endpoint;
+  /// The worker's own version, reported to the dispatcher in the protocol handshake
+  fn get_version(&self) -> String {
+    String::from("unknown")
+  }
+  /// Tasks converted at once
+  fn concurrency(&self) -> usize {
+    256
+  }
+  /// Time a conversion may take before it is dropped and its task answered as failed
+  fn timeout(&self) -> Duration {
+    Duration::new(600, 0)
+  }
+  /// Size of the frames results are sent in
+  fn message_size(&self) -> usize {
+    100_000
+  }
+  /// Compression negotiated for payloads on the wire, see the `protocol` module
+  fn compression(&self) -> Compression {
+    Compression::None
+  }
+}
+
+/// Run `worker` on a new multi-threaded tokio runtime, until it drained or converted `limit` tasks
+pub fn start<W: AsyncWorker>(worker: W, limit: Option<usize>) -> Result<(), Box<dyn Error>> {
+  let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+  runtime
+    .block_on(run(Arc::new(worker), limit))
+    .map_err(|e| e as Box<dyn Error>)
+}
+
+/// Fetch and convert the tasks of `worker` on the current tokio runtime, until a shutdown was
+/// requested or `limit` tasks were fetched, then wait for the conversions in flight
+pub async fn run<W: AsyncWorker>(worker: Arc<W>, limit: Option<usize>) -> Result<(), AsyncError> {
+  let identity = Identity::new(worker.get_service(), 1, 1).to_string();
+  let source_address = worker.get_source_address().to_string();
+  let source = endpoint::context(&source_address).socket(zmq::DEALER)?;
+  source.set_identity(identity.as_bytes())?;
+  // unanswered requests don't hold up the exit
+  source.set_linger(0)?;
+  source.connect(&source_address)?;
+  let sink_address = worker.get_sink_address().to_string();
+  let sink = endpoint::context(&sink_address).socket(zmq::PUSH)?;
+  sink.connect(&sink_address)?;
+
+  let concurrency = worker.concurrency().max(1);
+  let (requests, pending_requests) = mpsc::unbounded_channel();
+  let (received, mut incoming) = mpsc::channel(concurrency);
+  let (results, outgoing) = mpsc::channel(concurrency);
+  let request = request(worker.as_ref());
+  let fetcher = task::spawn_blocking(move || fetch(source, request, pending_requests, received));
+  let sender = task::spawn_blocking(move || send(sink, outgoing));
+
+  let slots = Arc::new(Semaphore::new(concurrency));
+  let converting = Arc::new(AtomicUsize::new(0));
+  // every request holds a slot, handed to the task answering it
+  let mut requested: Vec<OwnedSemaphorePermit> = Vec::new();
+  let mut fetched = 0;
+  let mut tasks = JoinSet::new();
+  // wake up every second, a drain may be requested without anything arriving
+  let mut ticks = tokio::time::interval(Duration::new(1, 0));
+  loop {
+    let draining = shutdown::requested();
+    let wanted = !draining && limit.is_none_or(|limit| fetched + requested.len() < limit);
+    if !wanted && (draining || requested.is_empty()) {
+      break;
+    }
+    tokio::select! {
+      slot = slots.clone().acquire_owned(), if wanted => {
+        if requests.send(()).is_err() {
+          break;
+        }
+        requested.push(slot.expect("the slots are never closed"));
+      }
+      frames = incoming.recv(), if !requested.is_empty() => {
+        // the fetcher stopped, its error is reported below
+        let Some(frames) = frames else {
+          break;
+        };
+        let slot = requested.pop();
+        fetched += 1;
+        let (worker, results, identity, converting) =
+          (worker.clone(), results.clone(), identity.clone(), converting.clone());
+        tasks.spawn(async move {
+          let result = answer(worker, frames, &identity, &converting).await;
+          drop(slot);
+          results.send(result).await.map_err(|_| "the sink is gone".into())
+        });
+      }
+      Some(joined) = tasks.join_next() => report(joined, &identity),
+      _ = ticks.tick() => {}
+    }
+  }
+  // requests the dispatcher didn't answer yet are given up
+  drop(requested);
+  drop(requests);
+  while let Some(joined) = tasks.join_next().await {
+    report(joined, &identity);
+  }
+  drop(results);
+  fetcher.await??;
+  sender.await??;
+  Ok(())
+}
+
+/// Send a task `request` to the `source` for every slot announced on `requests`, and pass the
+/// tasks arriving on it to `received`, until `requests` is closed
+fn fetch(
+  source: Socket,
+  request: Frames,
+  mut requests: UnboundedReceiver<()>,
+  received: Sender<Frames>,
+) -> Result<(), zmq::Error> {
+  loop {
+    loop {
+      match requests.try_recv() {
+        Ok(()) => source.send_multipart(&request, 0)?,
+        Err(TryRecvError::Empty) => break,
+        Err(TryRecvError::Disconnected) => return Ok(()),
+      }
+    }
+    match source.poll(zmq::POLLIN, 50) {
+      Ok(0) | Err(zmq::Error::EINTR) => continue,
+      Ok(_) => {}
+      Err(e) => return Err(e),
+    }
+    let frames = source.recv_multipart(0)?;
+    if received.blocking_send(frames).is_err() {
+      return Ok(());
+    }
+  }
+}
+
+/// Send every result arriving on `results` to the `sink`, until `results` is closed
+fn send(sink: Socket, mut results: Receiver<Frames>) -> Result<(), zmq::Error> {
+  while let Some(frames) = results.blocking_recv() {
+    sink.send_multipart(frames, 0)?;
+  }
+  Ok(())
+}
+
+/// The task request of `worker`, sent whenever a slot frees up
+fn request<W: AsyncWorker>(worker: &W) -> Frames {
+  let mut frames = vec![worker.get_service().as_bytes().to_vec()];
+  if worker.compression() == Compression::Zstd {
+    frames.push(protocol::ACCEPT_ZSTD.as_bytes().to_vec());
+  }
+  if protocol::version() >= protocol::HANDSHAKE_VERSION {
+    frames.push(Handshake::new(worker.get_version(), None).frame().into_bytes());
+  }
+  frames
+}
+
+fn report(joined: Result<Result<(), AsyncError>, tokio::task::JoinError>, identity: &str) {
+  let failure = match joined {
+    Ok(Ok(())) => return,
+    Ok(Err(e)) => e.to_string(),
+    Err(e) => e.to_string(),
+  };
+  error!(target: &format!("{}:async", identity), "a task was lost: {}", failure);
+}
+
+/// The result message answering the `frames` of a task as sent by the dispatcher
+async fn answer<W: AsyncWorker>(worker: Arc<W>, mut frames: Frames, identity: &str, converting: &AtomicUsize) -> Frames {
+  let start = Instant::now();
+  let taskid = String::from_utf8_lossy(&frames.remove(0)).into_owned();
+  // a metadata frame is always followed by the payload
+  let metadata = match frames.first() {
+    Some(frame) if frames.len() > 1 && protocol::version() >= protocol::METADATA_VERSION => TaskMetadata::parse(frame),
+    _ => None,
+  };
+  if metadata.is_some() {
+    frames.remove(0);
+  }
+  let payload = frames.concat();
+  let input_size = payload.len();
+  info!(
+    target: &format!("{}:received", identity),
+    "task {}, read {} bytes from CorTeX.", taskid, input_size
+  );
+
+  let probe = metadata.as_ref().is_some_and(|metadata| metadata.probe) && protocol::version() >= protocol::PROBE_VERSION;
+  let (result, status) = if probe {
+    let reply = ProbeReply {
+      service: worker.get_service().to_string(),
+      worker: worker.get_version(),
+      pericortex: env!("CARGO_PKG_VERSION").to_string(),
+      converting: converting.load(Ordering::SeqCst),
+    };
+    let reply = serde_json::to_vec(&reply).expect("probe replies are serializable");
+    (Some(reply), ResultStatus::probe())
+  } else if input_size == 0 {
+    (None, ResultStatus::empty_input())
+  } else {
+    converting.fetch_add(1, Ordering::SeqCst);
+    let converted = convert(worker.as_ref(), taskid.clone(), payload, metadata).await;
+    converting.fetch_sub(1, Ordering::SeqCst);
+    match converted {
+      Ok(result) => {
+        let log = adaptor::read_zip_entry(io::Cursor::new(&result), "cortex.log").ok().flatten();
+        let status = ResultStatus::of_log(&logcodes::parse(&String::from_utf8_lossy(&log.unwrap_or_default())));
+        (Some(result), status)
+      }
+      Err(e) => {
+        info!(
+          target: &format!("{}:result", identity),
+          " task {} ({} input bytes) came back empty: {}", taskid, input_size, e
+        );
+        (None, ResultStatus::failed(&*e, input_size))
+      }
+    }
+  };
+
+  let mut frames = vec![
+    identity.as_bytes().to_vec(),
+    worker.get_service().as_bytes().to_vec(),
+    taskid.as_bytes().to_vec(),
+  ];
+  if protocol::version() >= protocol::STATUS_VERSION {
+    frames.push(status.frame().into_bytes());
+  }
+  let result = result.map(|result| encode(result, worker.compression()));
+  if protocol::version() >= protocol::STATS_VERSION {
+    let stats = TaskStats {
+      duration_ms: start.elapsed().as_millis() as u64,
+      retries: 0,
+      output_bytes: result.as_ref().map_or(0, |result| result.len() as u64),
+      peak_rss_bytes: None,
+    };
+    frames.push(stats.frame().into_bytes());
+  }
+  match result {
+    // a single empty frame, as for an empty result of the threaded runtime
+    Some(result) if result.is_empty() => frames.push(Vec::new()),
+    Some(result) => {
+      info!(
+        target: &format!("{}:completed", identity),
+        " task {}, sent {} bytes back to CorTeX.", taskid, result.len()
+      );
+      frames.extend(result.chunks(worker.message_size().max(1)).map(<[u8]>::to_vec));
+    }
+    // an empty reply, so that cortex knows this is an aberrant task
+    None => frames.push(Vec::new()),
+  }
+  frames
+}
+
+/// Decode the payload of task `taskid` and convert it within the worker's timeout
+async fn convert<W: AsyncWorker>(
+  worker: &W,
+  taskid: String,
+  payload: Vec<u8>,
+  metadata: Option<TaskMetadata>,
+) -> Result<Vec<u8>, AsyncError> {
+  let compression = worker.compression();
+  let payload = if compression == Compression::Zstd && payload.starts_with(&protocol::ZSTD_MAGIC) {
+    task::spawn_blocking(move || decode(&payload, compression)).await??
+  } else {
+    payload
+  };
+  let task = AsyncTask {
+    taskid,
+    payload,
+    metadata,
+  };
+  match tokio::time::timeout(worker.timeout(), worker.convert(task)).await {
+    Ok(converted) => converted,
+    Err(_) => Err(Box::new(WorkerError::Transient(format!(
+      "the conversion timed out after {} seconds",
+      worker.timeout().as_secs()
+    )))),
+  }
+}
+
+/// Decompress a zstd payload, within the limits of [`protocol::decode_payload`]
+fn decode(payload: &[u8], compression: Compression) -> io::Result<Vec<u8>> {
+  let mut spooled = spooled_tempfile(SPOOL_THRESHOLD);
+  spooled.write_all(payload)?;
+  spooled.seek(SeekFrom::Start(0))?;
+  let mut decoded = protocol::decode_payload(spooled, compression)?;
+  let mut bytes = Vec::new();
+  decoded.read_to_end(&mut bytes)?;
+  Ok(bytes)
+}
+
+/// Compress a result with `compression`, leaving ZIP archives and empty results unchanged
+fn encode(result: Vec<u8>, compression: Compression) -> Vec<u8> {
+  if compression == Compression::None || result.is_empty() || result.starts_with(&protocol::ZIP_MAGIC) {
+    return result;
+  }
+  zstd::stream::encode_all(&result[..], 0).unwrap_or(result)
+}
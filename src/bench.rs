@@ -0,0 +1,183 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Converter throughput measurements on sample documents, for sizing pools and memory limits
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tempdir::TempDir;
+
+use crate::local;
+use crate::worker::Worker;
+
+/// Repeated conversions of a single sample document
+#[derive(Clone, Debug)]
+pub struct DocumentBench {
+  /// The sample document
+  pub entry: PathBuf,
+  /// Wall-clock time of every successful iteration, sorted
+  pub latencies: Vec<Duration>,
+  /// Size of the last successful result, in bytes
+  pub output_size: Option<u64>,
+  /// Number of failed iterations
+  pub failures: usize,
+}
+
+impl DocumentBench {
+  /// The `p`-th percentile (0-100) latency, by nearest rank
+  pub fn percentile(&self, p: f64) -> Option<Duration> { percentile(&self.latencies, p) }
+}
+
+/// Measurements of a whole benchmark run
+#[derive(Clone, Debug, Default)]
+pub struct BenchReport {
+  /// Per-document measurements
+  pub documents: Vec<DocumentBench>,
+  /// Peak resident memory of this process, in bytes
+  pub peak_rss: Option<u64>,
+  /// Peak resident memory of the largest converter child process, in bytes
+  pub peak_child_rss: Option<u64>,
+}
+
+impl BenchReport {
+  /// The `p`-th percentile latency across all documents
+  pub fn percentile(&self, p: f64) -> Option<Duration> {
+    let mut latencies: Vec<Duration> = self
+      .documents
+      .iter()
+      .flat_map(|document| document.latencies.iter().cloned())
+      .collect();
+    latencies.sort();
+    percentile(&latencies, p)
+  }
+}
+
+/// The `p`-th percentile (0-100) of `sorted` durations, by nearest rank
+pub(crate) fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+  if sorted.is_empty() {
+    return None;
+  }
+  let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+  Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn millis(duration: Option<Duration>) -> String {
+  duration
+    .map(|d| d.as_millis().to_string())
+    .unwrap_or_else(|| "-".to_string())
+}
+
+fn megabytes(bytes: Option<u64>) -> String {
+  bytes
+    .map(|b| format!("{} MB", b / 1_048_576))
+    .unwrap_or_else(|| "unknown".to_string())
+}
+
+impl fmt::Display for BenchReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "document\tp50_ms\tp90_ms\tmax_ms\toutput_bytes\tfailures")?;
+    for document in &self.documents {
+      writeln!(
+        f,
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        document.entry.display(),
+        millis(document.percentile(50.0)),
+        millis(document.percentile(90.0)),
+        millis(document.percentile(100.0)),
+        document
+          .output_size
+          .map(|size| size.to_string())
+          .unwrap_or_else(|| "-".to_string()),
+        document.failures
+      )?;
+    }
+    writeln!(
+      f,
+      "overall\t{}\t{}\t{}",
+      millis(self.percentile(50.0)),
+      millis(self.percentile(90.0)),
+      millis(self.percentile(100.0))
+    )?;
+    writeln!(
+      f,
+      "peak memory: {} (worker), {} (largest converter process)",
+      megabytes(self.peak_rss),
+      megabytes(self.peak_child_rss)
+    )
+  }
+}
+
+/// Convert every corpus ZIP under `input_dir` `iterations` times with `worker`
+pub fn bench<W: Worker>(
+  worker: &W,
+  input_dir: &Path,
+  iterations: usize,
+) -> Result<BenchReport, Box<dyn Error>> {
+  let scratch = TempDir::new("cortex_bench")?;
+  let mut report = BenchReport::default();
+  for entry in local::corpus_entries(input_dir) {
+    let destination = scratch.path().join("result.zip");
+    let mut document = DocumentBench {
+      entry: entry.strip_prefix(input_dir).unwrap_or(&entry).to_path_buf(),
+      latencies: Vec::new(),
+      output_size: None,
+      failures: 0,
+    };
+    for _ in 0..iterations {
+      let start = Instant::now();
+      match worker.convert_local(&entry, &destination) {
+        Ok(size) => {
+          document.latencies.push(start.elapsed());
+          document.output_size = Some(size);
+        }
+        Err(e) => {
+          warn!(target: "bench", "{}: {}", entry.display(), e);
+          document.failures += 1;
+        }
+      }
+      fs::remove_file(&destination).ok();
+    }
+    document.latencies.sort();
+    report.documents.push(document);
+  }
+  report.peak_rss = peak_rss();
+  report.peak_child_rss = peak_child_rss();
+  Ok(report)
+}
+
+/// Peak resident memory of this process, where available
+pub fn peak_rss() -> Option<u64> {
+  let status = fs::read_to_string("/proc/self/status").ok()?;
+  let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+  let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+  Some(kilobytes * 1024)
+}
+
+/// Peak resident memory of the largest terminated child process, where available
+#[cfg(unix)]
+pub fn peak_child_rss() -> Option<u64> {
+  let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+  // SAFETY: getrusage only writes into the provided, properly sized struct
+  if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+    return None;
+  }
+  let max_rss = usage.ru_maxrss as u64;
+  // kilobytes everywhere, except for bytes on macOS
+  if cfg!(target_os = "macos") {
+    Some(max_rss)
+  } else {
+    Some(max_rss * 1024)
+  }
+}
+
+/// Peak resident memory of the largest terminated child process, where available
+#[cfg(not(unix))]
+pub fn peak_child_rss() -> Option<u64> { None }
@@ -0,0 +1,93 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! What a build of pericortex can do: the workers, transports and integrations compiled into it,
+//! so that fleet tooling can verify a binary before rolling it out, and dispatchers can route
+//! tasks to the hosts able to convert them
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol;
+
+/// Every cargo feature of the crate, with whether it is enabled in this build
+pub const FEATURES: &[(&str, bool)] = &[
+  ("async", cfg!(feature = "async")),
+  ("cli", cfg!(feature = "cli")),
+  ("daemon", cfg!(feature = "daemon")),
+  ("engrafo", cfg!(feature = "engrafo")),
+  ("latexml", cfg!(feature = "latexml")),
+  ("object-storage", cfg!(feature = "object-storage")),
+  ("signing", cfg!(feature = "signing")),
+  ("tools", cfg!(feature = "tools")),
+  ("tui", cfg!(feature = "tui")),
+];
+
+/// Optional integrations other pericortex builds may offer, none of which this release provides
+pub const UNAVAILABLE: &[&str] = &["prometheus", "python"];
+
+/// The capabilities of this build
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Capabilities {
+  /// The pericortex version
+  pub version: String,
+  /// The newest dispatcher protocol version spoken
+  pub protocol_version: u32,
+  /// The workers which can be started
+  pub workers: Vec<String>,
+  /// The ZeroMQ transports available to the source and sink addresses
+  pub transports: Vec<String>,
+  /// The optional integrations, with whether they were compiled in
+  pub integrations: BTreeMap<String, bool>,
+}
+
+impl fmt::Display for Capabilities {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "pericortex {} (protocol version {})", self.version, self.protocol_version)?;
+    writeln!(f, "workers: {}", self.workers.join(", "))?;
+    writeln!(f, "transports: {}", self.transports.join(", "))?;
+    for (integration, enabled) in &self.integrations {
+      writeln!(f, "{}: {}", integration, if *enabled { "yes" } else { "no" })?;
+    }
+    Ok(())
+  }
+}
+
+/// The capabilities of this build, probing the linked libzmq for its optional transports
+pub fn capabilities() -> Capabilities {
+  let mut workers = vec!["echo", "command"];
+  if cfg!(feature = "latexml") {
+    workers.insert(1, "tex-to-html");
+  }
+  if cfg!(feature = "engrafo") {
+    workers.push("engrafo");
+  }
+  let mut transports = vec!["tcp".to_string(), "inproc".to_string()];
+  // encrypted (curve) and local socket (ipc) transports depend on how libzmq was built
+  for transport in ["ipc", "curve"] {
+    if zmq::has(transport).unwrap_or(false) {
+      transports.push(transport.to_string());
+    }
+  }
+  let mut integrations: BTreeMap<String, bool> = FEATURES
+    .iter()
+    .filter(|(feature, _)| *feature != "cli")
+    .map(|(feature, enabled)| (feature.to_string(), *enabled))
+    .collect();
+  for integration in UNAVAILABLE {
+    integrations.insert(integration.to_string(), false);
+  }
+  Capabilities {
+    version: env!("CARGO_PKG_VERSION").to_string(),
+    protocol_version: protocol::LATEST_VERSION,
+    workers: workers.into_iter().map(String::from).collect(),
+    transports,
+    integrations,
+  }
+}
@@ -0,0 +1,156 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Connectivity and environment diagnostics, run before pointing a worker at a dispatcher
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::health;
+use crate::worker::Worker;
+
+/// Outcome of a single diagnostic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+  /// The check succeeded
+  Pass,
+  /// The check failed, the worker is misconfigured
+  Fail,
+  /// The check could not be performed in this environment
+  Skip,
+}
+
+/// A single line of the diagnostic report
+#[derive(Clone, Debug)]
+pub struct CheckOutcome {
+  /// What was checked
+  pub name: &'static str,
+  /// Did it pass?
+  pub status: CheckStatus,
+  /// Details for the operator
+  pub detail: String,
+}
+
+/// The full diagnostic report for a worker
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+  /// All diagnostics, in the order they were run
+  pub outcomes: Vec<CheckOutcome>,
+}
+
+impl CheckReport {
+  /// Did every performed check pass?
+  pub fn passed(&self) -> bool {
+    self
+      .outcomes
+      .iter()
+      .all(|outcome| outcome.status != CheckStatus::Fail)
+  }
+
+  fn push(&mut self, name: &'static str, result: Result<String, String>) {
+    let (status, detail) = match result {
+      Ok(detail) => (CheckStatus::Pass, detail),
+      Err(detail) => (CheckStatus::Fail, detail),
+    };
+    self.outcomes.push(CheckOutcome {
+      name,
+      status,
+      detail,
+    });
+  }
+}
+
+impl fmt::Display for CheckReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for outcome in &self.outcomes {
+      let label = match outcome.status {
+        CheckStatus::Pass => "PASS",
+        CheckStatus::Fail => "FAIL",
+        CheckStatus::Skip => "SKIP",
+      };
+      writeln!(f, "[{}] {}: {}", label, outcome.name, outcome.detail)?;
+    }
+    Ok(())
+  }
+}
+
+/// Diagnose the configuration and environment of `worker`, requiring at least
+/// `min_scratch_space` bytes of free space in the temporary directory
+pub fn diagnose<W: Worker>(worker: &W, min_scratch_space: u64) -> CheckReport {
+  diagnose_in(worker, &env::temp_dir(), min_scratch_space)
+}
+
+/// As [`diagnose`], with the scratch space in `scratch_dir`
+pub fn diagnose_in<W: Worker>(worker: &W, scratch_dir: &Path, min_scratch_space: u64) -> CheckReport {
+  let mut report = CheckReport::default();
+  let timeout = Duration::new(5, 0);
+
+  let source = worker.get_source_address();
+  report.push(
+    "dispatcher source",
+    health::endpoint_reachable(source, timeout)
+      .map(|_| format!("{} is reachable", source))
+      .map_err(|e| format!("{} is unreachable: {}", source, e)),
+  );
+  let sink = worker.get_sink_address();
+  report.push(
+    "dispatcher sink",
+    health::endpoint_reachable(sink, timeout)
+      .map(|_| format!("{} is reachable", sink))
+      .map_err(|e| format!("{} is unreachable: {}", sink, e)),
+  );
+  report.outcomes.push(CheckOutcome {
+    name: "service registration",
+    status: CheckStatus::Skip,
+    detail: format!(
+      "the dispatcher does not expose its registered services, make sure {:?} is known to CorTeX",
+      worker.get_service()
+    ),
+  });
+  report.push(
+    "converter",
+    worker
+      .readiness_probe()
+      .map(|_| "available".to_string())
+      .map_err(|e| e.to_string()),
+  );
+  report.push("scratch space", scratch_space(scratch_dir, min_scratch_space));
+  report
+}
+
+/// Checks that `dir` is writable and has at least `minimum` bytes available
+fn scratch_space(dir: &Path, minimum: u64) -> Result<String, String> {
+  let probe = dir.join(format!("pericortex_check_{}", std::process::id()));
+  fs::write(&probe, b"probe").map_err(|e| format!("{} is not writable: {}", dir.display(), e))?;
+  fs::remove_file(&probe).ok();
+  match available_space(dir) {
+    Some(available) if available < minimum => Err(format!(
+      "{} has only {} MB available, {} MB required",
+      dir.display(),
+      available / 1_048_576,
+      minimum / 1_048_576
+    )),
+    Some(available) => Ok(format!(
+      "{} is writable, {} MB available",
+      dir.display(),
+      available / 1_048_576
+    )),
+    None => Ok(format!("{} is writable, available space unknown", dir.display())),
+  }
+}
+
+/// Bytes available on the filesystem of `dir`, as reported by `df`
+fn available_space(dir: &Path) -> Option<u64> {
+  let df = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+  let stdout = String::from_utf8_lossy(&df.stdout);
+  let kilobytes: u64 = stdout.lines().nth(1)?.split_whitespace().nth(3)?.parse().ok()?;
+  Some(kilobytes * 1024)
+}
@@ -0,0 +1,89 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The time source of the worker loop's pauses (retry backoff, throttling, simulated delays).
+//!
+//! Workers use the [`SystemClock`] unless a test installs a [`MockClock`], whose pauses return
+//! immediately while advancing its own time, so that backoff schedules can be checked instantly
+//! and deterministically.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A source of time
+pub trait Clock: Send + Sync {
+  /// The current time
+  fn now(&self) -> Instant;
+  /// Pause the calling thread for `duration`
+  fn sleep(&self, duration: Duration);
+}
+
+/// The real time, pausing the calling thread
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant { Instant::now() }
+  fn sleep(&self, duration: Duration) { thread::sleep(duration) }
+}
+
+/// A clock which only moves when slept on or advanced, recording every pause
+#[derive(Debug)]
+pub struct MockClock {
+  start: Instant,
+  elapsed: Mutex<Duration>,
+  pauses: Mutex<Vec<Duration>>,
+}
+
+impl Default for MockClock {
+  fn default() -> MockClock {
+    MockClock {
+      start: Instant::now(),
+      elapsed: Mutex::new(Duration::ZERO),
+      pauses: Mutex::new(Vec::new()),
+    }
+  }
+}
+
+impl MockClock {
+  /// Move the clock forward by `duration`
+  pub fn advance(&self, duration: Duration) { *self.elapsed.lock().unwrap() += duration; }
+
+  /// Every pause requested so far, in order
+  pub fn pauses(&self) -> Vec<Duration> { self.pauses.lock().unwrap().clone() }
+}
+
+impl Clock for MockClock {
+  fn now(&self) -> Instant { self.start + *self.elapsed.lock().unwrap() }
+  fn sleep(&self, duration: Duration) {
+    self.pauses.lock().unwrap().push(duration);
+    self.advance(duration);
+  }
+}
+
+static CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+
+/// Use `clock` for all pauses of this process from now on
+pub fn install(clock: Arc<dyn Clock>) { *CLOCK.write().unwrap() = Some(clock); }
+
+/// The current time of the installed clock
+pub fn now() -> Instant {
+  match *CLOCK.read().unwrap() {
+    Some(ref clock) => clock.now(),
+    None => SystemClock.now(),
+  }
+}
+
+/// Pause the calling thread for `duration` on the installed clock
+pub fn sleep(duration: Duration) {
+  let clock = CLOCK.read().unwrap().clone();
+  match clock {
+    Some(clock) => clock.sleep(duration),
+    None => SystemClock.sleep(duration),
+  }
+}
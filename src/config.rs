@@ -0,0 +1,332 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Worker configuration files, with hot reloading on SIGHUP
+//!
+//! A reload applies
+//! - at once, to running threads as they pick up their next task: `message_size`,
+//!   `message_size_bounds`, `throttle`, `log_level`, `compression_threads`, `compression`,
+//!   `quiet_hours`, `max_tasks_per_minute`, the `empty_input` policy and the output size limit;
+//! - by draining the current pool and reconnecting a new one: `pool_size`, `io_threads` and the
+//!   endpoints.
+//!
+//! A `[corpora.<name>]` table overrides settings for the tasks of one corpus, as named by the
+//! dispatcher's task metadata, e.g. a longer `timeout` for `longpapers` or another `image` for
+//! `arxiv-2024`. A [`ConfigResolver`] picks them up as each task is converted.
+//!
+//! [`show`] dumps the configuration a worker actually runs with, for answering "why is this
+//! worker connecting there": the file and command line merged, the worker's own defaults for
+//! whatever they leave unset, and the environment variables it reads, with secrets redacted.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+
+use crate::endpoint::{self, Endpoint};
+use crate::policy::{self, EmptyInput, OutputSizeLimit, OversizedOutput};
+use crate::protocol::{Compression, TaskMetadata};
+use crate::ratelimit;
+use crate::schedule::{self, QuietWindow};
+use crate::tuning::FrameSizeBounds;
+use crate::worker::Worker;
+
+/// Environment variables read by workers, e.g. the object storage credentials
+pub const ENVIRONMENT: [&str; 4] =
+  ["AWS_REGION", "AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY", "PERICORTEX_SIGNING_KEY"];
+
+/// Shown in place of secrets in configuration dumps
+pub const REDACTED: &str = "<redacted>";
+
+/// Settings read from a TOML configuration file, all of them optional
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+  /// Endpoint of the CorTeX dispatcher, e.g. `tcp://127.0.0.1:51695`
+  pub source: Option<Endpoint>,
+  /// Endpoint of the CorTeX sink, e.g. `tcp://127.0.0.1:51696`
+  pub sink: Option<Endpoint>,
+  /// Simultaneous threads used for one worker each
+  pub pool_size: Option<usize>,
+  /// Threads receiving tasks and sending results, by default one per worker thread
+  pub io_threads: Option<usize>,
+  /// Size of chunk for network communication
+  pub message_size: Option<usize>,
+  /// Bounds of a frame size picked per result from its size, e.g. `"65536:16777216"`,
+  /// replacing the static `message_size`
+  pub message_size_bounds: Option<FrameSizeBounds>,
+  /// Seconds to pause after an empty input or a failed conversion
+  pub throttle: Option<u64>,
+  /// One of `off`, `error`, `warn`, `info`, `debug`, `trace`
+  pub log_level: Option<String>,
+  /// Threads used to compress a single result archive
+  pub compression_threads: Option<usize>,
+  /// Compression of payloads on the wire, `none` or `zstd`
+  pub compression: Option<Compression>,
+  /// Seconds a single conversion may take, for converters which enforce a timeout
+  pub timeout: Option<u64>,
+  /// Docker image of the converter, for containerized workers
+  pub image: Option<String>,
+  /// Daily windows of local time without task fetches, e.g. `["08:00-20:00"]`
+  pub quiet_hours: Option<Vec<QuietWindow>>,
+  /// Tasks the whole worker may fetch per minute, spread evenly over the minute
+  pub max_tasks_per_minute: Option<f64>,
+  /// What to send for a task whose input was empty, `report` or `skip`
+  pub empty_input: Option<EmptyInput>,
+  /// Seconds to pause after an empty input, by default the `throttle`
+  pub empty_input_throttle: Option<u64>,
+  /// Bytes of a result archive at most, checked once it is archived
+  pub max_output_size: Option<u64>,
+  /// What to do with a result over `max_output_size`, `reject`, `truncate-assets` or `divert`
+  pub oversized_output: Option<OversizedOutput>,
+  /// Overrides for the tasks of a corpus, keyed by the corpus name in the task metadata
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub corpora: BTreeMap<String, WorkerConfig>,
+}
+
+impl WorkerConfig {
+  /// Read and parse a TOML configuration file
+  pub fn load(path: &Path) -> Result<WorkerConfig, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    let config: WorkerConfig = toml::from_str(&contents)?;
+    config.log_level_filter()?;
+    if let Some(io_threads) = config.io_threads {
+      endpoint::validate_io_threads(io_threads)?;
+    }
+    Ok(config)
+  }
+
+  /// The configured log level, if any
+  pub fn log_level_filter(&self) -> Result<Option<LevelFilter>, Box<dyn Error>> {
+    match self.log_level {
+      Some(ref level) => Ok(Some(
+        LevelFilter::from_str(level).map_err(|_| format!("unknown log_level {:?}", level))?,
+      )),
+      None => Ok(None),
+    }
+  }
+
+  /// The configured throttle, if any
+  pub fn throttle_duration(&self) -> Option<Duration> { self.throttle.map(|secs| Duration::new(secs, 0)) }
+
+  /// Answer empty inputs as configured from now on
+  pub fn apply_empty_input(&self) {
+    policy::set_empty_input(
+      self.empty_input.unwrap_or_default(),
+      self.empty_input_throttle.map(|secs| Duration::new(secs, 0)),
+    );
+  }
+
+  /// Hold results to the configured maximal output size from now on
+  pub fn apply_output_size_limit(&self) {
+    policy::set_output_size_limit(self.max_output_size.map(|max_bytes| OutputSizeLimit {
+      max_bytes,
+      action: self.oversized_output.unwrap_or_default(),
+    }));
+  }
+
+  /// Overlay the settings present in `overrides` (e.g. from command-line flags) onto this configuration
+  pub fn merged(self, overrides: &WorkerConfig) -> WorkerConfig {
+    WorkerConfig {
+      source: overrides.source.clone().or(self.source),
+      sink: overrides.sink.clone().or(self.sink),
+      pool_size: overrides.pool_size.or(self.pool_size),
+      io_threads: overrides.io_threads.or(self.io_threads),
+      message_size: overrides.message_size.or(self.message_size),
+      message_size_bounds: overrides.message_size_bounds.or(self.message_size_bounds),
+      throttle: overrides.throttle.or(self.throttle),
+      log_level: overrides.log_level.clone().or(self.log_level),
+      compression_threads: overrides.compression_threads.or(self.compression_threads),
+      compression: overrides.compression.or(self.compression),
+      timeout: overrides.timeout.or(self.timeout),
+      image: overrides.image.clone().or(self.image),
+      quiet_hours: overrides.quiet_hours.clone().or(self.quiet_hours),
+      max_tasks_per_minute: overrides.max_tasks_per_minute.or(self.max_tasks_per_minute),
+      empty_input: overrides.empty_input.or(self.empty_input),
+      empty_input_throttle: overrides.empty_input_throttle.or(self.empty_input_throttle),
+      max_output_size: overrides.max_output_size.or(self.max_output_size),
+      oversized_output: overrides.oversized_output.or(self.oversized_output),
+      corpora: if overrides.corpora.is_empty() {
+        self.corpora
+      } else {
+        overrides.corpora.clone()
+      },
+    }
+  }
+
+  /// This configuration, completed by the settings `worker` falls back on where it leaves them
+  /// unset, e.g. the worker's default endpoints
+  pub fn effective<W: Worker>(&self, worker: &W) -> WorkerConfig {
+    WorkerConfig {
+      source: Some(worker.get_source_address().clone()),
+      sink: Some(worker.get_sink_address().clone()),
+      pool_size: Some(worker.pool_size()),
+      io_threads: Some(worker.io_threads()),
+      message_size: Some(worker.message_size()),
+      message_size_bounds: worker.message_size_bounds(),
+      compression: Some(worker.compression()),
+      ..self.clone()
+    }
+  }
+
+  /// Does moving from `self` to `other` require draining and reconnecting the pool?
+  pub fn requires_restart(&self, other: &WorkerConfig) -> bool {
+    self.source != other.source
+      || self.sink != other.sink
+      || self.pool_size != other.pool_size
+      || self.io_threads != other.io_threads
+  }
+}
+
+/// Resolves the settings in effect for a task, from a configuration and the task's metadata
+#[derive(Clone, Debug, Default)]
+pub struct ConfigResolver {
+  base: WorkerConfig,
+}
+
+impl ConfigResolver {
+  /// A resolver of the overrides in `base`
+  pub fn new(base: WorkerConfig) -> ConfigResolver { ConfigResolver { base } }
+
+  /// The configuration of tasks without overrides
+  pub fn base(&self) -> &WorkerConfig { &self.base }
+
+  /// The overrides for the corpus of a task, if it has any
+  pub fn overrides(&self, metadata: Option<&TaskMetadata>) -> Option<&WorkerConfig> {
+    let corpus = metadata?.corpus.as_ref()?;
+    self.base.corpora.get(corpus)
+  }
+
+  /// The configuration in effect for a task
+  pub fn config_for(&self, metadata: Option<&TaskMetadata>) -> Cow<'_, WorkerConfig> {
+    match self.overrides(metadata) {
+      Some(overrides) => Cow::Owned(self.base.clone().merged(overrides)),
+      None => Cow::Borrowed(&self.base),
+    }
+  }
+
+  /// `worker`, configured with the overrides for a task, if it has any
+  pub fn worker_for<'a, W: Worker>(&self, worker: &'a W, metadata: Option<&TaskMetadata>) -> Cow<'a, W> {
+    match self.overrides(metadata) {
+      Some(overrides) => {
+        let mut configured = worker.clone();
+        configured.configure(overrides);
+        Cow::Owned(configured)
+      }
+      None => Cow::Borrowed(worker),
+    }
+  }
+}
+
+/// Bumped every time a configuration file is successfully reloaded
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+/// The most recently loaded configuration
+static CURRENT: Mutex<Option<WorkerConfig>> = Mutex::new(None);
+/// Set when a reload changed settings which require a restart of the pool
+static RESTART: AtomicBool = AtomicBool::new(false);
+
+/// The generation of the currently loaded configuration
+pub fn generation() -> usize { GENERATION.load(Ordering::SeqCst) }
+
+/// The currently loaded configuration, if any
+pub fn current() -> Option<WorkerConfig> { CURRENT.lock().unwrap().clone() }
+
+/// Request the pool to drain and restart with the current configuration
+pub fn request_restart() { RESTART.store(true, Ordering::SeqCst); }
+
+/// Was a restart requested since the last call?
+pub fn take_restart() -> bool { RESTART.swap(false, Ordering::SeqCst) }
+
+static FILE: Mutex<Option<(PathBuf, WorkerConfig)>> = Mutex::new(None);
+
+/// Install `config`, loaded from the file at `path` and overlaid with `overrides`, as the current
+/// configuration. The worker reloads the file on SIGHUP once it starts, with the same overrides
+pub fn install_from(path: PathBuf, config: WorkerConfig, overrides: WorkerConfig) {
+  install(config);
+  *FILE.lock().unwrap() = Some((path, overrides));
+}
+
+/// The configuration file installed with [`install_from`], and the overrides laid over it
+pub fn file() -> Option<(PathBuf, WorkerConfig)> { FILE.lock().unwrap().clone() }
+
+/// Install `config` as the current configuration and apply its log level, quiet hours, rate limit,
+/// empty input policy and output size limit
+pub fn install(config: WorkerConfig) {
+  if let Ok(Some(level)) = config.log_level_filter() {
+    log::set_max_level(level);
+  }
+  schedule::set_quiet_hours(config.quiet_hours.clone().unwrap_or_default());
+  ratelimit::set_max_tasks_per_minute(config.max_tasks_per_minute);
+  config.apply_empty_input();
+  config.apply_output_size_limit();
+  *CURRENT.lock().unwrap() = Some(config);
+  GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Is the environment variable `name` a secret, e.g. a key or password?
+pub fn is_secret(name: &str) -> bool {
+  let name = name.to_ascii_uppercase();
+  ["KEY", "SECRET", "TOKEN", "PASSWORD"].iter().any(|word| name.contains(word))
+}
+
+/// The effective configuration of `worker`, configured with `config`, as TOML, followed by an
+/// `[environment]` table of the [`ENVIRONMENT`] variables which are set, with secrets redacted
+pub fn show<W: Worker>(worker: &W, config: &WorkerConfig) -> Result<String, Box<dyn Error>> {
+  let mut dump = toml::to_string(&config.effective(worker))?;
+  let environment: BTreeMap<&str, String> = ENVIRONMENT
+    .iter()
+    .filter_map(|name| env::var(name).ok().map(|value| (*name, value)))
+    .map(|(name, value)| if is_secret(name) { (name, REDACTED.to_string()) } else { (name, value) })
+    .collect();
+  if !environment.is_empty() {
+    dump.push('\n');
+    dump.push_str(&toml::to_string(&BTreeMap::from([("environment", environment)]))?);
+  }
+  Ok(dump)
+}
+
+/// Reload the configuration file at `path` whenever the process receives SIGHUP,
+/// keeping any settings present in `overrides`
+pub fn watch(path: PathBuf, overrides: WorkerConfig) -> Result<(), Box<dyn Error>> {
+  let hangup = Arc::new(AtomicBool::new(false));
+  #[cfg(unix)]
+  signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hangup))?;
+  #[cfg(not(unix))]
+  warn!(
+    target: "config",
+    "SIGHUP is not available on this platform, {} will not be reloaded",
+    path.display()
+  );
+  thread::spawn(move || loop {
+    thread::sleep(Duration::new(1, 0));
+    if hangup.swap(false, Ordering::SeqCst) {
+      match WorkerConfig::load(&path) {
+        Ok(config) => {
+          info!(target: "config", "reloaded {}", path.display());
+          install(config.merged(&overrides));
+        }
+        Err(e) => warn!(
+          target: "config",
+          "failed to reload {}, keeping the previous configuration: {}",
+          path.display(),
+          e
+        ),
+      }
+    }
+  });
+  Ok(())
+}
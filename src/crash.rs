@@ -0,0 +1,151 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Crash reports: when a converter thread panics mid-task, a panic hook makes a best-effort
+//! attempt to answer the task with a `Fatal:workercrash` result, so that the dispatcher doesn't
+//! wait out its timeout for it.
+//!
+//! Every converter thread connects its own emergency socket to the sink ahead of time, as
+//! nothing can be relied upon after the panic, and marks the task it is converting in flight.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::panic::{self, PanicHookInfo};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use zmq::{Socket, SNDMORE};
+
+use crate::adaptor;
+use crate::endpoint;
+use crate::protocol::{self, ResultStatus, TaskStats};
+
+/// Milliseconds a crash report may take to reach the sink as the thread exits
+const REPORT_LINGER_MS: i32 = 1000;
+
+/// The task a converter thread is working on
+struct InFlight {
+  identity: String,
+  service: String,
+  taskid: String,
+  start: Instant,
+}
+
+thread_local! {
+  static EMERGENCY: RefCell<Option<Socket>> = const { RefCell::new(None) };
+  static IN_FLIGHT: RefCell<Option<InFlight>> = const { RefCell::new(None) };
+}
+
+/// Clears the task in flight when dropped, after the task was answered
+pub struct InFlightGuard;
+
+impl Drop for InFlightGuard {
+  fn drop(&mut self) {
+    let _ = IN_FLIGHT.try_with(|in_flight| in_flight.borrow_mut().take());
+  }
+}
+
+/// Connect the emergency socket of this thread to `sink`, and install the panic hook
+pub fn connect(sink: &str) -> Result<(), Box<dyn Error>> {
+  let socket = endpoint::context(sink).socket(zmq::PUSH)?;
+  socket.set_linger(REPORT_LINGER_MS)?;
+  socket.set_ipv6(true)?;
+  socket.connect(sink)?;
+  EMERGENCY.with(|emergency| *emergency.borrow_mut() = Some(socket));
+  install_hook();
+  Ok(())
+}
+
+/// Mark task `taskid` of `service`, fetched under `identity`, as in flight on this thread
+pub fn begin(identity: &str, service: &str, taskid: &str) -> InFlightGuard {
+  IN_FLIGHT.with(|in_flight| {
+    *in_flight.borrow_mut() = Some(InFlight {
+      identity: identity.to_string(),
+      service: service.to_string(),
+      taskid: taskid.to_string(),
+      start: Instant::now(),
+    })
+  });
+  InFlightGuard
+}
+
+/// Report the panics of threads with a task in flight, before the previous hook runs
+fn install_hook() {
+  static HOOK: Once = Once::new();
+  HOOK.call_once(|| {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+      report(info);
+      previous(info);
+    }));
+  });
+}
+
+/// The message of a panic, with its location
+fn describe(info: &PanicHookInfo) -> String {
+  let payload = info.payload();
+  let message = match payload.downcast_ref::<&str>() {
+    Some(message) => message.to_string(),
+    None => payload.downcast_ref::<String>().cloned().unwrap_or_else(|| String::from("unknown panic")),
+  };
+  match info.location() {
+    Some(location) => format!("{} at {}:{}", message.replace('\n', " "), location.file(), location.line()),
+    None => message.replace('\n', " "),
+  }
+}
+
+/// Send the crash report of this thread's task in flight, if any, without blocking
+fn report(info: &PanicHookInfo) {
+  let Some(task) = IN_FLIGHT.try_with(|in_flight| in_flight.try_borrow_mut().ok()?.take()).ok().flatten() else {
+    return;
+  };
+  let sent = EMERGENCY.try_with(|emergency| -> Result<(), Box<dyn Error>> {
+    let emergency = emergency.try_borrow()?;
+    let socket = emergency.as_ref().ok_or("no emergency socket")?;
+    let archive = adaptor::crash_archive(&describe(info))?;
+    send_report(socket, &task.identity, &task.service, &task.taskid, task.start.elapsed(), archive)
+  });
+  if let Ok(Err(e)) = sent {
+    warn!(target: "crash", "failed to report the crash to the sink: {}", e);
+  }
+}
+
+/// Send `archive` as the result of task `taskid`, framed for the protocol version in use,
+/// without blocking
+pub(crate) fn send_report(
+  socket: &Socket,
+  identity: &str,
+  service: &str,
+  taskid: &str,
+  duration: Duration,
+  mut archive: File,
+) -> Result<(), Box<dyn Error>> {
+  let mut payload = Vec::new();
+  archive.read_to_end(&mut payload)?;
+  let mut frames = vec![identity.as_bytes().to_vec(), service.as_bytes().to_vec(), taskid.as_bytes().to_vec()];
+  if protocol::version() >= protocol::STATUS_VERSION {
+    frames.push(ResultStatus::of_result(&mut archive).frame().into_bytes());
+  }
+  if protocol::version() >= protocol::STATS_VERSION {
+    let stats = TaskStats {
+      duration_ms: duration.as_millis() as u64,
+      retries: 0,
+      output_bytes: payload.len() as u64,
+      peak_rss_bytes: None,
+    };
+    frames.push(stats.frame().into_bytes());
+  }
+  frames.push(payload);
+  let last = frames.len() - 1;
+  for (index, frame) in frames.into_iter().enumerate() {
+    let more = if index < last { SNDMORE } else { 0 };
+    socket.send(frame, more | zmq::DONTWAIT)?;
+  }
+  Ok(())
+}
@@ -0,0 +1,55 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Detaching worker processes and PID files, for deployments under nohup or cron rather than systemd
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Fork into the background and detach from the terminal, writing the daemon's PID to `pid_file`
+/// and redirecting stdout and stderr to `log_file`, where given.
+/// Must be called before any threads are spawned.
+#[cfg(unix)]
+pub fn daemonize(pid_file: Option<&Path>, log_file: Option<&Path>) -> Result<(), Box<dyn Error>> {
+  use std::env;
+  use std::fs::OpenOptions;
+
+  // keep relative paths (configuration, recordings) meaningful
+  let mut daemon = daemonize::Daemonize::new().working_directory(env::current_dir()?);
+  if let Some(pid_file) = pid_file {
+    daemon = daemon.pid_file(pid_file);
+  }
+  if let Some(log_file) = log_file {
+    let log = OpenOptions::new().create(true).append(true).open(log_file)?;
+    daemon = daemon.stdout(log.try_clone()?).stderr(log);
+  }
+  daemon.start()?;
+  Ok(())
+}
+
+/// Fork into the background and detach from the terminal (unsupported on this platform)
+#[cfg(not(unix))]
+pub fn daemonize(_pid_file: Option<&Path>, _log_file: Option<&Path>) -> Result<(), Box<dyn Error>> {
+  Err(From::from("daemonizing is only supported on unix platforms"))
+}
+
+/// Write the PID of the current process to `path`
+pub fn write_pid_file(path: &Path) -> Result<(), Box<dyn Error>> {
+  fs::write(path, format!("{}\n", std::process::id()))?;
+  Ok(())
+}
+
+/// Remove a PID file on exit, if it still names the current process
+pub fn remove_pid_file(path: &Path) {
+  let ours = fs::read_to_string(path)
+    .map(|contents| contents.trim() == std::process::id().to_string())
+    .unwrap_or(false);
+  if ours {
+    fs::remove_file(path).ok();
+  }
+}
@@ -0,0 +1,244 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A terminal dashboard for a running worker process, for operators babysitting a conversion
+//! campaign: what every thread is doing and on which task, the throughput of the last minutes as
+//! a sparkline and the latest log lines, in place of the scrolling log on STDERR.
+//!
+//! [`show`] takes over the terminal from a thread of its own, redrawing a [`View`] of the
+//! worker's [`WorkerState`] until it is [`Dashboard::close`]d. As the terminal is in raw mode,
+//! `q` and Ctrl-C request the usual graceful shutdown, draining the tasks in flight.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table};
+use ratatui::Frame;
+
+use crate::logger;
+use crate::shutdown;
+use crate::state::{Phase, WorkerState};
+use crate::worker::Worker;
+
+/// Time between two redraws of the dashboard
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+/// Time between two throughput samples, each a bar of the sparkline
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Throughput samples kept, the sparkline showing as many as fit
+pub const THROUGHPUT_SAMPLES: usize = 600;
+/// Log lines kept, the log pane showing as many as fit
+pub const LOG_LINES: usize = 500;
+
+/// What the dashboard shows, besides the state of the worker drawn with it
+#[derive(Clone, Debug)]
+pub struct View {
+  /// The service of the worker, in the title
+  pub service: String,
+  /// Results sent per [`SAMPLE_INTERVAL`], oldest first
+  pub throughput: VecDeque<u64>,
+  /// The latest log lines, oldest first
+  pub log: VecDeque<String>,
+  results: Option<u64>,
+  started: Instant,
+}
+
+impl View {
+  /// An empty view of a worker for `service`
+  pub fn new(service: &str) -> View {
+    View {
+      service: service.to_string(),
+      throughput: VecDeque::new(),
+      log: VecDeque::new(),
+      results: None,
+      started: Instant::now(),
+    }
+  }
+
+  /// Keep a log line, without its colors and tabs
+  pub fn record_line(&mut self, line: &str) {
+    if self.log.len() == LOG_LINES {
+      self.log.pop_front();
+    }
+    self.log.push_back(strip_colors(line));
+  }
+
+  /// Count the results sent since the previous sample
+  pub fn sample(&mut self, state: &WorkerState) {
+    let results = state.completed + state.failed;
+    if let Some(previous) = self.results {
+      if self.throughput.len() == THROUGHPUT_SAMPLES {
+        self.throughput.pop_front();
+      }
+      self.throughput.push_back(results.saturating_sub(previous));
+    }
+    self.results = Some(results);
+  }
+
+  /// Draw the view of the worker in `state` onto the whole `frame`
+  pub fn draw(&self, frame: &mut Frame, state: &WorkerState) {
+    let [header, threads, throughput, log, footer] = Layout::vertical([
+      Constraint::Length(1),
+      Constraint::Max(state.threads.len() as u16 + 3),
+      Constraint::Length(6),
+      Constraint::Min(3),
+      Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let uptime = self.started.elapsed().as_secs();
+    let mut title = format!(
+      " pericortex {} | {} completed, {} failed | {} converting | up {}:{:02}:{:02}",
+      self.service,
+      state.completed,
+      state.failed,
+      state.count(Phase::Converting),
+      uptime / 3600,
+      uptime / 60 % 60,
+      uptime % 60
+    );
+    if shutdown::requested() {
+      title.push_str(" | draining");
+    }
+    frame.render_widget(
+      Paragraph::new(title).style(Style::default().add_modifier(Modifier::REVERSED)),
+      header,
+    );
+
+    let rows = state.threads.iter().map(|thread| {
+      let (phase, style) = if thread.alive {
+        (thread.phase.to_string(), Style::default())
+      } else {
+        ("dead".to_string(), Style::default().fg(Color::Red))
+      };
+      Row::new([
+        thread.identity.clone(),
+        phase,
+        thread.taskid.clone().unwrap_or_default(),
+        format!("{:.1}s", thread.elapsed_ms as f64 / 1000.0),
+      ])
+      .style(style)
+    });
+    let table = Table::new(
+      rows,
+      [
+        Constraint::Percentage(45),
+        Constraint::Length(10),
+        Constraint::Percentage(35),
+        Constraint::Length(10),
+      ],
+    )
+    .header(Row::new(["thread", "phase", "task", "elapsed"]).style(Style::default().add_modifier(Modifier::BOLD)))
+    .block(Block::bordered().title(" threads "));
+    frame.render_widget(table, threads);
+
+    // the latest samples that fit, the most recent on the right
+    let shown = self.throughput.len().min(throughput.width.saturating_sub(2) as usize);
+    let samples: Vec<u64> = self.throughput.iter().skip(self.throughput.len() - shown).copied().collect();
+    let peak = samples.iter().max().copied().unwrap_or_default();
+    let sparkline = Sparkline::default()
+      .data(&samples)
+      .style(Style::default().fg(Color::Green))
+      .block(Block::bordered().title(format!(
+        " results per {}s, last {} samples, peak {} ",
+        SAMPLE_INTERVAL.as_secs(),
+        shown,
+        peak
+      )));
+    frame.render_widget(sparkline, throughput);
+
+    let visible = log.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = self
+      .log
+      .iter()
+      .skip(self.log.len().saturating_sub(visible))
+      .map(|line| Line::raw(line.as_str()))
+      .collect();
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" log ")), log);
+
+    frame.render_widget(
+      Paragraph::new(" q: finish the tasks in flight and exit").style(Style::default().fg(Color::DarkGray)),
+      footer,
+    );
+  }
+}
+
+/// A dashboard drawn on the terminal, until closed
+pub struct Dashboard {
+  closing: Arc<AtomicBool>,
+  thread: JoinHandle<()>,
+}
+
+/// Take over the terminal with a dashboard of `worker`, log lines included
+pub fn show<W: Worker + 'static>(worker: W) -> Result<Dashboard, Box<dyn Error>> {
+  if !io::stdout().is_terminal() {
+    return Err(From::from("the dashboard needs a terminal"));
+  }
+  let view = Arc::new(Mutex::new(View::new(worker.get_service())));
+  let recorder = Arc::clone(&view);
+  logger::print_with(Some(Box::new(move |line| recorder.lock().unwrap().record_line(line))));
+  let mut terminal = ratatui::try_init()?;
+  let closing = Arc::new(AtomicBool::new(false));
+  let closed = Arc::clone(&closing);
+  let thread = thread::spawn(move || {
+    view.lock().unwrap().sample(&worker.state_snapshot());
+    let mut sampled = Instant::now();
+    while !closed.load(Ordering::SeqCst) {
+      let state = worker.state_snapshot();
+      if sampled.elapsed() >= SAMPLE_INTERVAL {
+        view.lock().unwrap().sample(&state);
+        sampled = Instant::now();
+      }
+      if let Err(e) = terminal.draw(|frame| view.lock().unwrap().draw(frame, &state)) {
+        view.lock().unwrap().record_line(&format!("failed to draw the dashboard: {}", e));
+      }
+      if let Ok(true) = event::poll(REFRESH_INTERVAL) {
+        if let Ok(Event::Key(key)) = event::read() {
+          let interrupt = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+          if key.kind == KeyEventKind::Press && (key.code == KeyCode::Char('q') || interrupt) {
+            shutdown::request();
+          }
+        }
+      }
+    }
+  });
+  Ok(Dashboard { closing, thread })
+}
+
+impl Dashboard {
+  /// Give the terminal back, printing log lines to STDERR as usual again
+  pub fn close(self) {
+    self.closing.store(true, Ordering::SeqCst);
+    self.thread.join().ok();
+    ratatui::try_restore().ok();
+    logger::print_with(None);
+  }
+}
+
+/// The `line` without the ANSI escape sequences coloring it, and with spaces for tabs, which
+/// the terminal would print as they are
+fn strip_colors(line: &str) -> String {
+  let mut plain = String::with_capacity(line.len());
+  let mut chars = line.chars();
+  while let Some(c) = chars.next() {
+    if c == '\x1B' {
+      // skip up to and including the final letter of the sequence
+      chars.by_ref().find(char::is_ascii_alphabetic);
+    } else {
+      plain.push(if c == '\t' { ' ' } else { c });
+    }
+  }
+  plain
+}
@@ -0,0 +1,120 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Detection of tasks CorTeX re-dispatched to this process while (or after) converting them,
+//! tracked over the most recent [`DEDUP_CAPACITY`] taskids shared by all threads
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tempdir::TempDir;
+
+/// How many recent taskids are remembered
+pub const DEDUP_CAPACITY: usize = 1024;
+
+/// What a worker does with a task it has already seen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupPolicy {
+  /// Convert every task, duplicates included
+  Off,
+  /// Drop duplicates without converting or responding, the first delivery answers for them
+  Skip,
+  /// Resend the cached result of an already completed duplicate, drop in-flight ones
+  Replay,
+}
+
+/// A task received before
+#[derive(Debug)]
+pub enum Duplicate {
+  /// The earlier delivery is still being converted
+  InFlight,
+  /// The earlier delivery is done, with its cached result when replaying
+  Completed(Option<File>),
+}
+
+enum TaskState {
+  InFlight,
+  Done(Option<PathBuf>),
+}
+
+#[derive(Default)]
+struct RecentTasks {
+  order: VecDeque<String>,
+  results: HashMap<String, TaskState>,
+  cache: Option<TempDir>,
+  cached: usize,
+}
+
+static RECENT: Mutex<Option<RecentTasks>> = Mutex::new(None);
+
+/// Register a received task, returning how it was handled before if it is a duplicate
+pub fn begin(taskid: &str) -> Option<Duplicate> {
+  let mut guard = RECENT.lock().unwrap();
+  let recent = guard.get_or_insert_with(RecentTasks::default);
+  if let Some(state) = recent.results.get(taskid) {
+    return Some(match state {
+      TaskState::InFlight => Duplicate::InFlight,
+      TaskState::Done(cached) => {
+        Duplicate::Completed(cached.as_ref().and_then(|path| File::open(path).ok()))
+      }
+    });
+  }
+  if recent.order.len() >= DEDUP_CAPACITY {
+    if let Some(evicted) = recent.order.pop_front() {
+      if let Some(TaskState::Done(Some(path))) = recent.results.remove(&evicted) {
+        fs::remove_file(path).ok();
+      }
+    }
+  }
+  recent.order.push_back(taskid.to_string());
+  recent.results.insert(taskid.to_string(), TaskState::InFlight);
+  None
+}
+
+/// Mark a task as completed, keeping a copy of its `result` for replaying duplicates
+pub fn complete(taskid: &str, result: Option<&mut File>) {
+  let mut guard = RECENT.lock().unwrap();
+  let recent = guard.get_or_insert_with(RecentTasks::default);
+  let cached = result.and_then(|file| match cache(recent, file) {
+    Ok(path) => Some(path),
+    Err(e) => {
+      warn!(target: "dedup", "failed to cache the result of task {}: {}", taskid, e);
+      None
+    }
+  });
+  if let Some(state) = recent.results.get_mut(taskid) {
+    *state = TaskState::Done(cached);
+  }
+}
+
+/// Taskids of the remembered tasks which were completed, oldest first
+pub fn completed() -> Vec<String> {
+  let guard = RECENT.lock().unwrap();
+  match *guard {
+    Some(ref recent) => recent
+      .order
+      .iter()
+      .filter(|taskid| matches!(recent.results.get(*taskid), Some(TaskState::Done(_))))
+      .cloned()
+      .collect(),
+    None => Vec::new(),
+  }
+}
+
+fn cache(recent: &mut RecentTasks, file: &mut File) -> io::Result<PathBuf> {
+  if recent.cache.is_none() {
+    recent.cache = Some(TempDir::new("cortex_dedup")?);
+  }
+  recent.cached += 1;
+  let path = recent.cache.as_ref().unwrap().path().join(recent.cached.to_string());
+  io::copy(file, &mut File::create(&path)?)?;
+  file.seek(SeekFrom::Start(0))?;
+  Ok(path)
+}
@@ -0,0 +1,284 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Structural comparison of two result archives, e.g. to assess the impact of a converter upgrade:
+//! file lists, normalized HTML differences, and `cortex.log` severity deltas. The comparisons
+//! need the `tools` feature; the HTML normalization is also used by the sanity checks.
+
+use std::collections::BTreeMap;
+#[cfg(feature = "tools")]
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "tools")]
+use std::path::Path;
+
+#[cfg(feature = "tools")]
+use similar::{ChangeTag, TextDiff};
+
+#[cfg(feature = "tools")]
+use crate::adaptor;
+#[cfg(feature = "tools")]
+use crate::logcodes;
+use crate::logcodes::Severity;
+
+#[cfg(feature = "tools")]
+/// Changed lines reported per HTML file, at most
+const MAX_REPORTED_CHANGES: usize = 20;
+
+/// A file present in both archives, with different contents
+#[derive(Clone, Debug)]
+pub struct EntryDiff {
+  /// Name of the entry in the archives
+  pub name: String,
+  /// For HTML entries, the normalized tokens removed (`-`) and added (`+`)
+  pub changes: Vec<String>,
+}
+
+/// The differences between an old and a new result archive
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveDiff {
+  /// Entries only in the new archive
+  pub added: Vec<String>,
+  /// Entries only in the old archive
+  pub removed: Vec<String>,
+  /// Entries whose contents changed (HTML entries only when their normalized DOM changed)
+  pub changed: Vec<EntryDiff>,
+  /// `cortex.log` message counts per severity, as (old, new), where they differ
+  pub severity_deltas: BTreeMap<Severity, (usize, usize)>,
+}
+
+impl ArchiveDiff {
+  /// Are the archives equivalent?
+  pub fn is_identical(&self) -> bool {
+    self.added.is_empty()
+      && self.removed.is_empty()
+      && self.changed.is_empty()
+      && self.severity_deltas.is_empty()
+  }
+}
+
+impl fmt::Display for ArchiveDiff {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.is_identical() {
+      return writeln!(f, "archives are equivalent");
+    }
+    for name in &self.added {
+      writeln!(f, "added:   {}", name)?;
+    }
+    for name in &self.removed {
+      writeln!(f, "removed: {}", name)?;
+    }
+    for entry in &self.changed {
+      writeln!(f, "changed: {}", entry.name)?;
+      for change in &entry.changes {
+        writeln!(f, "    {}", change)?;
+      }
+    }
+    for (severity, (old, new)) in &self.severity_deltas {
+      writeln!(f, "cortex.log {}: {} -> {}", severity, old, new)?;
+    }
+    Ok(())
+  }
+}
+
+/// Compare the result archives at `old` and `new`
+#[cfg(feature = "tools")]
+pub fn diff_archives(old: &Path, new: &Path) -> Result<ArchiveDiff, Box<dyn Error>> {
+  let old_manifest = adaptor::archive_manifest(old)?;
+  let new_manifest = adaptor::archive_manifest(new)?;
+  let mut diff = ArchiveDiff::default();
+
+  for (name, digest) in &new_manifest {
+    match old_manifest.get(name) {
+      None => diff.added.push(name.clone()),
+      Some(old_digest) if old_digest != digest => {
+        if is_html(name) {
+          let old_html = adaptor::read_archive_entry(old, name)?.unwrap_or_default();
+          let new_html = adaptor::read_archive_entry(new, name)?.unwrap_or_default();
+          let changes = html_changes(
+            &String::from_utf8_lossy(&old_html),
+            &String::from_utf8_lossy(&new_html),
+          );
+          if !changes.is_empty() {
+            diff.changed.push(EntryDiff {
+              name: name.clone(),
+              changes,
+            });
+          }
+        } else {
+          diff.changed.push(EntryDiff {
+            name: name.clone(),
+            changes: Vec::new(),
+          });
+        }
+      }
+      Some(_) => {}
+    }
+  }
+  diff.removed = old_manifest
+    .keys()
+    .filter(|name| !new_manifest.contains_key(*name))
+    .cloned()
+    .collect();
+
+  let old_counts = log_severities(old)?;
+  let new_counts = log_severities(new)?;
+  for severity in [Severity::Info, Severity::Warning, Severity::Error, Severity::Fatal] {
+    let old_count = old_counts.get(&severity).cloned().unwrap_or(0);
+    let new_count = new_counts.get(&severity).cloned().unwrap_or(0);
+    if old_count != new_count {
+      diff.severity_deltas.insert(severity, (old_count, new_count));
+    }
+  }
+  Ok(diff)
+}
+
+#[cfg(feature = "tools")]
+pub(crate) fn is_html(name: &str) -> bool {
+  let lower = name.to_lowercase();
+  lower.ends_with(".html") || lower.ends_with(".xhtml") || lower.ends_with(".htm")
+}
+
+#[cfg(feature = "tools")]
+fn log_severities(archive: &Path) -> Result<BTreeMap<Severity, usize>, Box<dyn Error>> {
+  let log = adaptor::read_archive_entry(archive, "cortex.log")?.unwrap_or_default();
+  Ok(logcodes::severity_counts(&logcodes::parse(
+    &String::from_utf8_lossy(&log),
+  )))
+}
+
+/// The normalized tokens removed and added between two HTML documents
+#[cfg(feature = "tools")]
+pub fn html_changes(old: &str, new: &str) -> Vec<String> {
+  token_changes(&normalize_html(old), &normalize_html(new))
+}
+
+/// The tokens removed and added between two normalized HTML documents
+#[cfg(feature = "tools")]
+pub fn token_changes(old_tokens: &[String], new_tokens: &[String]) -> Vec<String> {
+  let old_slices: Vec<&str> = old_tokens.iter().map(String::as_str).collect();
+  let new_slices: Vec<&str> = new_tokens.iter().map(String::as_str).collect();
+  let diff = TextDiff::from_slices(&old_slices, &new_slices);
+  diff
+    .iter_all_changes()
+    .filter_map(|change| match change.tag() {
+      ChangeTag::Delete => Some(format!("- {}", change.value())),
+      ChangeTag::Insert => Some(format!("+ {}", change.value())),
+      ChangeTag::Equal => None,
+    })
+    .take(MAX_REPORTED_CHANGES)
+    .collect()
+}
+
+/// Split HTML into one token per tag or text run, with collapsed whitespace,
+/// lowercased tag names, sorted attributes and without comments
+pub fn normalize_html(html: &str) -> Vec<String> {
+  let mut tokens = Vec::new();
+  let mut rest = html;
+  while !rest.is_empty() {
+    if let Some(comment) = rest.strip_prefix("<!--") {
+      rest = comment.split_once("-->").map(|(_, after)| after).unwrap_or("");
+    } else if rest.starts_with('<') {
+      match tag_end(rest) {
+        Some(end) => {
+          tokens.push(normalize_tag(&rest[1..end - 1]));
+          rest = &rest[end..];
+        }
+        // a tag cut off by the end of the document, e.g. of a truncated output, is kept as text
+        None => {
+          push_text(&mut tokens, rest);
+          rest = "";
+        }
+      }
+    } else {
+      let end = rest.find('<').unwrap_or(rest.len());
+      push_text(&mut tokens, &rest[..end]);
+      rest = &rest[end..];
+    }
+  }
+  tokens
+}
+
+/// Add a text run with collapsed whitespace, unless it is blank
+fn push_text(tokens: &mut Vec<String>, text: &str) {
+  let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+  if !text.is_empty() {
+    tokens.push(text);
+  }
+}
+
+/// Byte offset just past the `>` closing the tag at the start of `html`, respecting quotes,
+/// if the tag is closed at all
+fn tag_end(html: &str) -> Option<usize> {
+  let mut quote = None;
+  for (offset, c) in html.char_indices() {
+    match (quote, c) {
+      (None, '"') | (None, '\'') => quote = Some(c),
+      (Some(q), _) if q == c => quote = None,
+      (None, '>') => return Some(offset + 1),
+      _ => {}
+    }
+  }
+  None
+}
+
+/// Normalize the inside of a tag, e.g. `A  href="x" class='y'` into `<a class="y" href="x">`
+fn normalize_tag(inner: &str) -> String {
+  let inner = inner.trim();
+  let self_closing = inner.ends_with('/');
+  let inner = inner.trim_end_matches('/').trim();
+  let (name, mut attributes) = match inner.find(char::is_whitespace) {
+    Some(split) => (&inner[..split], parse_attributes(&inner[split..])),
+    None => (inner, Vec::new()),
+  };
+  attributes.sort();
+  let mut tag = format!("<{}", name.to_lowercase());
+  for (key, value) in attributes {
+    match value {
+      Some(value) => tag.push_str(&format!(" {}=\"{}\"", key, value)),
+      None => tag.push_str(&format!(" {}", key)),
+    }
+  }
+  tag.push_str(if self_closing { "/>" } else { ">" });
+  tag
+}
+
+fn parse_attributes(source: &str) -> Vec<(String, Option<String>)> {
+  let mut attributes = Vec::new();
+  let mut rest = source.trim_start();
+  while !rest.is_empty() {
+    let key_end = rest
+      .find(|c: char| c == '=' || c.is_whitespace())
+      .unwrap_or(rest.len());
+    let key = rest[..key_end].to_lowercase();
+    rest = rest[key_end..].trim_start();
+    let value = if let Some(after_eq) = rest.strip_prefix('=') {
+      let after_eq = after_eq.trim_start();
+      let (value, remaining) = match after_eq.chars().next() {
+        Some(q) if q == '"' || q == '\'' => {
+          let closing = after_eq[1..].find(q).map(|i| i + 1).unwrap_or(after_eq.len());
+          (
+            &after_eq[1..closing],
+            after_eq.get(closing + 1..).unwrap_or(""),
+          )
+        }
+        _ => {
+          let end = after_eq.find(char::is_whitespace).unwrap_or(after_eq.len());
+          (&after_eq[..end], &after_eq[end..])
+        }
+      };
+      rest = remaining.trim_start();
+      Some(value.split_whitespace().collect::<Vec<_>>().join(" "))
+    } else {
+      None
+    };
+    if !key.is_empty() {
+      attributes.push((key, value));
+    }
+  }
+  attributes
+}
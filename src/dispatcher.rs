@@ -0,0 +1,172 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The dispatcher's side of the result protocol, for custom sinks written in Rust.
+//!
+//! A worker sends every result as a single multipart message: its identity, the service name and
+//! the taskid, then a [`ResultStatus`] frame (protocol version 4 on), a [`TaskStats`] frame
+//! (version 5 on), a header and a contents frame per secondary artifact (version 10 on), and
+//! finally the payload frames, which are empty for a failed task.
+//! [`Sink::recv_result`] undoes that framing, recognizing the optional frames by their prefixes,
+//! so that a sink can serve workers speaking different protocol versions at once.
+//! From version 7 on, workers streaming a large result also send [`Keepalive`] messages, which
+//! [`Sink::recv_message`] returns as such and [`Sink::recv_result`] skips.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use zmq::{Context, Socket};
+
+use crate::protocol::{ArtifactHeader, Keepalive, ObjectManifest, Outcome, ProtocolError, ResultStatus, TaskStats};
+
+/// A result, as sent by a worker
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaskResult {
+  /// Identity of the worker thread which fetched the task
+  pub identity: String,
+  /// Name of the service which converted the task
+  pub service: String,
+  /// The CorTeX task id
+  pub taskid: String,
+  /// The result archive, possibly zstd-compressed or an object storage manifest; empty on failure
+  pub payload: Vec<u8>,
+  /// The classification of the result, from protocol version 4 on
+  pub status: Option<ResultStatus>,
+  /// The resource usage of the task, from protocol version 5 on
+  pub stats: Option<TaskStats>,
+  /// The secondary artifacts of the result by name, from protocol version 10 on
+  pub artifacts: BTreeMap<String, Vec<u8>>,
+}
+
+impl TaskResult {
+  /// How the task went: its status, or for older workers whether a result was sent at all
+  pub fn outcome(&self) -> Outcome {
+    match self.status {
+      Some(ref status) => status.status,
+      None if self.payload.is_empty() => Outcome::Fatal,
+      None => Outcome::Ok,
+    }
+  }
+
+  /// The object storage manifest sent in place of a large result, if any
+  pub fn manifest(&self) -> Option<ObjectManifest> { ObjectManifest::parse(&self.payload) }
+}
+
+/// A message received by the sink
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SinkMessage {
+  /// The result of a task
+  Result(TaskResult),
+  /// A worker is still streaming the result of a task, from protocol version 7 on
+  Keepalive {
+    /// Identity of the worker thread which fetched the task
+    identity: String,
+    /// The CorTeX task id
+    taskid: String,
+    /// How far the result got
+    keepalive: Keepalive,
+  },
+}
+
+/// A PULL socket receiving the results of workers
+pub struct Sink {
+  socket: Socket,
+}
+
+impl Sink {
+  /// Bind a new sink to `address`, e.g. `tcp://*:51696`
+  pub fn bind(address: &str) -> Result<Sink, Box<dyn Error>> {
+    let socket = Context::new().socket(zmq::PULL)?;
+    socket.bind(address)?;
+    Ok(Sink { socket })
+  }
+
+  /// Receive results on an existing PULL `socket`
+  pub fn from_socket(socket: Socket) -> Sink { Sink { socket } }
+
+  /// The underlying socket, e.g. to poll it
+  pub fn socket(&self) -> &Socket { &self.socket }
+
+  /// Block until the next result arrives. A message which doesn't follow the protocol is
+  /// reported as a [`ProtocolError`]. Keepalives are skipped
+  pub fn recv_result(&self) -> Result<TaskResult, Box<dyn Error>> {
+    loop {
+      if let SinkMessage::Result(result) = self.recv_message()? {
+        return Ok(result);
+      }
+    }
+  }
+
+  /// Block until the next result or keepalive arrives
+  pub fn recv_message(&self) -> Result<SinkMessage, Box<dyn Error>> { parse_message(self.socket.recv_multipart(0)?) }
+}
+
+/// The result or keepalive in the frames of a sink message
+pub fn parse_message(frames: Vec<Vec<u8>>) -> Result<SinkMessage, Box<dyn Error>> {
+  if frames.len() == 4 {
+    if let Some(keepalive) = Keepalive::parse(&frames[3]) {
+      let text = |frame: &[u8]| String::from_utf8_lossy(frame).into_owned();
+      return Ok(SinkMessage::Keepalive {
+        identity: text(&frames[0]),
+        taskid: text(&frames[2]),
+        keepalive,
+      });
+    }
+  }
+  parse_result(frames).map(SinkMessage::Result)
+}
+
+/// The result in the frames of a sink message
+pub fn parse_result(frames: Vec<Vec<u8>>) -> Result<TaskResult, Box<dyn Error>> {
+  if frames.len() < 4 {
+    return Err(Box::new(ProtocolError(format!(
+      "expected at least 4 frames in a result, received {}",
+      frames.len()
+    ))));
+  }
+  let mut frames = frames.into_iter();
+  let mut text = |name: &str| {
+    String::from_utf8(frames.next().unwrap_or_default())
+      .map_err(|_| ProtocolError(format!("the {} frame of a result isn't UTF-8", name)))
+  };
+  let identity = text("identity")?;
+  let service = text("service")?;
+  let taskid = text("taskid")?;
+  let mut rest: Vec<Vec<u8>> = frames.collect();
+  let mut status = None;
+  let mut stats = None;
+  // the optional frames come in a fixed order, and there is always a payload frame after them
+  if rest.len() > 1 {
+    status = ResultStatus::parse(&rest[0]);
+    if status.is_some() {
+      rest.remove(0);
+    }
+  }
+  if rest.len() > 1 {
+    stats = TaskStats::parse(&rest[0]);
+    if stats.is_some() {
+      rest.remove(0);
+    }
+  }
+  let mut artifacts = BTreeMap::new();
+  while rest.len() > 2 {
+    let Some(header) = ArtifactHeader::parse(&rest[0]) else {
+      break;
+    };
+    rest.remove(0);
+    artifacts.insert(header.name, rest.remove(0));
+  }
+  Ok(TaskResult {
+    identity,
+    service,
+    taskid,
+    payload: rest.concat(),
+    status,
+    stats,
+    artifacts,
+  })
+}
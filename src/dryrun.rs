@@ -0,0 +1,51 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Dry runs, to check a new host's network, disk and protocol behavior with live tasks.
+//!
+//! Tasks are received, extracted and validated as usual, but never converted: every task is
+//! answered with a `Fatal:dry_run` report, so that no bogus result enters the corpus. Task
+//! requests carry a [`DRY_RUN`](crate::protocol::DRY_RUN) frame, telling the dispatcher which workers are rehearsing.
+
+use std::error::Error;
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tempfile::SpooledTempFile;
+use walkdir::WalkDir;
+
+use crate::adaptor;
+use crate::protocol::PayloadFormat;
+use crate::worker::Worker;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Receive and answer tasks without converting them
+pub fn enable() { ENABLED.store(true, Ordering::SeqCst); }
+
+/// Is this a dry run?
+pub fn enabled() -> bool { ENABLED.load(Ordering::SeqCst) }
+
+/// Extract and validate `payload` like `worker` would before converting it,
+/// returning the dry-run report to send in place of a result
+pub fn rehearse<W: Worker>(worker: &W, payload: &mut SpooledTempFile) -> Result<File, Box<dyn Error>> {
+  if let PayloadFormat::SingleFile { .. } = worker.payload_format() {
+    // nothing to extract, the payload is the one input file
+    return adaptor::dry_run_archive(1);
+  }
+  let extracted = adaptor::extract_zip_reader_to_tmpdir(payload, "cortex_dry_run")?;
+  if worker.input_format().as_deref() == Some("tex") {
+    adaptor::validate_tex_sources(extracted.path())?;
+  }
+  let files = WalkDir::new(extracted.path())
+    .into_iter()
+    .filter_map(Result::ok)
+    .filter(|entry| entry.file_type().is_file())
+    .count();
+  extracted.close()?;
+  adaptor::dry_run_archive(files)
+}
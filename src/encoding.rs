@@ -0,0 +1,222 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Character encodings of TeX sources: arXiv-era documents come in UTF-8 (with or without a byte
+//! order mark), latin-1 or CP1251, often without saying which.
+//!
+//! Sources which are valid UTF-8 are taken as such. Otherwise an `inputenc` declaration decides,
+//! and lacking one, the shape of the text: Cyrillic words are runs of high bytes, while the
+//! accented letters of Western languages mostly stand alone between ASCII letters.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+/// Extensions of the TeX sources whose encoding matters to a conversion
+pub const TEX_EXTENSIONS: &[&str] = &["tex", "sty", "cls", "bbl", "bib", "def", "cfg", "ltx"];
+
+/// The UTF-8 byte order mark
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Share of the high bytes in runs of three or more, from which a text is taken for Cyrillic
+const CYRILLIC_RUN_SHARE: f64 = 0.5;
+
+/// The characters of CP1251 bytes 0x80 to 0xBF; 0xC0 to 0xFF are the Cyrillic letters U+0410 on
+const CP1251_HIGH: [char; 64] = [
+  'Ђ', 'Ѓ', '‚', 'ѓ', '„', '…', '†', '‡', '€', '‰', 'Љ', '‹', 'Њ', 'Ќ', 'Ћ', 'Џ', //
+  'ђ', '‘', '’', '“', '”', '•', '–', '—', '\u{98}', '™', 'љ', '›', 'њ', 'ќ', 'ћ', 'џ', //
+  '\u{A0}', 'Ў', 'ў', 'Ј', '¤', 'Ґ', '¦', '§', 'Ё', '©', 'Є', '«', '¬', '\u{AD}', '®', 'Ї', //
+  '°', '±', 'І', 'і', 'ґ', 'µ', '¶', '·', 'ё', '№', 'є', '»', 'ј', 'Ѕ', 'ѕ', 'ї',
+];
+
+/// The encoding of a source file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SourceEncoding {
+  /// UTF-8, which includes plain ASCII
+  Utf8,
+  /// UTF-8 preceded by a byte order mark
+  Utf8Bom,
+  /// ISO-8859-1
+  Latin1,
+  /// Windows-1251, for Cyrillic
+  Cp1251,
+}
+
+impl SourceEncoding {
+  /// Is this UTF-8, with or without a byte order mark?
+  pub fn is_utf8(self) -> bool { matches!(self, SourceEncoding::Utf8 | SourceEncoding::Utf8Bom) }
+
+  /// The name of the encoding for `latexmlc --inputencoding`
+  pub fn latexml_name(self) -> &'static str {
+    match self {
+      SourceEncoding::Utf8 | SourceEncoding::Utf8Bom => "utf8",
+      SourceEncoding::Latin1 => "iso-8859-1",
+      SourceEncoding::Cp1251 => "cp1251",
+    }
+  }
+}
+
+impl fmt::Display for SourceEncoding {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      SourceEncoding::Utf8 => "utf-8",
+      SourceEncoding::Utf8Bom => "utf-8 with BOM",
+      SourceEncoding::Latin1 => "latin-1",
+      SourceEncoding::Cp1251 => "cp1251",
+    };
+    f.write_str(name)
+  }
+}
+
+/// The encoding of the TeX source `bytes`
+pub fn detect(bytes: &[u8]) -> SourceEncoding {
+  if bytes.starts_with(BOM) {
+    return SourceEncoding::Utf8Bom;
+  }
+  if std::str::from_utf8(bytes).is_ok() {
+    return SourceEncoding::Utf8;
+  }
+  match declared(bytes) {
+    Some(encoding) if !encoding.is_utf8() => encoding,
+    _ => guess(bytes),
+  }
+}
+
+/// The encoding named by an `inputenc` declaration of the sources, if a supported one
+fn declared(bytes: &[u8]) -> Option<SourceEncoding> {
+  let text = String::from_utf8_lossy(bytes);
+  let option = inputenc_option(&text)?;
+  match option.1.trim().to_ascii_lowercase().as_str() {
+    "utf8" | "utf8x" => Some(SourceEncoding::Utf8),
+    "latin1" | "latin9" | "ansinew" => Some(SourceEncoding::Latin1),
+    "cp1251" => Some(SourceEncoding::Cp1251),
+    _ => None,
+  }
+}
+
+/// The byte range and text of the encoding option in `\usepackage[...]{inputenc}` or
+/// `\inputencoding{...}`, if any
+fn inputenc_option(text: &str) -> Option<(usize, &str)> {
+  if let Some(package) = text.find("]{inputenc}") {
+    let start = text[..package].rfind('[')? + 1;
+    return Some((start, &text[start..package]));
+  }
+  let start = text.find("\\inputencoding{")? + "\\inputencoding{".len();
+  let end = start + text[start..].find('}')?;
+  Some((start, &text[start..end]))
+}
+
+/// Latin-1 or CP1251, by the share of high bytes in runs of three or more
+fn guess(bytes: &[u8]) -> SourceEncoding {
+  let mut high = 0;
+  let mut in_runs = 0;
+  let mut run = 0;
+  for byte in bytes.iter().chain(std::iter::once(&0)) {
+    if *byte >= 0x80 {
+      high += 1;
+      run += 1;
+    } else {
+      if run >= 3 {
+        in_runs += run;
+      }
+      run = 0;
+    }
+  }
+  if high > 0 && in_runs as f64 / high as f64 >= CYRILLIC_RUN_SHARE {
+    SourceEncoding::Cp1251
+  } else {
+    SourceEncoding::Latin1
+  }
+}
+
+/// Decode `bytes` in `encoding`, without a byte order mark. Invalid UTF-8 is replaced
+pub fn decode(bytes: &[u8], encoding: SourceEncoding) -> String {
+  match encoding {
+    SourceEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+    SourceEncoding::Utf8Bom => String::from_utf8_lossy(bytes.strip_prefix(BOM).unwrap_or(bytes)).into_owned(),
+    SourceEncoding::Latin1 => bytes.iter().map(|byte| char::from(*byte)).collect(),
+    SourceEncoding::Cp1251 => bytes
+      .iter()
+      .map(|byte| match byte {
+        0x00..=0x7F => char::from(*byte),
+        0x80..=0xBF => CP1251_HIGH[(byte - 0x80) as usize],
+        _ => char::from_u32(0x0410 + (byte - 0xC0) as u32).unwrap(),
+      })
+      .collect(),
+  }
+}
+
+/// The TeX source `bytes` in UTF-8, with its detected encoding. An `inputenc` declaration of
+/// another encoding is changed to `utf8`, so that TeX reads the transcoded text as such
+pub fn transcode(bytes: &[u8]) -> (String, SourceEncoding) {
+  let encoding = detect(bytes);
+  let mut text = decode(bytes, encoding);
+  if !encoding.is_utf8() {
+    if let Some((start, option)) = inputenc_option(&text) {
+      let end = start + option.len();
+      text.replace_range(start..end, "utf8");
+    }
+  }
+  (text, encoding)
+}
+
+/// Is `path` a TeX source, by its extension?
+pub fn is_tex_source(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .map(|extension| TEX_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+    .unwrap_or(false)
+}
+
+/// Rewrite every TeX source under `dir` which isn't UTF-8 in UTF-8, dropping byte order marks.
+/// Returns the rewritten files with their original encodings
+pub fn transcode_dir(dir: &Path) -> Result<Vec<(PathBuf, SourceEncoding)>, Box<dyn Error>> {
+  let mut transcoded = Vec::new();
+  for entry in WalkDir::new(dir).into_iter().filter_map(Result::ok) {
+    if !entry.file_type().is_file() || !is_tex_source(entry.path()) {
+      continue;
+    }
+    let bytes = fs::read(entry.path())?;
+    let (text, encoding) = transcode(&bytes);
+    if encoding != SourceEncoding::Utf8 {
+      fs::write(entry.path(), text)?;
+      transcoded.push((entry.path().to_path_buf(), encoding));
+    }
+  }
+  Ok(transcoded)
+}
+
+/// The encoding in which to read the TeX sources of the ZIP archive at `path`: UTF-8 if all of
+/// them are, otherwise the most common other encoding among them
+pub fn detect_archive(path: &Path) -> Result<SourceEncoding, Box<dyn Error>> {
+  let mut archive = ZipArchive::new(File::open(path)?)?;
+  let mut counts = [0; 2];
+  for i in 0..archive.len() {
+    let mut entry = archive.by_index(i)?;
+    if !entry.is_file() || !is_tex_source(Path::new(entry.name())) {
+      continue;
+    }
+    let mut bytes = Vec::new();
+    entry.read_to_end(&mut bytes)?;
+    match detect(&bytes) {
+      SourceEncoding::Latin1 => counts[0] += 1,
+      SourceEncoding::Cp1251 => counts[1] += 1,
+      _ => {}
+    }
+  }
+  Ok(match counts {
+    [0, 0] => SourceEncoding::Utf8,
+    [latin1, cp1251] if cp1251 > latin1 => SourceEncoding::Cp1251,
+    _ => SourceEncoding::Latin1,
+  })
+}
@@ -0,0 +1,227 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Dispatcher endpoints given as IPv4 or IPv6 literals or as hostnames.
+//!
+//! Workers hold their source and sink as validated [`Endpoint`]s, parsed from `tcp://host:port`
+//! addresses, so that a missing scheme or a bad port is reported when the configuration is read
+//! rather than as a failed assertion once the pool connects.
+//!
+//! Hostnames are handed to ZeroMQ unresolved, which resolves them again on every reconnection,
+//! so a dispatcher failing over to another host via a DNS update is picked up by running workers.
+//! A task request sent to the old host is lost with it, the [`ReconnectionMonitor`] tells when
+//! to repeat it.
+//!
+//! Workers of one process can also be wired together over `inproc://name` endpoints, see
+//! [`pipeline`](crate::pipeline). Those only connect sockets of one ZeroMQ context, so sockets for
+//! an inproc endpoint are created in the context shared by the process, see [`context`].
+//!
+//! The network threads of a worker pool share a context of their own, with as many ZeroMQ I/O
+//! threads as the pool has network threads, see [`network_context`].
+
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use zmq::{Context, Socket, SocketEvent};
+
+/// A `tcp://host:port` endpoint of the dispatcher, or an `inproc://name` one within the process
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Endpoint {
+  /// The transport, `tcp` or `inproc`
+  pub scheme: String,
+  /// IPv4 or IPv6 literal (without brackets), or hostname; the name of an inproc endpoint
+  pub host: String,
+  /// TCP port, 0 for an inproc endpoint
+  pub port: u16,
+}
+
+impl Endpoint {
+  /// The endpoint at `host` and `port`
+  pub fn tcp(host: &str, port: u16) -> Endpoint {
+    Endpoint {
+      scheme: String::from("tcp"),
+      host: host.to_string(),
+      port,
+    }
+  }
+
+  /// The in-process endpoint called `name`
+  pub fn inproc(name: &str) -> Endpoint {
+    Endpoint {
+      scheme: String::from("inproc"),
+      host: name.to_string(),
+      port: 0,
+    }
+  }
+
+  /// Does the endpoint connect sockets within this process?
+  pub fn is_inproc(&self) -> bool { self.scheme == "inproc" }
+
+  /// The `host:port` part of the endpoint, for plain TCP connections
+  pub fn host_port(&self) -> String {
+    if self.host.contains(':') {
+      format!("[{}]:{}", self.host, self.port)
+    } else {
+      format!("{}:{}", self.host, self.port)
+    }
+  }
+}
+
+impl FromStr for Endpoint {
+  type Err = String;
+  fn from_str(address: &str) -> Result<Endpoint, String> {
+    let (scheme, host_port) = address
+      .split_once("://")
+      .ok_or_else(|| format!("endpoint {:?} has no scheme, expected tcp://host:port", address))?;
+    if scheme == "inproc" {
+      if host_port.is_empty() {
+        return Err(format!("endpoint {:?} has no name, expected inproc://name", address));
+      }
+      return Ok(Endpoint::inproc(host_port));
+    }
+    if scheme != "tcp" {
+      return Err(format!("endpoint {:?} has scheme {:?}, only tcp and inproc are supported", address, scheme));
+    }
+    let (host, port) = host_port
+      .rsplit_once(':')
+      .ok_or_else(|| format!("endpoint {:?} has no port, expected tcp://host:port", address))?;
+    let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+    if host.is_empty() || host.contains(['/', ' ']) {
+      return Err(format!("endpoint {:?} has no valid host", address));
+    }
+    match port.parse::<u16>() {
+      Ok(port) if port > 0 => Ok(Endpoint::tcp(host, port)),
+      _ => Err(format!("endpoint {:?} has a bad port {:?}, expected 1 to 65535", address, port)),
+    }
+  }
+}
+
+impl TryFrom<String> for Endpoint {
+  type Error = String;
+  fn try_from(address: String) -> Result<Endpoint, String> { address.parse() }
+}
+
+impl From<Endpoint> for String {
+  fn from(endpoint: Endpoint) -> String { endpoint.to_string() }
+}
+
+impl fmt::Display for Endpoint {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.is_inproc() {
+      write!(f, "inproc://{}", self.host)
+    } else {
+      write!(f, "{}://{}", self.scheme, self.host_port())
+    }
+  }
+}
+
+static SHARED_CONTEXT: OnceLock<Context> = OnceLock::new();
+
+/// The ZeroMQ context for a socket connecting to `address`: the one shared by the process for
+/// `inproc://` addresses, which only reach sockets of the same context, and a new one otherwise
+pub fn context(address: &str) -> Context {
+  if address.starts_with("inproc://") {
+    SHARED_CONTEXT.get_or_init(Context::new).clone()
+  } else {
+    Context::new()
+  }
+}
+
+/// Most ZeroMQ I/O threads of a context, each of which handles many connections
+pub const MAX_IO_THREADS: usize = 64;
+
+/// Check a number of network threads, between 1 and [`MAX_IO_THREADS`]
+pub fn validate_io_threads(threads: usize) -> Result<(), String> {
+  if (1..=MAX_IO_THREADS).contains(&threads) {
+    Ok(())
+  } else {
+    Err(format!("io_threads must be between 1 and {}, got {}", MAX_IO_THREADS, threads))
+  }
+}
+
+/// The ZeroMQ context shared by the network threads of a pool connecting to `address`, with
+/// `io_threads` ZeroMQ I/O threads. Sockets for `inproc://` addresses, which need no I/O threads,
+/// get the context shared by the process
+pub fn network_context(address: &str, io_threads: usize) -> Result<Context, Box<dyn Error>> {
+  validate_io_threads(io_threads)?;
+  let context = context(address);
+  if !address.starts_with("inproc://") {
+    context.set_io_threads(io_threads as i32)?;
+  }
+  Ok(context)
+}
+
+/// Formats a `tcp://host:port` address, bracketing IPv6 literals
+pub fn tcp_address(host: &str, port: usize) -> String {
+  if host.contains(':') && !host.starts_with('[') {
+    format!("tcp://[{}]:{}", host, port)
+  } else {
+    format!("tcp://{}:{}", host, port)
+  }
+}
+
+/// Splits a `tcp://host:port` address into its host and port, unbracketing IPv6 literals
+pub fn split_address(address: &str) -> Option<(String, usize)> {
+  let host_port = address.trim_start_matches("tcp://");
+  let (host, port) = host_port.rsplit_once(':')?;
+  let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+  if host.is_empty() {
+    return None;
+  }
+  Some((host.to_string(), port.parse().ok()?))
+}
+
+/// Watches a socket's connection, noticing when it connects again after losing its peer
+pub struct ReconnectionMonitor {
+  events: Socket,
+  disconnected: Cell<bool>,
+}
+
+impl ReconnectionMonitor {
+  /// Monitor `socket`, which belongs to `context`, under a name unique to `identity`
+  pub fn new(context: &Context, socket: &Socket, identity: &str) -> zmq::Result<ReconnectionMonitor> {
+    let endpoint = format!("inproc://monitor-{}", identity);
+    socket.monitor(
+      &endpoint,
+      SocketEvent::CONNECTED as i32 | SocketEvent::DISCONNECTED as i32,
+    )?;
+    let events = context.socket(zmq::PAIR)?;
+    events.connect(&endpoint)?;
+    Ok(ReconnectionMonitor {
+      events,
+      disconnected: Cell::new(false),
+    })
+  }
+
+  /// The socket delivering the monitored events, for polling
+  pub fn socket(&self) -> &Socket { &self.events }
+
+  /// Consume the pending events, returning whether the connection was re-established
+  pub fn reconnected(&self) -> bool {
+    let mut reconnected = false;
+    while let Ok(frames) = self.events.recv_multipart(zmq::DONTWAIT) {
+      let event = match frames.first() {
+        Some(frame) if frame.len() >= 2 => SocketEvent::from_raw(u16::from_ne_bytes([frame[0], frame[1]])),
+        _ => continue,
+      };
+      match event {
+        SocketEvent::DISCONNECTED => self.disconnected.set(true),
+        SocketEvent::CONNECTED if self.disconnected.get() => {
+          self.disconnected.set(false);
+          reconnected = true;
+        }
+        _ => {}
+      }
+    }
+    reconnected
+  }
+}
@@ -0,0 +1,57 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Worker failures, classified by whether trying again could help
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// A failure of a worker or its converter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WorkerError {
+  /// An infrastructure hiccup, e.g. an unavailable docker daemon, a full disk or an OOM-kill
+  Transient(String),
+  /// A failure inherent to the input or setup, which trying again would only repeat
+  Deterministic(String),
+}
+
+impl WorkerError {
+  /// Could trying again succeed?
+  pub fn is_transient(&self) -> bool { matches!(self, WorkerError::Transient(_)) }
+}
+
+impl fmt::Display for WorkerError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      WorkerError::Transient(message) => write!(f, "transient failure: {}", message),
+      WorkerError::Deterministic(message) => f.write_str(message),
+    }
+  }
+}
+
+impl Error for WorkerError {}
+
+/// Could trying again after `error` succeed? Transient `WorkerError`s and IO errors caused by the
+/// host (full disk, out of memory, timeouts) could, anything else is assumed to be deterministic.
+/// Both the retries and the status sent ahead of a failed task's result are decided by it
+pub fn is_transient(error: &(dyn Error + 'static)) -> bool {
+  if let Some(error) = error.downcast_ref::<WorkerError>() {
+    return error.is_transient();
+  }
+  if let Some(error) = error.downcast_ref::<io::Error>() {
+    return matches!(
+      error.kind(),
+      io::ErrorKind::StorageFull
+        | io::ErrorKind::OutOfMemory
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::ResourceBusy
+    );
+  }
+  false
+}
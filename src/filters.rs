@@ -0,0 +1,152 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Post-filters of conversion results: transformations of the extracted output directory applied
+//! before it is archived and sent, whichever worker produced it. Filters registered here are
+//! returned by the default [`Worker::output_filters`](crate::worker::Worker::output_filters),
+//! so a policy such as stripping large intermediates holds for every service of a process.
+
+use std::env;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use walkdir::WalkDir;
+
+use crate::adaptor;
+
+/// What a filter knows about the task whose output it transforms
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterContext {
+  /// The CorTeX task id, or the file name of a local input
+  pub taskid: String,
+  /// Name of the service which converted the task
+  pub service: String,
+  /// Identity of the worker thread which converted the task
+  pub identity: String,
+}
+
+/// A transformation of the output directory of a conversion
+pub trait OutputFilter: Send + Sync {
+  /// Name of the filter, as reported in `cortex.log`
+  fn name(&self) -> &str;
+  /// Transform the output in `dir`, which has a `cortex.log` at its root
+  fn apply(&self, dir: &Path, context: &FilterContext) -> Result<(), Box<dyn Error>>;
+}
+
+static FILTERS: Mutex<Vec<Arc<dyn OutputFilter>>> = Mutex::new(Vec::new());
+
+/// Apply `filter` to the results of every worker of this process, after the ones registered before
+pub fn register(filter: Arc<dyn OutputFilter>) { FILTERS.lock().unwrap().push(filter); }
+
+/// The filters registered for this process, in order
+pub fn registered() -> Vec<Arc<dyn OutputFilter>> { FILTERS.lock().unwrap().clone() }
+
+/// Run `filters` in order over the ZIP archive `result`, returning the re-archived output,
+/// or `result` itself if there are no filters
+pub fn apply(
+  filters: &[Arc<dyn OutputFilter>],
+  result: File,
+  context: &FilterContext,
+) -> Result<File, Box<dyn Error>> {
+  if filters.is_empty() {
+    return Ok(result);
+  }
+  let output = adaptor::extract_zip_reader_to_tmpdir(result, "cortex_filter")?;
+  for filter in filters {
+    filter
+      .apply(output.path(), context)
+      .map_err(|e| format!("output filter {} failed: {}", filter.name(), e))?;
+  }
+  adaptor::archive_tmpdir_to_zip(output)
+}
+
+/// Append an informational message of `filter` to the `cortex.log` in `dir`
+pub fn log_info(dir: &Path, filter: &str, what: &str) -> Result<(), Box<dyn Error>> {
+  let mut log = OpenOptions::new().create(true).append(true).open(dir.join("cortex.log"))?;
+  writeln!(log, "Info:{}:{}", filter, what)?;
+  Ok(())
+}
+
+/// Removes files larger than a bound, e.g. intermediate PDFs or DVIs, noting each in `cortex.log`
+#[derive(Clone, Debug)]
+pub struct StripLargeFiles {
+  /// Files of more bytes are removed; `cortex.log` is always kept
+  pub max_bytes: u64,
+}
+
+impl OutputFilter for StripLargeFiles {
+  fn name(&self) -> &str { "strip_large_files" }
+
+  fn apply(&self, dir: &Path, _context: &FilterContext) -> Result<(), Box<dyn Error>> {
+    let mut stripped = Vec::new();
+    for entry in WalkDir::new(dir).min_depth(1).into_iter().filter_map(Result::ok) {
+      let relative = entry.path().strip_prefix(dir)?.to_path_buf();
+      if entry.file_type().is_file() && relative != Path::new("cortex.log") {
+        let bytes = entry.metadata()?.len();
+        if bytes > self.max_bytes {
+          fs::remove_file(entry.path())?;
+          stripped.push((relative, bytes));
+        }
+      }
+    }
+    for (relative, bytes) in stripped {
+      let what = format!("stripped {} ({} bytes)", relative.display(), bytes);
+      log_info(dir, self.name(), &what)?;
+    }
+    Ok(())
+  }
+}
+
+/// Replaces host paths in the text files of the output, e.g. the scratch directory in converter
+/// messages, so that results don't reveal the layout of the worker hosts
+#[derive(Clone, Debug)]
+pub struct AnonymizePaths {
+  /// Every path is replaced by its placeholder, in order
+  pub replacements: Vec<(PathBuf, String)>,
+}
+
+impl Default for AnonymizePaths {
+  /// Replaces the temporary directory by `$TMPDIR` and the home directory by `~`
+  fn default() -> Self {
+    let mut replacements = vec![(env::temp_dir(), String::from("$TMPDIR"))];
+    if let Some(home) = env::var_os("HOME").filter(|home| !home.is_empty()) {
+      replacements.push((PathBuf::from(home), String::from("~")));
+    }
+    AnonymizePaths { replacements }
+  }
+}
+
+impl OutputFilter for AnonymizePaths {
+  fn name(&self) -> &str { "anonymize_paths" }
+
+  fn apply(&self, dir: &Path, _context: &FilterContext) -> Result<(), Box<dyn Error>> {
+    for entry in WalkDir::new(dir).min_depth(1).into_iter().filter_map(Result::ok) {
+      if !entry.file_type().is_file() {
+        continue;
+      }
+      // binary files are left alone
+      let Ok(text) = fs::read_to_string(entry.path()) else {
+        continue;
+      };
+      let mut anonymized = text.clone();
+      for (path, placeholder) in &self.replacements {
+        let path = path.to_string_lossy();
+        let path = path.trim_end_matches(['/', '\\']);
+        if !path.is_empty() {
+          anonymized = anonymized.replace(path, placeholder);
+        }
+      }
+      if anonymized != text {
+        fs::write(entry.path(), anonymized)?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,310 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Management of workers spread over many hosts, for the `pericortex-fleet` companion binary.
+//!
+//! An [`Inventory`] lists the hosts and the workers each of them runs: the command starting a
+//! worker, its pid file and the address of its `/state` endpoint (`run --health`). Workers are
+//! started and signalled over `ssh`, see [`remote_command`] and [`run_remote`], and the states of
+//! all of them are polled at once and aggregated into a [`FleetStatus`].
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::logcodes;
+use crate::state::{Phase, WorkerState};
+
+/// The command reaching a host, given the host as its last argument, by default
+pub const DEFAULT_SSH: &str = "ssh -o BatchMode=yes -o ConnectTimeout=10";
+/// Time given to a worker to answer a poll of its state
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+/// Log categories listed for the whole fleet
+pub const FLEET_LOG_CATEGORIES: usize = 5;
+
+/// The hosts of a fleet, and their workers
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Inventory {
+  /// The command reaching a host, given the host as its last argument, [`DEFAULT_SSH`] if unset
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ssh: Option<String>,
+  /// The hosts, as `[[host]]` tables
+  #[serde(rename = "host")]
+  pub hosts: Vec<Host>,
+}
+
+/// A host of the fleet
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Host {
+  /// Name of the host, as shown in summaries
+  pub name: String,
+  /// Destination given to ssh, e.g. `cortex@gpu-01.example.org`, the name if unset
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub address: Option<String>,
+  /// The workers of the host, as `[[host.worker]]` tables
+  #[serde(rename = "worker")]
+  pub workers: Vec<FleetWorker>,
+}
+
+impl Host {
+  /// Destination given to ssh
+  pub fn destination(&self) -> &str { self.address.as_deref().unwrap_or(&self.name) }
+}
+
+/// A worker process of a host
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FleetWorker {
+  /// The service it converts
+  pub service: String,
+  /// `host:port` of its `/state` endpoint, as given to `run --health`
+  pub state: String,
+  /// The shell command starting it on its host, detached, e.g. with `run --daemon`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub command: Option<String>,
+  /// Its pid file on its host, as given to `run --pid-file`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pid_file: Option<String>,
+}
+
+impl Inventory {
+  /// The inventory in the TOML file at `path`
+  pub fn load(path: &Path) -> Result<Inventory, Box<dyn Error>> {
+    toml::from_str(&fs::read_to_string(path)?).map_err(|e| From::from(format!("{}: {}", path.display(), e)))
+  }
+
+  /// The workers on the host named `host` and of `service`, or all of them
+  pub fn select(&self, host: Option<&str>, service: Option<&str>) -> Vec<(&Host, &FleetWorker)> {
+    self
+      .hosts
+      .iter()
+      .filter(|candidate| host.is_none_or(|name| candidate.name == name))
+      .flat_map(|host| host.workers.iter().map(move |worker| (host, worker)))
+      .filter(|(_, worker)| service.is_none_or(|name| worker.service == name))
+      .collect()
+  }
+}
+
+/// What to do to a worker on its host
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RemoteAction {
+  /// Run its start command
+  Start,
+  /// Send it a signal, e.g. `TERM` to drain it and `USR2` to upgrade it in place
+  Signal(String),
+}
+
+/// The shell command performing `action` on the host of `worker`
+pub fn remote_command(worker: &FleetWorker, action: &RemoteAction) -> Result<String, Box<dyn Error>> {
+  match action {
+    RemoteAction::Start => worker
+      .command
+      .clone()
+      .ok_or_else(|| From::from(format!("no command starts the {} worker", worker.service))),
+    RemoteAction::Signal(signal) => {
+      if signal.is_empty() || !signal.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(From::from(format!("invalid signal {:?}", signal)));
+      }
+      let pid_file = worker
+        .pid_file
+        .as_deref()
+        .ok_or_else(|| format!("no pid file is known for the {} worker", worker.service))?;
+      Ok(format!("kill -{} \"$(cat '{}')\"", signal, pid_file.replace('\'', "'\\''")))
+    }
+  }
+}
+
+/// Run the shell `command` on `host`, returning what it printed
+pub fn run_remote(inventory: &Inventory, host: &Host, command: &str) -> Result<String, Box<dyn Error>> {
+  let ssh = inventory.ssh.as_deref().unwrap_or(DEFAULT_SSH);
+  let mut words = ssh.split_whitespace();
+  let program = words.next().ok_or("empty ssh command")?;
+  let output = Command::new(program)
+    .args(words)
+    .arg(host.destination())
+    .arg(command)
+    .output()?;
+  if !output.status.success() {
+    return Err(From::from(format!(
+      "{} failed with {}: {}",
+      host.name,
+      output.status,
+      String::from_utf8_lossy(&output.stderr).trim()
+    )));
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Perform `action` on all of the `workers` at once, by ssh, returning for each worker what its
+/// command printed or why it failed
+pub fn perform(
+  inventory: &Inventory,
+  workers: &[(&Host, &FleetWorker)],
+  action: &RemoteAction,
+) -> Vec<Result<String, String>> {
+  thread::scope(|scope| {
+    let runs: Vec<_> = workers
+      .iter()
+      .map(|(host, worker)| {
+        scope.spawn(move || {
+          remote_command(worker, action)
+            .and_then(|command| run_remote(inventory, host, &command))
+            .map_err(|e| e.to_string())
+        })
+      })
+      .collect();
+    runs
+      .into_iter()
+      .map(|run| run.join().unwrap_or_else(|_| Err("the command panicked".to_string())))
+      .collect()
+  })
+}
+
+/// The state of the worker serving `/state` at `address`
+pub fn poll(address: &str, timeout: Duration) -> Result<WorkerState, Box<dyn Error>> {
+  let socket_address = address
+    .to_socket_addrs()?
+    .next()
+    .ok_or_else(|| format!("{} resolves to no address", address))?;
+  let mut stream = TcpStream::connect_timeout(&socket_address, timeout)?;
+  stream.set_read_timeout(Some(timeout))?;
+  stream.set_write_timeout(Some(timeout))?;
+  // one write for the whole request, so that it isn't split over several packets
+  let request = format!("GET /state HTTP/1.0\r\nHost: {}\r\n\r\n", address);
+  stream.write_all(request.as_bytes())?;
+  let mut response = String::new();
+  stream.read_to_string(&mut response)?;
+  let (head, body) = response.split_once("\r\n\r\n").ok_or("malformed HTTP response")?;
+  let status = head.lines().next().unwrap_or_default();
+  if status.split_whitespace().nth(1) != Some("200") {
+    return Err(From::from(format!("answered {}", status)));
+  }
+  Ok(serde_json::from_str(body)?)
+}
+
+/// The state of a worker of the fleet, or why it couldn't be polled
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WorkerStatus {
+  /// Name of its host
+  pub host: String,
+  /// The service it converts
+  pub service: String,
+  /// Its state, if it answered
+  pub state: Option<WorkerState>,
+  /// Why it didn't answer
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+/// The states of the workers of a fleet
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct FleetStatus {
+  /// A status per worker, in inventory order
+  pub workers: Vec<WorkerStatus>,
+}
+
+/// Poll the `workers` all at once
+pub fn status(workers: &[(&Host, &FleetWorker)]) -> FleetStatus {
+  let polled: Vec<Result<WorkerState, String>> = thread::scope(|scope| {
+    let polls: Vec<_> = workers
+      .iter()
+      .map(|(_, worker)| scope.spawn(move || poll(&worker.state, POLL_TIMEOUT).map_err(|e| e.to_string())))
+      .collect();
+    polls
+      .into_iter()
+      .map(|poll| poll.join().unwrap_or_else(|_| Err("the poll panicked".to_string())))
+      .collect()
+  });
+  FleetStatus {
+    workers: workers
+      .iter()
+      .zip(polled)
+      .map(|((host, worker), polled)| WorkerStatus {
+        host: host.name.clone(),
+        service: worker.service.clone(),
+        error: polled.as_ref().err().cloned(),
+        state: polled.ok(),
+      })
+      .collect(),
+  }
+}
+
+impl FleetStatus {
+  /// The most frequent log categories across the fleet, by the number of results reporting them
+  pub fn log_categories(&self, n: usize) -> Vec<(String, usize)> {
+    let mut counts = BTreeMap::new();
+    for state in self.workers.iter().filter_map(|worker| worker.state.as_ref()) {
+      for (category, count) in &state.log_categories {
+        *counts.entry(category.clone()).or_insert(0) += *count as usize;
+      }
+    }
+    logcodes::top_codes(&counts, n)
+  }
+}
+
+impl fmt::Display for FleetStatus {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(
+      f,
+      "{:<20} {:<16} {:>8} {:>10} {:>10} {:>8}  top log category",
+      "host", "service", "threads", "converting", "completed", "failed"
+    )?;
+    let (mut reachable, mut converting, mut completed, mut failed) = (0, 0, 0, 0);
+    for worker in &self.workers {
+      let Some(ref state) = worker.state else {
+        writeln!(
+          f,
+          "{:<20} {:<16} unreachable: {}",
+          worker.host,
+          worker.service,
+          worker.error.as_deref().unwrap_or_default()
+        )?;
+        continue;
+      };
+      reachable += 1;
+      converting += state.count(Phase::Converting);
+      completed += state.completed;
+      failed += state.failed;
+      let alive = state.threads.iter().filter(|thread| thread.alive).count();
+      writeln!(
+        f,
+        "{:<20} {:<16} {:>8} {:>10} {:>10} {:>8}  {}",
+        worker.host,
+        worker.service,
+        format!("{}/{}", alive, state.threads.len()),
+        state.count(Phase::Converting),
+        state.completed,
+        state.failed,
+        state.log_categories.first().map(|(category, _)| category.as_str()).unwrap_or("-")
+      )?;
+    }
+    writeln!(
+      f,
+      "{} of {} workers reachable, {} threads converting, {} results completed, {} failed",
+      reachable,
+      self.workers.len(),
+      converting,
+      completed,
+      failed
+    )?;
+    for (category, count) in self.log_categories(FLEET_LOG_CATEGORIES) {
+      writeln!(f, "  {} in {} results", category, count)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,163 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Golden-file regression testing: fixture documents are converted and their results summarized
+//! (file manifest, normalized HTML, `cortex.log` severity counts), then compared against the
+//! checked-in summaries of a known-good converter release, or recorded as the new golden files.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tempdir::TempDir;
+
+use crate::adaptor;
+use crate::diff;
+use crate::local;
+use crate::logcodes::{self, Severity};
+use crate::worker::Worker;
+
+/// What is compared of a result archive
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GoldenSummary {
+  /// Names of all entries of the archive
+  pub files: Vec<String>,
+  /// The normalized tokens of every HTML entry
+  pub html: BTreeMap<String, Vec<String>>,
+  /// `cortex.log` message counts per severity
+  pub severities: BTreeMap<Severity, usize>,
+}
+
+impl GoldenSummary {
+  /// Summarize the result archive at `path`
+  pub fn of(path: &Path) -> Result<Self, Box<dyn Error>> {
+    let files: Vec<String> = adaptor::archive_manifest(path)?.into_keys().collect();
+    let mut html = BTreeMap::new();
+    for name in files.iter().filter(|name| diff::is_html(name)) {
+      let contents = adaptor::read_archive_entry(path, name)?.unwrap_or_default();
+      html.insert(name.clone(), diff::normalize_html(&String::from_utf8_lossy(&contents)));
+    }
+    let log = adaptor::read_archive_entry(path, "cortex.log")?.unwrap_or_default();
+    let severities = logcodes::severity_counts(&logcodes::parse(&String::from_utf8_lossy(&log)));
+    Ok(GoldenSummary {
+      files,
+      html,
+      severities,
+    })
+  }
+
+  /// How `actual` deviates from this golden summary, one line per difference
+  pub fn differences(&self, actual: &GoldenSummary) -> Vec<String> {
+    let mut differences = Vec::new();
+    for name in actual.files.iter().filter(|name| !self.files.contains(name)) {
+      differences.push(format!("added:   {}", name));
+    }
+    for name in self.files.iter().filter(|name| !actual.files.contains(name)) {
+      differences.push(format!("removed: {}", name));
+    }
+    let empty = Vec::new();
+    for (name, tokens) in &actual.html {
+      let changes = diff::token_changes(self.html.get(name).unwrap_or(&empty), tokens);
+      if !changes.is_empty() {
+        differences.push(format!("changed: {}", name));
+        differences.extend(changes.into_iter().map(|change| format!("    {}", change)));
+      }
+    }
+    for severity in [Severity::Info, Severity::Warning, Severity::Error, Severity::Fatal] {
+      let expected = self.severities.get(&severity).cloned().unwrap_or(0);
+      let found = actual.severities.get(&severity).cloned().unwrap_or(0);
+      if expected != found {
+        differences.push(format!("cortex.log {}: {} -> {}", severity, expected, found));
+      }
+    }
+    differences
+  }
+}
+
+/// How a fixture's result compares to its golden summary
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GoldenStatus {
+  /// The result matches the golden summary
+  Matches,
+  /// The golden summary was (re)written from the result
+  Updated,
+  /// There is no golden summary for this fixture yet
+  Missing,
+  /// The result deviates from the golden summary
+  Changed(Vec<String>),
+  /// The fixture could not be converted
+  Failed(String),
+}
+
+/// A fixture document, with the outcome of its comparison
+#[derive(Clone, Debug)]
+pub struct GoldenOutcome {
+  /// Path of the fixture, relative to the fixtures directory
+  pub fixture: PathBuf,
+  /// How its result compared
+  pub status: GoldenStatus,
+}
+
+impl GoldenOutcome {
+  /// Does this outcome hold back a release? A fixture without a golden summary does, as its
+  /// result was never reviewed
+  pub fn is_regression(&self) -> bool {
+    matches!(self.status, GoldenStatus::Missing | GoldenStatus::Changed(_) | GoldenStatus::Failed(_))
+  }
+}
+
+/// The golden summary of `fixture` (relative to the fixtures directory) for `service`
+pub fn golden_path(golden_dir: &Path, service: &str, fixture: &Path) -> PathBuf {
+  golden_dir.join(service).join(fixture).with_extension("json")
+}
+
+/// Convert every corpus ZIP under `fixtures` with `worker`, comparing the results against the
+/// golden summaries under `golden_dir`, or overwriting them with the results when `update` is set
+pub fn check<W: Worker>(
+  worker: &W,
+  fixtures: &Path,
+  golden_dir: &Path,
+  update: bool,
+) -> Result<Vec<GoldenOutcome>, Box<dyn Error>> {
+  let scratch = TempDir::new("cortex_golden")?;
+  let destination = scratch.path().join("result.zip");
+  let mut outcomes = Vec::new();
+  for entry in local::corpus_entries(fixtures) {
+    let fixture = entry.strip_prefix(fixtures).unwrap_or(&entry).to_path_buf();
+    let golden = golden_path(golden_dir, worker.get_service(), &fixture);
+    let status = match worker
+      .convert_local(&entry, &destination)
+      .and_then(|_| GoldenSummary::of(&destination))
+    {
+      Err(e) => GoldenStatus::Failed(e.to_string()),
+      Ok(actual) => {
+        let expected: Option<GoldenSummary> = if golden.exists() {
+          Some(serde_json::from_str(&fs::read_to_string(&golden)?)?)
+        } else {
+          None
+        };
+        match expected {
+          Some(ref expected) if *expected == actual => GoldenStatus::Matches,
+          _ if update => {
+            if let Some(parent) = golden.parent() {
+              fs::create_dir_all(parent)?;
+            }
+            fs::write(&golden, serde_json::to_string_pretty(&actual)? + "\n")?;
+            GoldenStatus::Updated
+          }
+          Some(expected) => GoldenStatus::Changed(expected.differences(&actual)),
+          None => GoldenStatus::Missing,
+        }
+      }
+    };
+    fs::remove_file(&destination).ok();
+    outcomes.push(GoldenOutcome { fixture, status });
+  }
+  Ok(outcomes)
+}
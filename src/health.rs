@@ -0,0 +1,158 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A tiny HTTP responder for liveness (`/healthz`) and readiness (`/readyz`) probes,
+//! e.g. when running workers as Kubernetes Deployments, and the runtime state of the worker
+//! as JSON (`/state`).
+//!
+//! `/healthz` only reports threads which died, by an error return or a panic. A thread wedged on
+//! a conversion which never returns still counts as alive, unless the watchdog is enabled
+//! (`--hang-threshold`, see the `watchdog` module), which marks the threads it finds wedged as dead.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use crate::endpoint::Endpoint;
+use crate::state;
+use crate::worker::Worker;
+
+/// Marks a worker thread as alive for as long as it is held.
+/// Dropping it without calling `finish` (an error return or a panic) marks the thread as dead.
+pub struct Liveness {
+  identity: String,
+  finished: bool,
+}
+
+/// Registers a worker thread as alive under its identity
+pub fn register(identity: &str) -> Liveness {
+  state::register(identity);
+  Liveness {
+    identity: identity.to_string(),
+    finished: false,
+  }
+}
+
+impl Liveness {
+  /// The thread completed its work as requested, stop tracking it
+  pub fn finish(mut self) { self.finished = true; }
+}
+
+impl Drop for Liveness {
+  fn drop(&mut self) {
+    if self.finished {
+      state::unregister(&self.identity);
+    } else {
+      state::mark_dead(&self.identity);
+    }
+  }
+}
+
+/// Mark the worker thread `identity` as dead, e.g. when it is wedged
+pub fn mark_dead(identity: &str) { state::mark_dead(identity); }
+
+/// Identities of the worker threads that terminated unexpectedly
+pub fn dead_threads() -> Vec<String> { state::snapshot().dead_threads() }
+
+/// Checks that a tcp endpoint accepts connections. Inproc endpoints are bound by this process,
+/// and as reachable as it is alive
+pub fn endpoint_reachable(address: &Endpoint, timeout: Duration) -> Result<(), Box<dyn Error>> {
+  if address.is_inproc() {
+    return Ok(());
+  }
+  let socket_address = address
+    .host_port()
+    .to_socket_addrs()?
+    .next()
+    .ok_or_else(|| format!("could not resolve {}", address))?;
+  TcpStream::connect_timeout(&socket_address, timeout)?;
+  Ok(())
+}
+
+/// How long a probe client may take to send its request, or to read the response
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Threads answering probes, each one connection at a time
+const RESPONDERS: usize = 4;
+
+/// Serve the probe endpoints for `worker` on `address` (e.g. `0.0.0.0:8080`), in background
+/// threads. A few connections are answered at once, so that a slow client doesn't hold back the
+/// liveness probes, while a flood of them only queues up on the listener
+pub fn serve<W: Worker + 'static>(address: &str, worker: W) -> Result<(), Box<dyn Error>> {
+  let listener = TcpListener::bind(address)?;
+  info!(target: "health", "serving /healthz, /readyz and /state on {}", address);
+  for _ in 0..RESPONDERS {
+    let listener = listener.try_clone()?;
+    let worker = worker.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        if let Err(e) = respond(stream, &worker) {
+          warn!(target: "health", "failed to answer probe: {}", e);
+        }
+      }
+    });
+  }
+  Ok(())
+}
+
+fn respond<W: Worker>(mut stream: TcpStream, worker: &W) -> Result<(), Box<dyn Error>> {
+  stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+  stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+  let mut reader = BufReader::new(&stream);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  // read the headers up to their blank line, closing with unread data would reset the connection
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+    header.clear();
+  }
+  let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+  let (code, body) = match path {
+    "/healthz" => {
+      let dead = worker.state_snapshot().dead_threads();
+      if dead.is_empty() {
+        (200, "ok".to_string())
+      } else {
+        (503, format!("dead threads: {}", dead.join(", ")))
+      }
+    }
+    "/readyz" => {
+      let readiness = endpoint_reachable(worker.get_source_address(), Duration::new(2, 0))
+        .map_err(|e| format!("dispatcher unreachable: {}", e))
+        .and_then(|_| {
+          worker
+            .readiness_probe()
+            .map_err(|e| format!("probe failed: {}", e))
+        });
+      match readiness {
+        Ok(_) => (200, "ready".to_string()),
+        Err(reason) => (503, reason),
+      }
+    }
+    "/state" => (200, serde_json::to_string(&worker.state_snapshot())?),
+    _ => (404, "not found".to_string()),
+  };
+  let content_type = if path == "/state" { "application/json" } else { "text/plain" };
+  let status = match code {
+    200 => "OK",
+    404 => "Not Found",
+    _ => "Service Unavailable",
+  };
+  write!(
+    stream,
+    "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    code,
+    status,
+    content_type,
+    body.len(),
+    body
+  )?;
+  Ok(())
+}
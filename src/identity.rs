@@ -0,0 +1,103 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Identities under which worker threads fetch tasks and send results, `hostname:service:slot`
+//! by default. Hosts sharing a hostname (cloned VMs, containers) can set a prefix or suffix,
+//! e.g. their cloud instance id, or a random tag generated once per process.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::sync::Mutex;
+
+use rand::Rng;
+
+use crate::random;
+
+/// The identity of a worker thread
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+  /// Configured text before the hostname, if any
+  pub prefix: Option<String>,
+  /// Name of the host running the worker
+  pub hostname: String,
+  /// Name of the service, as registered in CorTeX
+  pub service: String,
+  /// Number of the thread within its pool, from 1
+  pub slot: usize,
+  /// Threads in the pool, their slots are zero-padded to two digits when there are several
+  pub pool_size: usize,
+  /// Configured text after the slot, if any
+  pub suffix: Option<String>,
+  /// Random tag of this process, if enabled
+  pub uuid: Option<String>,
+}
+
+struct IdentityFormat {
+  prefix: Option<String>,
+  suffix: Option<String>,
+  uuid: Option<String>,
+}
+
+static FORMAT: Mutex<IdentityFormat> = Mutex::new(IdentityFormat {
+  prefix: None,
+  suffix: None,
+  uuid: None,
+});
+
+/// Add `prefix` and `suffix` to the identities of this process, and a random tag if `random`
+pub fn configure(prefix: Option<String>, suffix: Option<String>, random: bool) {
+  let uuid = if random {
+    Some(format!("{:08x}", random::rng().gen::<u32>()))
+  } else {
+    None
+  };
+  *FORMAT.lock().unwrap() = IdentityFormat { prefix, suffix, uuid };
+}
+
+/// The name of this host
+pub fn hostname() -> String {
+  hostname::get()
+    .unwrap_or_else(|_| OsString::from("hostname"))
+    .to_string_lossy()
+    .into_owned()
+}
+
+impl Identity {
+  /// The identity of thread `slot` in a pool of `pool_size` threads of `service` on this host
+  pub fn new(service: &str, slot: usize, pool_size: usize) -> Identity {
+    let format = FORMAT.lock().unwrap();
+    Identity {
+      prefix: format.prefix.clone(),
+      hostname: hostname(),
+      service: service.to_string(),
+      slot,
+      pool_size,
+      suffix: format.suffix.clone(),
+      uuid: format.uuid.clone(),
+    }
+  }
+}
+
+impl fmt::Display for Identity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(ref prefix) = self.prefix {
+      write!(f, "{}:", prefix)?;
+    }
+    if self.pool_size > 1 {
+      write!(f, "{}:{}:{:02}", self.hostname, self.service, self.slot)?;
+    } else {
+      write!(f, "{}:{}:{}", self.hostname, self.service, self.slot)?;
+    }
+    if let Some(ref suffix) = self.suffix {
+      write!(f, ":{}", suffix)?;
+    }
+    if let Some(ref uuid) = self.uuid {
+      write!(f, ":{}", uuid)?;
+    }
+    Ok(())
+  }
+}
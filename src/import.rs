@@ -0,0 +1,293 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Preparation of new corpora: raw documents (arXiv tarballs, gzipped or plain `.tex` files,
+//! HTML pages and bundles) are normalized into corpus entry ZIPs following the CorTeX layout,
+//! `<output>/<name>/<name>.zip` with the sources at the root of the archive, and listed in an
+//! `index.json` manifest.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use tempdir::TempDir;
+use walkdir::WalkDir;
+
+use crate::adaptor;
+
+/// File name of the manifest written next to the imported entries
+pub const INDEX_FILE: &str = "index.json";
+
+/// What a raw document looked like
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+  /// A (possibly gzipped) tar archive, e.g. an arXiv source tarball
+  Tarball,
+  /// A gzipped single file, as arXiv serves single-file submissions
+  Gzip,
+  /// A plain TeX file
+  Tex,
+  /// A single HTML page
+  Html,
+  /// A directory holding the sources of one document
+  Bundle,
+  /// An existing ZIP archive, assumed to follow the conventions already
+  Zip,
+}
+
+impl fmt::Display for SourceKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      SourceKind::Tarball => "tarball",
+      SourceKind::Gzip => "gzip",
+      SourceKind::Tex => "tex",
+      SourceKind::Html => "html",
+      SourceKind::Bundle => "bundle",
+      SourceKind::Zip => "zip",
+    })
+  }
+}
+
+/// A corpus entry created from a raw document
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ImportedEntry {
+  /// Name of the entry, the file stem of its source
+  pub name: String,
+  /// Path of the raw document, relative to the input directory
+  pub source: PathBuf,
+  /// What the raw document looked like
+  pub kind: SourceKind,
+  /// Path of the corpus entry ZIP, relative to the output directory
+  pub entry: PathBuf,
+  /// Number of files in the entry
+  pub files: usize,
+  /// Size of the entry ZIP in bytes
+  pub bytes: u64,
+}
+
+/// A raw document which couldn't be imported
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SkippedSource {
+  /// Path of the raw document, relative to the input directory
+  pub source: PathBuf,
+  /// Why it was skipped
+  pub reason: String,
+}
+
+/// The manifest of an import, written as `index.json`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ImportIndex {
+  /// The corpus entries, sorted by source path
+  pub entries: Vec<ImportedEntry>,
+  /// The raw documents which couldn't be imported
+  pub skipped: Vec<SkippedSource>,
+}
+
+impl ImportIndex {
+  /// Read the manifest at `path`
+  pub fn load(path: &Path) -> Result<ImportIndex, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+  }
+}
+
+/// The kind of the raw document at `path`, if it is one
+pub fn source_kind(path: &Path) -> Option<SourceKind> {
+  if path.is_dir() {
+    return if is_bundle(path) { Some(SourceKind::Bundle) } else { None };
+  }
+  let name = path.file_name()?.to_string_lossy().to_lowercase();
+  if name.ends_with(".tar.gz") || name.ends_with(".tgz") || name.ends_with(".tar") {
+    Some(SourceKind::Tarball)
+  } else if name.ends_with(".gz") {
+    Some(SourceKind::Gzip)
+  } else if name.ends_with(".tex") {
+    Some(SourceKind::Tex)
+  } else if name.ends_with(".html") || name.ends_with(".htm") || name.ends_with(".xhtml") {
+    Some(SourceKind::Html)
+  } else if name.ends_with(".zip") {
+    Some(SourceKind::Zip)
+  } else {
+    None
+  }
+}
+
+/// A directory with a TeX or HTML file at its top level holds the sources of a single document
+fn is_bundle(dir: &Path) -> bool {
+  fs::read_dir(dir)
+    .map(|entries| {
+      entries.filter_map(Result::ok).any(|entry| {
+        entry.path().is_file()
+          && matches!(source_kind(&entry.path()), Some(SourceKind::Tex) | Some(SourceKind::Html))
+      })
+    })
+    .unwrap_or(false)
+}
+
+/// The entry name of a raw document: its file name without the archive extensions
+pub fn entry_name(path: &Path) -> String {
+  let mut name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+  for extension in [".tar.gz", ".tgz", ".tar", ".gz", ".tex", ".html", ".htm", ".xhtml", ".zip"] {
+    if name.to_lowercase().ends_with(extension) && name.len() > extension.len() {
+      name.truncate(name.len() - extension.len());
+      break;
+    }
+  }
+  name
+}
+
+/// Only relative paths without `..` may be unpacked from an archive
+fn is_safe(path: &Path) -> bool {
+  path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Unpack a tar archive into `dir`, skipping anything but regular files at safe paths
+fn unpack_tar<R: Read>(reader: R, dir: &Path) -> Result<(), Box<dyn Error>> {
+  let mut archive = tar::Archive::new(reader);
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    let path = entry.path()?.into_owned();
+    if !entry.header().entry_type().is_file() || !is_safe(&path) {
+      continue;
+    }
+    let target = dir.join(&path);
+    if let Some(parent) = target.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    io::copy(&mut entry, &mut File::create(target)?)?;
+  }
+  Ok(())
+}
+
+/// Does `data` look like a tar archive, i.e. carry the ustar magic of its first header?
+fn is_tar(data: &[u8]) -> bool { data.len() > 262 && &data[257..262] == b"ustar" }
+
+/// Normalize the raw document at `path` of `kind` into the sources of a corpus entry in `dir`
+fn normalize(path: &Path, kind: SourceKind, name: &str, dir: &Path) -> Result<(), Box<dyn Error>> {
+  match kind {
+    SourceKind::Tarball => {
+      let file = File::open(path)?;
+      if path.to_string_lossy().to_lowercase().ends_with(".tar") {
+        unpack_tar(file, dir)
+      } else {
+        unpack_tar(GzDecoder::new(file), dir)
+      }
+    }
+    SourceKind::Gzip => {
+      // arXiv gzips single-file submissions, but also serves some tarballs as plain .gz
+      let mut data = Vec::new();
+      GzDecoder::new(File::open(path)?).read_to_end(&mut data)?;
+      if is_tar(&data) {
+        unpack_tar(data.as_slice(), dir)
+      } else {
+        fs::write(dir.join(format!("{}.tex", name)), data)?;
+        Ok(())
+      }
+    }
+    SourceKind::Tex | SourceKind::Html => {
+      let file_name = path.file_name().ok_or("source has no file name")?;
+      fs::copy(path, dir.join(file_name))?;
+      Ok(())
+    }
+    SourceKind::Bundle => {
+      for entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
+        let relative = entry.path().strip_prefix(path)?;
+        if entry.file_type().is_dir() {
+          fs::create_dir_all(dir.join(relative))?;
+        } else if entry.file_type().is_file() {
+          fs::copy(entry.path(), dir.join(relative))?;
+        }
+      }
+      Ok(())
+    }
+    SourceKind::Zip => Err(From::from("ZIP archives are copied, not normalized")),
+  }
+}
+
+/// Import the raw document at `path` as the corpus entry `<output_dir>/<name>/<name>.zip`
+/// Returns the entry name, the path of the ZIP relative to `output_dir`, and its number of files
+fn import_source(
+  path: &Path,
+  kind: SourceKind,
+  output_dir: &Path,
+) -> Result<(String, PathBuf, usize), Box<dyn Error>> {
+  let name = entry_name(path);
+  if name.is_empty() {
+    return Err(From::from("source has no usable name"));
+  }
+  let entry = PathBuf::from(&name).join(format!("{}.zip", name));
+  let target = output_dir.join(&entry);
+  if target.exists() {
+    return Err(From::from(format!("{} was already imported from another source", entry.display())));
+  }
+  fs::create_dir_all(output_dir.join(&name))?;
+  if kind == SourceKind::Zip {
+    fs::copy(path, &target)?;
+  } else {
+    let sources = TempDir::new("cortex_import")?;
+    normalize(path, kind, &name, sources.path())?;
+    let mut archive = adaptor::archive_tmpdir_to_zip(sources)?;
+    io::copy(&mut archive, &mut File::create(&target)?)?;
+  }
+  let files = adaptor::archive_manifest(&target)?.len();
+  if files == 0 {
+    fs::remove_dir_all(output_dir.join(&name))?;
+    return Err(From::from("source contains no files"));
+  }
+  Ok((name, entry, files))
+}
+
+/// Import every raw document under `input_dir` into corpus entries under `output_dir`,
+/// writing and returning the manifest of the import
+pub fn import(input_dir: &Path, output_dir: &Path) -> Result<ImportIndex, Box<dyn Error>> {
+  fs::create_dir_all(output_dir)?;
+  let mut index = ImportIndex::default();
+  let mut walker = WalkDir::new(input_dir).min_depth(1).sort_by_file_name().into_iter();
+  while let Some(next) = walker.next() {
+    let path = next?.into_path();
+    if path.starts_with(output_dir) {
+      if path.is_dir() {
+        walker.skip_current_dir();
+      }
+      continue;
+    }
+    let Some(kind) = source_kind(&path) else {
+      continue;
+    };
+    if kind == SourceKind::Bundle {
+      // the whole directory is one document
+      walker.skip_current_dir();
+    }
+    let source = path.strip_prefix(input_dir).unwrap_or(&path).to_path_buf();
+    match import_source(&path, kind, output_dir) {
+      Ok((name, entry, files)) => {
+        let bytes = fs::metadata(output_dir.join(&entry))?.len();
+        index.entries.push(ImportedEntry {
+          name,
+          source,
+          kind,
+          entry,
+          files,
+          bytes,
+        });
+      }
+      Err(e) => {
+        warn!(target: "import", "skipping {}: {}", source.display(), e);
+        index.skipped.push(SkippedSource {
+          source,
+          reason: e.to_string(),
+        });
+      }
+    }
+  }
+  fs::write(output_dir.join(INDEX_FILE), serde_json::to_string_pretty(&index)? + "\n")?;
+  Ok(index)
+}
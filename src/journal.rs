@@ -0,0 +1,455 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An append-only journal of every task a worker process handled, kept in an SQLite database.
+//!
+//! A `started` row is committed to disk before the conversion begins, so after a crash the
+//! tasks without a matching `completed` or `failed` row are exactly the ones in flight.
+//! Taskids already started in the journal are reported as redeliveries.
+//!
+//! With snapshots enabled, the `started` row also refers to a content manifest (path, hash and
+//! size of every file) of the task's input tree, and the `completed` row to one of the converter's
+//! output tree, so that two runs of the same task can be compared file by file without keeping
+//! the artifacts themselves. Manifests go into the database file by file as they are hashed, so
+//! large trees are never held in memory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::Local;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OpenFlags, Row};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
+
+use crate::provenance;
+
+/// What happened to a task
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalEvent {
+  /// The task was received and handed to the converter
+  Started,
+  /// A result was sent to the sink
+  Completed,
+  /// An empty result was sent to the sink
+  Failed,
+}
+
+/// A single row of the journal
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct JournalEntry {
+  /// The CorTeX task id
+  pub taskid: String,
+  /// Identity of the worker thread handling the task
+  pub identity: String,
+  /// What happened
+  pub event: JournalEvent,
+  /// When it happened, in RFC 3339 format
+  pub time: String,
+  /// Size of the received payload
+  pub input_bytes: usize,
+  /// Size of the result, once completed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub output_bytes: Option<u64>,
+  /// Time spent on the task, once completed or failed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub duration_ms: Option<u64>,
+  /// Why the task failed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+  /// With snapshots, the files of the input tree when started, of the output tree when completed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub manifest: Option<Vec<ManifestEntry>>,
+}
+
+/// A file of a task's input or output tree
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ManifestEntry {
+  /// The path inside the archive, empty for a payload which isn't a ZIP archive
+  pub path: String,
+  /// The SHA-256 checksum of the contents, in hex
+  pub sha256: String,
+  /// The uncompressed size
+  pub size: u64,
+}
+
+static SNAPSHOTS: AtomicBool = AtomicBool::new(false);
+
+/// Record content manifests of the input and output trees of every journaled task from now on
+pub fn enable_snapshots() { SNAPSHOTS.store(true, Ordering::SeqCst); }
+
+/// Are content manifests recorded?
+pub fn snapshots() -> bool { SNAPSHOTS.load(Ordering::SeqCst) }
+
+/// The content manifest of a ZIP archive, in path order, or of `file` itself if it isn't one.
+/// Leaves `file` rewound
+pub fn manifest<F: Read + Seek>(file: &mut F) -> io::Result<Vec<ManifestEntry>> {
+  let mut entries = Vec::new();
+  scan(file, |entry| {
+    entries.push(entry);
+    Ok::<_, io::Error>(())
+  })?;
+  entries.sort_by(|a, b| a.path.cmp(&b.path));
+  Ok(entries)
+}
+
+/// Pass every file of a ZIP archive, in archive order, or `file` itself if it isn't one, to
+/// `visit`, hashing the contents as they are read. Leaves `file` rewound
+fn scan<F, E, V>(file: &mut F, mut visit: V) -> Result<(), E>
+where
+  F: Read + Seek,
+  E: From<io::Error>,
+  V: FnMut(ManifestEntry) -> Result<(), E>,
+{
+  file.seek(SeekFrom::Start(0))?;
+  match ZipArchive::new(&mut *file) {
+    Ok(mut archive) => {
+      for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(io::Error::from)?;
+        if !entry.is_file() {
+          continue;
+        }
+        let path = entry.name().to_string();
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut entry, &mut hasher)?;
+        visit(ManifestEntry {
+          path,
+          sha256: hex::encode(hasher.finalize()),
+          size,
+        })?;
+      }
+    }
+    Err(_) => {
+      let size = file.seek(SeekFrom::End(0))?;
+      visit(ManifestEntry {
+        path: String::new(),
+        sha256: provenance::sha256(file)?,
+        size,
+      })?;
+    }
+  }
+  file.seek(SeekFrom::Start(0))?;
+  Ok(())
+}
+
+/// The differences from the `old` to the `new` manifest, one line per added (`+`), removed (`-`)
+/// or changed (`~`) file
+pub fn manifest_changes(old: &[ManifestEntry], new: &[ManifestEntry]) -> Vec<String> {
+  let old: BTreeMap<&str, &ManifestEntry> = old.iter().map(|entry| (entry.path.as_str(), entry)).collect();
+  let new: BTreeMap<&str, &ManifestEntry> = new.iter().map(|entry| (entry.path.as_str(), entry)).collect();
+  let mut changes = Vec::new();
+  for (path, entry) in &old {
+    match new.get(path) {
+      None => changes.push(format!("- {} ({} bytes)", path, entry.size)),
+      Some(changed) if changed.sha256 != entry.sha256 => {
+        changes.push(format!("~ {} ({} -> {} bytes)", path, entry.size, changed.size))
+      }
+      Some(_) => {}
+    }
+  }
+  for (path, entry) in &new {
+    if !old.contains_key(path) {
+      changes.push(format!("+ {} ({} bytes)", path, entry.size));
+    }
+  }
+  changes
+}
+
+/// The last manifests recorded for a task
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TaskSnapshot<'a> {
+  /// The files of the input tree
+  pub input: Option<&'a [ManifestEntry]>,
+  /// The files of the converter's output tree
+  pub output: Option<&'a [ManifestEntry]>,
+}
+
+/// The last snapshot recorded for every task of a journal
+pub fn snapshots_by_task(entries: &[JournalEntry]) -> HashMap<&str, TaskSnapshot<'_>> {
+  let mut snapshots: HashMap<&str, TaskSnapshot> = HashMap::new();
+  for entry in entries {
+    let Some(ref manifest) = entry.manifest else {
+      continue;
+    };
+    let snapshot = snapshots.entry(entry.taskid.as_str()).or_default();
+    match entry.event {
+      JournalEvent::Started => snapshot.input = Some(manifest),
+      JournalEvent::Completed => snapshot.output = Some(manifest),
+      JournalEvent::Failed => {}
+    }
+  }
+  snapshots
+}
+
+static JOURNAL: Mutex<Option<Connection>> = Mutex::new(None);
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS journal (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  taskid TEXT NOT NULL,
+  identity TEXT NOT NULL,
+  event TEXT NOT NULL,
+  time TEXT NOT NULL,
+  input_bytes INTEGER NOT NULL,
+  output_bytes INTEGER,
+  duration_ms INTEGER,
+  error TEXT,
+  snapshot INTEGER REFERENCES snapshots (id)
+);
+CREATE INDEX IF NOT EXISTS journal_taskid ON journal (taskid, event);
+CREATE TABLE IF NOT EXISTS snapshots (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  taskid TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS manifests (
+  snapshot INTEGER NOT NULL REFERENCES snapshots (id),
+  path TEXT NOT NULL,
+  sha256 TEXT NOT NULL,
+  size INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS manifests_snapshot ON manifests (snapshot);";
+
+/// Files of a manifest inserted at once, so that snapshotting a large tree holds the journal only
+/// for the inserts, never for the hashing
+const MANIFEST_BATCH: usize = 256;
+
+/// The content manifest of a tree, as recorded in the journal by [`snapshot`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Snapshot(i64);
+
+/// Journal every task of this process into the SQLite database at `path`, appending to earlier runs
+pub fn open(path: &Path) -> Result<(), Box<dyn Error>> {
+  let connection = Connection::open(path)?;
+  // every commit reaches the disk before the conversion it announces begins
+  connection.pragma_update(None, "journal_mode", "WAL")?;
+  connection.pragma_update(None, "synchronous", "FULL")?;
+  connection.execute_batch(SCHEMA)?;
+  *JOURNAL.lock().unwrap() = Some(connection);
+  Ok(())
+}
+
+fn event_name(event: JournalEvent) -> &'static str {
+  match event {
+    JournalEvent::Started => "started",
+    JournalEvent::Completed => "completed",
+    JournalEvent::Failed => "failed",
+  }
+}
+
+/// Record the content manifest of `file`, a task's input or output tree, into the journal a batch
+/// of files at a time, to be journaled with the task by [`started`] or [`finished`]. There is none
+/// while no journal is open. Leaves `file` rewound
+pub fn snapshot<F: Read + Seek>(taskid: &str, file: &mut F) -> Result<Option<Snapshot>, Box<dyn Error>> {
+  let snapshot = {
+    let guard = JOURNAL.lock().unwrap();
+    let Some(ref connection) = *guard else {
+      return Ok(None);
+    };
+    connection.execute("INSERT INTO snapshots (taskid) VALUES (?1)", params![taskid])?;
+    Snapshot(connection.last_insert_rowid())
+  };
+  let mut batch = Vec::with_capacity(MANIFEST_BATCH);
+  scan(file, |entry| {
+    batch.push(entry);
+    if batch.len() < MANIFEST_BATCH {
+      return Ok(());
+    }
+    insert_manifest(snapshot, &mut batch)
+  })?;
+  insert_manifest(snapshot, &mut batch)?;
+  Ok(Some(snapshot))
+}
+
+/// Insert and clear a `batch` of the files of `snapshot`, in a single transaction
+fn insert_manifest(snapshot: Snapshot, batch: &mut Vec<ManifestEntry>) -> Result<(), Box<dyn Error>> {
+  let mut guard = JOURNAL.lock().unwrap();
+  let Some(ref mut connection) = *guard else {
+    return Ok(());
+  };
+  let transaction = connection.transaction()?;
+  {
+    let mut statement =
+      transaction.prepare_cached("INSERT INTO manifests (snapshot, path, sha256, size) VALUES (?1, ?2, ?3, ?4)")?;
+    for entry in batch.drain(..) {
+      statement.execute(params![snapshot.0, entry.path, entry.sha256, entry.size as i64])?;
+    }
+  }
+  transaction.commit()?;
+  Ok(())
+}
+
+fn insert(connection: &Connection, entry: &JournalEntry, snapshot: Option<Snapshot>) -> Result<(), Box<dyn Error>> {
+  connection.execute(
+    "INSERT INTO journal (taskid, identity, event, time, input_bytes, output_bytes, duration_ms, error, snapshot)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    params![
+      entry.taskid,
+      entry.identity,
+      event_name(entry.event),
+      entry.time,
+      entry.input_bytes as i64,
+      entry.output_bytes.map(|bytes| bytes as i64),
+      entry.duration_ms.map(|duration| duration as i64),
+      entry.error,
+      snapshot.map(|snapshot| snapshot.0),
+    ],
+  )?;
+  Ok(())
+}
+
+/// Journal the start of a task, with the snapshot of its input tree if taken, returning whether
+/// the same taskid was journaled before
+pub fn started(taskid: &str, identity: &str, input_bytes: usize, snapshot: Option<Snapshot>) -> bool {
+  let guard = JOURNAL.lock().unwrap();
+  let Some(ref connection) = *guard else {
+    return false;
+  };
+  let redelivered = connection
+    .query_row(
+      "SELECT EXISTS (SELECT 1 FROM journal WHERE taskid = ?1 AND event = 'started')",
+      params![taskid],
+      |row| row.get(0),
+    )
+    .unwrap_or_else(|e| {
+      warn!(target: "journal", "failed to look up task {}: {}", taskid, e);
+      false
+    });
+  if redelivered {
+    warn!(target: "journal", "task {} was delivered again", taskid);
+  }
+  let entry = JournalEntry {
+    taskid: taskid.to_string(),
+    identity: identity.to_string(),
+    event: JournalEvent::Started,
+    time: Local::now().to_rfc3339(),
+    input_bytes,
+    output_bytes: None,
+    duration_ms: None,
+    error: None,
+    manifest: None,
+  };
+  if let Err(e) = insert(connection, &entry, snapshot) {
+    warn!(target: "journal", "failed to journal task {}: {}", taskid, e);
+  }
+  redelivered
+}
+
+/// Journal the end of a task started at `start`, with the size of its result or its error, and
+/// the snapshot of its output tree if taken. The snapshot of a failed task is left unreferenced
+pub fn finished(
+  taskid: &str,
+  identity: &str,
+  input_bytes: usize,
+  start: Instant,
+  result: Result<u64, String>,
+  snapshot: Option<Snapshot>,
+) {
+  let guard = JOURNAL.lock().unwrap();
+  let Some(ref connection) = *guard else {
+    return;
+  };
+  let (event, output_bytes, error) = match result {
+    Ok(size) => (JournalEvent::Completed, Some(size), None),
+    Err(e) => (JournalEvent::Failed, None, Some(e)),
+  };
+  let entry = JournalEntry {
+    taskid: taskid.to_string(),
+    identity: identity.to_string(),
+    event,
+    time: Local::now().to_rfc3339(),
+    input_bytes,
+    output_bytes,
+    duration_ms: Some(start.elapsed().as_millis() as u64),
+    error,
+    manifest: None,
+  };
+  let snapshot = snapshot.filter(|_| event == JournalEvent::Completed);
+  if let Err(e) = insert(connection, &entry, snapshot) {
+    warn!(target: "journal", "failed to journal task {}: {}", taskid, e);
+  }
+}
+
+fn entry_from_row(row: &Row) -> rusqlite::Result<JournalEntry> {
+  let event = match row.get_ref(2)?.as_str()? {
+    "started" => JournalEvent::Started,
+    "completed" => JournalEvent::Completed,
+    "failed" => JournalEvent::Failed,
+    other => {
+      return Err(rusqlite::Error::FromSqlConversionFailure(
+        2,
+        Type::Text,
+        format!("unknown journal event {:?}", other).into(),
+      ))
+    }
+  };
+  Ok(JournalEntry {
+    taskid: row.get(0)?,
+    identity: row.get(1)?,
+    event,
+    time: row.get(3)?,
+    input_bytes: row.get::<_, i64>(4)? as usize,
+    output_bytes: row.get::<_, Option<i64>>(5)?.map(|bytes| bytes as u64),
+    duration_ms: row.get::<_, Option<i64>>(6)?.map(|duration| duration as u64),
+    error: row.get(7)?,
+    manifest: None,
+  })
+}
+
+/// All entries of the journal at `path`, in the order they were journaled
+pub fn read(path: &Path) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+  // don't create a database where there was none
+  let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+  let mut statement = connection.prepare(
+    "SELECT taskid, identity, event, time, input_bytes, output_bytes, duration_ms, error, snapshot
+     FROM journal ORDER BY id",
+  )?;
+  let mut files =
+    connection.prepare("SELECT path, sha256, size FROM manifests WHERE snapshot = ?1 ORDER BY path")?;
+  let mut entries = Vec::new();
+  for row in statement.query_map([], |row| Ok((entry_from_row(row)?, row.get::<_, Option<i64>>(8)?)))? {
+    let (mut entry, snapshot) = row?;
+    if let Some(snapshot) = snapshot {
+      let manifest = files
+        .query_map(params![snapshot], |file| {
+          Ok(ManifestEntry {
+            path: file.get(0)?,
+            sha256: file.get(1)?,
+            size: file.get::<_, i64>(2)? as u64,
+          })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+      entry.manifest = Some(manifest);
+    }
+    entries.push(entry);
+  }
+  Ok(entries)
+}
+
+/// Tasks that were started but never completed or failed, e.g. because they crashed the process
+pub fn in_progress(entries: &[JournalEntry]) -> Vec<&JournalEntry> {
+  let mut open: Vec<&JournalEntry> = Vec::new();
+  for entry in entries {
+    match entry.event {
+      JournalEvent::Started => open.push(entry),
+      JournalEvent::Completed | JournalEvent::Failed => {
+        if let Some(index) = open
+          .iter()
+          .position(|started| started.taskid == entry.taskid && started.identity == entry.identity)
+        {
+          open.remove(index);
+        }
+      }
+    }
+  }
+  open
+}
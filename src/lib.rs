@@ -18,5 +18,80 @@
 extern crate log;
 
 pub mod adaptor;
+pub mod analysis;
+pub mod announcements;
+pub mod artifacts;
+#[cfg(feature = "async")]
+pub mod async_worker;
+pub mod bench;
+pub mod capabilities;
+pub mod check;
+pub mod clock;
+pub mod config;
+pub mod crash;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod dedup;
+pub mod diff;
+pub mod dispatcher;
+pub mod dryrun;
+pub mod encoding;
+pub mod endpoint;
+pub mod error;
+pub mod filters;
+#[cfg(feature = "tools")]
+pub mod fleet;
+#[cfg(feature = "tools")]
+pub mod golden;
+pub mod health;
+pub mod identity;
+#[cfg(feature = "tools")]
+pub mod import;
+pub mod journal;
+#[cfg(feature = "tools")]
+pub mod loadtest;
+pub mod local;
+pub mod logcodes;
 pub mod logger;
+pub mod mirror;
+pub mod mmap;
+pub mod parallel;
+pub mod pipeline;
+pub mod policy;
+pub mod pressure;
+pub mod progress;
+pub mod protocol;
+pub mod provenance;
+pub mod quarantine;
+pub mod quota;
+pub mod random;
+pub mod ratelimit;
+pub mod registration;
+pub mod replay;
+pub mod report;
+pub mod retry;
+mod runtime;
+pub mod sanity;
+pub mod schedule;
+pub mod scratch;
+pub mod secrets;
+pub mod selftest;
+pub mod shutdown;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod stages;
+pub mod state;
+#[cfg(feature = "object-storage")]
+pub mod storage;
+#[cfg(feature = "tools")]
+pub mod submit;
+pub mod sync;
+pub mod transfer;
+#[cfg(feature = "tools")]
+pub mod triage;
+pub mod tuning;
+pub mod upgrade;
+pub mod watchdog;
 pub mod worker;
@@ -0,0 +1,227 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Load generation against a CorTeX dispatcher: a fleet of lightweight simulated workers, each a
+//! thread with its own identity and sockets, answering every task with its payload after a
+//! latency drawn from a [`LatencyDistribution`]. Reports the throughput the dispatcher sustained,
+//! how long workers waited for tasks, and how fairly the tasks were spread across the fleet.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use zmq::{Context, SNDMORE};
+
+use crate::bench::percentile;
+use crate::random;
+
+/// Simulated workers sharing a ZMQ context, which allows about a thousand sockets
+const WORKERS_PER_CONTEXT: usize = 256;
+
+/// How long a simulated worker takes to answer a task
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LatencyDistribution {
+  /// Always the same, `fixed:MS`
+  Fixed(Duration),
+  /// Uniformly distributed between two bounds, `uniform:MIN_MS:MAX_MS`
+  Uniform(Duration, Duration),
+  /// Exponentially distributed around a mean, `exponential:MEAN_MS`
+  Exponential(Duration),
+}
+
+impl LatencyDistribution {
+  /// Draw a latency
+  pub fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+    match *self {
+      LatencyDistribution::Fixed(latency) => latency,
+      LatencyDistribution::Uniform(min, max) if min < max => rng.gen_range(min..=max),
+      LatencyDistribution::Uniform(min, _) => min,
+      LatencyDistribution::Exponential(mean) => mean.mul_f64(-(1.0 - rng.gen::<f64>()).ln()),
+    }
+  }
+}
+
+impl FromStr for LatencyDistribution {
+  type Err = String;
+  fn from_str(spec: &str) -> Result<LatencyDistribution, String> {
+    let invalid = || {
+      format!(
+        "invalid latency {:?}, expected fixed:MS, uniform:MIN_MS:MAX_MS or exponential:MEAN_MS",
+        spec
+      )
+    };
+    let parts: Vec<&str> = spec.split(':').collect();
+    let millis = |part: &str| part.parse().map(Duration::from_millis).map_err(|_| invalid());
+    match parts[..] {
+      ["fixed", latency] => Ok(LatencyDistribution::Fixed(millis(latency)?)),
+      ["uniform", min, max] => Ok(LatencyDistribution::Uniform(millis(min)?, millis(max)?)),
+      ["exponential", mean] => Ok(LatencyDistribution::Exponential(millis(mean)?)),
+      _ => Err(invalid()),
+    }
+  }
+}
+
+/// The settings of a load test
+#[derive(Clone, Debug)]
+pub struct LoadTest {
+  /// URL to the CorTeX dispatcher
+  pub source: String,
+  /// URL to the CorTeX sink
+  pub sink: String,
+  /// Service the simulated workers ask tasks for
+  pub service: String,
+  /// Number of simulated workers
+  pub workers: usize,
+  /// How long to keep asking for tasks
+  pub duration: Duration,
+  /// How long answering a task takes
+  pub latency: LatencyDistribution,
+}
+
+/// What a single simulated worker went through
+#[derive(Clone, Debug, Default)]
+pub struct WorkerLoad {
+  /// Tasks received and answered
+  pub completed: usize,
+  /// Time between every request and its task
+  pub waits: Vec<Duration>,
+}
+
+/// Measurements of a whole load test
+#[derive(Clone, Debug)]
+pub struct LoadTestReport {
+  /// Per-worker measurements
+  pub workers: Vec<WorkerLoad>,
+  /// Wall-clock time of the test
+  pub elapsed: Duration,
+}
+
+impl LoadTestReport {
+  /// Tasks answered by all workers
+  pub fn completed(&self) -> usize { self.workers.iter().map(|worker| worker.completed).sum() }
+
+  /// Tasks answered per second
+  pub fn throughput(&self) -> f64 { self.completed() as f64 / self.elapsed.as_secs_f64().max(0.001) }
+
+  /// Jain's fairness index of the tasks per worker: 1 when all got the same share,
+  /// down to 1/n when a single worker got them all
+  pub fn fairness(&self) -> f64 {
+    let counts = self.workers.iter().map(|worker| worker.completed as f64);
+    let sum: f64 = counts.clone().sum();
+    let squares: f64 = counts.map(|count| count * count).sum();
+    if squares == 0.0 {
+      return 1.0;
+    }
+    sum * sum / (self.workers.len() as f64 * squares)
+  }
+
+  /// The `p`-th percentile (0-100) wait for a task, across all workers
+  pub fn wait_percentile(&self, p: f64) -> Option<Duration> {
+    let mut waits: Vec<Duration> =
+      self.workers.iter().flat_map(|worker| worker.waits.iter().cloned()).collect();
+    waits.sort();
+    percentile(&waits, p)
+  }
+}
+
+impl fmt::Display for LoadTestReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let millis = |wait: Option<Duration>| {
+      wait
+        .map(|wait| wait.as_millis().to_string())
+        .unwrap_or_else(|| "-".to_string())
+    };
+    let counts = self.workers.iter().map(|worker| worker.completed);
+    writeln!(
+      f,
+      "{} simulated workers answered {} tasks in {:.1}s: {:.1} tasks/s",
+      self.workers.len(),
+      self.completed(),
+      self.elapsed.as_secs_f64(),
+      self.throughput()
+    )?;
+    writeln!(
+      f,
+      "wait for a task: p50 {} ms, p95 {} ms, max {} ms",
+      millis(self.wait_percentile(50.0)),
+      millis(self.wait_percentile(95.0)),
+      millis(self.wait_percentile(100.0))
+    )?;
+    writeln!(
+      f,
+      "tasks per worker: min {}, max {}, {} workers never served",
+      counts.clone().min().unwrap_or(0),
+      counts.clone().max().unwrap_or(0),
+      counts.filter(|count| *count == 0).count()
+    )?;
+    writeln!(f, "fairness (Jain's index, 1 is even): {:.3}", self.fairness())
+  }
+}
+
+/// Run the load test, returning once every simulated worker is done
+pub fn run(test: LoadTest) -> Result<LoadTestReport, Box<dyn Error>> {
+  let test = Arc::new(test);
+  let contexts: Vec<Context> =
+    (0..test.workers.div_ceil(WORKERS_PER_CONTEXT)).map(|_| Context::new()).collect();
+  let start = Instant::now();
+  let deadline = start + test.duration;
+  let mut threads = Vec::new();
+  for index in 0..test.workers {
+    let context = contexts[index / WORKERS_PER_CONTEXT].clone();
+    let test = Arc::clone(&test);
+    threads.push(thread::spawn(move || simulate(&context, &test, index, deadline)));
+  }
+  let mut workers = Vec::new();
+  for thread in threads {
+    let load = thread.join().map_err(|_| "a simulated worker panicked")?;
+    workers.push(load.map_err(|e| e as Box<dyn Error>)?);
+  }
+  Ok(LoadTestReport {
+    workers,
+    elapsed: start.elapsed(),
+  })
+}
+
+fn simulate(
+  context: &Context,
+  test: &LoadTest,
+  index: usize,
+  deadline: Instant,
+) -> Result<WorkerLoad, Box<dyn Error + Send + Sync>> {
+  let identity = format!("loadtest:{}:{:04}", test.service, index + 1);
+  let source = context.socket(zmq::DEALER)?;
+  source.set_identity(identity.as_bytes())?;
+  source.set_linger(0)?;
+  source.connect(&test.source)?;
+  let sink = context.socket(zmq::PUSH)?;
+  sink.set_linger(1000)?;
+  sink.connect(&test.sink)?;
+  let mut rng = random::rng();
+  let mut load = WorkerLoad::default();
+  while Instant::now() < deadline {
+    let requested = Instant::now();
+    source.send(test.service.as_str(), 0)?;
+    let remaining = deadline.saturating_duration_since(requested);
+    if source.poll(zmq::POLLIN, remaining.as_millis() as i64)? == 0 {
+      break;
+    }
+    let frames = source.recv_multipart(0)?;
+    load.waits.push(requested.elapsed());
+    let Some((taskid, payload)) = frames.split_first() else {
+      continue;
+    };
+    thread::sleep(test.latency.sample(&mut rng));
+    sink.send_multipart([identity.as_bytes(), test.service.as_bytes(), taskid], SNDMORE)?;
+    sink.send(payload.concat(), 0)?;
+    load.completed += 1;
+  }
+  Ok(load)
+}
@@ -0,0 +1,199 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Batch conversion of a local directory of corpus entry ZIPs, without a CorTeX dispatcher
+//!
+//! A [`BatchObserver`] follows a run as it goes, e.g. to display its progress over thousands of
+//! entries; the `replay` module reports to the same kind of observer.
+
+use std::error::Error;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use walkdir::WalkDir;
+
+use crate::report::{csv_field, LogSummary, Report, TaskReport};
+use crate::worker::Worker;
+
+/// The outcome of converting a single local corpus entry
+#[derive(Clone, Debug)]
+pub struct LocalOutcome {
+  /// Path of the entry, relative to the input directory
+  pub entry: PathBuf,
+  /// Size of the result in bytes, or the conversion error
+  pub result: Result<u64, String>,
+  /// Wall-clock time spent on the conversion
+  pub duration: Duration,
+  /// What the `cortex.log` of the result reports
+  pub log: LogSummary,
+}
+
+impl From<&LocalOutcome> for TaskReport {
+  fn from(outcome: &LocalOutcome) -> TaskReport {
+    TaskReport::new(&outcome.entry.to_string_lossy(), outcome.duration, &outcome.result, &outcome.log)
+  }
+}
+
+/// Follows the progress of a local batch run, task by task, from the conversion threads
+pub trait BatchObserver: Send + Sync {
+  /// The run is about to convert `total` tasks
+  fn planned(&self, _total: usize) {}
+  /// The conversion thread `identity` started converting `task`
+  fn started(&self, _identity: &str, _task: &str) {}
+  /// The conversion thread `identity` is done with `task`, which failed with `error`, if any
+  fn finished(&self, _identity: &str, _task: &str, _error: Option<&str>) {}
+}
+
+/// Observes nothing
+impl BatchObserver for () {}
+
+/// Feeds every `.zip` under `input_dir` through a worker's `convert`, using a pool of
+/// `worker.pool_size()` threads, writing results under `output_dir` at the same relative paths
+#[derive(Clone, Debug)]
+pub struct LocalRunner<W: Worker> {
+  /// The worker performing the conversions
+  pub worker: W,
+  /// Directory tree containing corpus entry ZIPs
+  pub input_dir: PathBuf,
+  /// Directory receiving the results, a `summary.csv` and a `report.csv` and `report.json`
+  pub output_dir: PathBuf,
+}
+
+impl<W: Worker + 'static> LocalRunner<W> {
+  /// All corpus entries to be converted, relative to the input directory
+  pub fn entries(&self) -> Vec<PathBuf> {
+    corpus_entries(&self.input_dir)
+      .into_iter()
+      .filter(|path| !path.starts_with(&self.output_dir))
+      .filter_map(|path| path.strip_prefix(&self.input_dir).ok().map(Path::to_path_buf))
+      .collect()
+  }
+
+  /// Convert all entries and write `summary.csv` and a [`Report`], returning the per-entry outcomes
+  pub fn run(&self) -> Result<Vec<LocalOutcome>, Box<dyn Error>> { self.run_observed(Arc::new(())) }
+
+  /// As [`run`](LocalRunner::run), reporting every conversion to `observer`
+  pub fn run_observed(&self, observer: Arc<dyn BatchObserver>) -> Result<Vec<LocalOutcome>, Box<dyn Error>> {
+    create_dir_all(&self.output_dir)?;
+    let entries = self.entries();
+    observer.planned(entries.len());
+    let queue = Arc::new(Mutex::new(entries));
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+
+    let mut threads = Vec::new();
+    for thread in 1..=self.worker.pool_size().max(1) {
+      let mut thread_self = self.clone();
+      thread_self
+        .worker
+        .set_identity(format!("local:{}:{}", self.worker.get_service(), thread));
+      let queue = Arc::clone(&queue);
+      let outcomes = Arc::clone(&outcomes);
+      let observer = Arc::clone(&observer);
+      threads.push(thread::spawn(move || loop {
+        let next = queue.lock().unwrap().pop();
+        match next {
+          Some(entry) => {
+            let identity = thread_self.worker.get_identity().to_string();
+            let task = entry.to_string_lossy().into_owned();
+            observer.started(&identity, &task);
+            let outcome = thread_self.convert_entry(entry);
+            observer.finished(&identity, &task, outcome.result.as_ref().err().map(String::as_str));
+            outcomes.lock().unwrap().push(outcome);
+          }
+          None => break,
+        }
+      }));
+    }
+    for t in threads {
+      t.join().map_err(|_| "a local conversion thread panicked")?;
+    }
+
+    let mut outcomes = Arc::try_unwrap(outcomes)
+      .map_err(|_| "conversion threads still running")?
+      .into_inner()?;
+    outcomes.sort_by(|a, b| a.entry.cmp(&b.entry));
+    write_summary(&self.output_dir.join("summary.csv"), &outcomes)?;
+    Report::new(outcomes.iter().map(TaskReport::from).collect()).write(&self.output_dir)?;
+    Ok(outcomes)
+  }
+
+  fn convert_entry(&self, entry: PathBuf) -> LocalOutcome {
+    let start = Instant::now();
+    let destination = self.output_dir.join(&entry);
+    let result = destination
+      .parent()
+      .map(create_dir_all)
+      .unwrap_or(Ok(()))
+      .map_err(Into::into)
+      .and_then(|_| {
+        self
+          .worker
+          .convert_local(&self.input_dir.join(&entry), &destination)
+      })
+      .map_err(|e| e.to_string());
+    match result {
+      Ok(size) => info!(
+        target: &format!("{}:local", self.worker.get_identity()),
+        "{}: {} bytes", entry.display(), size
+      ),
+      Err(ref e) => warn!(
+        target: &format!("{}:local", self.worker.get_identity()),
+        "{}: {}", entry.display(), e
+      ),
+    }
+    let duration = start.elapsed();
+    // results which aren't archives have no log to summarize
+    let log = match result {
+      Ok(_) => LogSummary::of(&destination).unwrap_or_default(),
+      Err(_) => LogSummary::default(),
+    };
+    LocalOutcome {
+      entry,
+      result,
+      duration,
+      log,
+    }
+  }
+}
+
+/// Every corpus entry ZIP under `dir`, sorted by path
+pub fn corpus_entries(dir: &Path) -> Vec<PathBuf> {
+  let mut entries: Vec<PathBuf> = WalkDir::new(dir)
+    .into_iter()
+    .filter_map(Result::ok)
+    .map(|entry| entry.into_path())
+    .filter(|path| path.is_file() && path.extension().map(|ext| ext == "zip").unwrap_or(false))
+    .collect();
+  entries.sort();
+  entries
+}
+
+/// Write a CSV summary with one row per converted entry
+pub fn write_summary(path: &Path, outcomes: &[LocalOutcome]) -> Result<(), Box<dyn Error>> {
+  let mut summary = File::create(path)?;
+  writeln!(summary, "entry,status,duration_ms,output_bytes,error")?;
+  for outcome in outcomes {
+    let (status, size, error) = match outcome.result {
+      Ok(size) => ("ok", size.to_string(), String::new()),
+      Err(ref e) => ("error", String::new(), e.clone()),
+    };
+    writeln!(
+      summary,
+      "{},{},{},{},{}",
+      csv_field(&outcome.entry.to_string_lossy()),
+      status,
+      outcome.duration.as_millis(),
+      size,
+      csv_field(&error)
+    )?;
+  }
+  Ok(())
+}
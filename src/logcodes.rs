@@ -0,0 +1,134 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Parsing of `cortex.log` messages, following the LaTeXML reporting syntax at
+//! http://dlmf.nist.gov/LaTeXML/manual/errorcodes/ , i.e. `Severity:category:what details`
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Severity of a log message, ordered from least to most severe
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Severity {
+  /// Informational
+  Info,
+  /// Something went wrong, but the output is usable
+  Warning,
+  /// Parts of the output are broken
+  Error,
+  /// The conversion failed
+  Fatal,
+}
+
+impl Severity {
+  /// Parse the severity prefix of a log message
+  pub fn parse(prefix: &str) -> Option<Severity> {
+    match prefix {
+      "Info" => Some(Severity::Info),
+      "Warning" | "Warn" => Some(Severity::Warning),
+      "Error" => Some(Severity::Error),
+      "Fatal" => Some(Severity::Fatal),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      Severity::Info => "Info",
+      Severity::Warning => "Warning",
+      Severity::Error => "Error",
+      Severity::Fatal => "Fatal",
+    };
+    f.write_str(name)
+  }
+}
+
+/// A single message of a `cortex.log`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogMessage {
+  /// How severe the reported issue is
+  pub severity: Severity,
+  /// The broad category, e.g. `undefined`
+  pub category: String,
+  /// The specific issue, e.g. `\foo`
+  pub what: String,
+  /// Free-form details following the code
+  pub details: String,
+}
+
+impl LogMessage {
+  /// The `Severity:category:what` code of the message
+  pub fn code(&self) -> String { format!("{}:{}:{}", self.severity, self.category, self.what) }
+
+  /// The `Severity:category` of the message
+  pub fn category_code(&self) -> String { format!("{}:{}", self.severity, self.category) }
+}
+
+/// Parse a single log line, if it carries a message code
+pub fn parse_line(line: &str) -> Option<LogMessage> {
+  let mut parts = line.splitn(3, ':');
+  let severity = Severity::parse(parts.next()?.trim())?;
+  let category = parts.next()?.trim().to_string();
+  let rest = parts.next().unwrap_or("").trim();
+  let (what, details) = match rest.split_once(char::is_whitespace) {
+    Some((what, details)) => (what.to_string(), details.trim().to_string()),
+    None => (rest.to_string(), String::new()),
+  };
+  Some(LogMessage {
+    severity,
+    category,
+    what,
+    details,
+  })
+}
+
+/// All coded messages of a log
+pub fn parse(log: &str) -> Vec<LogMessage> { log.lines().filter_map(parse_line).collect() }
+
+/// Number of messages of each severity
+pub fn severity_counts(messages: &[LogMessage]) -> BTreeMap<Severity, usize> {
+  let mut counts = BTreeMap::new();
+  for message in messages {
+    *counts.entry(message.severity).or_insert(0) += 1;
+  }
+  counts
+}
+
+/// The most severe message level of a log, if any message was found
+pub fn max_severity(messages: &[LogMessage]) -> Option<Severity> {
+  messages.iter().map(|message| message.severity).max()
+}
+
+/// Number of messages per code, counting those of at least `min_severity`
+pub fn code_counts(messages: &[LogMessage], min_severity: Severity) -> BTreeMap<String, usize> {
+  let mut counts = BTreeMap::new();
+  for message in messages.iter().filter(|message| message.severity >= min_severity) {
+    *counts.entry(message.code()).or_insert(0) += 1;
+  }
+  counts
+}
+
+/// The distinct `Severity:category` of the messages of at least `min_severity`
+pub fn categories(messages: &[LogMessage], min_severity: Severity) -> BTreeSet<String> {
+  messages
+    .iter()
+    .filter(|message| message.severity >= min_severity)
+    .map(LogMessage::category_code)
+    .collect()
+}
+
+/// The `n` most frequent entries of `counts`, most frequent first
+pub fn top_codes(counts: &BTreeMap<String, usize>, n: usize) -> Vec<(String, usize)> {
+  let mut top: Vec<(String, usize)> = counts.iter().map(|(code, count)| (code.clone(), *count)).collect();
+  top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+  top.truncate(n);
+  top
+}
@@ -6,9 +6,19 @@ use ansi_term::Style;
 use chrono::Local;
 use log::max_level;
 use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
 
 struct RtxLogger;
 static LOGGER: RtxLogger = RtxLogger;
+/// When set, log lines are appended to this file (uncolored) instead of STDERR
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+/// Prints the log lines meant for STDERR instead, e.g. above a progress display
+pub type Printer = Box<dyn Fn(&str) + Send>;
+static PRINTER: Mutex<Option<Printer>> = Mutex::new(None);
 
 /// Convenient printing to STDERR (with \n)
 #[macro_export]
@@ -62,6 +72,14 @@ impl log::Log for RtxLogger {
       // };
 
       let message = format!("{}\t", category_object);
+      let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
+
+      if let Ok(mut log_file) = LOG_FILE.lock() {
+        if let Some(ref mut file) = *log_file {
+          writeln!(file, "[{}] {}{}", timestamp, message, details).ok();
+          return;
+        }
+      }
 
       let painted_message = match record.level() {
         Level::Info => Green.paint(message),
@@ -73,17 +91,29 @@ impl log::Log for RtxLogger {
       .to_string()
         + &details.to_string();
 
-      println_stderr!(
-        "\r[{}] {}",
-        Local::now().format("%Y-%m-%d %H:%M:%S"),
-        painted_message
-      );
+      if let Ok(printer) = PRINTER.lock() {
+        if let Some(ref print) = *printer {
+          print(&format!("[{}] {}", timestamp, painted_message));
+          return;
+        }
+      }
+      println_stderr!("\r[{}] {}", timestamp, painted_message);
     }
   }
 
   fn flush(&self) {}
 }
 
+/// Append all further log lines to the file at `path`, instead of STDERR
+pub fn log_to_file(path: &Path) -> io::Result<()> {
+  let file = OpenOptions::new().create(true).append(true).open(path)?;
+  *LOG_FILE.lock().unwrap() = Some(file);
+  Ok(())
+}
+
+/// Hand the log lines meant for STDERR to `print` from now on, or print them again with `None`
+pub fn print_with(print: Option<Printer>) { *PRINTER.lock().unwrap() = print; }
+
 /// Initialize the logger with an appropriate level of verbosity
 pub fn init(level: LevelFilter) -> Result<(), SetLoggerError> {
   log::set_logger(&LOGGER).unwrap();
@@ -0,0 +1,233 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Mirror sinks, receiving a copy of every result sent to the sink, e.g. a mirror CorTeX instance
+//! or an archival consumer.
+//!
+//! Every mirror is served by a thread of its own, with its own socket and a bounded queue of the
+//! copies [`forward`]ed by the IO threads. A slow or unreachable mirror only ever fills its own
+//! queue: the copies arriving while it is full, or which the mirror doesn't accept within
+//! [`MIRROR_SEND_TIMEOUT`], are dropped and counted, so that the sink, and thus the fetching of
+//! tasks, is never held back by a mirror.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::artifacts::Artifact;
+use crate::endpoint;
+use crate::pressure;
+use crate::protocol::{ResultStatus, TaskStats};
+use crate::worker::Worker;
+
+/// Results queued for a mirror, beyond which copies are dropped
+pub const MIRROR_QUEUE: usize = 16;
+/// Time a mirror is given to accept a result before its copy is dropped
+pub const MIRROR_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time the results still queued in a socket are given to reach their mirror at exit
+const MIRROR_LINGER: Duration = Duration::from_secs(10);
+
+/// A copy of a result on its way to a mirror
+struct Mirrored {
+  identity: String,
+  taskid: String,
+  result: Result<File, String>,
+  status: Option<ResultStatus>,
+  stats: Option<TaskStats>,
+  artifacts: Vec<Artifact>,
+  input_size: usize,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+  sent: AtomicU64,
+  dropped: AtomicU64,
+}
+
+impl Counts {
+  fn drop_copy(&self, address: &str, taskid: &str, reason: &str) {
+    let dropped = self.dropped.fetch_add(1, Ordering::SeqCst) + 1;
+    // a mirror which is down drops every copy, a line per hundred is plenty
+    if dropped == 1 || dropped.is_multiple_of(100) {
+      warn!(
+        target: "mirror",
+        "{}: dropped the result of task {}, {} dropped so far: {}", address, taskid, dropped, reason
+      );
+    }
+  }
+}
+
+struct Mirror {
+  address: String,
+  queue: SyncSender<Mirrored>,
+  counts: Arc<Counts>,
+  thread: JoinHandle<()>,
+}
+
+static ADDRESSES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static MIRRORS: Mutex<Vec<Mirror>> = Mutex::new(Vec::new());
+/// Set once the run is over, from when mirrors aren't waited for anymore
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Send a copy of every result to the sinks at `addresses` as well
+pub fn enable(addresses: &[String]) { *ADDRESSES.lock().unwrap() = addresses.to_vec(); }
+
+/// The addresses of the mirror sinks
+pub fn addresses() -> Vec<String> { ADDRESSES.lock().unwrap().clone() }
+
+/// Start a thread per mirror sink, sending the copies of the results of `worker`
+pub fn spawn<W: Worker + 'static>(worker: &W) {
+  let mut mirrors = MIRRORS.lock().unwrap();
+  DRAINING.store(false, Ordering::SeqCst);
+  for address in addresses() {
+    let (queue, copies) = mpsc::sync_channel(MIRROR_QUEUE);
+    let counts = Arc::new(Counts::default());
+    let thread = {
+      let (worker, address, counts) = (worker.clone(), address.clone(), Arc::clone(&counts));
+      thread::spawn(move || serve(worker, &address, copies, &counts))
+    };
+    mirrors.push(Mirror {
+      address,
+      queue,
+      counts,
+      thread,
+    });
+  }
+}
+
+/// Queue a copy of the result of task `taskid`, sent by the thread `identity`, for every mirror,
+/// leaving `result` and the `artifacts` rewound for the sink
+pub fn forward(
+  identity: &str,
+  taskid: &str,
+  result: &mut Result<File, String>,
+  status: Option<&ResultStatus>,
+  stats: Option<&TaskStats>,
+  artifacts: &mut [Artifact],
+  input_size: usize,
+) {
+  let mirrors = MIRRORS.lock().unwrap();
+  for mirror in mirrors.iter() {
+    let copied = match result {
+      Ok(file) => copy_file(file).map(Ok),
+      Err(e) => Ok(Err(e.clone())),
+    };
+    let copied_artifacts = artifacts
+      .iter_mut()
+      .map(|artifact| {
+        copy_file(&mut artifact.file).map(|file| Artifact {
+          name: artifact.name.clone(),
+          file,
+        })
+      })
+      .collect::<io::Result<Vec<Artifact>>>();
+    let (result, artifacts) = match (copied, copied_artifacts) {
+      (Ok(result), Ok(artifacts)) => (result, artifacts),
+      (Err(e), _) | (_, Err(e)) => {
+        mirror.counts.drop_copy(&mirror.address, taskid, &format!("failed to copy it: {}", e));
+        continue;
+      }
+    };
+    let copy = Mirrored {
+      identity: identity.to_string(),
+      taskid: taskid.to_string(),
+      result,
+      status: status.cloned(),
+      stats: stats.cloned(),
+      artifacts,
+      input_size,
+    };
+    match mirror.queue.try_send(copy) {
+      Ok(()) => {}
+      Err(TrySendError::Full(_)) => mirror.counts.drop_copy(&mirror.address, taskid, "its queue is full"),
+      Err(TrySendError::Disconnected(_)) => mirror.counts.drop_copy(&mirror.address, taskid, "it is gone"),
+    }
+  }
+}
+
+/// Send the copies still queued to the mirrors accepting them right away, then stop the mirror
+/// threads, logging what each mirror received
+pub fn finish() {
+  let mirrors: Vec<Mirror> = MIRRORS.lock().unwrap().drain(..).collect();
+  DRAINING.store(true, Ordering::SeqCst);
+  for mirror in mirrors {
+    drop(mirror.queue);
+    if mirror.thread.join().is_err() {
+      warn!(target: "mirror", "{}: the mirror thread panicked.", mirror.address);
+    }
+    info!(
+      target: "mirror",
+      "{}: sent {} results, dropped {}.",
+      mirror.address,
+      mirror.counts.sent.load(Ordering::SeqCst),
+      mirror.counts.dropped.load(Ordering::SeqCst)
+    );
+  }
+}
+
+fn copy_file(file: &mut File) -> io::Result<File> {
+  let mut copy = tempfile::tempfile()?;
+  file.seek(SeekFrom::Start(0))?;
+  io::copy(file, &mut copy)?;
+  file.seek(SeekFrom::Start(0))?;
+  copy.seek(SeekFrom::Start(0))?;
+  Ok(copy)
+}
+
+/// Mirror thread: send the copies queued for the mirror at `address`
+fn serve<W: Worker>(mut worker: W, address: &str, copies: Receiver<Mirrored>, counts: &Counts) {
+  let context = endpoint::context(address);
+  let sink = context.socket(zmq::PUSH).unwrap();
+  sink.set_sndhwm(pressure::sink_high_water_mark(&worker)).unwrap();
+  // queue results only while connected, so that those for a mirror which is down are dropped
+  sink.set_immediate(true).unwrap();
+  sink.set_linger(MIRROR_LINGER.as_millis() as i32).unwrap();
+  sink.set_ipv6(true).unwrap();
+  if let Err(e) = sink.connect(address) {
+    warn!(target: "mirror", "{}: failed to connect: {}", address, e);
+    for copy in copies {
+      counts.drop_copy(address, &copy.taskid, "not connected");
+    }
+    return;
+  }
+  for copy in copies {
+    if !accepting(&sink) {
+      counts.drop_copy(address, &copy.taskid, "the mirror isn't accepting results");
+      continue;
+    }
+    worker.set_identity(copy.identity);
+    let result: Result<File, Box<dyn Error>> = copy.result.map_err(From::from);
+    worker.respond_to_cortex(
+      result,
+      copy.status.as_ref(),
+      copy.stats.as_ref(),
+      copy.artifacts,
+      copy.input_size,
+      &copy.taskid,
+      &sink,
+    );
+    counts.sent.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+/// Whether the mirror accepts a result within [`MIRROR_SEND_TIMEOUT`], or right away once draining
+fn accepting(sink: &zmq::Socket) -> bool {
+  let waiting = Instant::now();
+  loop {
+    if sink.poll(zmq::POLLOUT, 1000).unwrap_or(0) > 0 {
+      return true;
+    }
+    if DRAINING.load(Ordering::SeqCst) || waiting.elapsed() >= MIRROR_SEND_TIMEOUT {
+      return false;
+    }
+  }
+}
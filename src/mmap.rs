@@ -0,0 +1,98 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Read-only memory maps of result archives, so that very large results are framed
+//! straight from the page cache instead of being read through an intermediate buffer. Each
+//! frame is still copied once, into the ZeroMQ message carrying it.
+//!
+//! Only files without any link on the filesystem, such as the anonymous temporary files results
+//! are written to, are mapped: nobody else can truncate them while they are mapped, which would
+//! fault the sending thread with a `SIGBUS`. Other results are sent through the buffered path.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Results of at least this many bytes are sent from a memory map, by default
+pub const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+static THRESHOLD: AtomicU64 = AtomicU64::new(MMAP_THRESHOLD);
+
+/// Send results of at least `bytes` bytes from a memory map from now on
+pub fn set_threshold(bytes: u64) { THRESHOLD.store(bytes, Ordering::SeqCst); }
+
+/// The size from which results are sent from a memory map
+pub fn threshold() -> u64 { THRESHOLD.load(Ordering::SeqCst) }
+
+/// A read-only, private memory map of an entire file
+pub struct MappedFile {
+  ptr: *const u8,
+  len: usize,
+}
+
+impl MappedFile {
+  /// Map all of `file`. Fails for empty files, files linked on the filesystem, and on platforms
+  /// or filesystems without mmap, in which case callers fall back to buffered reads
+  #[cfg(unix)]
+  pub fn map(file: &File) -> io::Result<MappedFile> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+    let metadata = file.metadata()?;
+    let len = metadata.len() as usize;
+    if len == 0 {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot map an empty file"));
+    }
+    if metadata.nlink() > 0 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "cannot map a linked file, which may be truncated while mapped",
+      ));
+    }
+    let ptr = unsafe {
+      libc::mmap(
+        std::ptr::null_mut(),
+        len,
+        libc::PROT_READ,
+        libc::MAP_PRIVATE,
+        file.as_raw_fd(),
+        0,
+      )
+    };
+    if ptr == libc::MAP_FAILED {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(MappedFile {
+      ptr: ptr as *const u8,
+      len,
+    })
+  }
+
+  /// Map all of `file`. Fails for empty files, files linked on the filesystem, and on platforms
+  /// or filesystems without mmap, in which case callers fall back to buffered reads
+  #[cfg(not(unix))]
+  pub fn map(_file: &File) -> io::Result<MappedFile> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "memory maps are only supported on unix platforms",
+    ))
+  }
+}
+
+impl Deref for MappedFile {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] { unsafe { std::slice::from_raw_parts(self.ptr, self.len) } }
+}
+
+impl Drop for MappedFile {
+  fn drop(&mut self) {
+    #[cfg(unix)]
+    unsafe {
+      libc::munmap(self.ptr as *mut libc::c_void, self.len);
+    }
+  }
+}
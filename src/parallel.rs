@@ -0,0 +1,68 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Intra-task parallelism (per-file validation, per-image conversion, ...) on a single
+//! process-wide thread pool, so that the threads of a worker pool don't each spawn
+//! their own and oversubscribe the host
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use walkdir::WalkDir;
+
+static POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// Size the shared pool to `threads` threads. Only possible before its first use,
+/// by default it has one thread per CPU
+pub fn set_threads(threads: usize) -> Result<(), Box<dyn Error>> {
+  let pool = ThreadPoolBuilder::new()
+    .num_threads(threads.max(1))
+    .thread_name(|index| format!("pericortex-parallel-{}", index))
+    .build()?;
+  POOL
+    .set(pool)
+    .map_err(|_| From::from("the parallel thread pool is already running"))
+}
+
+fn pool() -> &'static ThreadPool {
+  POOL.get_or_init(|| {
+    ThreadPoolBuilder::new()
+      .num_threads(num_cpus::get())
+      .thread_name(|index| format!("pericortex-parallel-{}", index))
+      .build()
+      .expect("failed to start the parallel thread pool")
+  })
+}
+
+/// Run `convert` on every file under `dir` on the shared pool,
+/// returning the outcomes sorted by path
+pub fn par_convert_files<T, E, F>(dir: &Path, convert: F) -> Vec<(PathBuf, Result<T, E>)>
+where
+  T: Send,
+  E: Send,
+  F: Fn(&Path) -> Result<T, E> + Sync,
+{
+  let mut files: Vec<PathBuf> = WalkDir::new(dir)
+    .into_iter()
+    .filter_map(Result::ok)
+    .filter(|entry| entry.file_type().is_file())
+    .map(|entry| entry.into_path())
+    .collect();
+  files.sort();
+  pool().install(|| {
+    files
+      .into_par_iter()
+      .map(|path| {
+        let outcome = convert(&path);
+        (path, outcome)
+      })
+      .collect()
+  })
+}
@@ -0,0 +1,179 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pipelines of workers on one host (normalize → convert → validate), handing their
+//! intermediate results to each other in memory rather than through TCP and the dispatcher.
+//!
+//! A [`Handoff`] is a mini-dispatcher between two stages: the upstream workers use its
+//! [`sink`](Handoff::sink) endpoint as their sink, and the downstream workers its
+//! [`source`](Handoff::source) endpoint as their source, both `inproc://` endpoints. Every result
+//! of the upstream stage becomes a task of the downstream stage, under the same taskid. The first
+//! stage fetches from the dispatcher and the last one answers its sink as usual, so stages too
+//! cheap to schedule globally never cost a round trip.
+//!
+//! Results are read from the upstream stage only as fast as the downstream one asks for tasks,
+//! but for a couple read ahead, so that a slow downstream stage holds back the upstream one
+//! through its sink rather than piling up results in memory.
+//!
+//! A result which leaves nothing to convert (a Fatal or empty one, or one with errors under
+//! [`EarlyExit::OnError`]) ends the pipeline early: it is sent to the
+//! [`bypass`](Handoff::bypass) sink as the answer to its task, or dropped without one.
+//!
+//! Stages run in one process, so they need distinct service names to get distinct thread
+//! identities.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::thread::{self, JoinHandle};
+
+use zmq::Socket;
+
+use crate::dispatcher::{self, SinkMessage, TaskResult};
+use crate::endpoint::{self, Endpoint};
+use crate::protocol::{ArtifactHeader, Outcome};
+use crate::shutdown;
+use crate::stages::EarlyExit;
+
+/// Results read ahead of the downstream requests, so that an idle downstream thread rarely waits
+const PENDING_SLACK: usize = 2;
+
+/// An in-process mini-dispatcher, turning the results of one stage into tasks of the next
+pub struct Handoff {
+  name: String,
+  results: Socket,
+  tasks: Socket,
+  bypass: Option<Socket>,
+  early_exit: EarlyExit,
+}
+
+impl Handoff {
+  /// Bind the handoff called `name`, unique within the process
+  pub fn bind(name: &str) -> Result<Handoff, Box<dyn Error>> {
+    let sink = Endpoint::inproc(&format!("{}/results", name)).to_string();
+    let source = Endpoint::inproc(&format!("{}/tasks", name)).to_string();
+    let results = endpoint::context(&sink).socket(zmq::PULL)?;
+    results.bind(&sink)?;
+    let tasks = endpoint::context(&source).socket(zmq::ROUTER)?;
+    tasks.bind(&source)?;
+    Ok(Handoff {
+      name: name.to_string(),
+      results,
+      tasks,
+      bypass: None,
+      early_exit: EarlyExit::default(),
+    })
+  }
+
+  /// The endpoint the upstream stage sends its results to
+  pub fn sink(&self) -> Endpoint { Endpoint::inproc(&format!("{}/results", self.name)) }
+
+  /// The endpoint the downstream stage fetches its tasks from
+  pub fn source(&self) -> Endpoint { Endpoint::inproc(&format!("{}/tasks", self.name)) }
+
+  /// Answer the tasks ending the pipeline early at `sink`, usually that of the last stage, where
+  /// `early_exit` tells whether results with errors end it too
+  pub fn bypass(mut self, sink: &Endpoint, early_exit: EarlyExit) -> Result<Handoff, Box<dyn Error>> {
+    let address = sink.to_string();
+    let socket = endpoint::context(&address).socket(zmq::PUSH)?;
+    socket.set_ipv6(true)?;
+    socket.connect(&address)?;
+    self.bypass = Some(socket);
+    self.early_exit = early_exit;
+    Ok(self)
+  }
+
+  /// Hand over results in a background thread, until the process shuts down
+  pub fn spawn(self) -> JoinHandle<()> { thread::spawn(move || self.run()) }
+
+  fn run(self) {
+    let target = format!("pipeline:{}", self.name);
+    let mut pending: VecDeque<TaskResult> = VecDeque::new();
+    let mut idle: VecDeque<Vec<u8>> = VecDeque::new();
+    loop {
+      // results beyond what the downstream stage asked for wait upstream
+      let accepting = pending.len() < idle.len() + PENDING_SLACK;
+      let mut items = [
+        self.results.as_poll_item(if accepting { zmq::POLLIN } else { zmq::PollEvents::empty() }),
+        self.tasks.as_poll_item(zmq::POLLIN),
+      ];
+      match zmq::poll(&mut items, 1000) {
+        Ok(_) | Err(zmq::Error::EINTR) => {}
+        Err(e) => {
+          error!(target: &target, "stopped handing over results: {}", e);
+          break;
+        }
+      }
+      if accepting && items[0].is_readable() {
+        let received = self.results.recv_multipart(0);
+        match received.map_err(From::from).and_then(dispatcher::parse_message) {
+          Ok(SinkMessage::Result(result)) if self.ends_pipeline(&result) => self.end_early(&target, result),
+          Ok(SinkMessage::Result(result)) => pending.push_back(result),
+          Ok(SinkMessage::Keepalive { .. }) => {}
+          Err(e) => warn!(target: &target, "dropped a malformed result: {}", e),
+        }
+      }
+      if items[1].is_readable() {
+        // the downstream thread's identity, followed by its task request
+        if let Some(identity) = self.tasks.recv_multipart(0).ok().and_then(|frames| frames.into_iter().next()) {
+          idle.push_back(identity);
+        }
+      }
+      while !idle.is_empty() && !pending.is_empty() {
+        let (identity, result) = (idle.pop_front().unwrap(), pending.pop_front().unwrap());
+        debug!(target: &target, "handing task {} from {} to the next stage", result.taskid, result.service);
+        let frames: [&[u8]; 3] = [&identity, result.taskid.as_bytes(), &result.payload];
+        if let Err(e) = self.tasks.send_multipart(frames, 0) {
+          warn!(target: &target, "failed to hand over task {}: {}", result.taskid, e);
+        }
+      }
+      if shutdown::requested() {
+        if !pending.is_empty() {
+          warn!(target: &target, "exiting with {} tasks not handed over.", pending.len());
+        }
+        break;
+      }
+    }
+  }
+
+  fn ends_pipeline(&self, result: &TaskResult) -> bool {
+    match result.outcome() {
+      Outcome::Fatal | Outcome::Empty => true,
+      Outcome::Error => self.early_exit == EarlyExit::OnError,
+      Outcome::Ok | Outcome::Warning => result.payload.is_empty(),
+    }
+  }
+
+  fn end_early(&self, target: &str, result: TaskResult) {
+    let Some(ref bypass) = self.bypass else {
+      warn!(target: target, "task {} ended the pipeline at {}, dropping it.", result.taskid, result.service);
+      return;
+    };
+    let mut frames = vec![
+      result.identity.into_bytes(),
+      result.service.into_bytes(),
+      result.taskid.clone().into_bytes(),
+    ];
+    if let Some(status) = result.status {
+      frames.push(status.frame().into_bytes());
+    }
+    if let Some(stats) = result.stats {
+      frames.push(stats.frame().into_bytes());
+    }
+    for (name, contents) in result.artifacts {
+      let header = ArtifactHeader {
+        size: contents.len() as u64,
+        name,
+      };
+      frames.push(header.frame().into_bytes());
+      frames.push(contents);
+    }
+    frames.push(result.payload);
+    if let Err(e) = bypass.send_multipart(frames, 0) {
+      warn!(target: target, "failed to answer task {}: {}", result.taskid, e);
+    }
+  }
+}
@@ -0,0 +1,358 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Policies applied around the conversions: retrying transient failures with backoff, answering
+//! empty inputs, attaching the input to failure reports, holding results to a maximal size,
+//! throttling a converter after a failure, and the limits (pauses, quiet hours, quotas and rate
+//! limits) gating the next task.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tempdir::TempDir;
+use tempfile::SpooledTempFile;
+use zip::ZipArchive;
+
+use crate::adaptor;
+use crate::announcements;
+use crate::artifacts::{self, Artifact};
+use crate::clock;
+use crate::quota;
+use crate::ratelimit;
+use crate::schedule;
+use crate::shutdown;
+use crate::watchdog;
+use crate::worker::Worker;
+
+/// The result of a task, with what goes along with it to the sink
+#[derive(Debug)]
+pub struct Conversion {
+  /// The result archive, or why there is none
+  pub result: Result<File, Box<dyn Error>>,
+  /// How many times the conversion was retried
+  pub retries: usize,
+  /// The secondary artifacts attached by the converter, see the `artifacts` module
+  pub artifacts: Vec<Artifact>,
+}
+
+impl Conversion {
+  /// A `result` the converter had no part in, e.g. a report on a malformed task
+  pub fn unconverted(result: Result<File, Box<dyn Error>>) -> Self {
+    Conversion {
+      result,
+      retries: 0,
+      artifacts: Vec::new(),
+    }
+  }
+}
+
+/// Run `convert_payload`, retrying transient failures as the worker's `retry_policy` allows
+pub fn convert_with_retries<W: Worker>(
+  worker: &W,
+  payload: &mut SpooledTempFile,
+  taskid: &str,
+  scratch: &TempDir,
+) -> Result<File, Box<dyn Error>> {
+  convert_counting_retries(worker, payload, taskid, scratch).result
+}
+
+/// As [`convert_with_retries`], also returning how many times the conversion was retried and the
+/// artifacts attached by its last attempt
+pub fn convert_counting_retries<W: Worker>(
+  worker: &W,
+  payload: &mut SpooledTempFile,
+  taskid: &str,
+  scratch: &TempDir,
+) -> Conversion {
+  let policy = worker.retry_policy();
+  let mut attempt = 1;
+  loop {
+    if let Err(e) = payload.seek(SeekFrom::Start(0)) {
+      return Conversion {
+        result: Err(e.into()),
+        retries: attempt - 1,
+        artifacts: Vec::new(),
+      };
+    }
+    // only the artifacts of the last attempt go with its result
+    artifacts::collect();
+    match worker.convert_payload(payload, taskid, scratch) {
+      Err(e) if attempt < policy.attempts && policy.is_retryable(&*e) && !shutdown::requested() => {
+        let delay = policy.delay(attempt);
+        warn!(
+          target: &format!("{}:retry", worker.get_identity()),
+          "task {}, attempt {} of {} failed: {}. Retrying in {} seconds.",
+          taskid,
+          attempt,
+          policy.attempts,
+          e,
+          delay.as_secs()
+        );
+        watchdog::progress("backing off");
+        clock::sleep(delay);
+        watchdog::progress("converting");
+        attempt += 1;
+      }
+      result => {
+        return Conversion {
+          result,
+          retries: attempt - 1,
+          artifacts: artifacts::collect(),
+        }
+      }
+    }
+  }
+}
+
+/// What to send for a task whose input was empty
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyInput {
+  /// A result whose `cortex.log` reports the empty input as `Fatal:empty_input:empty`, `report`
+  #[default]
+  Report,
+  /// An empty result, as for a failed conversion, `skip`
+  Skip,
+}
+
+impl FromStr for EmptyInput {
+  type Err = String;
+  fn from_str(action: &str) -> Result<EmptyInput, String> {
+    match action {
+      "report" => Ok(EmptyInput::Report),
+      "skip" => Ok(EmptyInput::Skip),
+      _ => Err(format!("invalid empty input action {:?}, expected report or skip", action)),
+    }
+  }
+}
+
+static EMPTY_INPUT_SKIPPED: AtomicBool = AtomicBool::new(false);
+static EMPTY_INPUT_THROTTLE: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Answer empty inputs with `action` from now on, pausing for `throttle` after each, or for the
+/// worker's throttle if `None`
+pub fn set_empty_input(action: EmptyInput, throttle: Option<Duration>) {
+  EMPTY_INPUT_SKIPPED.store(action == EmptyInput::Skip, Ordering::SeqCst);
+  *EMPTY_INPUT_THROTTLE.lock().unwrap() = throttle;
+}
+
+/// What to send for a task whose input was empty
+pub fn empty_input() -> EmptyInput {
+  if EMPTY_INPUT_SKIPPED.load(Ordering::SeqCst) {
+    EmptyInput::Skip
+  } else {
+    EmptyInput::Report
+  }
+}
+
+/// Which files of a failed task's input are attached to its failure report, so that triage on
+/// the dispatcher's side can reproduce the failure without fetching the corpus entry again
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputAttachment {
+  /// Extensions of the attached files, e.g. `tex`, or all files if empty
+  pub extensions: Vec<String>,
+  /// Bytes of input attached at most, the files beyond are only listed in the report
+  pub max_bytes: Option<u64>,
+}
+
+impl InputAttachment {
+  /// Is the input file `name` one to attach?
+  pub fn includes(&self, name: &str) -> bool {
+    self.extensions.is_empty()
+      || Path::new(name)
+        .extension()
+        .is_some_and(|extension| self.extensions.iter().any(|wanted| extension.eq_ignore_ascii_case(wanted.trim_start_matches('.'))))
+  }
+}
+
+static ATTACH_INPUT: Mutex<Option<InputAttachment>> = Mutex::new(None);
+
+/// Answer failed conversions with a report attaching their input as `attachment` selects, or
+/// with an empty result if `None`, from now on
+pub fn set_attach_input(attachment: Option<InputAttachment>) { *ATTACH_INPUT.lock().unwrap() = attachment; }
+
+/// Which input files to attach to failure reports, if any
+pub fn attach_input() -> Option<InputAttachment> { ATTACH_INPUT.lock().unwrap().clone() }
+
+/// What to do with a result archive over the maximal output size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OversizedOutput {
+  /// Replace it with a report of the excess, `Fatal:output_size:exceeded`, `reject`
+  #[default]
+  Reject,
+  /// Drop its largest assets until it fits, keeping the HTML and the `cortex.*` files and noting
+  /// the dropped files as `Warning:output_size:truncated`, or else reject it, `truncate-assets`
+  TruncateAssets,
+  /// Upload it to the object store whatever the upload threshold, the sink receiving its
+  /// manifest, noted as `Warning:output_size:diverted`, `divert`
+  Divert,
+}
+
+impl FromStr for OversizedOutput {
+  type Err = String;
+  fn from_str(action: &str) -> Result<OversizedOutput, String> {
+    match action {
+      "reject" => Ok(OversizedOutput::Reject),
+      "truncate-assets" => Ok(OversizedOutput::TruncateAssets),
+      "divert" => Ok(OversizedOutput::Divert),
+      _ => Err(format!(
+        "invalid oversized output action {:?}, expected reject, truncate-assets or divert",
+        action
+      )),
+    }
+  }
+}
+
+/// The maximal size of a result archive, and what to do with the results over it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputSizeLimit {
+  /// Bytes of a result archive at most
+  pub max_bytes: u64,
+  /// What to do with the results over it
+  pub action: OversizedOutput,
+}
+
+static OUTPUT_SIZE_LIMIT: Mutex<Option<OutputSizeLimit>> = Mutex::new(None);
+
+/// Hold result archives to `limit` from now on, or let them be of any size with `None`
+pub fn set_output_size_limit(limit: Option<OutputSizeLimit>) { *OUTPUT_SIZE_LIMIT.lock().unwrap() = limit; }
+
+/// The limit result archives are held to, if any
+pub fn output_size_limit() -> Option<OutputSizeLimit> { *OUTPUT_SIZE_LIMIT.lock().unwrap() }
+
+/// Hold the `result` archive of task `taskid` to the output size limit, if any. An oversized
+/// result is rejected, truncated or marked for diversion as the limit says; the `cortex.log`
+/// notes what was done, so that the status sent ahead of the result reflects it
+pub fn limit_output_size(mut result: File, taskid: &str, identity: &str) -> Result<File, Box<dyn Error>> {
+  let Some(limit) = output_size_limit() else {
+    return Ok(result);
+  };
+  let size = result.metadata()?.len();
+  if size <= limit.max_bytes {
+    return Ok(result);
+  }
+  warn!(
+    target: &format!("{}:output_size", identity),
+    "task {}: the result of {} bytes is over the limit of {} bytes, applying {:?}.",
+    taskid,
+    size,
+    limit.max_bytes,
+    limit.action
+  );
+  match limit.action {
+    OversizedOutput::Reject => adaptor::oversized_output_archive(size, limit.max_bytes),
+    OversizedOutput::Divert => {
+      let note = format!(
+        "Warning:output_size:diverted the result of {} bytes is over the limit of {} bytes, sent to object storage\n",
+        size, limit.max_bytes
+      );
+      // a result which isn't a ZIP archive is diverted as it is
+      let mut original = result.try_clone()?;
+      adaptor::remove_zip_entries(result, &[], &note).or_else(|_| {
+        original.seek(SeekFrom::Start(0))?;
+        Ok(original)
+      })
+    }
+    OversizedOutput::TruncateAssets => {
+      let Ok(assets) = assets_by_size(&mut result) else {
+        return adaptor::oversized_output_archive(size, limit.max_bytes);
+      };
+      let mut dropped = Vec::new();
+      let mut remaining = size;
+      for (name, compressed) in assets {
+        if remaining <= limit.max_bytes {
+          break;
+        }
+        remaining = remaining.saturating_sub(compressed);
+        dropped.push(name);
+      }
+      let note: String = dropped
+        .iter()
+        .map(|name| format!("Warning:output_size:truncated dropped {} to fit the limit of {} bytes\n", name, limit.max_bytes))
+        .collect();
+      let truncated = adaptor::remove_zip_entries(result, &dropped, &note)?;
+      let truncated_size = truncated.metadata()?.len();
+      if truncated_size > limit.max_bytes {
+        // the document itself is too large, dropping assets can't help
+        return adaptor::oversized_output_archive(truncated_size, limit.max_bytes);
+      }
+      Ok(truncated)
+    }
+  }
+}
+
+/// Should a result of `size` bytes go to the object store, whatever its upload threshold?
+pub fn diverts(size: u64) -> bool {
+  output_size_limit().is_some_and(|limit| limit.action == OversizedOutput::Divert && size > limit.max_bytes)
+}
+
+/// The assets of a result archive, the files other than HTML documents and `cortex.*` files, with
+/// their compressed sizes, largest first. The archive is rewound afterwards
+fn assets_by_size(result: &mut File) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+  let mut assets = Vec::new();
+  {
+    let mut archive = ZipArchive::new(&mut *result)?;
+    for i in 0..archive.len() {
+      let entry = archive.by_index_raw(i)?;
+      let path = Path::new(entry.name());
+      let document = path
+        .extension()
+        .is_some_and(|extension| ["html", "xhtml", "htm"].iter().any(|html| extension.eq_ignore_ascii_case(html)));
+      let metadata = path.to_str().is_some_and(|name| name.starts_with("cortex.") || name.starts_with("cortex_"));
+      if entry.is_file() && !document && !metadata {
+        assets.push((entry.name().to_string(), entry.compressed_size()));
+      }
+    }
+  }
+  result.seek(SeekFrom::Start(0))?;
+  assets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+  Ok(assets)
+}
+
+/// After an empty input or a failed conversion, pause the converter of `worker` in case there is a
+/// temporary local issue, such as running out of available RAM, but also to protect the server
+/// from DDoS-like behavior where we send broken requests at nauseam.
+pub fn throttle<W: Worker>(worker: &W, input_size: usize, failure: &str) {
+  if input_size == 0 {
+    return throttle_empty_input(worker);
+  }
+  info!(
+    target: &format!("{}:result", worker.get_identity()),
+    "Conversion came back empty: {}. Throttling for {} seconds.",
+    failure,
+    worker.throttle().as_secs()
+  );
+  clock::sleep(worker.throttle());
+}
+
+/// After an empty input, pause the converter of `worker` for the empty input throttle, which
+/// corpora with many empty placeholder entries may set apart from the failure throttle
+pub fn throttle_empty_input<W: Worker>(worker: &W) {
+  let throttle = EMPTY_INPUT_THROTTLE.lock().unwrap().unwrap_or_else(|| worker.throttle());
+  info!(
+    target: &format!("{}:result", worker.get_identity()),
+    "Empty input. Throttling for {} seconds.",
+    throttle.as_secs()
+  );
+  clock::sleep(throttle);
+}
+
+/// Blocks until the dispatcher lifted any pause and quiet hours are over. Returns false if the
+/// converter should check back later, e.g. as a shutdown was requested meanwhile
+pub fn await_turn(identity: &str) -> bool { announcements::await_resume(identity) && schedule::await_active(identity) }
+
+/// Whether another task may be fetched under the run quotas, after waiting for the rate limit.
+/// Returns false once the fetcher should stop
+pub fn admit_fetch(identity: &str) -> bool { quota::admit_task() && ratelimit::acquire(identity) }
@@ -0,0 +1,182 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Adaptive concurrency: pause task fetches on some threads while the host is saturated
+//! (high load average, low available memory), resuming them as the pressure drops.
+//!
+//! Each IO thread also stops sending while its sink socket is at its high water mark, i.e. while
+//! [`sink_queue_limit`] bytes of results are queued for a slow sink, which holds back the
+//! converters and thus the task fetches, instead of queueing results in memory until the
+//! process runs out of it.
+
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zmq::Socket;
+
+use crate::sync::{Permit, Permits};
+use crate::worker::Worker;
+use crate::{shutdown, state, tuning};
+
+/// Bytes of results queued for the sink, by default, before a thread stops fetching tasks
+pub const SINK_QUEUE_LIMIT: usize = 256 * 1024 * 1024;
+
+/// Thresholds at which a host is considered saturated
+#[derive(Clone, Debug)]
+pub struct LoadLimits {
+  /// Maximum 1-minute load average per CPU before fetching is throttled
+  pub max_load_per_cpu: f64,
+  /// Minimum available memory (in bytes) before fetching is throttled
+  pub min_available_memory: u64,
+  /// How often to sample the host
+  pub poll_interval: Duration,
+}
+impl Default for LoadLimits {
+  fn default() -> LoadLimits {
+    LoadLimits {
+      max_load_per_cpu: 1.5,
+      min_available_memory: 2 * 1024 * 1024 * 1024,
+      poll_interval: Duration::new(15, 0),
+    }
+  }
+}
+
+/// How many threads may currently be fetching or working on a task
+fn permits() -> &'static Permits {
+  static PERMITS: OnceLock<Permits> = OnceLock::new();
+  PERMITS.get_or_init(|| Permits::new(usize::MAX))
+}
+/// Bytes of results each thread may queue for the sink
+static SINK_LIMIT: AtomicUsize = AtomicUsize::new(SINK_QUEUE_LIMIT);
+
+/// Threads the controller currently lets fetch tasks, if it runs
+pub fn allowed_fetches() -> Option<usize> { Some(permits().allowed()).filter(|allowed| *allowed != usize::MAX) }
+
+/// Threads currently holding a fetch permit
+pub fn active_fetches() -> usize { permits().active() }
+
+/// Blocks until the controller allows one more thread to fetch a task.
+/// Returns None if a shutdown was requested meanwhile
+pub fn acquire() -> Option<Permit<'static>> { permits().acquire(shutdown::signal(), Duration::new(1, 0)) }
+
+/// The 1-minute load average of the host, where available
+pub fn load_average() -> Option<f64> {
+  let loadavg = fs::read_to_string("/proc/loadavg").ok()?;
+  loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// The memory available to new processes on the host in bytes, where available
+pub fn available_memory() -> Option<u64> {
+  let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+  let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+  let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+  Some(kilobytes * 1024)
+}
+
+/// Is the host over any of the given limits?
+pub fn saturated(limits: &LoadLimits) -> bool {
+  let overloaded = load_average()
+    .map(|load| load / num_cpus::get() as f64 > limits.max_load_per_cpu)
+    .unwrap_or(false);
+  let out_of_memory = available_memory()
+    .map(|available| available < limits.min_available_memory)
+    .unwrap_or(false);
+  overloaded || out_of_memory
+}
+
+/// Threads of the running pool, the most the controller lets fetch
+static POOL_SIZE: AtomicUsize = AtomicUsize::new(1);
+
+/// Threads of the running pool
+pub fn pool_size() -> usize { POOL_SIZE.load(Ordering::SeqCst) }
+
+/// Record the size of a pool being started, e.g. after a reload changed it, at once lowering the
+/// threads the controller lets fetch to fit a smaller pool
+pub fn set_pool_size(pool_size: usize) {
+  let pool_size = pool_size.max(1);
+  POOL_SIZE.store(pool_size, Ordering::SeqCst);
+  if let Some(allowed) = allowed_fetches() {
+    permits().set_allowed(allowed.min(pool_size));
+  }
+}
+
+/// Start the controller in a background thread, adjusting between 1 and the pool size active
+/// threads, starting from `pool_size`
+pub fn control(limits: LoadLimits, pool_size: usize) {
+  POOL_SIZE.store(pool_size.max(1), Ordering::SeqCst);
+  permits().set_allowed(pool_size.max(1));
+  thread::spawn(move || loop {
+    thread::sleep(limits.poll_interval);
+    // re-read on every poll, the pool may have been restarted with another size
+    let pool_size = self::pool_size();
+    let allowed = permits().allowed();
+    let adjusted = if saturated(&limits) {
+      allowed.saturating_sub(1).max(1).min(pool_size)
+    } else {
+      (allowed + 1).min(pool_size)
+    };
+    if adjusted != allowed {
+      info!(
+        target: "pressure",
+        "host pressure changed, {} of {} threads may fetch tasks.", adjusted, pool_size
+      );
+      permits().set_allowed(adjusted);
+    }
+  });
+}
+
+/// Let every thread queue up to `bytes` of results for the sink, applied to sockets connected afterwards
+pub fn limit_sink_queue(bytes: usize) { SINK_LIMIT.store(bytes, Ordering::SeqCst); }
+
+/// Bytes of results each thread may queue for the sink
+pub fn sink_queue_limit() -> usize { SINK_LIMIT.load(Ordering::SeqCst) }
+
+/// The largest frame `worker` may send a result in: its `message_size`, or the largest frame size
+/// its `message_size_bounds` or the frame size tuner may pick for a result
+pub fn largest_frame_size<W: Worker>(worker: &W) -> usize {
+  [worker.message_size_bounds(), tuning::bounds()]
+    .into_iter()
+    .flatten()
+    .map(|bounds| bounds.max)
+    .fold(worker.message_size(), usize::max)
+}
+
+/// The send high water mark of `worker`, in frames of the largest size it sends, enforcing the
+/// sink queue limit whichever frame sizes its results are sent in
+pub fn sink_high_water_mark<W: Worker>(worker: &W) -> i32 {
+  (sink_queue_limit() / largest_frame_size(worker).max(1)).clamp(1, i32::MAX as usize) as i32
+}
+
+/// Blocks while `sink` is at its high water mark. Returns false if a shutdown was requested meanwhile
+pub fn await_sink(sink: &Socket, identity: &str) -> bool {
+  if sink.poll(zmq::POLLOUT, 0).unwrap_or(1) > 0 {
+    return true;
+  }
+  warn!(
+    target: &format!("{}:backpressure", identity),
+    "the sink queue is full ({} bytes), pausing task fetches.",
+    sink_queue_limit()
+  );
+  let paused = Instant::now();
+  state::sink_wait(true);
+  while sink.poll(zmq::POLLOUT, 1000).unwrap_or(1) == 0 {
+    if shutdown::requested() {
+      state::sink_wait(false);
+      return false;
+    }
+  }
+  state::sink_wait(false);
+  info!(
+    target: &format!("{}:backpressure", identity),
+    "the sink caught up after {} seconds, resuming task fetches.",
+    paused.elapsed().as_secs()
+  );
+  true
+}
@@ -0,0 +1,124 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Progress of large transfers to and from CorTeX, logged every `--progress-interval` seconds,
+//! so that a slow transfer of a multi-hundred-MB document can be told apart from a hung
+//! dispatcher while watching a worker.
+//!
+//! From protocol version [`KEEPALIVE_VERSION`](protocol::KEEPALIVE_VERSION) on, each progress
+//! line of a result being sent is accompanied by a keepalive message to the sink. It goes out on
+//! a socket of its own, as the result is a single multipart message which reaches the sink only
+//! once it is complete.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use zmq::Socket;
+
+use crate::endpoint;
+use crate::protocol::{self, Keepalive};
+
+/// Seconds between two progress lines of the same transfer, by default
+pub const DEFAULT_INTERVAL_SECS: u64 = 10;
+
+static INTERVAL_MS: AtomicU64 = AtomicU64::new(DEFAULT_INTERVAL_SECS * 1000);
+
+/// Log the progress of transfers every `interval` from now on
+pub fn set_interval(interval: Duration) { INTERVAL_MS.store(interval.as_millis() as u64, Ordering::SeqCst); }
+
+/// The time between two progress lines of the same transfer
+pub fn interval() -> Duration { Duration::from_millis(INTERVAL_MS.load(Ordering::SeqCst)) }
+
+thread_local! {
+  static KEEPALIVE: RefCell<Option<Socket>> = const { RefCell::new(None) };
+}
+
+/// Connect the keepalive socket of this thread to `sink`, if the protocol version sends keepalives
+pub fn connect_keepalive(sink: &str) -> Result<(), Box<dyn Error>> {
+  if protocol::version() < protocol::KEEPALIVE_VERSION {
+    return Ok(());
+  }
+  let socket = endpoint::context(sink).socket(zmq::PUSH)?;
+  // a keepalive is worthless once the result is through
+  socket.set_linger(0)?;
+  socket.set_ipv6(true)?;
+  socket.connect(sink)?;
+  KEEPALIVE.with(|keepalive| *keepalive.borrow_mut() = Some(socket));
+  Ok(())
+}
+
+/// Tell the sink that `bytes_sent` bytes of the result of task `taskid` are on their way, without
+/// blocking. Does nothing unless this thread connected a keepalive socket
+pub fn keepalive(identity: &str, service: &str, taskid: &str, bytes_sent: usize) {
+  let sent = KEEPALIVE.with(|keepalive| -> Result<(), zmq::Error> {
+    let keepalive = keepalive.borrow();
+    let Some(socket) = keepalive.as_ref() else {
+      return Ok(());
+    };
+    let frame = Keepalive {
+      bytes_sent: bytes_sent as u64,
+    }
+    .frame();
+    socket.send_multipart([identity, service, taskid, &frame], zmq::DONTWAIT)
+  });
+  if let Err(e) = sent {
+    debug!(target: &format!("{}:sending", identity), "failed to send a keepalive for task {}: {}", taskid, e);
+  }
+}
+
+/// The bytes transferred so far for a task, in one direction
+#[derive(Debug)]
+pub struct TransferProgress {
+  target: String,
+  verb: &'static str,
+  taskid: String,
+  bytes: usize,
+  started: Instant,
+  logged: Instant,
+}
+
+impl TransferProgress {
+  /// Track the transfer of task `taskid`, logged under `target` as e.g. "received" or "sent"
+  pub fn new(target: String, verb: &'static str, taskid: &str) -> TransferProgress {
+    let now = Instant::now();
+    TransferProgress {
+      target,
+      verb,
+      taskid: taskid.to_string(),
+      bytes: 0,
+      started: now,
+      logged: now,
+    }
+  }
+
+  /// The bytes transferred so far
+  pub fn bytes(&self) -> usize { self.bytes }
+
+  /// Account for `bytes` more, logging the progress once the interval since the last line passed.
+  /// Returns whether a line was logged
+  pub fn advance(&mut self, bytes: usize) -> bool {
+    self.bytes += bytes;
+    let now = Instant::now();
+    if now.duration_since(self.logged) < interval() {
+      return false;
+    }
+    self.logged = now;
+    let elapsed = now.duration_since(self.started).as_secs_f64();
+    info!(
+      target: &self.target,
+      " task {}: {} {} bytes so far in {:.0} seconds ({:.1} MB/s).",
+      self.taskid,
+      self.verb,
+      self.bytes,
+      elapsed,
+      self.bytes as f64 / elapsed.max(0.001) / 1_000_000.0
+    );
+    true
+  }
+}
@@ -0,0 +1,576 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Optional extensions of the CorTeX wire protocol
+//!
+//! A worker with `compression = "zstd"` appends an [`ACCEPT_ZSTD`] frame to its task requests,
+//! telling the dispatcher it may send zstd-compressed payloads, and compresses the results it
+//! sends to the sink unless they already are ZIP archives. Both sides tell compressed payloads
+//! apart by their leading magic number; workers which didn't negotiate zstd never decompress, so
+//! their traffic is unaffected. Decompressed payloads are capped at [`MAX_DECODED_SIZE`].
+//!
+//! From protocol version [`HANDSHAKE_VERSION`] on, selected with [`set_version`], every task
+//! request also carries a [`Handshake`] frame naming the pericortex, worker and converter
+//! versions, so that CorTeX can tell which converter build produced each result.
+//!
+//! From protocol version [`MANIFEST_VERSION`] on, a result or payload frame may instead be an
+//! [`ObjectManifest`], pointing to an archive kept in object storage.
+//!
+//! From protocol version [`STATUS_VERSION`] on, every result sent to the sink is preceded by a
+//! [`ResultStatus`] frame, classifying it as ok, warning, error or fatal with a short reason code,
+//! so that the dispatcher doesn't need to tell failures apart by empty frames and `cortex.log`s.
+//! From protocol version [`STATS_VERSION`] on, the status is followed by a [`TaskStats`] frame
+//! with the duration, retries, output size and converter memory of the task.
+//!
+//! From protocol version [`METADATA_VERSION`] on, the dispatcher may precede the payload of a
+//! task with a [`TaskMetadata`] frame, e.g. naming the corpus of the task, against which the
+//! worker resolves per-corpus settings.
+//!
+//! From protocol version [`KEEPALIVE_VERSION`] on, a worker streaming a large result also sends
+//! the sink a separate message every progress interval, its identity, service and taskid
+//! followed by a [`Keepalive`] frame, so that the dispatcher doesn't take it for dead meanwhile.
+//!
+//! From protocol version [`EMPTY_INPUT_VERSION`] on, a task whose input was empty is reported
+//! with the status `empty` rather than `fatal`, as corpora may hold empty placeholder entries.
+//!
+//! From protocol version [`PROBE_VERSION`] on, a task whose [`TaskMetadata`] marks it as a probe
+//! is answered right away without running the converter: with the status `ok` and the reason
+//! `probe`, the usual stats, and a [`ProbeReply`] as its payload, so that CorTeX can check the
+//! liveness of a fleet and measure round trips cheaply.
+//!
+//! From protocol version [`ARTIFACTS_VERSION`] on, the secondary artifacts of a conversion, see
+//! [`artifacts`](crate::artifacts), follow the status and stats of its result: each of them as an
+//! [`ArtifactHeader`] frame naming it, then a frame of its contents.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::{spooled_tempfile, tempfile, SpooledTempFile};
+
+use crate::adaptor;
+use crate::error;
+use crate::logcodes::{self, LogMessage, Severity};
+use crate::transfer::SPOOL_THRESHOLD;
+
+/// Frame appended to a task request by workers accepting zstd-compressed payloads
+pub const ACCEPT_ZSTD: &str = "accept-encoding:zstd";
+/// Frame appended to a task request by workers in a dry run, which don't convert their tasks
+pub const DRY_RUN: &str = "mode:dry-run";
+/// Leading bytes of a zstd frame
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+/// Leading bytes of a ZIP archive
+pub const ZIP_MAGIC: [u8; 4] = [b'P', b'K', 0x03, 0x04];
+/// The protocol version CorTeX dispatchers speak without any negotiation
+pub const BASE_VERSION: u32 = 1;
+/// The first protocol version whose task requests carry a handshake frame
+pub const HANDSHAKE_VERSION: u32 = 2;
+/// Prefix of the handshake frame, followed by the `Handshake` as JSON
+pub const HANDSHAKE_PREFIX: &str = "handshake:";
+/// The first protocol version whose results may be object storage manifests
+pub const MANIFEST_VERSION: u32 = 3;
+/// Prefix of a manifest frame, followed by the `ObjectManifest` as JSON
+pub const MANIFEST_PREFIX: &str = "manifest:";
+/// The first protocol version whose results are preceded by a status frame
+pub const STATUS_VERSION: u32 = 4;
+/// Prefix of the status frame, followed by the `ResultStatus` as JSON
+pub const STATUS_PREFIX: &str = "status:";
+/// The first protocol version whose results are also preceded by a stats frame
+pub const STATS_VERSION: u32 = 5;
+/// Prefix of the stats frame, followed by the `TaskStats` as JSON
+pub const STATS_PREFIX: &str = "stats:";
+/// The first protocol version whose tasks may carry a metadata frame
+pub const METADATA_VERSION: u32 = 6;
+/// Prefix of the metadata frame, followed by the `TaskMetadata` as JSON
+pub const METADATA_PREFIX: &str = "metadata:";
+/// The first protocol version whose workers send keepalives while streaming a result
+pub const KEEPALIVE_VERSION: u32 = 7;
+/// Prefix of the keepalive frame, followed by the `Keepalive` as JSON
+pub const KEEPALIVE_PREFIX: &str = "keepalive:";
+/// The first protocol version reporting empty inputs with a status of their own
+pub const EMPTY_INPUT_VERSION: u32 = 8;
+/// The first protocol version answering probe tasks without a conversion
+pub const PROBE_VERSION: u32 = 9;
+/// The first protocol version sending the secondary artifacts of a result in frames of their own
+pub const ARTIFACTS_VERSION: u32 = 10;
+/// Prefix of an artifact header frame, followed by the `ArtifactHeader` as JSON
+pub const ARTIFACT_PREFIX: &str = "artifact:";
+/// The newest protocol version this release speaks
+pub const LATEST_VERSION: u32 = ARTIFACTS_VERSION;
+
+static VERSION: AtomicU32 = AtomicU32::new(BASE_VERSION);
+
+/// Speak protocol `version` with the dispatcher, from the next task request on
+pub fn set_version(version: u32) -> Result<(), Box<dyn Error>> {
+  if !(BASE_VERSION..=LATEST_VERSION).contains(&version) {
+    return Err(From::from(format!(
+      "unsupported protocol version {}, expected {} to {}",
+      version, BASE_VERSION, LATEST_VERSION
+    )));
+  }
+  VERSION.store(version, Ordering::SeqCst);
+  Ok(())
+}
+
+/// The protocol version spoken with the dispatcher
+pub fn version() -> u32 { VERSION.load(Ordering::SeqCst) }
+
+/// Versions of the software converting the tasks of a worker
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Handshake {
+  /// The protocol version spoken by the worker
+  pub protocol: u32,
+  /// Version of the pericortex crate
+  pub pericortex: String,
+  /// The worker's own version
+  pub worker: String,
+  /// Version of the underlying converter (docker image, latexmlc, ...), if known
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tool: Option<String>,
+}
+
+impl Handshake {
+  /// The handshake of a worker at `worker` version, converting with `tool`
+  pub fn new(worker: String, tool: Option<String>) -> Handshake {
+    Handshake {
+      protocol: version(),
+      pericortex: env!("CARGO_PKG_VERSION").to_string(),
+      worker,
+      tool,
+    }
+  }
+
+  /// The frame appended to task requests
+  pub fn frame(&self) -> String {
+    format!(
+      "{}{}",
+      HANDSHAKE_PREFIX,
+      serde_json::to_string(self).expect("handshakes are serializable")
+    )
+  }
+}
+
+/// A payload kept in object storage, sent in place of its bytes
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ObjectManifest {
+  /// Where the payload can be downloaded
+  pub url: String,
+  /// SHA-256 checksum of the payload, in hex
+  pub sha256: String,
+  /// Size of the payload in bytes
+  pub size: u64,
+}
+
+impl ObjectManifest {
+  /// The frame sent in place of the payload
+  pub fn frame(&self) -> String {
+    format!(
+      "{}{}",
+      MANIFEST_PREFIX,
+      serde_json::to_string(self).expect("manifests are serializable")
+    )
+  }
+
+  /// The manifest in `frame`, if it is a manifest frame
+  pub fn parse(frame: &[u8]) -> Option<ObjectManifest> {
+    serde_json::from_slice(frame.strip_prefix(MANIFEST_PREFIX.as_bytes())?).ok()
+  }
+}
+
+/// How a task went, from the dispatcher's point of view
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+  /// Converted, at most with informational messages
+  Ok,
+  /// Converted, with warnings
+  Warning,
+  /// Converted, with errors in parts of the result
+  Error,
+  /// No usable result
+  Fatal,
+  /// Nothing to convert, the input was empty
+  Empty,
+}
+
+/// The messages of the `cortex.log` of a result archive, none for results which aren't CorTeX
+/// archives. The archive is rewound afterwards
+pub fn result_log(result: &mut File) -> Vec<LogMessage> {
+  let log = adaptor::read_zip_entry(&mut *result, "cortex.log").ok().flatten();
+  result.seek(SeekFrom::Start(0)).ok();
+  logcodes::parse(&String::from_utf8_lossy(&log.unwrap_or_default()))
+}
+
+/// The classification of a result, sent ahead of it
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResultStatus {
+  /// How the task went
+  pub status: Outcome,
+  /// A short reason code, e.g. `empty_input` or the `category:what` of the most severe message
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub reason: Option<String>,
+}
+
+impl ResultStatus {
+  /// Classify a result archive by the most severe message of its `cortex.log`. Results which aren't
+  /// CorTeX archives are ok. The archive is rewound afterwards
+  pub fn of_result(result: &mut File) -> ResultStatus { ResultStatus::of_log(&result_log(result)) }
+
+  /// Classify a result by the most severe of the `messages` of its `cortex.log`
+  pub fn of_log(messages: &[LogMessage]) -> ResultStatus {
+    let status = match logcodes::max_severity(messages) {
+      None | Some(Severity::Info) => Outcome::Ok,
+      Some(Severity::Warning) => Outcome::Warning,
+      Some(Severity::Error) => Outcome::Error,
+      Some(Severity::Fatal) => Outcome::Fatal,
+    };
+    let reason = match status {
+      Outcome::Ok => None,
+      _ => messages
+        .iter()
+        .filter(|message| Some(message.severity) == logcodes::max_severity(messages))
+        .map(|message| format!("{}:{}", message.category, message.what))
+        .next(),
+    };
+    // the report of an empty input, see `adaptor::empty_input_archive`
+    if version() >= EMPTY_INPUT_VERSION && reason.as_deref().is_some_and(|reason| reason.starts_with("empty_input:")) {
+      return ResultStatus::empty_input();
+    }
+    ResultStatus { status, reason }
+  }
+
+  /// Classify a task whose input was empty
+  pub fn empty_input() -> ResultStatus {
+    ResultStatus {
+      status: if version() >= EMPTY_INPUT_VERSION {
+        Outcome::Empty
+      } else {
+        Outcome::Fatal
+      },
+      reason: Some(String::from("empty_input")),
+    }
+  }
+
+  /// Classify the answer to a probe task
+  pub fn probe() -> ResultStatus {
+    ResultStatus {
+      status: Outcome::Ok,
+      reason: Some(String::from("probe")),
+    }
+  }
+
+  /// Classify a task without any result, which failed with `error`
+  pub fn failed(error: &(dyn Error + 'static), input_size: usize) -> ResultStatus {
+    if input_size == 0 {
+      return ResultStatus::empty_input();
+    }
+    let reason = if error::is_transient(error) {
+      "transient"
+    } else {
+      "no_result"
+    };
+    ResultStatus {
+      status: Outcome::Fatal,
+      reason: Some(reason.to_string()),
+    }
+  }
+
+  /// The frame sent ahead of the result
+  pub fn frame(&self) -> String {
+    format!(
+      "{}{}",
+      STATUS_PREFIX,
+      serde_json::to_string(self).expect("statuses are serializable")
+    )
+  }
+
+  /// The status in `frame`, if it is a status frame
+  pub fn parse(frame: &[u8]) -> Option<ResultStatus> {
+    serde_json::from_slice(frame.strip_prefix(STATUS_PREFIX.as_bytes())?).ok()
+  }
+}
+
+/// Resource usage of a task, sent ahead of its result
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TaskStats {
+  /// Time from receiving the task to handing off its result
+  pub duration_ms: u64,
+  /// How many times a transient failure was retried
+  pub retries: usize,
+  /// Size of the result, before any upload to object storage
+  pub output_bytes: u64,
+  /// Peak resident memory of the largest converter process so far, where available
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub peak_rss_bytes: Option<u64>,
+}
+
+impl TaskStats {
+  /// The frame sent ahead of the result
+  pub fn frame(&self) -> String {
+    format!(
+      "{}{}",
+      STATS_PREFIX,
+      serde_json::to_string(self).expect("stats are serializable")
+    )
+  }
+
+  /// The stats in `frame`, if it is a stats frame
+  pub fn parse(frame: &[u8]) -> Option<TaskStats> {
+    serde_json::from_slice(frame.strip_prefix(STATS_PREFIX.as_bytes())?).ok()
+  }
+}
+
+/// What the dispatcher tells about a task, ahead of its payload
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TaskMetadata {
+  /// Name of the corpus the task belongs to
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub corpus: Option<String>,
+  /// Marks a probe task, answered without a conversion from protocol version 9 on
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub probe: bool,
+}
+
+impl TaskMetadata {
+  /// The frame sent ahead of the payload
+  pub fn frame(&self) -> String {
+    format!(
+      "{}{}",
+      METADATA_PREFIX,
+      serde_json::to_string(self).expect("metadata is serializable")
+    )
+  }
+
+  /// The metadata in `frame`, if it is a metadata frame
+  pub fn parse(frame: &[u8]) -> Option<TaskMetadata> {
+    serde_json::from_slice(frame.strip_prefix(METADATA_PREFIX.as_bytes())?).ok()
+  }
+}
+
+/// The payload answering a probe task
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProbeReply {
+  /// The service of the worker
+  pub service: String,
+  /// The worker's own version
+  pub worker: String,
+  /// Version of the pericortex crate
+  pub pericortex: String,
+  /// Threads of the worker converting a task when the probe was answered
+  pub converting: usize,
+}
+
+/// Announces a secondary artifact of a result, sent ahead of the frame of its contents
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ArtifactHeader {
+  /// Name of the artifact, e.g. `report.pdf`
+  pub name: String,
+  /// Size of the artifact in bytes
+  pub size: u64,
+}
+
+impl ArtifactHeader {
+  /// The frame preceding the contents of the artifact
+  pub fn frame(&self) -> String {
+    format!(
+      "{}{}",
+      ARTIFACT_PREFIX,
+      serde_json::to_string(self).expect("artifact headers are serializable")
+    )
+  }
+
+  /// The artifact header in `frame`, if it is an artifact header frame
+  pub fn parse(frame: &[u8]) -> Option<ArtifactHeader> {
+    serde_json::from_slice(frame.strip_prefix(ARTIFACT_PREFIX.as_bytes())?).ok()
+  }
+}
+
+/// Sent to the sink while the result of a task is still being streamed
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Keepalive {
+  /// Bytes of the result sent so far
+  pub bytes_sent: u64,
+}
+
+impl Keepalive {
+  /// The frame following the taskid
+  pub fn frame(&self) -> String {
+    format!(
+      "{}{}",
+      KEEPALIVE_PREFIX,
+      serde_json::to_string(self).expect("keepalives are serializable")
+    )
+  }
+
+  /// The keepalive in `frame`, if it is a keepalive frame
+  pub fn parse(frame: &[u8]) -> Option<Keepalive> {
+    serde_json::from_slice(frame.strip_prefix(KEEPALIVE_PREFIX.as_bytes())?).ok()
+  }
+}
+
+/// Compression of payloads on the wire
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+  /// Payloads are sent as they are
+  #[default]
+  None,
+  /// Payloads which aren't already compressed are sent as zstd frames
+  Zstd,
+}
+
+impl FromStr for Compression {
+  type Err = String;
+  fn from_str(name: &str) -> Result<Compression, String> {
+    match name {
+      "none" => Ok(Compression::None),
+      "zstd" => Ok(Compression::Zstd),
+      _ => Err(format!("unknown compression {:?}, expected none or zstd", name)),
+    }
+  }
+}
+
+impl fmt::Display for Compression {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Compression::None => "none",
+      Compression::Zstd => "zstd",
+    })
+  }
+}
+
+/// A message from the dispatcher which doesn't follow the protocol
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolError(pub String);
+
+impl fmt::Display for ProtocolError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(&self.0) }
+}
+
+impl Error for ProtocolError {}
+
+/// The first bytes of `reader`, which is rewound afterwards
+fn magic<R: Read + Seek>(reader: &mut R) -> io::Result<[u8; 4]> {
+  let mut magic = [0; 4];
+  let mut filled = 0;
+  while filled < magic.len() {
+    match reader.read(&mut magic[filled..])? {
+      0 => break,
+      read => filled += read,
+    }
+  }
+  reader.seek(SeekFrom::Start(0))?;
+  Ok(magic)
+}
+
+/// Bytes a compressed payload may decompress to at most, so that a small malicious frame can't
+/// fill the scratch disk
+pub const MAX_DECODED_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Decompress a received payload if zstd was negotiated with `compression` and it is a zstd
+/// frame, otherwise return it unchanged
+pub fn decode_payload(payload: SpooledTempFile, compression: Compression) -> io::Result<SpooledTempFile> {
+  decode_payload_within(payload, compression, MAX_DECODED_SIZE)
+}
+
+/// As [`decode_payload`], failing payloads which decompress to more than `max_bytes`
+pub fn decode_payload_within(
+  mut payload: SpooledTempFile,
+  compression: Compression,
+  max_bytes: u64,
+) -> io::Result<SpooledTempFile> {
+  // without a negotiation, a payload starting with the magic number is e.g. a `.zst` input
+  if compression == Compression::None || magic(&mut payload)? != ZSTD_MAGIC {
+    return Ok(payload);
+  }
+  let mut decoded = spooled_tempfile(SPOOL_THRESHOLD);
+  let decoder = zstd::stream::read::Decoder::new(&mut payload)?;
+  let written = io::copy(&mut decoder.take(max_bytes.saturating_add(1)), &mut decoded)?;
+  if written > max_bytes {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!("the payload decompresses to more than {} bytes", max_bytes),
+    ));
+  }
+  decoded.seek(SeekFrom::Start(0))?;
+  Ok(decoded)
+}
+
+/// Compress a result with `compression`, leaving ZIP archives and empty results unchanged
+pub fn encode_result(mut result: File, compression: Compression) -> io::Result<File> {
+  if compression == Compression::None
+    || result.metadata()?.len() == 0
+    || magic(&mut result)? == ZIP_MAGIC
+  {
+    return Ok(result);
+  }
+  let mut encoded = tempfile()?;
+  zstd::stream::copy_encode(&mut result, &mut encoded, 0)?;
+  encoded.seek(SeekFrom::Start(0))?;
+  Ok(encoded)
+}
+
+/// How a service's task inputs are packaged
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum PayloadFormat {
+  /// A ZIP archive following the CorTeX conventions
+  #[default]
+  Zip,
+  /// A single file, e.g. a PDF, received as is
+  SingleFile {
+    /// Extension of the file, without the leading dot
+    extension: String,
+  },
+}
+
+impl PayloadFormat {
+  /// The file name of the input of task `taskid`, in the task's scratch directory
+  pub fn file_name(&self, taskid: &str) -> String {
+    match self {
+      PayloadFormat::Zip => format!("{}.zip", taskid_file_stem(taskid)),
+      PayloadFormat::SingleFile { extension } => {
+        format!("{}.{}", taskid_file_stem(taskid), extension.trim_start_matches('.'))
+      }
+    }
+  }
+}
+
+/// Longest file name stem of a taskid, leaving room for an extension within the 255 bytes file
+/// systems allow in a file name
+const MAX_STEM: usize = 200;
+
+/// Hex digits of the taskid's SHA-256 checksum ending a shortened stem
+const STEM_HASH_DIGITS: usize = 16;
+
+/// A file name stem for `taskid`, which comes straight from the dispatcher: every byte outside
+/// `[A-Za-z0-9_-]` is percent-encoded, so that separators and `..` can't escape a directory.
+/// Stems beyond 200 bytes are cut short, and end in a hash of the whole taskid instead.
+/// The original taskid is still the one used in protocol frames.
+pub fn taskid_file_stem(taskid: &str) -> String {
+  let mut stem = String::with_capacity(taskid.len());
+  for byte in taskid.bytes() {
+    if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' {
+      stem.push(byte as char);
+    } else {
+      stem.push_str(&format!("%{:02X}", byte));
+    }
+  }
+  // shortened stems are longer than any other, so they never collide with a stem kept whole
+  let kept = MAX_STEM - STEM_HASH_DIGITS - 1;
+  if stem.len() > kept {
+    let digest = hex::encode(Sha256::digest(taskid.as_bytes()));
+    stem.truncate(kept);
+    stem.push('-');
+    stem.push_str(&digest[..STEM_HASH_DIGITS]);
+  }
+  stem
+}
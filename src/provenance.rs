@@ -0,0 +1,109 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provenance records embedded in result archives as `cortex_provenance.json`: which worker and
+//! converter release produced a result, from which input, when, and under which configuration,
+//! so that any document of a converted corpus can be traced back and reproduced.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::adaptor;
+use crate::config::WorkerConfig;
+use crate::worker::Worker;
+
+/// Name of the provenance record at the root of every verified result archive
+pub const PROVENANCE_FILE: &str = "cortex_provenance.json";
+
+/// How a result came to be
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Provenance {
+  /// The CorTeX task id
+  pub taskid: String,
+  /// Name of the service which converted the task
+  pub service: String,
+  /// Identity of the worker thread which fetched the task
+  pub identity: String,
+  /// Version of the pericortex crate
+  pub pericortex: String,
+  /// The worker's own version
+  pub worker_version: String,
+  /// Version of the underlying converter, if known
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tool_version: Option<String>,
+  /// When the task was received, in RFC 3339 format
+  pub received: String,
+  /// When the result was completed, in RFC 3339 format
+  pub completed: String,
+  /// SHA-256 checksum of the task payload, in hex
+  pub input_sha256: String,
+  /// SHA-256 checksum of the worker configuration in effect, in hex
+  pub config_sha256: String,
+}
+
+impl Provenance {
+  /// The provenance of the result `worker` completes now for task `taskid`, received at
+  /// `received` with a payload of checksum `input_sha256`, under `config`
+  pub fn of<W: Worker>(
+    worker: &W,
+    taskid: &str,
+    received: DateTime<Local>,
+    input_sha256: String,
+    config: &WorkerConfig,
+  ) -> Provenance {
+    Provenance {
+      taskid: taskid.to_string(),
+      service: worker.get_service().to_string(),
+      identity: worker.get_identity().to_string(),
+      pericortex: env!("CARGO_PKG_VERSION").to_string(),
+      worker_version: worker.get_version(),
+      tool_version: tool_version(worker),
+      received: received.to_rfc3339(),
+      completed: Local::now().to_rfc3339(),
+      input_sha256,
+      config_sha256: config_digest(config),
+    }
+  }
+
+  /// Add this record to the ZIP archive `result`
+  pub fn embed(&self, result: File) -> Result<File, Box<dyn Error>> {
+    adaptor::upsert_zip_entry(result, PROVENANCE_FILE, &serde_json::to_vec_pretty(self)?)
+  }
+}
+
+/// Converter versions per service, probed once per process as probes may start containers
+static TOOL_VERSIONS: Mutex<BTreeMap<String, Option<String>>> = Mutex::new(BTreeMap::new());
+
+fn tool_version<W: Worker>(worker: &W) -> Option<String> {
+  let mut versions = TOOL_VERSIONS.lock().unwrap();
+  versions
+    .entry(worker.get_service().to_string())
+    .or_insert_with(|| worker.tool_version().ok())
+    .clone()
+}
+
+/// The SHA-256 checksum (in hex) of `file`, which is rewound afterwards
+pub fn sha256<F: Read + Seek>(file: &mut F) -> io::Result<String> {
+  file.seek(SeekFrom::Start(0))?;
+  let mut hasher = Sha256::new();
+  io::copy(file, &mut hasher)?;
+  file.seek(SeekFrom::Start(0))?;
+  Ok(hex::encode(hasher.finalize()))
+}
+
+/// The SHA-256 checksum (in hex) of the canonical JSON form of `config`
+pub fn config_digest(config: &WorkerConfig) -> String {
+  let json = serde_json::to_vec(config).unwrap_or_default();
+  hex::encode(Sha256::digest(&json))
+}
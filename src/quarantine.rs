@@ -0,0 +1,94 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Quarantine of poison tasks, which crash or wedge the worker every time they are converted.
+//!
+//! Each task is given a strike in a persistent quarantine file before its conversion starts,
+//! and the strike is lifted once the conversion returns, successfully or not. A task that
+//! takes the process down with it keeps its strike across restarts, and after
+//! [`QUARANTINE_STRIKES`] of them it is answered with a `Fatal:quarantined` report instead.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How many interrupted conversions put a task in quarantine, by default
+pub const QUARANTINE_STRIKES: u32 = 3;
+
+struct Quarantine {
+  path: PathBuf,
+  limit: u32,
+  strikes: BTreeMap<String, u32>,
+}
+
+static QUARANTINE: Mutex<Option<Quarantine>> = Mutex::new(None);
+
+/// Keep the strikes of this process in the file at `path`, quarantining tasks
+/// interrupted `limit` times
+pub fn open(path: &Path, limit: u32) -> Result<(), Box<dyn Error>> {
+  let strikes = read(path)?;
+  for (taskid, count) in &strikes {
+    if *count >= limit {
+      warn!(target: "quarantine", "task {} is quarantined after {} strikes", taskid, count);
+    }
+  }
+  *QUARANTINE.lock().unwrap() = Some(Quarantine {
+    path: path.to_path_buf(),
+    limit: limit.max(1),
+    strikes,
+  });
+  Ok(())
+}
+
+/// The strikes recorded in the quarantine file at `path`, by taskid
+pub fn read(path: &Path) -> Result<BTreeMap<String, u32>, Box<dyn Error>> {
+  if path.exists() {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+  } else {
+    Ok(BTreeMap::new())
+  }
+}
+
+fn persist(quarantine: &Quarantine) -> Result<(), Box<dyn Error>> {
+  // replace the file atomically, a crash mid-write must not lose the strikes
+  let staging = quarantine.path.with_extension("partial");
+  let mut file = File::create(&staging)?;
+  file.write_all(serde_json::to_string_pretty(&quarantine.strikes)?.as_bytes())?;
+  file.sync_data()?;
+  fs::rename(&staging, &quarantine.path)?;
+  Ok(())
+}
+
+/// Strike a task about to be converted, unless it is quarantined already.
+/// Returns the number of strikes of a quarantined task
+pub fn admit(taskid: &str) -> Result<(), u32> {
+  if let Some(ref mut quarantine) = *QUARANTINE.lock().unwrap() {
+    let count = quarantine.strikes.get(taskid).copied().unwrap_or(0);
+    if count >= quarantine.limit {
+      return Err(count);
+    }
+    quarantine.strikes.insert(taskid.to_string(), count + 1);
+    if let Err(e) = persist(quarantine) {
+      warn!(target: "quarantine", "failed to record a strike for task {}: {}", taskid, e);
+    }
+  }
+  Ok(())
+}
+
+/// Lift the strike of a task whose conversion returned
+pub fn release(taskid: &str) {
+  if let Some(ref mut quarantine) = *QUARANTINE.lock().unwrap() {
+    if quarantine.strikes.remove(taskid).is_some() {
+      if let Err(e) = persist(quarantine) {
+        warn!(target: "quarantine", "failed to lift the strike of task {}: {}", taskid, e);
+      }
+    }
+  }
+}
@@ -0,0 +1,100 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Run-level quotas: once the worker process fetched a number of tasks, failed a number of them,
+//! or transferred a number of bytes, it drains and exits with [`EXIT_STATUS`], so that an
+//! experimental deployment can't reprocess a whole corpus by accident.
+//!
+//! Tasks are counted as they are fetched, so `max_tasks` is never exceeded; failures and bytes
+//! are counted as results are sent, and the tasks in flight at that point are still completed.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::shutdown;
+
+/// Exit status of a worker which drained because a quota was reached
+pub const EXIT_STATUS: i32 = 3;
+
+/// Limits on a single run of the worker process, all of them optional
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Quota {
+  /// Tasks fetched across all threads
+  pub max_tasks: Option<u64>,
+  /// Tasks answered with a failure
+  pub max_failures: Option<u64>,
+  /// Bytes received and sent, payloads and results together
+  pub max_bytes: Option<u64>,
+}
+
+/// The quota a run drained on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuotaExceeded(pub String);
+
+impl fmt::Display for QuotaExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "quota reached: {}", self.0) }
+}
+
+impl Error for QuotaExceeded {}
+
+static QUOTA: Mutex<Option<Quota>> = Mutex::new(None);
+static EXCEEDED: Mutex<Option<QuotaExceeded>> = Mutex::new(None);
+static TASKS: AtomicU64 = AtomicU64::new(0);
+static FAILURES: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Enforce `quota` on this run, counting from zero
+pub fn enable(quota: Quota) {
+  TASKS.store(0, Ordering::SeqCst);
+  FAILURES.store(0, Ordering::SeqCst);
+  BYTES.store(0, Ordering::SeqCst);
+  *EXCEEDED.lock().unwrap() = None;
+  *QUOTA.lock().unwrap() = Some(quota);
+}
+
+/// The quota the run drained on, if any
+pub fn exceeded() -> Option<QuotaExceeded> { EXCEEDED.lock().unwrap().clone() }
+
+/// Record that a quota was reached, and drain
+fn exceed(reason: String) {
+  let mut exceeded = EXCEEDED.lock().unwrap();
+  if exceeded.is_none() {
+    let quota = QuotaExceeded(reason);
+    warn!(target: "quota", "{}, draining.", quota);
+    *exceeded = Some(quota);
+  }
+  shutdown::request();
+}
+
+/// May another task be fetched? Counts the task if so, and drains if not
+pub fn admit_task() -> bool {
+  let Some(max_tasks) = QUOTA.lock().unwrap().as_ref().and_then(|quota| quota.max_tasks) else {
+    return true;
+  };
+  if TASKS.fetch_add(1, Ordering::SeqCst) < max_tasks {
+    true
+  } else {
+    exceed(format!("fetched {} tasks", max_tasks));
+    false
+  }
+}
+
+/// Count a result sent for a task of `input_size` bytes, and drain if a quota is reached
+pub fn record(failed: bool, input_size: u64, result_size: u64) {
+  let Some(quota) = QUOTA.lock().unwrap().clone() else {
+    return;
+  };
+  let failures = FAILURES.fetch_add(failed as u64, Ordering::SeqCst) + failed as u64;
+  let bytes = BYTES.fetch_add(input_size + result_size, Ordering::SeqCst) + input_size + result_size;
+  if let Some(max_failures) = quota.max_failures.filter(|max| failures >= *max) {
+    exceed(format!("{} tasks failed", max_failures));
+  } else if let Some(max_bytes) = quota.max_bytes.filter(|max| bytes >= *max) {
+    exceed(format!("transferred {} of {} bytes", bytes, max_bytes));
+  }
+}
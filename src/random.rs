@@ -0,0 +1,26 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The source of randomness (identity tags, simulated latencies), seedable for reproducible runs
+
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+static SEEDED: Mutex<Option<StdRng>> = Mutex::new(None);
+
+/// Derive every generator of this process from `seed` from now on
+pub fn seed(seed: u64) { *SEEDED.lock().unwrap() = Some(StdRng::seed_from_u64(seed)); }
+
+/// A new generator, drawn from the seed if there is one, from the operating system otherwise
+pub fn rng() -> StdRng {
+  match *SEEDED.lock().unwrap() {
+    Some(ref mut seeded) => StdRng::seed_from_u64(seeded.gen()),
+    None => StdRng::from_entropy(),
+  }
+}
@@ -0,0 +1,92 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A ceiling on the task throughput of the whole worker process, `max_tasks_per_minute`, for
+//! converters calling metered backends, or for easing a new converter release into a corpus.
+//!
+//! Fetches are spaced by a token bucket holding a single token, so the tasks are spread evenly
+//! over the minute instead of arriving in bursts. Its pauses use the installed [`clock`].
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::shutdown;
+
+/// A token bucket, refilled at a constant rate, holding at most one token
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+  per_minute: f64,
+  tokens: f64,
+  refilled: Instant,
+}
+
+impl TokenBucket {
+  /// A full bucket granting `per_minute` tokens a minute
+  pub fn new(per_minute: f64, now: Instant) -> TokenBucket {
+    TokenBucket {
+      per_minute,
+      tokens: 1.0,
+      refilled: now,
+    }
+  }
+
+  /// The rate of the bucket, in tokens per minute
+  pub fn per_minute(&self) -> f64 { self.per_minute }
+
+  /// Take a token at `now`, returning how long to wait until it is due
+  pub fn take(&mut self, now: Instant) -> Duration {
+    let per_second = self.per_minute / 60.0;
+    let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * per_second).min(1.0) - 1.0;
+    self.refilled = now;
+    if self.tokens >= 0.0 {
+      Duration::ZERO
+    } else {
+      Duration::from_secs_f64(-self.tokens / per_second)
+    }
+  }
+}
+
+static BUCKET: Mutex<Option<TokenBucket>> = Mutex::new(None);
+
+/// Fetch at most `per_minute` tasks a minute from now on, or any number of them for `None`
+pub fn set_max_tasks_per_minute(per_minute: Option<f64>) {
+  let mut bucket = BUCKET.lock().unwrap();
+  match per_minute.filter(|rate| *rate > 0.0) {
+    // keep the tokens already earned when a reload leaves the rate unchanged
+    Some(rate) if bucket.as_ref().map(TokenBucket::per_minute) == Some(rate) => {}
+    Some(rate) => *bucket = Some(TokenBucket::new(rate, clock::now())),
+    None => *bucket = None,
+  }
+}
+
+/// Blocks until the next task may be fetched. Returns false if a shutdown was requested meanwhile
+pub fn acquire(identity: &str) -> bool {
+  let wait = match *BUCKET.lock().unwrap() {
+    Some(ref mut bucket) => bucket.take(clock::now()),
+    None => return true,
+  };
+  if wait > Duration::new(1, 0) {
+    debug!(
+      target: &format!("{}:ratelimit", identity),
+      "waiting {:.1} seconds for the next task.",
+      wait.as_secs_f64()
+    );
+  }
+  let due = clock::now() + wait;
+  loop {
+    let now = clock::now();
+    if now >= due {
+      return true;
+    }
+    if shutdown::requested() {
+      return false;
+    }
+    clock::sleep((due - now).min(Duration::new(1, 0)));
+  }
+}
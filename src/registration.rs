@@ -0,0 +1,124 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Optional service registration with a CorTeX registration endpoint at startup.
+//!
+//! The worker sends a JSON [`Registration`] over a REQ socket and expects a JSON
+//! [`RegistrationReply`]. A service unknown to CorTeX, or an endpoint that doesn't answer,
+//! stops the worker before it starts polling for tasks that would never be scheduled.
+
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::worker::Worker;
+
+/// How long to wait for the registration endpoint to answer
+pub const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a worker announces about itself
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Registration {
+  /// Name of the service, as registered in CorTeX
+  pub service: String,
+  /// The worker's own version
+  pub version: String,
+  /// Version of the underlying converter, if known
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tool: Option<String>,
+  /// Format of the task inputs, e.g. `tex`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub input_format: Option<String>,
+  /// Format of the results, e.g. `html`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub output_format: Option<String>,
+  /// Tasks the worker converts simultaneously
+  pub capacity: usize,
+  /// Version of the pericortex crate
+  pub pericortex: String,
+}
+
+impl Registration {
+  /// The announcement of `worker`
+  pub fn of<W: Worker>(worker: &W) -> Registration {
+    Registration {
+      service: worker.get_service().to_string(),
+      version: worker.get_version(),
+      tool: worker.tool_version().ok(),
+      input_format: worker.input_format(),
+      output_format: worker.output_format(),
+      capacity: worker.pool_size(),
+      pericortex: env!("CARGO_PKG_VERSION").to_string(),
+    }
+  }
+}
+
+/// The answer of the registration endpoint
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RegistrationReply {
+  /// `registered` if CorTeX will schedule tasks for the service, e.g. `unknown_service` otherwise
+  pub status: String,
+  /// Details for the operator
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub message: Option<String>,
+}
+
+static ENDPOINT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Register with the endpoint at `address` (e.g. `tcp://127.0.0.1:51697`) whenever a worker starts
+pub fn enable(address: &str) { *ENDPOINT.lock().unwrap() = Some(address.to_string()); }
+
+/// Register `worker` with the enabled endpoint, if any
+pub fn register_if_enabled<W: Worker>(worker: &W) -> Result<(), Box<dyn Error>> {
+  let address = ENDPOINT.lock().unwrap().clone();
+  match address {
+    Some(address) => register(&address, worker, REGISTRATION_TIMEOUT),
+    None => Ok(()),
+  }
+}
+
+/// Announce `worker` to the registration endpoint at `address`, failing unless it
+/// answers within `timeout` that the service is registered
+pub fn register<W: Worker>(address: &str, worker: &W, timeout: Duration) -> Result<(), Box<dyn Error>> {
+  let registration = Registration::of(worker);
+  let context = zmq::Context::new();
+  let socket = context.socket(zmq::REQ)?;
+  socket.set_linger(0)?;
+  socket.set_ipv6(true)?;
+  socket.set_rcvtimeo(timeout.as_millis() as i32)?;
+  socket.connect(address)?;
+  socket.send(serde_json::to_string(&registration)?.as_bytes(), 0)?;
+  let reply = match socket.recv_bytes(0) {
+    Ok(reply) => reply,
+    Err(zmq::Error::EAGAIN) => {
+      return Err(From::from(format!(
+        "the registration endpoint {} did not answer within {} seconds",
+        address,
+        timeout.as_secs()
+      )))
+    }
+    Err(e) => return Err(e.into()),
+  };
+  let reply: RegistrationReply = serde_json::from_slice(&reply)
+    .map_err(|e| format!("malformed answer from the registration endpoint {}: {}", address, e))?;
+  if reply.status == "registered" {
+    info!(
+      target: "registration",
+      "registered service {:?} with capacity {} at {}", registration.service, registration.capacity, address
+    );
+    Ok(())
+  } else {
+    Err(From::from(format!(
+      "CorTeX refused service {:?} ({}){}; make sure the service is set up in CorTeX before starting its workers",
+      registration.service,
+      reply.status,
+      reply.message.map(|message| format!(": {}", message)).unwrap_or_default()
+    )))
+  }
+}
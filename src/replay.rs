@@ -0,0 +1,221 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Task record-and-replay, for reproducing dispatcher-reported failures locally
+//! and for regression-testing converter upgrades.
+//!
+//! A replay directory holds, for every recorded task, the received payload `<taskid>.zip`,
+//! its metadata `<taskid>.json` and, if the conversion succeeded, `<taskid>.result.zip`.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use tempdir::TempDir;
+
+use crate::adaptor;
+use crate::local::BatchObserver;
+use crate::protocol::taskid_file_stem;
+use crate::report::{LogSummary, TaskReport};
+use crate::worker::Worker;
+
+/// Metadata of a recorded task
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskRecord {
+  /// The task id assigned by the dispatcher
+  pub taskid: String,
+  /// The service the task was requested for
+  pub service: String,
+  /// Identity of the worker thread which received it
+  pub identity: String,
+  /// Time of receipt, in RFC 3339 format
+  pub received: String,
+  /// Size of the payload in bytes
+  pub input_size: usize,
+}
+
+/// Directory into which received tasks are recorded, if recording
+static RECORD_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Record every task received from now on into `dir`
+pub fn record_into(dir: &Path) -> Result<(), Box<dyn Error>> {
+  fs::create_dir_all(dir)?;
+  *RECORD_DIR.lock().unwrap() = Some(dir.to_path_buf());
+  Ok(())
+}
+
+fn recording() -> Option<PathBuf> { RECORD_DIR.lock().unwrap().clone() }
+
+/// Record a received task payload with its metadata, if recording
+pub fn record_task<R: Read + Seek>(record: TaskRecord, payload: &mut R) {
+  if let Some(dir) = recording() {
+    let stem = taskid_file_stem(&record.taskid);
+    let recorded = File::create(dir.join(format!("{}.zip", stem)))
+      .and_then(|mut destination| io::copy(payload, &mut destination))
+      .and_then(|_| payload.seek(SeekFrom::Start(0)))
+      .map_err(Box::<dyn Error>::from)
+      .and_then(|_| serde_json::to_string_pretty(&record).map_err(Into::into))
+      .and_then(|json| {
+        fs::write(dir.join(format!("{}.json", stem)), json).map_err(Into::into)
+      });
+    if let Err(e) = recorded {
+      warn!(target: "replay", "failed to record task {}: {}", record.taskid, e);
+    }
+  }
+}
+
+/// Record the result of a task, if recording, leaving `result` rewound for transmission
+pub fn record_result(taskid: &str, result: &mut File) {
+  if let Some(dir) = recording() {
+    let recorded = File::create(dir.join(format!("{}.result.zip", taskid_file_stem(taskid))))
+      .and_then(|mut destination| io::copy(result, &mut destination))
+      .and_then(|_| result.seek(SeekFrom::Start(0)));
+    if let Err(e) = recorded {
+      warn!(target: "replay", "failed to record the result of task {}: {}", taskid, e);
+    }
+  }
+}
+
+/// Metadata stamp for a task received now
+pub fn stamp(taskid: &str, service: &str, identity: &str, input_size: usize) -> TaskRecord {
+  TaskRecord {
+    taskid: taskid.to_string(),
+    service: service.to_string(),
+    identity: identity.to_string(),
+    received: Local::now().to_rfc3339(),
+    input_size,
+  }
+}
+
+/// How a replayed task compares to its recorded result
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplayVerdict {
+  /// Every file in the new result matches the recorded one
+  Identical,
+  /// The named files were added, removed or changed
+  Differs(Vec<String>),
+  /// The conversion succeeded, but there was no recorded result to compare against
+  NoRecordedResult,
+  /// The conversion failed
+  Failed(String),
+}
+
+/// The replay of a single recorded task
+#[derive(Clone, Debug)]
+pub struct ReplayOutcome {
+  /// The recorded task
+  pub record: TaskRecord,
+  /// Comparison against the recorded result
+  pub verdict: ReplayVerdict,
+  /// Size of the new result, or the conversion error
+  pub result: Result<u64, String>,
+  /// Wall-clock time spent on the conversion
+  pub duration: Duration,
+  /// What the `cortex.log` of the new result reports
+  pub log: LogSummary,
+}
+
+impl From<&ReplayOutcome> for TaskReport {
+  fn from(outcome: &ReplayOutcome) -> TaskReport {
+    let mut report = TaskReport::new(&outcome.record.taskid, outcome.duration, &outcome.result, &outcome.log);
+    if let ReplayVerdict::Differs(ref files) = outcome.verdict {
+      report.changed_files = files.clone();
+    }
+    report
+  }
+}
+
+/// All task records in a replay directory, ordered by task id
+pub fn records(dir: &Path) -> Result<Vec<TaskRecord>, Box<dyn Error>> {
+  let mut records = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.extension().map(|ext| ext == "json").unwrap_or(false) {
+      records.push(serde_json::from_str::<TaskRecord>(&fs::read_to_string(&path)?)?);
+    }
+  }
+  records.sort_by(|a, b| a.taskid.cmp(&b.taskid));
+  Ok(records)
+}
+
+/// Re-run every task recorded in `dir` through `worker.convert`, comparing against the recorded results
+pub fn replay<W: Worker>(worker: &W, dir: &Path) -> Result<Vec<ReplayOutcome>, Box<dyn Error>> {
+  replay_observed(worker, dir, &())
+}
+
+/// As [`replay`], reporting every task to `observer`, where a task fails when its result changed
+pub fn replay_observed<W: Worker>(
+  worker: &W,
+  dir: &Path,
+  observer: &dyn BatchObserver,
+) -> Result<Vec<ReplayOutcome>, Box<dyn Error>> {
+  let scratch = TempDir::new("cortex_replay")?;
+  let mut outcomes = Vec::new();
+  let records = records(dir)?;
+  observer.planned(records.len());
+  for record in records {
+    observer.started(worker.get_identity(), &record.taskid);
+    let stem = taskid_file_stem(&record.taskid);
+    let payload = dir.join(format!("{}.zip", stem));
+    let replayed = scratch.path().join(format!("{}.result.zip", stem));
+    let start = Instant::now();
+    let result = worker.convert_local(&payload, &replayed).map_err(|e| e.to_string());
+    let duration = start.elapsed();
+    let verdict = match result {
+      Err(ref e) => ReplayVerdict::Failed(e.clone()),
+      Ok(_) => {
+        let recorded = dir.join(format!("{}.result.zip", stem));
+        if recorded.exists() {
+          compare(&recorded, &replayed)?
+        } else {
+          ReplayVerdict::NoRecordedResult
+        }
+      }
+    };
+    let error = match verdict {
+      ReplayVerdict::Differs(ref files) => Some(format!("differs in {}", files.join(", "))),
+      ReplayVerdict::Failed(ref e) => Some(format!("failed: {}", e)),
+      ReplayVerdict::Identical | ReplayVerdict::NoRecordedResult => None,
+    };
+    observer.finished(worker.get_identity(), &record.taskid, error.as_deref());
+    let log = match result {
+      Ok(_) => LogSummary::of(&replayed).unwrap_or_default(),
+      Err(_) => LogSummary::default(),
+    };
+    outcomes.push(ReplayOutcome {
+      record,
+      verdict,
+      result,
+      duration,
+      log,
+    });
+  }
+  Ok(outcomes)
+}
+
+/// Compare two result archives file by file
+fn compare(recorded: &Path, replayed: &Path) -> Result<ReplayVerdict, Box<dyn Error>> {
+  let old = adaptor::archive_manifest(recorded)?;
+  let new = adaptor::archive_manifest(replayed)?;
+  let mut differing: Vec<String> = old
+    .iter()
+    .filter(|(name, digest)| new.get(*name) != Some(digest))
+    .map(|(name, _)| name.clone())
+    .collect();
+  differing.extend(new.keys().filter(|name| !old.contains_key(*name)).cloned());
+  Ok(if differing.is_empty() {
+    ReplayVerdict::Identical
+  } else {
+    differing.sort();
+    ReplayVerdict::Differs(differing)
+  })
+}
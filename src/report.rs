@@ -0,0 +1,182 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Machine-readable reports of local batch runs and replays, for analyzing converter evaluations
+//! in notebooks rather than scraping logs.
+//!
+//! A [`Report`] holds a [`TaskReport`] per task, with its status, duration, output size and most
+//! frequent `cortex.log` codes, and a [`RunSummary`] aggregating them. [`Report::write`] saves
+//! the tasks as `report.csv` and the whole report as `report.json`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::adaptor;
+use crate::logcodes::{self, Severity};
+
+/// Log codes listed per task
+pub const TOP_CODES_PER_TASK: usize = 5;
+/// Log codes listed for a whole run
+pub const TOP_CODES_PER_RUN: usize = 20;
+
+/// What the `cortex.log` of a result archive reports
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LogSummary {
+  /// The most severe message level, if any message was found
+  pub severity: Option<Severity>,
+  /// Number of messages per `Severity:category:what` code, warnings and worse only
+  pub codes: BTreeMap<String, usize>,
+}
+
+impl LogSummary {
+  /// Summarize the `cortex.log` of the result archive at `path`, if it has one
+  pub fn of(path: &Path) -> Result<LogSummary, Box<dyn Error>> {
+    let log = adaptor::read_archive_entry(path, "cortex.log")?.unwrap_or_default();
+    let messages = logcodes::parse(&String::from_utf8_lossy(&log));
+    Ok(LogSummary {
+      severity: logcodes::max_severity(&messages),
+      codes: logcodes::code_counts(&messages, Severity::Warning),
+    })
+  }
+}
+
+/// The report of a single task
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TaskReport {
+  /// The task: the entry path of a batch run, or the taskid of a replay
+  pub task: String,
+  /// `ok`, `warning`, `error` or `fatal` as per the result's `cortex.log`, or `failed` when the
+  /// conversion returned no result
+  pub status: String,
+  /// Wall-clock time spent on the conversion
+  pub duration_ms: u64,
+  /// Size of the result, if there is one
+  pub output_bytes: Option<u64>,
+  /// The most frequent log codes of the result, with their counts
+  pub top_codes: Vec<(String, usize)>,
+  /// Why the conversion failed, if it did
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+  /// The files of a replayed result which differ from the recorded one
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub changed_files: Vec<String>,
+}
+
+impl TaskReport {
+  /// The report of `task`, which took `duration` and produced a result of `output` bytes whose
+  /// log says `log`, or failed with an error
+  pub fn new(task: &str, duration: Duration, output: &Result<u64, String>, log: &LogSummary) -> TaskReport {
+    let status = match (output, log.severity) {
+      (Err(_), _) => "failed",
+      (Ok(_), Some(Severity::Fatal)) => "fatal",
+      (Ok(_), Some(Severity::Error)) => "error",
+      (Ok(_), Some(Severity::Warning)) => "warning",
+      (Ok(_), Some(Severity::Info) | None) => "ok",
+    };
+    TaskReport {
+      task: task.to_string(),
+      status: status.to_string(),
+      duration_ms: duration.as_millis() as u64,
+      output_bytes: output.as_ref().ok().copied(),
+      top_codes: logcodes::top_codes(&log.codes, TOP_CODES_PER_TASK),
+      error: output.as_ref().err().cloned(),
+      changed_files: Vec::new(),
+    }
+  }
+}
+
+/// The aggregate of a run
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RunSummary {
+  /// Tasks in the run
+  pub tasks: usize,
+  /// Tasks per status
+  pub statuses: BTreeMap<String, usize>,
+  /// Total conversion time of all tasks
+  pub total_duration_ms: u64,
+  /// Median conversion time
+  pub median_duration_ms: u64,
+  /// Longest conversion time
+  pub max_duration_ms: u64,
+  /// Total size of the results
+  pub output_bytes: u64,
+  /// The most frequent log codes across the run, with the number of tasks reporting them
+  pub top_codes: Vec<(String, usize)>,
+}
+
+/// The reports of all tasks of a run, and their aggregate
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Report {
+  /// The aggregate of the run
+  pub summary: RunSummary,
+  /// A report per task
+  pub tasks: Vec<TaskReport>,
+}
+
+impl Report {
+  /// The report of a run of `tasks`
+  pub fn new(tasks: Vec<TaskReport>) -> Report {
+    let mut durations: Vec<u64> = tasks.iter().map(|task| task.duration_ms).collect();
+    durations.sort_unstable();
+    let mut statuses = BTreeMap::new();
+    let mut codes = BTreeMap::new();
+    for task in &tasks {
+      *statuses.entry(task.status.clone()).or_insert(0) += 1;
+      for (code, _) in &task.top_codes {
+        *codes.entry(code.clone()).or_insert(0) += 1;
+      }
+    }
+    let summary = RunSummary {
+      tasks: tasks.len(),
+      statuses,
+      total_duration_ms: durations.iter().sum(),
+      median_duration_ms: durations.get(durations.len() / 2).copied().unwrap_or(0),
+      max_duration_ms: durations.last().copied().unwrap_or(0),
+      output_bytes: tasks.iter().filter_map(|task| task.output_bytes).sum(),
+      top_codes: logcodes::top_codes(&codes, TOP_CODES_PER_RUN),
+    };
+    Report { summary, tasks }
+  }
+
+  /// Write `report.csv`, a row per task, and `report.json`, the summary and the tasks, into `dir`
+  pub fn write(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let mut csv = File::create(dir.join("report.csv"))?;
+    writeln!(csv, "task,status,duration_ms,output_bytes,top_codes,error,changed_files")?;
+    for task in &self.tasks {
+      let codes: Vec<String> = task.top_codes.iter().map(|(code, count)| format!("{}={}", code, count)).collect();
+      writeln!(
+        csv,
+        "{},{},{},{},{},{},{}",
+        csv_field(&task.task),
+        task.status,
+        task.duration_ms,
+        task.output_bytes.map(|bytes| bytes.to_string()).unwrap_or_default(),
+        csv_field(&codes.join(";")),
+        csv_field(task.error.as_deref().unwrap_or_default()),
+        csv_field(&task.changed_files.join(";"))
+      )?;
+    }
+    fs::write(dir.join("report.json"), serde_json::to_vec_pretty(self)?)?;
+    Ok(())
+  }
+}
+
+/// Quote a CSV field when needed
+pub fn csv_field(field: &str) -> String {
+  if field.contains([',', '"', '\n']) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
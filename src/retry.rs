@@ -0,0 +1,42 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! In-place retries of conversions that failed for transient reasons
+
+use std::error::Error;
+use std::time::Duration;
+
+use crate::error;
+
+/// How often, and how patiently, to retry a conversion that failed transiently
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+  /// Conversion attempts per task, including the first one
+  pub attempts: usize,
+  /// Pause before the first retry, doubled for every further one
+  pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+  /// A single attempt, failures are reported right away
+  fn default() -> RetryPolicy {
+    RetryPolicy {
+      attempts: 1,
+      backoff: Duration::new(5, 0),
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Is `error` worth retrying? Only transient failures are, see [`error::is_transient`]
+  pub fn is_retryable(&self, error: &(dyn Error + 'static)) -> bool { error::is_transient(error) }
+
+  /// Pause before retry number `retry`, counting from 1
+  pub fn delay(&self, retry: usize) -> Duration {
+    self.backoff * 2u32.saturating_pow(retry.saturating_sub(1) as u32)
+  }
+}
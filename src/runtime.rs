@@ -0,0 +1,870 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The worker runtime: the pool of IO and converter threads behind [`Worker::start`], the loop
+//! restarting it for a reloaded configuration, and the drain on shutdown. Chunked IO lives in
+//! [`transfer`](crate::transfer), the policies gating and retrying tasks in [`policy`].
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use tempfile::SpooledTempFile;
+use zmq::Context;
+
+use crate::adaptor::{self, ArchivingTotals, RejectReason};
+use crate::artifacts::{self, Artifact};
+use crate::bench;
+use crate::config::{self, ConfigResolver, WorkerConfig};
+use crate::crash;
+use crate::dedup::{self, DedupPolicy, Duplicate};
+use crate::dryrun;
+use crate::endpoint::{self, ReconnectionMonitor};
+use crate::error::WorkerError;
+use crate::filters::{self, FilterContext};
+use crate::health;
+use crate::identity::Identity;
+use crate::journal::{self, Snapshot};
+use crate::mirror;
+use crate::policy::{self, Conversion, EmptyInput};
+use crate::pressure;
+use crate::progress;
+use crate::protocol::{self, Handshake, ProbeReply, ProtocolError, ResultStatus, TaskMetadata, TaskStats};
+use crate::provenance::{self, Provenance};
+use crate::quarantine;
+use crate::quota;
+use crate::registration;
+use crate::replay;
+use crate::scratch;
+use crate::shutdown;
+#[cfg(feature = "signing")]
+use crate::signing;
+use crate::state::{self, Phase};
+#[cfg(feature = "object-storage")]
+use crate::storage;
+use crate::transfer;
+use crate::tuning::{self, FrameSizeTuner};
+use crate::watchdog;
+use crate::worker::Worker;
+
+/// Set up the worker process for `worker`, with as many threads as requested, and run it until it
+/// drained or converted `limit` tasks per converter
+pub(crate) fn start<W: Worker + 'static>(worker: &mut W, limit: Option<usize>) -> Result<(), Box<dyn Error>> {
+  // the one place the configuration file is watched, with the overrides of whoever installed it
+  let file = match (config::file(), worker.config_path()) {
+    (Some(file), _) => Some(file),
+    (None, Some(path)) => {
+      let config = WorkerConfig::load(&path)?;
+      worker.configure(&config);
+      config::install(config);
+      Some((path, WorkerConfig::default()))
+    }
+    (None, None) => None,
+  };
+  if let Some((path, overrides)) = file {
+    config::watch(path, overrides)?;
+  }
+  endpoint::validate_io_threads(worker.io_threads())?;
+  worker.probe_endpoints()?;
+  if let Some(address) = worker.health_address() {
+    health::serve(&address, worker.clone())?;
+  }
+  if let Some(limits) = worker.load_limits() {
+    pressure::control(limits, worker.pool_size());
+  }
+  worker.warmup()?;
+  registration::register_if_enabled(worker)?;
+  mirror::spawn(worker);
+  match worker.tool_version() {
+    Ok(version) => info!(target: "pericortex", "{} converts with {}", worker.get_service(), version),
+    Err(e) => warn!(target: "pericortex", "{} converter version unknown: {}", worker.get_service(), e),
+  }
+  loop {
+    let threads = |count: usize| -> Vec<W> {
+      let count = count.max(1);
+      (1..=count)
+        .map(|slot| {
+          let mut thread_worker: W = worker.clone();
+          thread_worker.set_identity(Identity::new(worker.get_service(), slot, count).to_string());
+          thread_worker
+        })
+        .collect()
+    };
+    pressure::set_pool_size(worker.pool_size());
+    run_pool(threads(worker.io_threads()), threads(worker.pool_size()), limit)?;
+    // The pool was drained for a reloaded configuration, reconnect with the new settings
+    if !config::take_restart() || shutdown::requested() {
+      report_log_categories(worker);
+      mirror::finish();
+      return Ok(());
+    }
+    if let Some(config) = config::current() {
+      worker.configure(&config);
+    }
+    info!(target: "config", "restarting the worker pool with the reloaded configuration.");
+  }
+}
+
+/// Log the dominant failure modes of the converter over the run
+fn report_log_categories<W: Worker>(worker: &W) {
+  let state = state::snapshot();
+  if state.log_categories.is_empty() {
+    return;
+  }
+  let target = format!("{}:summary", worker.get_service());
+  info!(
+    target: &target,
+    "the most frequent log categories of {} results:", state.completed + state.failed
+  );
+  for (category, results) in &state.log_categories {
+    info!(target: &target, "{:>8}  {}", results, category);
+  }
+}
+
+/// The settings applying the frame size chosen by `tuner`
+fn tuned(tuner: &FrameSizeTuner) -> WorkerConfig {
+  WorkerConfig {
+    message_size: Some(tuner.message_size()),
+    ..WorkerConfig::default()
+  }
+}
+
+/// A task received by a fetcher thread, waiting for the next idle converter thread
+struct ReceivedTask {
+  /// Identity of the slot which fetched the task, under which its result is sent
+  identity: String,
+  taskid: String,
+  payload: Result<SpooledTempFile, Box<dyn Error + Send + Sync>>,
+  input_size: usize,
+  metadata: Option<TaskMetadata>,
+}
+
+/// A result on its way to the sink
+struct Outgoing {
+  /// Identity of the slot which fetched the task
+  identity: String,
+  taskid: String,
+  result: Result<File, String>,
+  /// The classification sent ahead of the result, from protocol version 4 on
+  status: Option<ResultStatus>,
+  /// The resource usage sent ahead of the result, from protocol version 5 on
+  stats: Option<TaskStats>,
+  /// The secondary artifacts sent after the stats, from protocol version 10 on
+  artifacts: Vec<Artifact>,
+  input_size: usize,
+}
+
+/// Run a pool of IO threads, each receiving tasks and sending results under its own identity,
+/// and a separately sized pool of converter threads. Converters take the next received task
+/// whenever they are idle, so that a thread stuck on a long conversion doesn't hold back tasks
+/// the others could be working on, and hand their results to whichever IO thread sends next
+pub(crate) fn run_pool<W: Worker + 'static>(
+  io_threads: Vec<W>,
+  converters: Vec<W>,
+  limit: Option<usize>,
+) -> Result<(), Box<dyn Error>> {
+  // idle converters ask for a task, and fetchers request exactly one task per idle converter
+  let (idle_sender, idle_receiver) = mpsc::sync_channel::<()>(converters.len());
+  let (task_sender, task_receiver) = mpsc::sync_channel::<ReceivedTask>(converters.len());
+  // converters block once every IO thread is busy sending, e.g. to a slow sink
+  let (result_sender, result_receiver) = mpsc::sync_channel::<Outgoing>(io_threads.len());
+  let idle_receiver = Arc::new(Mutex::new(idle_receiver));
+  let task_receiver = Arc::new(Mutex::new(task_receiver));
+  let result_receiver = Arc::new(Mutex::new(result_receiver));
+  // the IO threads share one ZeroMQ context per endpoint, with an I/O thread each
+  let endpoints = &io_threads[0];
+  let source_context = endpoint::network_context(&endpoints.get_source_address().to_string(), io_threads.len())?;
+  let sink_context = endpoint::network_context(&endpoints.get_sink_address().to_string(), io_threads.len())?;
+  let mut threads = Vec::new();
+  for io_thread in io_threads {
+    let fetcher = io_thread.clone();
+    let idle = Arc::clone(&idle_receiver);
+    let tasks = task_sender.clone();
+    let context = source_context.clone();
+    threads.push(thread::spawn(move || fetch_tasks(fetcher, &context, &idle, tasks)));
+    let results = Arc::clone(&result_receiver);
+    let context = sink_context.clone();
+    threads.push(thread::spawn(move || send_results(io_thread, &context, &results)));
+  }
+  let spawn_converter = |converter: W| {
+    let idle = idle_sender.clone();
+    let tasks = Arc::clone(&task_receiver);
+    let results = result_sender.clone();
+    let identity = converter.get_identity().to_string();
+    (identity, thread::spawn(move || convert_tasks(converter, idle, &tasks, results, limit)))
+  };
+  let mut converter_threads: Vec<_> =
+    converters.iter().map(|converter| (converter.clone(), spawn_converter(converter.clone()))).collect();
+  // wedged converters are left behind, and replaced by fresh ones
+  while watchdog::respawning() && !converter_threads.iter().all(|(_, (_, t))| t.is_finished()) {
+    for wedged in watchdog::take_wedged() {
+      if let Some(index) = converter_threads.iter().position(|(_, (identity, _))| *identity == wedged) {
+        let (converter, _) = converter_threads.swap_remove(index);
+        info!(target: &format!("{}:watchdog", wedged), "respawning the wedged thread.");
+        converter_threads.push((converter.clone(), spawn_converter(converter)));
+      }
+    }
+    thread::sleep(Duration::new(1, 0));
+  }
+  // the channels close once all threads on either side are done
+  drop(idle_sender);
+  drop(task_sender);
+  drop(result_sender);
+  for (_, (_, t)) in converter_threads {
+    t.join().map_err(|_| "a worker thread panicked")?;
+  }
+  // an IO thread failing on its socket leaves the others running, and is reported once all are done
+  let mut failure = None;
+  for t in threads {
+    if let Err(e) = t.join().map_err(|_| "a worker thread panicked")? {
+      failure = Some(e);
+    }
+  }
+  match failure {
+    Some(e) => Err(From::from(format!("an IO thread failed: {}", e))),
+    None => Ok(()),
+  }
+}
+
+/// Fetcher thread: request a task from the dispatcher for every idle converter, until the source
+/// socket can't be set up or its context is terminated
+fn fetch_tasks<W: Worker>(
+  mut worker: W,
+  context_source: &Context,
+  idle: &Mutex<Receiver<()>>,
+  tasks: SyncSender<ReceivedTask>,
+) -> zmq::Result<()> {
+  let fetcher = format!("{}:fetch", worker.get_identity());
+  let liveness = health::register(&fetcher);
+  // Connect to a task ventilator
+  let source_address = worker.get_source_address().to_string();
+  let source = context_source.socket(zmq::DEALER)?;
+  source.set_identity(worker.get_identity().as_bytes())?;
+  // pending service requests are worthless once we exit, don't block on them at shutdown
+  source.set_linger(0)?;
+  source.set_ipv6(true)?;
+
+  source.connect(&source_address)?;
+  let reconnections = ReconnectionMonitor::new(context_source, &source, worker.get_identity())?;
+  // Converter versions only change with a redeployment, probe them once per thread
+  let handshake = if protocol::version() >= protocol::HANDSHAKE_VERSION {
+    Some(Handshake::new(worker.get_version(), worker.tool_version().ok()).frame())
+  } else {
+    None
+  };
+  let mut applied_generation = config::generation();
+  loop {
+    // Wait for an idle converter, a received task never waits on a busy one
+    let waiting = idle.lock().unwrap().recv_timeout(Duration::new(1, 0));
+    match waiting {
+      Ok(()) if !shutdown::requested() => {}
+      Err(RecvTimeoutError::Timeout) if !shutdown::requested() => continue,
+      _ => break,
+    }
+    // the negotiated compression may have been reloaded
+    if config::generation() != applied_generation {
+      applied_generation = config::generation();
+      worker.configure(&config::current().unwrap_or_default());
+    }
+    if !policy::admit_fetch(worker.get_identity()) {
+      break;
+    }
+    state::enter(&fetcher, Phase::Receiving, None);
+    let received = loop {
+      match worker.receive_from_cortex(&source, &reconnections, handshake.as_deref()) {
+        Ok(received) => break received,
+        Err(e @ zmq::Error::ETERM) => return Err(e),
+        Err(e) => {
+          // the converter is still idle, resynchronize and ask for its task again
+          warn!(
+            target: &format!("{}:source", worker.get_identity()),
+            "failed to receive from CorTeX, requesting a task again: {}", e
+          );
+          transfer::drain_message(&source);
+          thread::sleep(Duration::new(1, 0));
+        }
+      }
+    };
+    state::enter(&fetcher, Phase::Idle, None);
+    let (payload, input_size, taskid, metadata) = received;
+    if taskid.is_empty() && shutdown::requested() {
+      // interrupted while idle, there is no task to complete
+      info!(target: &format!("{}:shutdown", worker.get_identity()), "exiting.");
+      break;
+    }
+    let payload = payload.map_err(sendable);
+    let task = ReceivedTask {
+      identity: worker.get_identity().to_string(),
+      taskid,
+      payload,
+      input_size,
+      metadata,
+    };
+    if tasks.send(task).is_err() {
+      break;
+    }
+  }
+  liveness.finish();
+  Ok(())
+}
+
+/// A receive failure, handed to a converter thread as the error it is where its type is one the
+/// converters classify, and by its message otherwise
+fn sendable(error: Box<dyn Error>) -> Box<dyn Error + Send + Sync> {
+  let error = match error.downcast::<ProtocolError>() {
+    Ok(error) => return error,
+    Err(error) => error,
+  };
+  let error = match error.downcast::<WorkerError>() {
+    Ok(error) => return error,
+    Err(error) => error,
+  };
+  match error.downcast::<io::Error>() {
+    Ok(error) => error,
+    Err(error) => From::from(error.to_string()),
+  }
+}
+
+/// IO thread: send the results of the converters to the sink, until the sink socket can't be set up
+fn send_results<W: Worker>(
+  mut worker: W,
+  context_sink: &Context,
+  results: &Mutex<Receiver<Outgoing>>,
+) -> zmq::Result<()> {
+  let identity = worker.get_identity().to_string();
+  let sender = format!("{}:send", identity);
+  let liveness = health::register(&sender);
+  // Connect to a task sink
+  let sink_address = worker.get_sink_address().to_string();
+  let mut high_water_mark = pressure::sink_high_water_mark(&worker);
+  let mut sink = connect_sink(context_sink, &sink_address, high_water_mark)?;
+  if let Err(e) = progress::connect_keepalive(&sink_address) {
+    warn!(target: &format!("{}:send", identity), "sending no keepalives: {}", e);
+  }
+  let mut applied_generation = config::generation();
+  // Search for the best frame size on the first tasks, if asked to
+  let mut tuner = tuning::tuner();
+  if let Some(ref tuner) = tuner {
+    worker.configure(&tuned(tuner));
+  }
+  loop {
+    let received = results.lock().unwrap().recv();
+    let Ok(outgoing) = received else {
+      // all converters are done
+      break;
+    };
+    // the message size may have been reloaded
+    if config::generation() != applied_generation {
+      applied_generation = config::generation();
+      worker.configure(&config::current().unwrap_or_default());
+      if let Some(ref tuner) = tuner {
+        worker.configure(&tuned(tuner));
+      }
+      // the high water mark only applies to new connections, reconnect for larger frames, while
+      // the closed socket still delivers the results queued on it
+      let reloaded = pressure::sink_high_water_mark(&worker);
+      if reloaded != high_water_mark {
+        high_water_mark = reloaded;
+        sink = connect_sink(context_sink, &sink_address, high_water_mark)?;
+      }
+    }
+    // Wait for a slow sink to catch up, which holds back the converters and thus the fetchers
+    if !pressure::await_sink(&sink, &identity) {
+      // a shutdown was requested while the sink is still full, don't block exiting on it; the
+      // dispatcher redelivers tasks it has no result for
+      warn!(
+        target: &format!("{}:shutdown", identity),
+        "the sink is full, dropping the result of task {}.", outgoing.taskid
+      );
+      state::record(true);
+      continue;
+    }
+    worker.set_identity(outgoing.identity);
+    let result = outgoing.result;
+    // Results too large for the sink go to object storage, the sink receives their manifest
+    #[cfg(feature = "object-storage")]
+    let result = result.map(|file| storage::offload(file, worker.get_service(), &outgoing.taskid));
+    let sent_bytes = result
+      .as_ref()
+      .ok()
+      .and_then(|file| file.metadata().ok())
+      .map(|metadata| metadata.len());
+    let sending = Instant::now();
+    let failed = result.is_err();
+    state::enter(&sender, Phase::Sending, Some(&outgoing.taskid));
+    // the mirror sinks get a copy of whatever the sink gets
+    let (mut result, mut artifacts) = (result, outgoing.artifacts);
+    mirror::forward(
+      worker.get_identity(),
+      &outgoing.taskid,
+      &mut result,
+      outgoing.status.as_ref(),
+      outgoing.stats.as_ref(),
+      &mut artifacts,
+      outgoing.input_size,
+    );
+    let result = result.map_err(From::from);
+    worker.respond_to_cortex(
+      result,
+      outgoing.status.as_ref(),
+      outgoing.stats.as_ref(),
+      artifacts,
+      outgoing.input_size,
+      &outgoing.taskid,
+      &sink,
+    );
+    state::enter(&sender, Phase::Idle, None);
+    state::record(failed);
+    quota::record(failed, outgoing.input_size as u64, sent_bytes.unwrap_or(0));
+    if let (Some(tuner), Some(bytes)) = (tuner.as_mut(), sent_bytes) {
+      if let Some(message_size) = tuner.observe(bytes, sending.elapsed()) {
+        worker.configure(&tuned(tuner));
+        tuning::report(&identity, message_size);
+        if tuner.converged() {
+          info!(
+            target: &format!("{}:tuning", identity),
+            "settled on a message_size of {} bytes: {}", message_size, tuner
+          );
+        }
+      }
+    }
+  }
+  liveness.finish();
+  Ok(())
+}
+
+/// A PUSH socket connected to the sink at `address`, queueing at most `high_water_mark` frames
+fn connect_sink(context: &Context, address: &str, high_water_mark: i32) -> zmq::Result<zmq::Socket> {
+  let sink = context.socket(zmq::PUSH)?;
+  // bound the results queued for a slow sink, sends block beyond the mark
+  sink.set_sndhwm(high_water_mark)?;
+  sink.set_ipv6(true)?;
+  sink.connect(address)?;
+  Ok(sink)
+}
+
+/// Hand the result of a `conversion` to the IO threads, with its artifacts, throttling this
+/// converter after an empty input or a failed conversion
+fn hand_off<W: Worker>(
+  worker: &W,
+  results: &SyncSender<Outgoing>,
+  conversion: Conversion,
+  input_size: usize,
+  taskid: &str,
+  start: Instant,
+) {
+  let Conversion {
+    mut result,
+    retries,
+    artifacts,
+  } = conversion;
+  if watchdog::abandoned() {
+    warn!(
+      target: &format!("{}:watchdog", worker.get_identity()),
+      "task {} was already reported as wedged, discarding its result.", taskid
+    );
+    return;
+  }
+  watchdog::progress("handing off");
+  let failure = result.as_ref().err().map(|e| format!("{:?}", e));
+  // classified here, on the converter threads, rather than while the IO threads send
+  let messages = result.as_mut().map(protocol::result_log).unwrap_or_default();
+  state::record_log(&messages);
+  let status = (protocol::version() >= protocol::STATUS_VERSION).then(|| match result {
+    Ok(_) => ResultStatus::of_log(&messages),
+    Err(ref e) => ResultStatus::failed(e.as_ref(), input_size),
+  });
+  let stats = (protocol::version() >= protocol::STATS_VERSION).then(|| TaskStats {
+    duration_ms: start.elapsed().as_millis() as u64,
+    retries,
+    output_bytes: result
+      .as_ref()
+      .ok()
+      .and_then(|file| file.metadata().ok())
+      .map(|metadata| metadata.len())
+      .unwrap_or(0),
+    peak_rss_bytes: bench::peak_child_rss(),
+  });
+  // the artifacts left over from a failed conversion are discarded
+  let outgoing = Outgoing {
+    identity: worker.get_identity().to_string(),
+    taskid: taskid.to_string(),
+    artifacts: if result.is_ok() && protocol::version() >= protocol::ARTIFACTS_VERSION {
+      artifacts
+    } else {
+      Vec::new()
+    },
+    result: result.map_err(|e| e.to_string()),
+    status,
+    stats,
+    input_size,
+  };
+  if results.send(outgoing).is_err() {
+    warn!(
+      target: &format!("{}:result", worker.get_identity()),
+      "no IO thread left to send task {}", taskid
+    );
+  }
+  if let Some(e) = failure {
+    policy::throttle(worker, input_size, &e);
+  }
+}
+
+/// Hand the answer to a probe task to the IO threads, without running the converter
+fn answer_probe<W: Worker>(worker: &W, results: &SyncSender<Outgoing>, input_size: usize, taskid: &str) {
+  let reply = ProbeReply {
+    service: worker.get_service().to_string(),
+    worker: worker.get_version(),
+    pericortex: env!("CARGO_PKG_VERSION").to_string(),
+    converting: state::snapshot().count(Phase::Converting),
+  };
+  let answer = tempfile::tempfile().and_then(|mut file| {
+    serde_json::to_writer(&mut file, &reply)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+  });
+  let outgoing = Outgoing {
+    identity: worker.get_identity().to_string(),
+    taskid: taskid.to_string(),
+    // the round trip is measured by the dispatcher, the worker spends no time on it
+    stats: Some(TaskStats {
+      output_bytes: answer.as_ref().ok().and_then(|file| file.metadata().ok()).map_or(0, |metadata| metadata.len()),
+      ..TaskStats::default()
+    }),
+    result: answer.map_err(|e| e.to_string()),
+    status: Some(ResultStatus::probe()),
+    artifacts: Vec::new(),
+    input_size,
+  };
+  if results.send(outgoing).is_err() {
+    warn!(
+      target: &format!("{}:probe", worker.get_identity()),
+      "no IO thread left to answer probe {}", taskid
+    );
+  }
+}
+
+/// The content manifest of a task's input or output, recorded in the journal if snapshots are enabled
+fn snapshot<W: Worker, F: Read + Seek>(worker: &W, taskid: &str, file: &mut F) -> Option<Snapshot> {
+  if !journal::snapshots() {
+    return None;
+  }
+  journal::snapshot(taskid, file)
+    .map_err(|e| {
+      warn!(
+        target: &format!("{}:journal", worker.get_identity()),
+        "failed to snapshot task {}: {}", taskid, e
+      )
+    })
+    .ok()
+    .flatten()
+}
+
+/// Converter thread: convert the next received task whenever idle, up to `limit` tasks
+fn convert_tasks<W: Worker>(
+  mut worker: W,
+  idle: SyncSender<()>,
+  tasks: &Mutex<Receiver<ReceivedTask>>,
+  results: SyncSender<Outgoing>,
+  limit: Option<usize>,
+) {
+  let mut work_counter = 0;
+  let identity = worker.get_identity().to_string();
+  let liveness = health::register(&identity);
+  // a panic mid-task is reported to the sink on a socket connected ahead of time
+  if let Err(e) = crash::connect(&worker.get_sink_address().to_string()) {
+    warn!(target: &format!("{}:crash", identity), "crashes will not be reported: {}", e);
+  }
+  // Settings that change on reload are applied to a thread-local copy of the worker
+  let mut applied_generation = config::generation();
+  // ... and per-corpus overrides to a copy for the task at hand
+  let mut resolver = ConfigResolver::new(config::current().unwrap_or_default());
+  // Work in perpetuity
+  loop {
+    // also after the tasks answered without a conversion
+    state::enter(&identity, Phase::Idle, None);
+    if shutdown::requested() {
+      info!(target: &format!("{}:shutdown", identity), "drained, exiting.");
+      break;
+    }
+    if config::generation() != applied_generation {
+      applied_generation = config::generation();
+      let reloaded = config::current().unwrap_or_default();
+      if resolver.base().requires_restart(&reloaded) {
+        // drain this thread, the pool reconnects once all threads are done
+        config::request_restart();
+        break;
+      }
+      worker.configure(&reloaded);
+      resolver = ConfigResolver::new(reloaded);
+    }
+    // Wait for the dispatcher to lift a pause, for quiet hours to end, and for the host to have
+    // capacity for another task
+    if !policy::await_turn(&identity) {
+      continue;
+    }
+    let Some(_permit) = pressure::acquire() else {
+      continue;
+    };
+    // counted from before the task is requested until its result is handed off
+    let Some(_task) = shutdown::tasks().begin() else {
+      continue;
+    };
+    if idle.send(()).is_err() {
+      break;
+    }
+    let received = loop {
+      let received = tasks.lock().unwrap().recv_timeout(Duration::new(1, 0));
+      match received {
+        Ok(task) => break Some(task),
+        Err(RecvTimeoutError::Timeout) => continue,
+        Err(RecvTimeoutError::Disconnected) => break None,
+      }
+    };
+    let Some(task) = received else {
+      // all fetchers are gone, e.g. interrupted by a shutdown
+      break;
+    };
+    // the result is sent under the identity which fetched the task
+    worker.set_identity(task.identity);
+    let taskid = task.taskid;
+    // a liveness check from the dispatcher, answered at once and not counted towards the limit
+    if protocol::version() >= protocol::PROBE_VERSION && task.metadata.as_ref().is_some_and(|metadata| metadata.probe) {
+      answer_probe(&worker, &results, task.input_size, &taskid);
+      continue;
+    }
+    state::enter(&identity, Phase::Converting, Some(&taskid));
+    let _in_flight = crash::begin(worker.get_identity(), worker.get_service(), &taskid);
+    let _scratch = scratch::begin(&identity, &taskid);
+    let _activity = watchdog::begin(
+      &identity,
+      worker.get_identity(),
+      worker.get_service(),
+      &taskid,
+      &worker.get_sink_address().to_string(),
+    );
+    let input_size = task.input_size;
+    let metadata = task.metadata;
+    let start = Instant::now();
+    let received_at = Local::now();
+    // Prepare a File for the input
+    let input_tmpdir = scratch::tempdir("cortex_task").unwrap();
+    let mut payload = match task.payload {
+      Ok(payload) => payload,
+      Err(e) => {
+        match e.downcast::<ProtocolError>() {
+          Ok(error) => {
+            // report, and ask for the next task right away, no need to throttle
+            warn!(
+              target: &format!("{}:protocol", worker.get_identity()),
+              "malformed message from CorTeX: {}", error
+            );
+            let report = adaptor::protocol_error_archive(&error);
+            hand_off(&worker, &results, Conversion::unconverted(report), input_size, &taskid, start);
+            continue;
+          }
+          // e.g. an empty placeholder entry of the corpus, which no converter could work on
+          Err(e) if input_size == 0 => match policy::empty_input() {
+            EmptyInput::Report => {
+              let report = Conversion::unconverted(adaptor::empty_input_archive());
+              hand_off(&worker, &results, report, input_size, &taskid, start);
+              policy::throttle_empty_input(&worker);
+            }
+            EmptyInput::Skip => hand_off(&worker, &results, Conversion::unconverted(Err(e)), input_size, &taskid, start),
+          },
+          // the task never arrived whole, there is nothing to convert
+          Err(e) => hand_off(&worker, &results, Conversion::unconverted(Err(e)), input_size, &taskid, start),
+        }
+        // answered like a converted task, it counts towards the limit
+        work_counter += 1;
+        if limit.is_some_and(|upper_bound| work_counter >= upper_bound) {
+          thread::sleep(Duration::new(1, 0));
+          break;
+        }
+        continue;
+      }
+    };
+    let dedup_policy = worker.dedup_policy();
+    if dedup_policy != DedupPolicy::Off {
+      if let Some(duplicate) = dedup::begin(&taskid) {
+        match (dedup_policy, duplicate) {
+          (DedupPolicy::Replay, Duplicate::Completed(Some(cached))) => {
+            info!(
+              target: &format!("{}:dedup", worker.get_identity()),
+              "task {} was already converted, resending its result.", taskid
+            );
+            hand_off(&worker, &results, Conversion::unconverted(Ok(cached)), input_size, &taskid, start);
+          }
+          (_, duplicate) => info!(
+            target: &format!("{}:dedup", worker.get_identity()),
+            "skipping task {}, already {}.",
+            taskid,
+            match duplicate {
+              Duplicate::InFlight => "in progress",
+              Duplicate::Completed(_) => "converted",
+            }
+          ),
+        }
+        continue;
+      }
+    }
+    if let Err(strikes) = quarantine::admit(&taskid) {
+      warn!(
+        target: &format!("{}:quarantine", worker.get_identity()),
+        "task {} is quarantined, not converting it.", taskid
+      );
+      let mut report = adaptor::quarantine_archive(strikes);
+      // answered like a conversion, or redeliveries would be dropped as in progress for good
+      if dedup_policy != DedupPolicy::Off {
+        let cached = report.as_mut().ok().filter(|_| dedup_policy == DedupPolicy::Replay);
+        dedup::complete(&taskid, cached);
+      }
+      hand_off(&worker, &results, Conversion::unconverted(report), input_size, &taskid, start);
+      continue;
+    }
+    let input_snapshot = snapshot(&worker, &taskid, &mut payload);
+    journal::started(&taskid, worker.get_identity(), input_size, input_snapshot);
+    let mut input_sha256 = provenance::sha256(&mut payload).ok();
+    replay::record_task(
+      replay::stamp(&taskid, worker.get_service(), worker.get_identity(), input_size),
+      &mut payload,
+    );
+    let (mut retries, mut artifacts) = (0, Vec::new());
+    let converted = if dryrun::enabled() {
+      dryrun::rehearse(&worker, &mut payload)
+    } else {
+      let task_worker = resolver.worker_for(&worker, metadata.as_ref());
+      adaptor::take_archiving();
+      let converting = Instant::now();
+      let conversion = policy::convert_counting_retries(task_worker.as_ref(), &mut payload, &taskid, &input_tmpdir);
+      (retries, artifacts) = (conversion.retries, conversion.artifacts);
+      // tells whether the time per task goes into the converter or into (de)archiving
+      let archiving = adaptor::take_archiving();
+      if archiving != ArchivingTotals::default() {
+        info!(
+          target: &format!("{}:timing", worker.get_identity()),
+          "task {} took {} ms: extracted {}, archived {}.",
+          taskid,
+          converting.elapsed().as_millis(),
+          archiving.extracted,
+          archiving.archived
+        );
+      }
+      let context = FilterContext {
+        taskid: taskid.clone(),
+        service: worker.get_service().to_string(),
+        identity: worker.get_identity().to_string(),
+      };
+      conversion
+        .result
+        .and_then(|file| filters::apply(&worker.output_filters(), file, &context))
+    };
+    let mut converted_result = converted.or_else(|e| match e.downcast_ref::<RejectReason>() {
+      Some(reason) => {
+        info!(
+          target: &format!("{}:rejected", worker.get_identity()),
+          "task {}: {}", taskid, reason
+        );
+        adaptor::rejection_archive(reason)
+      }
+      None => Err(e),
+    });
+    quarantine::release(&taskid);
+    // older dispatchers receive the secondary artifacts inside the result archive
+    if protocol::version() < protocol::ARTIFACTS_VERSION {
+      converted_result = converted_result.and_then(|file| artifacts::fold(file, mem::take(&mut artifacts)));
+    }
+    let mut output_snapshot = None;
+    if let Ok(ref mut converted_file) = converted_result {
+      replay::record_result(&taskid, converted_file);
+      // before provenance is embedded, which differs on every run
+      output_snapshot = snapshot(&worker, &taskid, converted_file);
+    }
+    watchdog::progress("verifying");
+    let mut converted_result = converted_result.and_then(|mut file| {
+      if !worker.verify_results() {
+        return Ok(file);
+      }
+      match adaptor::verify_result(&mut file) {
+        Ok(()) => {
+          let input_sha256 = input_sha256.take().unwrap_or_default();
+          let config = resolver.config_for(metadata.as_ref());
+          Provenance::of(&worker, &taskid, received_at, input_sha256, &config).embed(file)
+        }
+        Err(violation) => {
+          warn!(
+            target: &format!("{}:contract", worker.get_identity()),
+            "task {}: {}", taskid, violation
+          );
+          adaptor::failure_archive(&violation, &mut file)
+        }
+      }
+    });
+    // enforced on the archive as it is sent, but for its signature
+    converted_result = converted_result.and_then(|file| policy::limit_output_size(file, &taskid, worker.get_identity()));
+    // signed last, over the archive as it is sent
+    #[cfg(feature = "signing")]
+    {
+      converted_result = converted_result.and_then(|file| signing::sign(file, &taskid));
+    }
+    if dedup_policy != DedupPolicy::Off {
+      let cached = converted_result.as_mut().ok().filter(|_| dedup_policy == DedupPolicy::Replay);
+      dedup::complete(&taskid, cached);
+    }
+    let converted_result = converted_result
+      .and_then(|file| protocol::encode_result(file, worker.compression()).map_err(Into::into));
+    let outcome = match converted_result {
+      Ok(ref file) => file.metadata().map(|metadata| metadata.len()).map_err(|e| e.to_string()),
+      Err(ref e) => Err(e.to_string()),
+    };
+    journal::finished(&taskid, worker.get_identity(), input_size, start, outcome, output_snapshot);
+    // a failure may be reported with its input attached, for triage on the dispatcher's side
+    let mut attached_failure = None;
+    let converted_result = match (converted_result, policy::attach_input()) {
+      (Err(e), Some(attachment)) => {
+        match adaptor::failure_with_input_archive(&e.to_string(), &mut payload, &attachment) {
+          Ok(report) => {
+            attached_failure = Some(e.to_string());
+            // a failure report, which the artifacts of the failed conversion don't go with
+            artifacts.clear();
+            Ok(report)
+          }
+          Err(_) => Err(e),
+        }
+      }
+      (converted, _) => converted,
+    };
+
+    let conversion = Conversion {
+      result: converted_result,
+      retries,
+      artifacts,
+    };
+    hand_off(&worker, &results, conversion, input_size, &taskid, start);
+    if let Some(failure) = attached_failure {
+      // reported like a result, but throttled like the failure it is
+      policy::throttle(&worker, input_size, &failure);
+    }
+
+    input_tmpdir.close().unwrap();
+    work_counter += 1;
+    if let Some(upper_bound) = limit {
+      if work_counter >= upper_bound {
+        // Give enough time to complete the Final job.
+        thread::sleep(Duration::new(1, 0));
+        break;
+      }
+    }
+  }
+  liveness.finish();
+}
@@ -0,0 +1,163 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sanity scoring of HTML conversions, for converters which report success on an empty or
+//! skeletal page. The signals (page size, body text, rendered math against the equations of the
+//! sources) are turned into `sanity` messages appended to `cortex.log`, which downgrade the
+//! result's status to a warning or an error.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::diff;
+
+/// When a conversion looks suspicious
+#[derive(Clone, Debug, PartialEq)]
+pub struct SanityThresholds {
+  /// Pages of fewer bytes are reported as short
+  pub min_html_bytes: usize,
+  /// Pages with fewer characters of body text are reported as empty
+  pub min_body_chars: usize,
+  /// Sources with fewer equations aren't checked for missing math
+  pub min_equations: usize,
+  /// Conversions rendering a smaller share of the source equations are reported
+  pub min_math_ratio: f64,
+}
+
+impl Default for SanityThresholds {
+  fn default() -> Self {
+    SanityThresholds {
+      min_html_bytes: 2048,
+      min_body_chars: 200,
+      min_equations: 5,
+      min_math_ratio: 0.5,
+    }
+  }
+}
+
+/// Number of equations in TeX source: display environments, `\[`, `\(`, `$$` and `$` pairs
+pub fn source_equations(tex: &str) -> usize {
+  let mut count = 0;
+  for line in tex.lines() {
+    // comments don't count
+    let line = match line.find('%') {
+      Some(comment) if comment == 0 || !line[..comment].ends_with('\\') => &line[..comment],
+      _ => line,
+    };
+    for environment in ["equation", "align", "eqnarray", "gather", "multline", "displaymath"] {
+      count += line.matches(&format!("\\begin{{{}", environment)).count();
+    }
+    // `\\[2pt]` is a line break, not display math
+    count += line.matches("\\[").count() - line.matches("\\\\[").count() + line.matches("\\(").count();
+    let dollars = line.replace("\\$", "");
+    let double = dollars.matches("$$").count();
+    count += double / 2 + (dollars.matches('$').count() - 2 * double) / 2;
+  }
+  count
+}
+
+/// Number of rendered math elements in an HTML page
+pub fn rendered_math(html: &str) -> usize { html.matches("<math").count() }
+
+/// The text of the body of an HTML page, without markup, scripts and styles
+pub fn body_text(html: &str) -> String {
+  let lowercase = html.to_ascii_lowercase();
+  let body = match (lowercase.find("<body"), lowercase.rfind("</body>")) {
+    (Some(start), Some(end)) if start < end => &html[start..end],
+    (Some(start), None) => &html[start..],
+    _ => html,
+  };
+  let mut text = String::new();
+  let mut skipping = None;
+  for token in diff::normalize_html(body) {
+    if let Some(closing) = skipping {
+      if token.starts_with(closing) {
+        skipping = None;
+      }
+    } else if token.starts_with('<') {
+      if token.starts_with("<script") {
+        skipping = Some("</script");
+      } else if token.starts_with("<style") {
+        skipping = Some("</style");
+      }
+    } else {
+      text.push_str(&token);
+    }
+  }
+  text
+}
+
+/// The `cortex.log` messages for an HTML page converted from sources with `equations` equations,
+/// none if the page looks sane
+pub fn assess(html: &str, equations: usize, thresholds: &SanityThresholds) -> Vec<String> {
+  let mut messages = Vec::new();
+  let body_chars = body_text(html).chars().filter(|c| !c.is_whitespace()).count();
+  if body_chars == 0 {
+    messages.push(String::from("Error:sanity:empty_body the HTML page has no body text"));
+  } else if body_chars < thresholds.min_body_chars {
+    messages.push(format!(
+      "Warning:sanity:skeletal_body the HTML page has only {} characters of body text",
+      body_chars
+    ));
+  }
+  if html.len() < thresholds.min_html_bytes {
+    messages.push(format!("Warning:sanity:short_output the HTML page has only {} bytes", html.len()));
+  }
+  if equations >= thresholds.min_equations {
+    let rendered = rendered_math(html);
+    if rendered == 0 {
+      messages.push(format!(
+        "Error:sanity:missing_math none of the {} source equations were rendered",
+        equations
+      ));
+    } else if (rendered as f64) < thresholds.min_math_ratio * equations as f64 {
+      messages.push(format!(
+        "Warning:sanity:missing_math {} math elements rendered for {} source equations",
+        rendered, equations
+      ));
+    }
+  }
+  messages
+}
+
+/// Files under `dir` with one of `extensions`
+fn files_with_extensions(dir: &Path, extensions: &[&str]) -> Vec<PathBuf> {
+  WalkDir::new(dir)
+    .into_iter()
+    .filter_map(Result::ok)
+    .filter(|entry| entry.file_type().is_file())
+    .map(|entry| entry.into_path())
+    .filter(|path| {
+      path
+        .extension()
+        .map(|extension| extensions.iter().any(|wanted| extension.eq_ignore_ascii_case(wanted)))
+        .unwrap_or(false)
+    })
+    .collect()
+}
+
+/// Assess the largest HTML page in `output_dir` against the TeX sources in `input_dir`
+pub fn check_conversion(
+  input_dir: &Path,
+  output_dir: &Path,
+  thresholds: &SanityThresholds,
+) -> Vec<String> {
+  let equations = files_with_extensions(input_dir, &["tex"])
+    .iter()
+    .filter_map(|path| fs::read(path).ok())
+    .map(|tex| source_equations(&String::from_utf8_lossy(&tex)))
+    .sum();
+  let page = files_with_extensions(output_dir, &["html", "htm", "xhtml"])
+    .into_iter()
+    .max_by_key(|path| fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0));
+  match page.and_then(|path| fs::read(path).ok()) {
+    Some(html) => assess(&String::from_utf8_lossy(&html), equations, thresholds),
+    None => vec![String::from("Error:sanity:no_html the conversion produced no HTML page")],
+  }
+}
@@ -0,0 +1,100 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Quiet hours: daily windows of local time, e.g. `08:00-20:00`, during which the worker fetches
+//! no new tasks. Tasks in flight when a window opens are completed, so the threads drain and idle
+//! until it closes, and shared interactive machines only contribute to corpus runs off hours.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+use crate::shutdown;
+
+/// A daily window of local time, which may wrap around midnight, e.g. `22:00-06:00`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietWindow {
+  /// When the window opens
+  pub start: NaiveTime,
+  /// When the window closes
+  pub stop: NaiveTime,
+}
+
+impl QuietWindow {
+  /// Is `time` within the window?
+  pub fn contains(&self, time: NaiveTime) -> bool {
+    if self.start <= self.stop {
+      self.start <= time && time < self.stop
+    } else {
+      self.start <= time || time < self.stop
+    }
+  }
+}
+
+impl FromStr for QuietWindow {
+  type Err = String;
+  fn from_str(spec: &str) -> Result<QuietWindow, String> {
+    let invalid = || format!("invalid quiet hours {:?}, expected HH:MM-HH:MM", spec);
+    let (start, stop) = spec.split_once('-').ok_or_else(invalid)?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?;
+    let stop = NaiveTime::parse_from_str(stop.trim(), "%H:%M").map_err(|_| invalid())?;
+    if start == stop {
+      return Err(format!("the quiet hours {:?} are empty", spec));
+    }
+    Ok(QuietWindow { start, stop })
+  }
+}
+
+impl TryFrom<String> for QuietWindow {
+  type Error = String;
+  fn try_from(spec: String) -> Result<QuietWindow, String> { spec.parse() }
+}
+
+impl From<QuietWindow> for String {
+  fn from(window: QuietWindow) -> String { window.to_string() }
+}
+
+impl fmt::Display for QuietWindow {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}-{}", self.start.format("%H:%M"), self.stop.format("%H:%M"))
+  }
+}
+
+static QUIET_HOURS: Mutex<Vec<QuietWindow>> = Mutex::new(Vec::new());
+
+/// Fetch no tasks during `windows` from now on, replacing any previous quiet hours
+pub fn set_quiet_hours(windows: Vec<QuietWindow>) { *QUIET_HOURS.lock().unwrap() = windows; }
+
+/// The window of quiet hours containing `time`, if any
+pub fn quiet_at(time: NaiveTime) -> Option<QuietWindow> {
+  QUIET_HOURS.lock().unwrap().iter().copied().find(|window| window.contains(time))
+}
+
+/// Blocks during quiet hours. Returns false if a shutdown was requested meanwhile
+pub fn await_active(identity: &str) -> bool {
+  let Some(window) = quiet_at(Local::now().time()) else {
+    return true;
+  };
+  info!(
+    target: &format!("{}:schedule", identity),
+    "quiet hours {}, pausing task fetches.", window
+  );
+  while quiet_at(Local::now().time()).is_some() {
+    if shutdown::requested() {
+      return false;
+    }
+    thread::sleep(Duration::new(1, 0));
+  }
+  info!(target: &format!("{}:schedule", identity), "quiet hours are over, resuming task fetches.");
+  true
+}
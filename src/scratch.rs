@@ -0,0 +1,180 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scratch space of the tasks, and a sweeper keeping it within a budget over weeks-long runs.
+//!
+//! The temporary directories of a task are created with [`tempdir`], which records them under
+//! the converter thread and task at hand, see [`begin`]. A directory left behind once its thread
+//! moved on to another task, was found wedged or terminated unexpectedly is a leftover, and the
+//! [`sweep`] deletes leftovers oldest first whenever the directories of this process exceed the
+//! budget. Directories of tasks still being converted are never deleted, nor are those of other
+//! processes sharing the temporary directory.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use tempdir::TempDir;
+use walkdir::WalkDir;
+
+use crate::shutdown;
+use crate::state;
+
+/// Seconds between two sweeps of the scratch space
+pub const SWEEP_INTERVAL_SECS: u64 = 60;
+
+/// The task a scratch directory was created for
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Owner {
+  thread: String,
+  taskid: String,
+  /// Order of creation, across the threads of the process
+  created: u64,
+}
+
+static DIRECTORIES: Mutex<BTreeMap<PathBuf, Owner>> = Mutex::new(BTreeMap::new());
+static CREATED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+  static CURRENT: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+}
+
+/// Record the scratch directories created on this thread from now on as those of task `taskid`,
+/// converted by the thread `identity`, until the returned guard is dropped
+pub fn begin(identity: &str, taskid: &str) -> TaskGuard {
+  CURRENT.with(|current| *current.borrow_mut() = Some((identity.to_string(), taskid.to_string())));
+  TaskGuard
+}
+
+/// Ends the task of a thread when dropped
+pub struct TaskGuard;
+
+impl Drop for TaskGuard {
+  fn drop(&mut self) {
+    let _ = CURRENT.try_with(|current| current.borrow_mut().take());
+  }
+}
+
+/// A new temporary directory named after `prefix`, recorded under the task of this thread, if any
+pub fn tempdir(prefix: &str) -> io::Result<TempDir> {
+  let dir = TempDir::new(prefix)?;
+  if let Some((thread, taskid)) = CURRENT.with(|current| current.borrow().clone()) {
+    DIRECTORIES.lock().unwrap().insert(
+      dir.path().to_path_buf(),
+      Owner {
+        thread,
+        taskid,
+        created: CREATED.fetch_add(1, Ordering::SeqCst),
+      },
+    );
+  }
+  Ok(dir)
+}
+
+/// What a sweep found and did
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SweepReport {
+  /// Bytes in the scratch directories of this process before the sweep
+  pub used_bytes: u64,
+  /// Leftover directories deleted
+  pub deleted: Vec<PathBuf>,
+  /// Bytes freed by deleting them
+  pub freed_bytes: u64,
+}
+
+impl fmt::Display for SweepReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} bytes of scratch space in use, deleted {} leftover directories freeing {} bytes",
+      self.used_bytes,
+      self.deleted.len(),
+      self.freed_bytes
+    )
+  }
+}
+
+/// Delete leftover scratch directories, oldest first, until the directories of this process take
+/// at most `budget` bytes
+pub fn sweep(budget: u64) -> SweepReport {
+  let threads = state::snapshot().threads;
+  let mut directories = DIRECTORIES.lock().unwrap();
+  // the directories removed as their task completed
+  directories.retain(|path, _| path.exists());
+  let sizes: BTreeMap<PathBuf, u64> = directories.keys().map(|path| (path.clone(), size(path))).collect();
+  let mut report = SweepReport {
+    used_bytes: sizes.values().sum(),
+    ..SweepReport::default()
+  };
+  let mut leftovers: Vec<(PathBuf, Owner)> = directories
+    .iter()
+    .filter(|(_, owner)| {
+      !threads
+        .iter()
+        .any(|thread| thread.alive && thread.identity == owner.thread && thread.taskid.as_deref() == Some(&owner.taskid))
+    })
+    .map(|(path, owner)| (path.clone(), owner.clone()))
+    .collect();
+  leftovers.sort_by_key(|(_, owner)| owner.created);
+  for (path, owner) in leftovers {
+    if report.used_bytes - report.freed_bytes <= budget {
+      break;
+    }
+    match fs::remove_dir_all(&path) {
+      Ok(()) => {
+        info!(
+          target: "scratch",
+          "deleted {}, left behind by task {} of {}", path.display(), owner.taskid, owner.thread
+        );
+        report.freed_bytes += sizes[&path];
+        report.deleted.push(path.clone());
+        directories.remove(&path);
+      }
+      Err(e) => warn!(target: "scratch", "failed to delete {}: {}", path.display(), e),
+    }
+  }
+  if report.used_bytes - report.freed_bytes > budget {
+    warn!(
+      target: "scratch",
+      "the tasks in flight use {} bytes of scratch space, over the budget of {} bytes",
+      report.used_bytes - report.freed_bytes,
+      budget
+    );
+  }
+  report
+}
+
+/// Sweep the scratch space every `interval` in a background thread, keeping it within `budget`
+/// bytes, until the process shuts down
+pub fn spawn_sweeper(budget: u64, interval: Duration) {
+  thread::spawn(move || {
+    while !shutdown::requested() {
+      thread::sleep(interval);
+      let report = sweep(budget);
+      if !report.deleted.is_empty() {
+        info!(target: "scratch", "{}", report);
+      }
+    }
+  });
+}
+
+fn size(path: &PathBuf) -> u64 {
+  WalkDir::new(path)
+    .into_iter()
+    .filter_map(Result::ok)
+    .filter_map(|entry| entry.metadata().ok())
+    .filter(|metadata| metadata.is_file())
+    .map(|metadata| metadata.len())
+    .sum()
+}
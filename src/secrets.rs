@@ -0,0 +1,139 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Secrets, such as the object storage credentials and the result signing key, looked up by name
+//! from a pluggable [`SecretProvider`] rather than kept in plaintext configuration.
+//!
+//! The layers needing a secret ask for it by name at startup, see [`get`] and [`require`]. By
+//! default secrets come from the environment; [`install`] a [`Secrets`] provider to read them from
+//! files instead, each readable by its owner alone, or from an external command such as a vault
+//! client, or a provider of your own.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// The access key id of the object store
+pub const AWS_ACCESS_KEY_ID: &str = "AWS_ACCESS_KEY_ID";
+/// The secret access key of the object store
+pub const AWS_SECRET_ACCESS_KEY: &str = "AWS_SECRET_ACCESS_KEY";
+/// The result signing key, in hex
+pub const SIGNING_KEY: &str = "PERICORTEX_SIGNING_KEY";
+
+/// A source of secrets
+pub trait SecretProvider: Send + Sync {
+  /// The secret `name`, e.g. `AWS_SECRET_ACCESS_KEY`, or `None` if the provider has no such secret
+  fn secret(&self, name: &str) -> Result<Option<String>, Box<dyn Error>>;
+}
+
+/// The secret providers built in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Secrets {
+  /// The environment variable of the secret's name: `env`
+  Env,
+  /// The file of the secret's name in a directory, readable by its owner alone: `file:<dir>`
+  Files(PathBuf),
+  /// The output of a command given the secret's name as its last argument: `command:<command>`
+  Command(String),
+}
+
+impl FromStr for Secrets {
+  type Err = String;
+  fn from_str(spec: &str) -> Result<Secrets, String> {
+    match spec.split_once(':') {
+      None if spec == "env" => Ok(Secrets::Env),
+      Some(("file", dir)) if !dir.is_empty() => Ok(Secrets::Files(PathBuf::from(dir))),
+      Some(("command", command)) if !command.trim().is_empty() => Ok(Secrets::Command(command.to_string())),
+      _ => Err(format!("expected env, file:<dir> or command:<command>, got {:?}", spec)),
+    }
+  }
+}
+
+impl fmt::Display for Secrets {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Secrets::Env => write!(f, "env"),
+      Secrets::Files(dir) => write!(f, "file:{}", dir.display()),
+      Secrets::Command(command) => write!(f, "command:{}", command),
+    }
+  }
+}
+
+impl SecretProvider for Secrets {
+  fn secret(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match self {
+      Secrets::Env => Ok(std::env::var(name).ok()),
+      Secrets::Files(dir) => {
+        let path = dir.join(name);
+        if !path.exists() {
+          return Ok(None);
+        }
+        read_protected(&path).map(Some)
+      }
+      Secrets::Command(command) => {
+        // the name is passed as a positional parameter, never spliced into the command line
+        let output = Command::new("sh")
+          .arg("-c")
+          .arg(format!("{} \"$1\"", command))
+          .arg("sh")
+          .arg(name)
+          .output()?;
+        if !output.status.success() {
+          return Err(From::from(format!(
+            "{} failed with {} for {}: {}",
+            command,
+            output.status,
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+          )));
+        }
+        let secret = String::from_utf8(output.stdout)?.trim().to_string();
+        Ok(Some(secret).filter(|secret| !secret.is_empty()))
+      }
+    }
+  }
+}
+
+static PROVIDER: RwLock<Option<Box<dyn SecretProvider>>> = RwLock::new(None);
+
+/// Look secrets up with `provider` from now on, rather than in the environment
+pub fn install(provider: Box<dyn SecretProvider>) { *PROVIDER.write().unwrap() = Some(provider); }
+
+/// The secret `name`, if the installed provider, or else the environment, has it
+pub fn get(name: &str) -> Result<Option<String>, Box<dyn Error>> {
+  let secret = match *PROVIDER.read().unwrap() {
+    Some(ref provider) => provider.secret(name),
+    None => Secrets::Env.secret(name),
+  };
+  secret.map_err(|e| From::from(format!("failed to look up {}: {}", name, e)))
+}
+
+/// The secret `name`, which must be provided
+pub fn require(name: &str) -> Result<String, Box<dyn Error>> {
+  get(name)?.ok_or_else(|| From::from(format!("{} is not set", name)))
+}
+
+/// The trimmed contents of the secret file at `path`, which must be readable by its owner alone
+pub fn read_protected(path: &Path) -> Result<String, Box<dyn Error>> {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+      return Err(From::from(format!(
+        "{} is accessible to other users (mode {:o}), restrict it with chmod 600",
+        path.display(),
+        mode & 0o777
+      )));
+    }
+  }
+  Ok(fs::read_to_string(path)?.trim().to_string())
+}
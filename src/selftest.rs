@@ -0,0 +1,165 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Converter version probing and end-to-end conversion of a bundled sample document, verifying that a node is
+//! production-ready before it is pointed at the live dispatcher
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tempdir::TempDir;
+use zip::write::FileOptions;
+
+use crate::adaptor;
+use crate::check::CheckStatus;
+use crate::worker::Worker;
+
+/// The bundled sample document, converted as `sample.tex` inside a corpus entry ZIP
+pub const SAMPLE_TEX: &str = r"\documentclass{article}
+\title{Self-test}
+\begin{document}
+\maketitle
+\section{Introduction}
+A worker which converts this document, including the equation
+\begin{equation}
+  e^{i\pi} + 1 = 0,
+\end{equation}
+is ready for CorTeX.
+\end{document}
+";
+
+/// A single stage of the self-test
+#[derive(Clone, Debug)]
+pub struct StageOutcome {
+  /// What was done
+  pub name: &'static str,
+  /// Did it succeed?
+  pub status: CheckStatus,
+  /// How long it took
+  pub duration: Duration,
+  /// Details for the operator
+  pub detail: String,
+}
+
+/// The stages of a self-test, in the order they ran. A failed stage ends the test
+#[derive(Clone, Debug, Default)]
+pub struct SelfTestReport {
+  /// All stages that ran
+  pub stages: Vec<StageOutcome>,
+}
+
+impl SelfTestReport {
+  /// Did every stage that ran succeed?
+  pub fn passed(&self) -> bool {
+    self.stages.iter().all(|stage| stage.status != CheckStatus::Fail)
+  }
+
+  /// Time the stage `name`, recording its outcome. Returns the value of a successful stage
+  fn stage<T, F>(&mut self, name: &'static str, run: F) -> Option<T>
+  where F: FnOnce() -> Result<(T, String), Box<dyn Error>> {
+    let start = Instant::now();
+    let result = run();
+    let duration = start.elapsed();
+    let (status, detail, value) = match result {
+      Ok((value, detail)) => (CheckStatus::Pass, detail, Some(value)),
+      Err(e) => (CheckStatus::Fail, e.to_string(), None),
+    };
+    self.stages.push(StageOutcome {
+      name,
+      status,
+      duration,
+      detail,
+    });
+    value
+  }
+}
+
+impl fmt::Display for SelfTestReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for stage in &self.stages {
+      let label = match stage.status {
+        CheckStatus::Pass => "PASS",
+        CheckStatus::Fail => "FAIL",
+        CheckStatus::Skip => "SKIP",
+      };
+      writeln!(
+        f,
+        "[{}] {} ({:.3}s): {}",
+        label,
+        stage.name,
+        stage.duration.as_secs_f64(),
+        stage.detail
+      )?;
+    }
+    let total: Duration = self.stages.iter().map(|stage| stage.duration).sum();
+    writeln!(f, "total: {:.3}s", total.as_secs_f64())
+  }
+}
+
+/// Write the bundled sample document as a corpus entry ZIP at `path`
+pub fn write_sample(path: &Path) -> Result<(), Box<dyn Error>> {
+  let mut zip = zip::ZipWriter::new(File::create(path)?);
+  zip.start_file("sample.tex", FileOptions::default())?;
+  zip.write_all(SAMPLE_TEX.as_bytes())?;
+  zip.finish()?;
+  Ok(())
+}
+
+/// Convert the bundled sample document with `worker` and validate the result
+pub fn selftest<W: Worker>(worker: &W) -> Result<SelfTestReport, Box<dyn Error>> {
+  let scratch = TempDir::new("cortex_selftest")?;
+  let input = scratch.path().join("sample.zip");
+  let output = scratch.path().join("sample.result.zip");
+  let mut report = SelfTestReport::default();
+
+  // an unknown converter version is reported, but doesn't stop the conversion test
+  report.stage("tool version", || Ok(((), worker.tool_version()?)));
+
+  if report
+    .stage("fixture", || {
+      write_sample(&input)?;
+      Ok(((), format!("{} bytes of TeX", SAMPLE_TEX.len())))
+    })
+    .is_none()
+  {
+    return Ok(report);
+  }
+  let converted = report.stage("convert", || {
+    let mut result = worker.convert(&input)?;
+    let written = io::copy(&mut result, &mut File::create(&output)?)?;
+    Ok(((), format!("{} bytes of results", written)))
+  });
+  if converted.is_none() {
+    return Ok(report);
+  }
+  if !worker.verify_results() {
+    report.stages.push(StageOutcome {
+      name: "validate",
+      status: CheckStatus::Skip,
+      duration: Duration::default(),
+      detail: format!("{} results are not CorTeX archives", worker.get_service()),
+    });
+    return Ok(report);
+  }
+  report.stage("validate", || {
+    adaptor::verify_result(&mut File::open(&output)?)?;
+    let log = adaptor::read_archive_entry(&output, "cortex.log")?.unwrap_or_default();
+    let log = String::from_utf8_lossy(&log);
+    match log.lines().find(|line| line.starts_with("Fatal:")) {
+      Some(fatal) => Err(From::from(format!("cortex.log reports {}", fatal))),
+      None => Ok((
+        (),
+        format!("result archive with {} cortex.log messages", log.lines().count()),
+      )),
+    }
+  });
+  Ok(report)
+}
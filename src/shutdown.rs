@@ -0,0 +1,61 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Graceful shutdown: threads stop fetching new tasks, while in-flight tasks are completed
+
+use std::error::Error;
+use std::sync::OnceLock;
+
+#[cfg(not(loom))]
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+#[cfg(not(loom))]
+use crate::sync::Arc;
+use crate::sync::{ShutdownSignal, TaskCounter};
+
+/// The shutdown signal of this process
+pub fn signal() -> &'static ShutdownSignal {
+  static SIGNAL: OnceLock<ShutdownSignal> = OnceLock::new();
+  SIGNAL.get_or_init(ShutdownSignal::new)
+}
+
+/// The tasks this process is working on, refused once a shutdown was requested
+pub fn tasks() -> &'static TaskCounter {
+  static TASKS: OnceLock<TaskCounter> = OnceLock::new();
+  TASKS.get_or_init(|| TaskCounter::new(signal().clone()))
+}
+
+/// Ask all worker threads to drain and exit
+pub fn request() { signal().request(); }
+
+/// Was a shutdown requested?
+pub fn requested() -> bool { signal().requested() }
+
+/// Drain on `signal`, without terminating on a repeated one
+#[cfg(not(loom))]
+pub fn request_on(signal: i32) -> Result<(), Box<dyn Error>> {
+  signal_hook::flag::register(signal, Arc::clone(self::signal().flag()))?;
+  Ok(())
+}
+
+/// Drain on the first SIGTERM or SIGINT, terminate immediately on the second one
+#[cfg(not(loom))]
+pub fn on_signals() -> Result<(), Box<dyn Error>> {
+  for signal in [SIGTERM, SIGINT] {
+    signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(self::signal().flag()))?;
+    signal_hook::flag::register(signal, Arc::clone(self::signal().flag()))?;
+  }
+  Ok(())
+}
+
+/// Signal handlers can't raise loom's atomics, there are no signals in a loom model
+#[cfg(loom)]
+pub fn request_on(_signal: i32) -> Result<(), Box<dyn Error>> { Ok(()) }
+
+/// Signal handlers can't raise loom's atomics, there are no signals in a loom model
+#[cfg(loom)]
+pub fn on_signals() -> Result<(), Box<dyn Error>> { Ok(()) }
@@ -0,0 +1,182 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Ed25519 signatures of result archives, for corpora which must be able to tell that every
+//! document really came from an authorized worker fleet.
+//!
+//! With a signing key [`enable`]d, every result archive gets a [`SIGNATURE_FILE`] embedded as its
+//! last change, just before it is sent: a [`ResultSignature`] over the task id and the SHA-256
+//! checksums of all other files of the archive. The signature thus travels with the archive into
+//! the corpus, and anyone holding the fleet's public key can [`verify`] a stored result, without
+//! the dispatcher taking part.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, SECRET_KEY_LENGTH};
+use serde::{Deserialize, Serialize};
+
+use crate::adaptor;
+use crate::journal;
+use crate::protocol::ZIP_MAGIC;
+use crate::secrets;
+
+/// Name of the signature at the root of every signed result archive
+pub const SIGNATURE_FILE: &str = "cortex.sig";
+/// What a signature is made over, ahead of the signed statement
+const SIGNATURE_CONTEXT: &[u8] = b"pericortex result signature v1\n";
+
+/// The signature of a result archive
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResultSignature {
+  /// The CorTeX task id the result answers
+  pub taskid: String,
+  /// The public key of the signer, in hex
+  pub public_key: String,
+  /// The SHA-256 checksum of every other file of the archive, in hex, by path
+  pub files: BTreeMap<String, String>,
+  /// The Ed25519 signature of the task id, public key and checksums, in hex
+  pub signature: String,
+}
+
+impl ResultSignature {
+  /// The bytes signed: the task id, the public key and the checksums, in their JSON form
+  fn statement(taskid: &str, public_key: &str, files: &BTreeMap<String, String>) -> Vec<u8> {
+    let mut statement = SIGNATURE_CONTEXT.to_vec();
+    statement.extend(serde_json::to_vec(&(taskid, public_key, files)).unwrap_or_default());
+    statement
+  }
+}
+
+static KEY: Mutex<Option<SigningKey>> = Mutex::new(None);
+
+/// Sign every result archive with `key` from now on
+pub fn enable(key: SigningKey) { *KEY.lock().unwrap() = Some(key); }
+
+/// Are results signed?
+pub fn enabled() -> bool { KEY.lock().unwrap().is_some() }
+
+/// A new random signing key
+pub fn generate_key() -> SigningKey {
+  let mut secret = [0; SECRET_KEY_LENGTH];
+  rand::Rng::fill(&mut rand::thread_rng(), &mut secret[..]);
+  SigningKey::from_bytes(&secret)
+}
+
+/// The signing key written in hex, e.g. by [`generate_key`], in `encoded`
+pub fn parse_key(encoded: &str) -> Result<SigningKey, Box<dyn Error>> {
+  let secret: [u8; SECRET_KEY_LENGTH] = hex::decode(encoded.trim())?
+    .try_into()
+    .map_err(|_| format!("a signing key is {} bytes in hex", SECRET_KEY_LENGTH))?;
+  Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Store `key` in hex in a new file at `path`, readable by its owner alone
+pub fn save_key(path: &Path, key: &SigningKey) -> Result<(), Box<dyn Error>> {
+  let mut options = OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  writeln!(options.open(path)?, "{}", hex::encode(key.to_bytes()))?;
+  Ok(())
+}
+
+/// The public key of `key`, in hex
+pub fn public_key(key: &SigningKey) -> String { hex::encode(key.verifying_key().as_bytes()) }
+
+/// The signing key stored in hex in the file at `path`, which must be readable by its owner alone
+pub fn load_key(path: &Path) -> Result<SigningKey, Box<dyn Error>> {
+  parse_key(&secrets::read_protected(path)?).map_err(|e| From::from(format!("{}: {}", path.display(), e)))
+}
+
+/// The signing key held by the secret provider as [`secrets::SIGNING_KEY`], if any
+pub fn provided_key() -> Result<Option<SigningKey>, Box<dyn Error>> {
+  secrets::get(secrets::SIGNING_KEY)?.map(|encoded| parse_key(&encoded)).transpose()
+}
+
+/// A public key in hex, as printed with a generated key
+pub fn parse_public_key(encoded: &str) -> Result<VerifyingKey, Box<dyn Error>> {
+  let bytes: [u8; 32] = hex::decode(encoded.trim())?
+    .try_into()
+    .map_err(|_| "a public key is 32 bytes in hex")?;
+  Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Embed the signature of the `result` archive of task `taskid`, if results are signed. A result
+/// which isn't a ZIP archive is left unsigned, with a warning
+pub fn sign(mut result: File, taskid: &str) -> Result<File, Box<dyn Error>> {
+  let Some(key) = KEY.lock().unwrap().clone() else {
+    return Ok(result);
+  };
+  let mut magic = [0; 4];
+  let is_zip = result.read_exact(&mut magic).is_ok() && magic == ZIP_MAGIC;
+  result.seek(SeekFrom::Start(0))?;
+  if !is_zip {
+    warn!(target: "signing", "task {}: not signing the result, it isn't a ZIP archive.", taskid);
+    return Ok(result);
+  }
+  let files = checksums(&mut result)?;
+  let public_key = public_key(&key);
+  let signature = key.sign(&ResultSignature::statement(taskid, &public_key, &files));
+  let signature = ResultSignature {
+    taskid: taskid.to_string(),
+    public_key,
+    files,
+    signature: hex::encode(signature.to_bytes()),
+  };
+  adaptor::upsert_zip_entry(result, SIGNATURE_FILE, &serde_json::to_vec_pretty(&signature)?)
+}
+
+/// Check the signature embedded in the result archive at `path` against the `trusted` public keys,
+/// returning it if the archive is unchanged since it was signed by one of them
+pub fn verify(path: &Path, trusted: &[VerifyingKey]) -> Result<ResultSignature, Box<dyn Error>> {
+  let Some(embedded) = adaptor::read_archive_entry(path, SIGNATURE_FILE)? else {
+    return Err(From::from(format!("{} is not signed", path.display())));
+  };
+  let embedded: ResultSignature = serde_json::from_slice(&embedded)?;
+  let key = parse_public_key(&embedded.public_key)?;
+  if !trusted.contains(&key) {
+    return Err(From::from(format!("signed by the untrusted key {}", embedded.public_key)));
+  }
+  let signature: [u8; 64] = hex::decode(&embedded.signature)?
+    .try_into()
+    .map_err(|_| "malformed signature")?;
+  key
+    .verify(
+      &ResultSignature::statement(&embedded.taskid, &embedded.public_key, &embedded.files),
+      &Signature::from_bytes(&signature),
+    )
+    .map_err(|_| "the signature doesn't match its statement")?;
+  let files = checksums(&mut File::open(path)?)?;
+  if files != embedded.files {
+    let mut changed: Vec<&str> = files
+      .keys()
+      .chain(embedded.files.keys())
+      .filter(|name| files.get(*name) != embedded.files.get(*name))
+      .map(String::as_str)
+      .collect();
+    changed.sort_unstable();
+    changed.dedup();
+    return Err(From::from(format!("changed since signed: {}", changed.join(", "))));
+  }
+  Ok(embedded)
+}
+
+/// The checksums of the files of an archive, but for its signature
+fn checksums(archive: &mut File) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+  Ok(
+    journal::manifest(archive)?
+      .into_iter()
+      .filter(|entry| entry.path != SIGNATURE_FILE)
+      .map(|entry| (entry.path, entry.sha256))
+      .collect(),
+  )
+}
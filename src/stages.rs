@@ -0,0 +1,97 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Whether a worker chaining several converters runs its later stages after an earlier one
+//! reported errors. A Fatal stage always ends the chain; whether Error-level results are worth
+//! postprocessing is a policy, as doing so for a broken document can waste hours.
+//!
+//! The decision and its reason are returned as a `cortex.log` line, for the worker to record
+//! next to the messages of the stages.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::logcodes::{self, Severity};
+
+/// When the later stages of a multi-stage conversion are skipped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EarlyExit {
+  /// Only after a Fatal stage, `fatal`
+  #[default]
+  OnFatal,
+  /// Also after a stage which reported errors, `error`
+  OnError,
+}
+
+impl FromStr for EarlyExit {
+  type Err = String;
+  fn from_str(policy: &str) -> Result<EarlyExit, String> {
+    match policy {
+      "fatal" => Ok(EarlyExit::OnFatal),
+      "error" => Ok(EarlyExit::OnError),
+      _ => Err(format!("invalid early exit policy {:?}, expected fatal or error", policy)),
+    }
+  }
+}
+
+impl fmt::Display for EarlyExit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      EarlyExit::OnFatal => "fatal",
+      EarlyExit::OnError => "error",
+    })
+  }
+}
+
+/// Whether to run the stages after one, and why
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageDecision {
+  /// Run the later stages?
+  pub proceed: bool,
+  /// The `cortex.log` line recording the decision, if the stage reported errors
+  pub message: Option<String>,
+}
+
+impl EarlyExit {
+  /// Decide on the stages after `stage`, from the `cortex.log` messages it produced
+  pub fn decide(self, stage: &str, log: &str) -> StageDecision {
+    let messages = logcodes::parse(log);
+    let severity = logcodes::max_severity(&messages);
+    if severity < Some(Severity::Error) {
+      return StageDecision {
+        proceed: true,
+        message: None,
+      };
+    }
+    let count = logcodes::severity_counts(&messages);
+    let errors = count.get(&Severity::Error).copied().unwrap_or(0);
+    let (proceed, message) = match (severity, self) {
+      (Some(Severity::Fatal), _) => (
+        false,
+        format!("Fatal:stages:skipped {} failed fatally, the later stages were not run", stage),
+      ),
+      (_, EarlyExit::OnError) => (
+        false,
+        format!(
+          "Error:stages:skipped {} reported {} errors, the later stages were not run (early exit on error)",
+          stage, errors
+        ),
+      ),
+      (_, EarlyExit::OnFatal) => (
+        true,
+        format!(
+          "Info:stages:continued {} reported {} errors, the later stages ran (early exit on fatal only)",
+          stage, errors
+        ),
+      ),
+    };
+    StageDecision {
+      proceed,
+      message: Some(message),
+    }
+  }
+}
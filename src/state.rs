@@ -0,0 +1,240 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The runtime state of a worker process in one place: what every thread is doing, how many
+//! tasks went through, and what holds the threads back. The probe endpoints and anything else
+//! reporting on a running worker read a [`WorkerState`] snapshot rather than the statics of the
+//! modules that keep the state.
+//!
+//! The warning, error and fatal `Severity:category` codes of the results are tallied over the
+//! whole run, see [`record_log`], so that the dominant failure modes of a converter version show
+//! in every snapshot and in the report logged at the end of the run.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::logcodes::{self, LogMessage, Severity};
+use crate::{pressure, shutdown};
+
+/// Log categories listed in a snapshot
+pub const TOP_LOG_CATEGORIES: usize = 10;
+
+/// What a thread is doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+  /// Waiting for work
+  Idle,
+  /// Receiving a task from the dispatcher
+  Receiving,
+  /// Converting a task
+  Converting,
+  /// Sending a result to the sink
+  Sending,
+}
+
+impl fmt::Display for Phase {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Phase::Idle => "idle",
+      Phase::Receiving => "receiving",
+      Phase::Converting => "converting",
+      Phase::Sending => "sending",
+    })
+  }
+}
+
+/// A thread of the worker, at the time of the snapshot
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ThreadState {
+  /// Identity of the thread, `:fetch` and `:send` for the IO threads
+  pub identity: String,
+  /// False once the thread terminated unexpectedly or was found wedged
+  pub alive: bool,
+  /// What it is doing
+  pub phase: Phase,
+  /// The task it is working on, if any
+  pub taskid: Option<String>,
+  /// Milliseconds since it entered its phase
+  pub elapsed_ms: u64,
+}
+
+/// What holds the threads back, at the time of the snapshot
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Backpressure {
+  /// Threads the host pressure controller lets fetch tasks, if it runs
+  pub allowed_fetches: Option<usize>,
+  /// Threads holding a fetch permit
+  pub active_fetches: usize,
+  /// IO threads waiting for a full sink queue to drain
+  pub sink_waits: usize,
+  /// Tasks between their request and the hand-off of their result
+  pub tasks_in_flight: usize,
+}
+
+/// A snapshot of the runtime state of a worker process
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorkerState {
+  /// Every thread ever registered and not finished, by identity
+  pub threads: Vec<ThreadState>,
+  /// Results sent to the sink
+  pub completed: u64,
+  /// Empty results sent to the sink, for failed tasks
+  pub failed: u64,
+  /// The most frequent warning, error and fatal categories of the results' logs, by the number
+  /// of results reporting them, most frequent first
+  pub log_categories: Vec<(String, u64)>,
+  /// What holds the threads back
+  pub backpressure: Backpressure,
+}
+
+impl WorkerState {
+  /// Identities of the threads that terminated unexpectedly
+  pub fn dead_threads(&self) -> Vec<String> {
+    self
+      .threads
+      .iter()
+      .filter(|thread| !thread.alive)
+      .map(|thread| thread.identity.clone())
+      .collect()
+  }
+
+  /// Threads in `phase`
+  pub fn count(&self, phase: Phase) -> usize {
+    self.threads.iter().filter(|thread| thread.alive && thread.phase == phase).count()
+  }
+}
+
+struct Entry {
+  alive: bool,
+  phase: Phase,
+  taskid: Option<String>,
+  since: Instant,
+}
+
+static THREADS: Mutex<BTreeMap<String, Entry>> = Mutex::new(BTreeMap::new());
+static COMPLETED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+static SINK_WAITS: AtomicUsize = AtomicUsize::new(0);
+/// Results reporting each `Severity:category` code, over the run
+static LOG_CATEGORIES: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Track the thread `identity`, alive and idle
+pub fn register(identity: &str) {
+  THREADS.lock().unwrap().insert(
+    identity.to_string(),
+    Entry {
+      alive: true,
+      phase: Phase::Idle,
+      taskid: None,
+      since: Instant::now(),
+    },
+  );
+}
+
+/// Stop tracking the thread `identity`, which completed its work
+pub fn unregister(identity: &str) { THREADS.lock().unwrap().remove(identity); }
+
+/// Mark the thread `identity` as dead
+pub fn mark_dead(identity: &str) {
+  let mut threads = THREADS.lock().unwrap();
+  match threads.get_mut(identity) {
+    Some(entry) => entry.alive = false,
+    None => {
+      threads.insert(
+        identity.to_string(),
+        Entry {
+          alive: false,
+          phase: Phase::Idle,
+          taskid: None,
+          since: Instant::now(),
+        },
+      );
+    }
+  }
+}
+
+/// The thread `identity` entered `phase`, working on task `taskid` if any
+pub fn enter(identity: &str, phase: Phase, taskid: Option<&str>) {
+  if let Some(entry) = THREADS.lock().unwrap().get_mut(identity) {
+    entry.phase = phase;
+    entry.taskid = taskid.map(str::to_string);
+    entry.since = Instant::now();
+  }
+}
+
+/// Count a result sent to the sink
+pub fn record(failed: bool) {
+  if failed {
+    FAILED.fetch_add(1, Ordering::SeqCst);
+  } else {
+    COMPLETED.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+/// Tally the warning, error and fatal categories among the `messages` of a result's log
+pub fn record_log(messages: &[LogMessage]) {
+  let mut tally = LOG_CATEGORIES.lock().unwrap();
+  for category in logcodes::categories(messages, Severity::Warning) {
+    *tally.entry(category).or_insert(0) += 1;
+  }
+}
+
+/// The `n` most frequent categories tallied by [`record_log`], most frequent first
+pub fn log_categories(n: usize) -> Vec<(String, u64)> {
+  let mut top: Vec<(String, u64)> =
+    LOG_CATEGORIES.lock().unwrap().iter().map(|(category, count)| (category.clone(), *count)).collect();
+  top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+  top.truncate(n);
+  top
+}
+
+/// An IO thread started (`true`) or stopped (`false`) waiting for the sink queue to drain
+pub fn sink_wait(waiting: bool) {
+  if waiting {
+    SINK_WAITS.fetch_add(1, Ordering::SeqCst);
+  } else {
+    SINK_WAITS.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// The current state of this worker process
+pub fn snapshot() -> WorkerState {
+  let now = Instant::now();
+  let threads = THREADS
+    .lock()
+    .map(|threads| {
+      threads
+        .iter()
+        .map(|(identity, entry)| ThreadState {
+          identity: identity.clone(),
+          alive: entry.alive,
+          phase: entry.phase,
+          taskid: entry.taskid.clone(),
+          elapsed_ms: now.saturating_duration_since(entry.since).as_millis() as u64,
+        })
+        .collect()
+    })
+    .unwrap_or_default();
+  WorkerState {
+    threads,
+    completed: COMPLETED.load(Ordering::SeqCst),
+    failed: FAILED.load(Ordering::SeqCst),
+    log_categories: log_categories(TOP_LOG_CATEGORIES),
+    backpressure: Backpressure {
+      allowed_fetches: pressure::allowed_fetches(),
+      active_fetches: pressure::active_fetches(),
+      sink_waits: SINK_WAITS.load(Ordering::SeqCst),
+      tasks_in_flight: shutdown::tasks().in_flight(),
+    },
+  }
+}
@@ -0,0 +1,337 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Object storage (S3, MinIO) for result archives too large to push through the sink comfortably.
+//!
+//! With protocol version [`MANIFEST_VERSION`], results of at least the configured threshold are
+//! uploaded to an S3-compatible bucket, and the sink receives an [`ObjectManifest`] frame with
+//! their URL, SHA-256 checksum and size instead. Requests are signed with AWS Signature
+//! Version 4, with the `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` credentials of the
+//! [`secrets`] provider, by default environment variables, and the `AWS_REGION` environment
+//! variable.
+//!
+//! Symmetrically, a dispatcher speaking the same protocol version may send a manifest in place of
+//! a task payload, which is then downloaded (signed when it lives in the configured bucket),
+//! verified against its checksum and size, and retried with [`DOWNLOAD_RETRIES`].
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tempfile::{spooled_tempfile, SpooledTempFile};
+
+use crate::clock;
+use crate::policy;
+use crate::protocol::{self, ObjectManifest, MANIFEST_VERSION};
+use crate::retry::RetryPolicy;
+use crate::secrets;
+use crate::shutdown;
+use crate::transfer::SPOOL_THRESHOLD;
+
+/// Results of at least this many bytes are uploaded, unless configured otherwise
+pub const UPLOAD_THRESHOLD: u64 = 64 * 1_048_576;
+/// How long to wait for a connection to the object store
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Download attempts for a task payload, and the pause before the first retry
+pub const DOWNLOAD_RETRIES: RetryPolicy = RetryPolicy {
+  attempts: 4,
+  backoff: Duration::from_secs(2),
+};
+
+/// An S3-compatible bucket, addressed path-style as `endpoint/bucket/key`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectStore {
+  /// Base URL of the object store, e.g. `http://minio:9000`
+  pub endpoint: String,
+  /// Name of the bucket
+  pub bucket: String,
+  /// Region of the bucket, `us-east-1` for most MinIO deployments
+  pub region: String,
+  /// Access key id
+  pub access_key: String,
+  /// Secret access key
+  pub secret_key: String,
+}
+
+impl ObjectStore {
+  /// The bucket at `url`, e.g. `http://minio:9000/results`, with credentials from the secret provider
+  pub fn new(url: &str) -> Result<ObjectStore, Box<dyn Error>> {
+    let (endpoint, bucket) = url
+      .trim_end_matches('/')
+      .rsplit_once('/')
+      .filter(|(endpoint, _)| endpoint.contains("://") && !endpoint.ends_with('/'))
+      .ok_or_else(|| {
+        format!("expected an object store URL such as http://minio:9000/bucket, got {:?}", url)
+      })?;
+    Ok(ObjectStore {
+      endpoint: endpoint.to_string(),
+      bucket: bucket.to_string(),
+      region: env::var("AWS_REGION").unwrap_or_else(|_| String::from("us-east-1")),
+      access_key: secrets::require(secrets::AWS_ACCESS_KEY_ID)?,
+      secret_key: secrets::require(secrets::AWS_SECRET_ACCESS_KEY)?,
+    })
+  }
+
+  /// `host[:port]` of the endpoint
+  fn authority(&self) -> &str {
+    let address = self.endpoint.split_once("://").map_or(&*self.endpoint, |(_, address)| address);
+    address.split('/').next().unwrap_or(address)
+  }
+
+  /// The URL-encoded path of `key`
+  fn path(&self, key: &str) -> String {
+    let mut path = format!("/{}", uri_encode(&self.bucket));
+    for segment in key.split('/') {
+      path.push('/');
+      path.push_str(&uri_encode(segment));
+    }
+    path
+  }
+
+  /// The URL of the object at `key`
+  pub fn object_url(&self, key: &str) -> String { format!("{}{}", self.endpoint, self.path(key)) }
+
+  /// The headers authorizing a `method` request for the URL-encoded `path`, e.g.
+  /// `/bucket/key.zip`, with a payload of `payload_sha256`
+  pub fn sign(
+    &self,
+    method: &str,
+    path: &str,
+    payload_sha256: &str,
+    now: DateTime<Utc>,
+  ) -> Vec<(String, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+      "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+      method,
+      path,
+      self.authority(),
+      payload_sha256,
+      amz_date,
+      signed_headers,
+      payload_sha256
+    );
+    let string_to_sign = format!(
+      "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+      amz_date,
+      scope,
+      hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut signing_key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), date.as_bytes());
+    for part in [&self.region, "s3", "aws4_request"] {
+      signing_key = hmac(&signing_key, part.as_bytes());
+    }
+    let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+    vec![
+      (String::from("x-amz-content-sha256"), payload_sha256.to_string()),
+      (String::from("x-amz-date"), amz_date),
+      (
+        String::from("Authorization"),
+        format!(
+          "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+          self.access_key, scope, signed_headers, signature
+        ),
+      ),
+    ]
+  }
+
+  /// Upload `file` as the object at `key`, returning its manifest
+  pub fn upload(&self, key: &str, file: &mut File) -> Result<ObjectManifest, Box<dyn Error>> {
+    let (sha256, size) = checksum(file)?;
+    let mut request = ureq::AgentBuilder::new()
+      .timeout_connect(CONNECT_TIMEOUT)
+      .build()
+      .put(&self.object_url(key))
+      .set("Content-Length", &size.to_string());
+    for (name, value) in self.sign("PUT", &self.path(key), &sha256, Utc::now()) {
+      request = request.set(&name, &value);
+    }
+    match request.send(&mut *file) {
+      Ok(_) => {}
+      Err(ureq::Error::Status(status, response)) => {
+        return Err(From::from(format!(
+          "uploading {} failed with {}: {}",
+          key,
+          status,
+          response.into_string().unwrap_or_default()
+        )))
+      }
+      Err(e) => return Err(From::from(format!("uploading {} failed: {}", key, e))),
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(ObjectManifest {
+      url: self.object_url(key),
+      sha256,
+      size,
+    })
+  }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+  let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+  mac.update(data);
+  mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but the unreserved characters, as signatures expect
+fn uri_encode(segment: &str) -> String {
+  let mut encoded = String::with_capacity(segment.len());
+  for byte in segment.bytes() {
+    if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+      encoded.push(byte as char);
+    } else {
+      encoded.push_str(&format!("%{:02X}", byte));
+    }
+  }
+  encoded
+}
+
+/// The SHA-256 checksum (in hex) and size of `file`, which is rewound afterwards
+pub fn checksum<F: Read + Seek>(file: &mut F) -> io::Result<(String, u64)> {
+  file.seek(SeekFrom::Start(0))?;
+  let mut hasher = Sha256::new();
+  let size = io::copy(file, &mut hasher)?;
+  file.seek(SeekFrom::Start(0))?;
+  Ok((hex::encode(hasher.finalize()), size))
+}
+
+static STORE: OnceLock<(ObjectStore, u64)> = OnceLock::new();
+
+/// Upload results of at least `threshold` bytes to `store`
+pub fn enable(store: ObjectStore, threshold: u64) {
+  STORE.set((store, threshold)).ok();
+}
+
+/// Upload `result` to the configured object store if it is large enough, or over the output size
+/// limit diverting results to object storage, returning the file to send to the sink: the
+/// manifest of the upload, or `result` itself when it wasn't uploaded
+pub fn offload(mut result: File, service: &str, taskid: &str) -> File {
+  let Some((store, threshold)) = STORE.get() else {
+    return result;
+  };
+  let size = result.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+  if protocol::version() < MANIFEST_VERSION || (size < *threshold && !policy::diverts(size)) {
+    return result;
+  }
+  let key = format!("{}/{}.zip", service, protocol::taskid_file_stem(taskid));
+  let manifest = store.upload(&key, &mut result).and_then(|manifest| {
+    let mut frame = tempfile::tempfile()?;
+    frame.write_all(manifest.frame().as_bytes())?;
+    frame.seek(SeekFrom::Start(0))?;
+    Ok((manifest, frame))
+  });
+  match manifest {
+    Ok((manifest, frame)) => {
+      info!(
+        target: "storage",
+        "task {}, uploaded {} bytes to {}.", taskid, manifest.size, manifest.url
+      );
+      frame
+    }
+    Err(e) => {
+      warn!(target: "storage", "task {}, sending it to the sink instead: {}", taskid, e);
+      result.seek(SeekFrom::Start(0)).ok();
+      result
+    }
+  }
+}
+
+/// Download the payload described by `manifest`, signing the request when it lives in the
+/// configured bucket, and verify its checksum and size
+pub fn fetch(manifest: &ObjectManifest) -> Result<SpooledTempFile, Box<dyn Error>> {
+  let agent = ureq::AgentBuilder::new().timeout_connect(CONNECT_TIMEOUT).build();
+  let mut request = agent.get(&manifest.url);
+  if let Some((store, _)) = STORE.get() {
+    let bucket = format!("{}/{}/", store.endpoint, uri_encode(&store.bucket));
+    if manifest.url.starts_with(&bucket) {
+      // nothing to hash for a GET, the empty payload is signed
+      let empty = hex::encode(Sha256::digest(b""));
+      let path = &manifest.url[store.endpoint.len()..];
+      for (name, value) in store.sign("GET", path, &empty, Utc::now()) {
+        request = request.set(&name, &value);
+      }
+    }
+  }
+  let response = match request.call() {
+    Ok(response) => response,
+    Err(ureq::Error::Status(status, _)) => {
+      return Err(From::from(format!("downloading {} failed with {}", manifest.url, status)))
+    }
+    Err(e) => return Err(From::from(format!("downloading {} failed: {}", manifest.url, e))),
+  };
+  let mut payload = spooled_tempfile(SPOOL_THRESHOLD);
+  io::copy(&mut response.into_reader(), &mut payload)?;
+  let (sha256, size) = checksum(&mut payload)?;
+  if size != manifest.size || !sha256.eq_ignore_ascii_case(&manifest.sha256) {
+    return Err(From::from(format!(
+      "{} has {} bytes with checksum {}, expected {} bytes with checksum {}",
+      manifest.url, size, sha256, manifest.size, manifest.sha256
+    )));
+  }
+  Ok(payload)
+}
+
+/// Download the payload described by `manifest`, retrying failed or corrupted downloads
+pub fn download(
+  manifest: &ObjectManifest,
+  identity: &str,
+) -> Result<SpooledTempFile, Box<dyn Error>> {
+  let mut attempt = 1;
+  loop {
+    match fetch(manifest) {
+      Ok(payload) => return Ok(payload),
+      Err(e) if attempt < DOWNLOAD_RETRIES.attempts && !shutdown::requested() => {
+        let delay = DOWNLOAD_RETRIES.delay(attempt);
+        warn!(
+          target: &format!("{}:download", identity),
+          "attempt {} failed, retrying in {} seconds: {}",
+          attempt,
+          delay.as_secs(),
+          e
+        );
+        clock::sleep(delay);
+        attempt += 1;
+      }
+      Err(e) => return Err(e),
+    }
+  }
+}
+
+/// The payload an object storage manifest in `payload` refers to, or `payload` itself.
+/// Manifests are only recognized in protocol version [`MANIFEST_VERSION`]
+pub fn resolve(
+  mut payload: SpooledTempFile,
+  identity: &str,
+) -> Result<SpooledTempFile, Box<dyn Error>> {
+  if protocol::version() < MANIFEST_VERSION {
+    return Ok(payload);
+  }
+  let mut prefix = Vec::new();
+  (&mut payload).take(protocol::MANIFEST_PREFIX.len() as u64).read_to_end(&mut prefix)?;
+  if prefix != protocol::MANIFEST_PREFIX.as_bytes() {
+    payload.seek(SeekFrom::Start(0))?;
+    return Ok(payload);
+  }
+  payload.seek(SeekFrom::Start(0))?;
+  let mut frame = Vec::new();
+  payload.read_to_end(&mut frame)?;
+  let manifest = ObjectManifest::parse(&frame).ok_or("the payload manifest is malformed")?;
+  info!(
+    target: &format!("{}:download", identity),
+    "fetching {} bytes from {}.", manifest.size, manifest.url
+  );
+  download(&manifest, identity)
+}
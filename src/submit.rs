@@ -0,0 +1,174 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Batch submission of an imported corpus to a CorTeX ventilator, the client side of the
+//! producer protocol: every entry is sent over a REQ socket as a JSON [`Submission`] frame
+//! followed by the entry ZIP, and the ventilator answers with a JSON [`SubmissionReply`].
+//!
+//! Acknowledged entries are appended to a progress file next to the manifest, so that an
+//! interrupted submission resumes with the first entry the ventilator hasn't acknowledged.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::import::ImportIndex;
+
+/// How long to wait for the ventilator to acknowledge an entry
+pub const SUBMISSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The header of a submitted corpus entry
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Submission {
+  /// Name of the corpus the entry belongs to
+  pub corpus: String,
+  /// Name of the entry
+  pub entry: String,
+  /// Size of the entry ZIP in bytes, which follows in the next frame
+  pub bytes: u64,
+}
+
+/// The answer of the ventilator to a submission
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SubmissionReply {
+  /// `accepted` if the entry was added to the corpus, e.g. `unknown_corpus` otherwise
+  pub status: String,
+  /// Details for the operator
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub message: Option<String>,
+}
+
+/// How to submit a corpus
+#[derive(Clone, Debug)]
+pub struct SubmitOptions {
+  /// URL to the CorTeX ventilator accepting submissions
+  pub ventilator: String,
+  /// Name of the corpus the entries are added to
+  pub corpus: String,
+  /// Entries submitted per second at most, unlimited if `None`
+  pub rate: Option<f64>,
+  /// How long to wait for every acknowledgement
+  pub timeout: Duration,
+}
+
+/// What a submission run did
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubmitReport {
+  /// Entries acknowledged in this run
+  pub submitted: usize,
+  /// Entries skipped, as acknowledged in an earlier run
+  pub resumed: usize,
+  /// Entries the ventilator refused, with its reason
+  pub rejected: Vec<(String, String)>,
+}
+
+impl fmt::Display for SubmitReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (entry, reason) in &self.rejected {
+      writeln!(f, "rejected {}: {}", entry, reason)?;
+    }
+    writeln!(
+      f,
+      "submitted {} entries, {} acknowledged earlier, {} rejected",
+      self.submitted,
+      self.resumed,
+      self.rejected.len()
+    )
+  }
+}
+
+/// The progress file of the submission of the manifest at `manifest`
+pub fn progress_path(manifest: &Path) -> PathBuf { manifest.with_extension("submitted") }
+
+/// The entries acknowledged in earlier submissions of the manifest at `manifest`
+pub fn acknowledged(manifest: &Path) -> Result<HashSet<String>, Box<dyn Error>> {
+  let path = progress_path(manifest);
+  if !path.exists() {
+    return Ok(HashSet::new());
+  }
+  Ok(
+    fs::read_to_string(path)?
+      .lines()
+      .filter(|line| !line.is_empty())
+      .map(str::to_string)
+      .collect(),
+  )
+}
+
+/// Submit every entry of the import manifest at `manifest` which wasn't acknowledged before
+pub fn submit(manifest: &Path, options: &SubmitOptions) -> Result<SubmitReport, Box<dyn Error>> {
+  let index = ImportIndex::load(manifest)?;
+  let corpus_dir = manifest.parent().unwrap_or_else(|| Path::new("."));
+  let done = acknowledged(manifest)?;
+  let mut progress = OpenOptions::new().create(true).append(true).open(progress_path(manifest))?;
+
+  let context = zmq::Context::new();
+  let socket = context.socket(zmq::REQ)?;
+  socket.set_linger(0)?;
+  socket.set_ipv6(true)?;
+  socket.set_rcvtimeo(options.timeout.as_millis() as i32)?;
+  socket.connect(&options.ventilator)?;
+
+  let interval = options
+    .rate
+    .filter(|rate| *rate > 0.0)
+    .map(|rate| Duration::from_secs_f64(1.0 / rate));
+  let mut next_send = Instant::now();
+  let mut report = SubmitReport::default();
+  for entry in &index.entries {
+    if done.contains(&entry.name) {
+      report.resumed += 1;
+      continue;
+    }
+    if let Some(interval) = interval {
+      thread::sleep(next_send.saturating_duration_since(Instant::now()));
+      next_send = Instant::now() + interval;
+    }
+    let payload = fs::read(corpus_dir.join(&entry.entry))?;
+    let submission = Submission {
+      corpus: options.corpus.clone(),
+      entry: entry.name.clone(),
+      bytes: payload.len() as u64,
+    };
+    socket.send(serde_json::to_string(&submission)?.as_bytes(), zmq::SNDMORE)?;
+    socket.send(payload, 0)?;
+    let reply = match socket.recv_bytes(0) {
+      Ok(reply) => reply,
+      Err(zmq::Error::EAGAIN) => {
+        return Err(From::from(format!(
+          "the ventilator {} did not acknowledge {} within {} seconds; submit again to resume",
+          options.ventilator,
+          entry.name,
+          options.timeout.as_secs()
+        )))
+      }
+      Err(e) => return Err(e.into()),
+    };
+    let reply: SubmissionReply = serde_json::from_slice(&reply)
+      .map_err(|e| format!("malformed answer from the ventilator {}: {}", options.ventilator, e))?;
+    if reply.status == "accepted" {
+      writeln!(progress, "{}", entry.name)?;
+      progress.flush()?;
+      report.submitted += 1;
+    } else {
+      let reason = match reply.message {
+        Some(message) => format!("{} ({})", reply.status, message),
+        None => reply.status,
+      };
+      warn!(target: "submit", "the ventilator rejected {}: {}", entry.name, reason);
+      report.rejected.push((entry.name.clone(), reason));
+    }
+  }
+  Ok(report)
+}
@@ -0,0 +1,186 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The coordination primitives shared by the threads of a worker process: the shutdown signal,
+//! the count of tasks in flight, and the task permits throttling fetches under host pressure.
+//!
+//! They are built on the atomics, locks and condition variables re-exported here, which are
+//! loom's when compiled with `RUSTFLAGS="--cfg loom"`, so that `cargo test --test sync_test`
+//! explores every interleaving of the concurrency tests instead of the few the scheduler picks.
+
+use std::time::Duration;
+
+#[cfg(loom)]
+pub use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(loom)]
+pub use loom::sync::{Arc, Condvar, Mutex};
+#[cfg(not(loom))]
+pub use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+pub use std::sync::{Arc, Condvar, Mutex};
+
+/// A flag asking every thread to drain and exit, shared by all its clones
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownSignal {
+  flag: Arc<AtomicBool>,
+}
+
+impl ShutdownSignal {
+  /// A signal nobody raised yet
+  pub fn new() -> Self { ShutdownSignal::default() }
+
+  /// Ask all threads to drain and exit
+  pub fn request(&self) { self.flag.store(true, Ordering::SeqCst); }
+
+  /// Was a shutdown requested?
+  pub fn requested(&self) -> bool { self.flag.load(Ordering::SeqCst) }
+
+  /// The underlying flag, for raising the signal from a signal handler
+  pub fn flag(&self) -> &Arc<AtomicBool> { &self.flag }
+}
+
+#[derive(Debug, Default)]
+struct InFlight {
+  count: Mutex<usize>,
+  finished: Condvar,
+}
+
+/// The tasks currently being worked on, refusing new ones once a shutdown was requested,
+/// so that a drain waits for exactly the tasks which started before it
+#[derive(Clone, Debug)]
+pub struct TaskCounter {
+  shutdown: ShutdownSignal,
+  in_flight: Arc<InFlight>,
+}
+
+/// Held for the duration of a task, counted by its [`TaskCounter`] until dropped
+#[derive(Debug)]
+pub struct TaskGuard {
+  in_flight: Arc<InFlight>,
+}
+
+impl TaskCounter {
+  /// Count the tasks of the threads draining on `shutdown`
+  pub fn new(shutdown: ShutdownSignal) -> Self {
+    TaskCounter {
+      shutdown,
+      in_flight: Arc::new(InFlight::default()),
+    }
+  }
+
+  /// Start a task, unless a shutdown was requested
+  pub fn begin(&self) -> Option<TaskGuard> {
+    let mut count = self.in_flight.count.lock().unwrap();
+    // checked under the lock, so that a drain can't miss a task starting concurrently
+    if self.shutdown.requested() {
+      return None;
+    }
+    *count += 1;
+    Some(TaskGuard {
+      in_flight: Arc::clone(&self.in_flight),
+    })
+  }
+
+  /// How many tasks are in flight
+  pub fn in_flight(&self) -> usize { *self.in_flight.count.lock().unwrap() }
+
+  /// Request a shutdown and block until the tasks in flight are done, or `timeout` passed.
+  /// Returns the number of tasks still in flight
+  pub fn drain(&self, timeout: Option<Duration>) -> usize {
+    self.shutdown.request();
+    let mut count = self.in_flight.count.lock().unwrap();
+    match timeout {
+      Some(timeout) => {
+        while *count > 0 {
+          let (waited, result) = self.in_flight.finished.wait_timeout(count, timeout).unwrap();
+          count = waited;
+          if result.timed_out() {
+            break;
+          }
+        }
+      }
+      None => {
+        while *count > 0 {
+          count = self.in_flight.finished.wait(count).unwrap();
+        }
+      }
+    }
+    *count
+  }
+}
+
+impl Drop for TaskGuard {
+  fn drop(&mut self) {
+    if let Ok(mut count) = self.in_flight.count.lock() {
+      *count -= 1;
+    }
+    self.in_flight.finished.notify_all();
+  }
+}
+
+/// A semaphore with an adjustable number of permits, the backpressure gauge of a pool: how many
+/// of its threads may currently be fetching or working on a task
+#[derive(Debug)]
+pub struct Permits {
+  allowed: AtomicUsize,
+  active: Mutex<usize>,
+  released: Condvar,
+}
+
+/// Held by a thread while it fetches and works on a single task
+#[derive(Debug)]
+pub struct Permit<'a> {
+  permits: &'a Permits,
+}
+
+impl Permits {
+  /// Allow `allowed` threads at a time
+  pub fn new(allowed: usize) -> Self {
+    Permits {
+      allowed: AtomicUsize::new(allowed),
+      active: Mutex::new(0),
+      released: Condvar::new(),
+    }
+  }
+
+  /// Blocks until one more thread is allowed, or `shutdown` is requested (checked every `poll`)
+  pub fn acquire(&self, shutdown: &ShutdownSignal, poll: Duration) -> Option<Permit<'_>> {
+    let mut active = self.active.lock().unwrap();
+    while *active >= self.allowed.load(Ordering::SeqCst) {
+      if shutdown.requested() {
+        return None;
+      }
+      active = self.released.wait_timeout(active, poll).unwrap().0;
+    }
+    *active += 1;
+    Some(Permit { permits: self })
+  }
+
+  /// Change how many threads are allowed, waking up the waiting ones
+  pub fn set_allowed(&self, allowed: usize) {
+    {
+      let _active = self.active.lock().unwrap();
+      self.allowed.store(allowed, Ordering::SeqCst);
+    }
+    self.released.notify_all();
+  }
+
+  /// How many threads are allowed
+  pub fn allowed(&self) -> usize { self.allowed.load(Ordering::SeqCst) }
+
+  /// How many permits are held
+  pub fn active(&self) -> usize { *self.active.lock().unwrap() }
+}
+
+impl Drop for Permit<'_> {
+  fn drop(&mut self) {
+    if let Ok(mut active) = self.permits.active.lock() {
+      *active -= 1;
+    }
+    self.permits.released.notify_all();
+  }
+}
@@ -0,0 +1,321 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Chunked IO with CorTeX: requesting a task and spooling its payload frames, and streaming a
+//! result back to the sink in frames of the worker's `message_size`, or of a size picked within its
+//! `message_size_bounds` from the result's size. The `receive_from_cortex`
+//! and `respond_to_cortex` methods of [`Worker`] default to [`receive`] and [`respond`].
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+
+use tempfile::{spooled_tempfile, SpooledTempFile};
+use zmq::{Message, Socket, SNDMORE};
+
+use crate::artifacts::Artifact;
+use crate::dryrun;
+use crate::endpoint::ReconnectionMonitor;
+use crate::mmap::{self, MappedFile};
+use crate::progress::{self, TransferProgress};
+use crate::protocol::{self, Compression, ProtocolError, ResultStatus, TaskMetadata, TaskStats};
+use crate::shutdown;
+#[cfg(feature = "object-storage")]
+use crate::storage;
+use crate::worker::Worker;
+
+/// Task payloads up to this many bytes are received in memory, larger ones are spooled to disk
+pub const SPOOL_THRESHOLD: usize = 16 * 1024 * 1024;
+
+/// A task as received from CorTeX: its payload, size, taskid and metadata
+pub type ReceivedFrames = (Result<SpooledTempFile, Box<dyn Error>>, usize, String, Option<TaskMetadata>);
+
+/// Request a task for `worker` from the `source` endpoint, with an optional `handshake` frame, and
+/// receive it with its metadata, if the dispatcher sent any.
+/// The request is repeated whenever the source reconnects, as the dispatcher may have changed.
+/// Fails on socket errors, possibly amid the frames of a task, see [`drain_message`]
+pub fn receive<W: Worker>(
+  worker: &W,
+  source: &Socket,
+  reconnections: &ReconnectionMonitor,
+  handshake: Option<&str>,
+) -> zmq::Result<ReceivedFrames> {
+  let mut taskid_msg = Message::new();
+  let mut recv_msg = Message::new();
+  let mut request = vec![worker.get_service()];
+  if worker.compression() == Compression::Zstd {
+    request.push(protocol::ACCEPT_ZSTD);
+  }
+  if dryrun::enabled() {
+    request.push(protocol::DRY_RUN);
+  }
+  if let Some(handshake) = handshake {
+    request.push(handshake);
+  }
+  source.send_multipart(request.iter().copied(), 0)?;
+  // Waiting for a task is interrupted by signals, give up only when shutting down
+  loop {
+    let mut items = [
+      source.as_poll_item(zmq::POLLIN),
+      reconnections.socket().as_poll_item(zmq::POLLIN),
+    ];
+    // wake up every second, a drain may also be requested without a signal
+    match zmq::poll(&mut items, 1000) {
+      Ok(_) | Err(zmq::Error::EINTR) => {}
+      Err(e) => return Err(e),
+    }
+    if items[1].is_readable() && reconnections.reconnected() {
+      warn!(
+        target: &format!("{}:source", worker.get_identity()),
+        "reconnected to {}, repeating the task request.",
+        worker.get_source_address()
+      );
+      source.send_multipart(request.iter().copied(), 0)?;
+    }
+    if items[0].is_readable() {
+      source.recv(&mut taskid_msg, 0)?;
+      break;
+    }
+    if shutdown::requested() {
+      return Ok((Err(From::from("interrupted by shutdown")), 0, String::new(), None));
+    }
+  }
+  let taskid = match taskid_msg.as_str() {
+    Some(taskid) => taskid.to_string(),
+    None => {
+      drain_message(source);
+      return Ok((
+        Err(Box::new(ProtocolError(String::from("the taskid is not valid UTF-8")))),
+        0,
+        String::from_utf8_lossy(&taskid_msg).into_owned(),
+        None,
+      ));
+    }
+  };
+  if !taskid_msg.get_more() {
+    let error = ProtocolError(format!("task {} arrived without payload frames", taskid));
+    return Ok((Err(Box::new(error)), 0, taskid, None));
+  }
+
+  // Small payloads stay in memory, larger ones spill over to a temporary file
+  let mut payload = spooled_tempfile(SPOOL_THRESHOLD);
+  let mut input_size = 0;
+  let mut metadata = None;
+  let mut first_frame = true;
+  let mut progress = TransferProgress::new(format!("{}:receiving", worker.get_identity()), "received", &taskid);
+  loop {
+    match source.recv(&mut recv_msg, 0) {
+      Ok(_) => {}
+      Err(zmq::Error::EINTR) => continue,
+      Err(e) => return Err(e),
+    }
+    // a metadata frame is always followed by the payload
+    if first_frame {
+      first_frame = false;
+      if recv_msg.get_more() && protocol::version() >= protocol::METADATA_VERSION {
+        metadata = TaskMetadata::parse(&recv_msg);
+        if metadata.is_some() {
+          continue;
+        }
+      }
+    }
+
+    if payload.write_all(recv_msg.deref()).is_ok() {
+      input_size += recv_msg.len();
+      progress.advance(recv_msg.len());
+      worker.on_receive_progress(&taskid, input_size);
+    }
+    if !recv_msg.get_more() {
+      break;
+    }
+  }
+
+  let payload_result = if input_size > 0 {
+    payload.seek(SeekFrom::Start(0)).unwrap();
+    // Payloads kept in object storage arrive as manifests, and are downloaded here
+    #[cfg(feature = "object-storage")]
+    let payload = match storage::resolve(payload, worker.get_identity()) {
+      Ok(payload) => payload,
+      Err(e) => return Ok((Err(e), input_size, taskid, metadata)),
+    };
+    protocol::decode_payload(payload, worker.compression()).map_err(Into::into)
+  } else {
+    Err(From::from("Input was empty.")) // No input, no conversion needed
+  };
+
+  info!(
+    target: &format!("{}:received", worker.get_identity()),
+    "task {}, read {} bytes from CorTeX.", taskid, input_size
+  );
+  Ok((payload_result, input_size, taskid, metadata))
+}
+
+/// Send the result of task `taskid` of `worker` to the `sink`, preceded by its `status` and
+/// `stats` where the protocol version calls for them, and by its secondary `artifacts`
+#[allow(clippy::too_many_arguments)]
+pub fn respond<W: Worker>(
+  worker: &W,
+  file_result: Result<File, Box<dyn Error>>,
+  status: Option<&ResultStatus>,
+  stats: Option<&TaskStats>,
+  artifacts: Vec<Artifact>,
+  input_size: usize,
+  taskid: &str,
+  sink: &Socket,
+) {
+  // The envelope frames go out in a single multipart send
+  sink
+    .send_multipart([worker.get_identity(), worker.get_service(), taskid], SNDMORE)
+    .unwrap();
+  if let Some(status) = status {
+    sink.send(status.frame().as_bytes(), SNDMORE).unwrap();
+  }
+  if let Some(stats) = stats {
+    sink.send(stats.frame().as_bytes(), SNDMORE).unwrap();
+  }
+  // handed over by the runtime from protocol version 10 on, and only for successful conversions
+  match file_result {
+    Ok(converted_file) => {
+      for mut artifact in artifacts {
+        match artifact.contents() {
+          Ok(contents) => {
+            sink.send(artifact.header().frame().as_bytes(), SNDMORE).unwrap();
+            sink.send(contents, SNDMORE).unwrap();
+          }
+          Err(e) => warn!(
+            target: &format!("{}:artifacts", worker.get_identity()),
+            "task {}, failed to read artifact {}: {}", taskid, artifact.name, e
+          ),
+        }
+      }
+      let mut progress = TransferProgress::new(format!("{}:sending", worker.get_identity()), "sent", taskid);
+      let mut sent = |bytes: usize| {
+        if progress.advance(bytes) {
+          progress::keepalive(worker.get_identity(), worker.get_service(), taskid, progress.bytes());
+        }
+        worker.on_send_progress(taskid, progress.bytes());
+      };
+      let mut total_size = 0;
+      let result_bytes = converted_file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+      let message_size = worker.message_size_for(result_bytes);
+      let large = result_bytes >= mmap::threshold();
+      // falls back to buffered reads wherever mmap fails
+      let mapped = if large { MappedFile::map(&converted_file).ok() } else { None };
+      match mapped {
+        Some(mapped) => {
+          // Frame very large results directly from the memory map, each frame copied once into
+          // its message
+          let mut frames = mapped.chunks(message_size.max(1)).peekable();
+          while let Some(frame) = frames.next() {
+            total_size += frame.len();
+            let flags = if frames.peek().is_some() { SNDMORE } else { 0 };
+            sink.send(frame, flags).unwrap();
+            sent(frame.len());
+          }
+        }
+        None => {
+          // Stream converted data via zmq, flagging all but the last frame with SNDMORE. Every
+          // frame is a message of its own, which ZeroMQ's I/O thread batches into as few socket
+          // writes as it can, so there is no vectored send to make on top of it
+          let mut frames = FrameChunker::new(converted_file, message_size);
+          while let Some((frame, last)) = frames.next_frame().unwrap() {
+            total_size += frame.len();
+            let frame_size = frame.len();
+            sink.send(frame, if last { 0 } else { SNDMORE }).unwrap();
+            sent(frame_size);
+          }
+        }
+      }
+      info!(
+        target: &format!("{}:completed", worker.get_identity()),
+        " task {}, sent {} bytes back to CorTeX.", taskid, total_size
+      );
+    }
+    Err(e) => {
+      // Send an empty reply, so that cortex knows this is an aberrant task
+      sink.send(Vec::new(), 0).unwrap();
+      info!(
+        target: &format!("{}:result", worker.get_identity()),
+        " task {} ({} input bytes) came back empty: {}", taskid, input_size, e
+      );
+    }
+  }
+}
+
+/// Discard the remaining frames of a partially read message, to resynchronize with the dispatcher
+pub(crate) fn drain_message(source: &Socket) {
+  let mut frame = Message::new();
+  while source.get_rcvmore().unwrap_or(false) {
+    match source.recv(&mut frame, 0) {
+      Ok(_) | Err(zmq::Error::EINTR) => {}
+      Err(_) => break,
+    }
+  }
+}
+
+/// Splits a result into frames of exactly `message_size` bytes, except for the last one.
+/// Short reads never end a frame early: only a read of zero bytes counts as the end of input.
+/// Frames are read straight into a single buffer reused for the whole result.
+pub struct FrameChunker<R: Read> {
+  reader: R,
+  frame: Vec<u8>,
+  /// The byte read past a full frame to tell whether it was the last one, starting the next frame
+  lookahead: Option<u8>,
+  finished: bool,
+}
+
+impl<R: Read> FrameChunker<R> {
+  /// Chunk `reader` into frames of `message_size` bytes
+  pub fn new(reader: R, message_size: usize) -> Self {
+    FrameChunker {
+      reader,
+      frame: vec![0; message_size.max(1)],
+      lookahead: None,
+      finished: false,
+    }
+  }
+
+  /// The next frame and whether it is the last one, or `None` once the last frame was returned.
+  /// An empty input yields a single empty last frame.
+  pub fn next_frame(&mut self) -> io::Result<Option<(&[u8], bool)>> {
+    if self.finished {
+      return Ok(None);
+    }
+    let mut filled = 0;
+    if let Some(byte) = self.lookahead.take() {
+      self.frame[0] = byte;
+      filled = 1;
+    }
+    filled += fill(&mut self.reader, &mut self.frame[filled..])?;
+    // A full frame is the last one only if nothing is left behind it
+    if filled < self.frame.len() {
+      self.finished = true;
+    } else {
+      let mut byte = [0];
+      match fill(&mut self.reader, &mut byte)? {
+        0 => self.finished = true,
+        _ => self.lookahead = Some(byte[0]),
+      }
+    }
+    Ok(Some((&self.frame[..filled], self.finished)))
+  }
+}
+
+/// Read from `reader` until `buffer` is full or the input ends, returning the bytes read
+fn fill<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+  let mut filled = 0;
+  while filled < buffer.len() {
+    match reader.read(&mut buffer[filled..]) {
+      Ok(0) => break,
+      Ok(read) => filled += read,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e),
+    }
+  }
+  Ok(filled)
+}
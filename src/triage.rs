@@ -0,0 +1,279 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Triage bundles of the failures of a run, for sharing with converter developers.
+//!
+//! [`collect`] finds the failed tasks of a run in its journal (`run --journal`), together with
+//! the tasks whose recorded result (`run --record`) reports an error or fatal message, and groups
+//! them by the `Severity:category` of their most severe `cortex.log` message, or under
+//! [`FAILED_CATEGORY`] when the conversion returned no result. A few representative tasks are
+//! picked per category, those with the smallest inputs first, within a size budget shared by all
+//! categories. [`write_bundle`] packages their recorded inputs, logs and errors as a `.tar.gz`,
+//! with an `index.json` of the categories and the environment of the run.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::Local;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::adaptor;
+use crate::capabilities::{self, Capabilities};
+use crate::journal::{self, JournalEntry, JournalEvent};
+use crate::logcodes::{self, Severity};
+use crate::protocol::taskid_file_stem;
+
+/// Category of the tasks whose conversion returned no result
+pub const FAILED_CATEGORY: &str = "failed";
+/// Tasks packaged per category, by default
+pub const TRIAGE_PER_CATEGORY: usize = 5;
+/// Bytes of inputs and logs in a bundle at most, by default
+pub const TRIAGE_MAX_BYTES: u64 = 50 * 1024 * 1024;
+/// Top-level directory of a bundle
+pub const BUNDLE_DIR: &str = "triage";
+
+/// Which failed tasks go into a bundle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TriageOptions {
+  /// Tasks packaged per category at most
+  pub per_category: usize,
+  /// Bytes of inputs and logs packaged at most, across all categories
+  pub max_bytes: u64,
+}
+
+impl Default for TriageOptions {
+  fn default() -> TriageOptions {
+    TriageOptions {
+      per_category: TRIAGE_PER_CATEGORY,
+      max_bytes: TRIAGE_MAX_BYTES,
+    }
+  }
+}
+
+/// A failed task
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TriageCase {
+  /// The CorTeX task id
+  pub taskid: String,
+  /// Identity of the worker thread which converted it
+  pub identity: String,
+  /// Size of the received payload
+  pub input_bytes: u64,
+  /// Time spent on the task
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub duration_ms: Option<u64>,
+  /// Why the conversion failed, when it returned no result
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+  /// The recorded input, if the task was recorded
+  #[serde(skip)]
+  pub input: Option<PathBuf>,
+  /// The `cortex.log` of the recorded result, if any
+  #[serde(skip)]
+  pub log: Option<Vec<u8>>,
+}
+
+impl TriageCase {
+  /// Bytes the task adds to a bundle
+  pub fn size(&self) -> u64 {
+    let input = if self.input.is_some() { self.input_bytes } else { 0 };
+    input + self.log.as_ref().map(|log| log.len() as u64).unwrap_or(0)
+  }
+}
+
+/// The failed tasks of a category
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TriageCategory {
+  /// The `Severity:category` of the failures, or [`FAILED_CATEGORY`]
+  pub category: String,
+  /// Number of failed tasks in the category
+  pub failures: usize,
+  /// The representative tasks packaged
+  pub cases: Vec<TriageCase>,
+}
+
+impl TriageCategory {
+  /// Name of the bundle directory of the category
+  pub fn dir_name(&self) -> String {
+    self
+      .category
+      .chars()
+      .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+      .collect()
+  }
+}
+
+/// Where and with what a bundle was made
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Environment {
+  /// The pericortex build packaging the bundle
+  pub capabilities: Capabilities,
+  /// Operating system, as in `std::env::consts::OS`
+  pub os: String,
+  /// CPU architecture, as in `std::env::consts::ARCH`
+  pub arch: String,
+  /// The services of the recorded tasks
+  pub services: Vec<String>,
+  /// When the bundle was made, in RFC 3339 format
+  pub generated: String,
+}
+
+/// The `index.json` of a bundle
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TriageIndex {
+  /// Where and with what the bundle was made
+  pub environment: Environment,
+  /// The categories of failures, most frequent first
+  pub categories: Vec<TriageCategory>,
+}
+
+/// The failed tasks of the run journaled at `journal`, by category, most frequent first, with the
+/// representative tasks picked as per `options`. Recorded inputs and results are looked up in
+/// `recordings`; without them, only the tasks which returned no result are found
+pub fn collect(
+  journal: &Path,
+  recordings: Option<&Path>,
+  options: TriageOptions,
+) -> Result<Vec<TriageCategory>, Box<dyn Error>> {
+  // a task may have been converted again after a restart, its last outcome counts
+  let mut outcomes: BTreeMap<String, JournalEntry> = BTreeMap::new();
+  for entry in journal::read(journal)? {
+    if entry.event != JournalEvent::Started {
+      outcomes.insert(entry.taskid.clone(), entry);
+    }
+  }
+  let mut failures: BTreeMap<String, Vec<TriageCase>> = BTreeMap::new();
+  for (taskid, entry) in outcomes {
+    let stem = taskid_file_stem(&taskid);
+    let recorded = |suffix: &str| {
+      recordings
+        .map(|dir| dir.join(format!("{}{}", stem, suffix)))
+        .filter(|path| path.is_file())
+    };
+    let log = match (&entry.event, recorded(".result.zip")) {
+      (JournalEvent::Completed, Some(result)) => adaptor::read_archive_entry(&result, "cortex.log")?,
+      _ => None,
+    };
+    let category = match entry.event {
+      JournalEvent::Failed => FAILED_CATEGORY.to_string(),
+      _ => {
+        let messages = logcodes::parse(&String::from_utf8_lossy(log.as_deref().unwrap_or_default()));
+        let worst = logcodes::max_severity(&messages).filter(|severity| *severity >= Severity::Error);
+        match worst.and_then(|severity| messages.iter().find(|message| message.severity == severity)) {
+          Some(message) => message.category_code(),
+          None => continue,
+        }
+      }
+    };
+    failures.entry(category).or_default().push(TriageCase {
+      taskid,
+      identity: entry.identity,
+      input_bytes: entry.input_bytes as u64,
+      duration_ms: entry.duration_ms,
+      error: entry.error,
+      input: recorded(".zip"),
+      log,
+    });
+  }
+
+  let mut categories: Vec<(String, Vec<TriageCase>)> = failures.into_iter().collect();
+  categories.sort_by(|(a, a_cases), (b, b_cases)| b_cases.len().cmp(&a_cases.len()).then(a.cmp(b)));
+  for (_, cases) in categories.iter_mut() {
+    cases.sort_by(|a, b| a.size().cmp(&b.size()).then(a.taskid.cmp(&b.taskid)));
+  }
+  // pick by rounds, so that the budget isn't spent on the most frequent categories alone
+  let mut picked: Vec<Vec<TriageCase>> = vec![Vec::new(); categories.len()];
+  let mut remaining = options.max_bytes;
+  for round in 0..options.per_category {
+    for ((_, cases), picked) in categories.iter().zip(picked.iter_mut()) {
+      if let Some(case) = cases.get(round) {
+        if case.size() <= remaining {
+          remaining -= case.size();
+          picked.push(case.clone());
+        }
+      }
+    }
+  }
+  Ok(
+    categories
+      .into_iter()
+      .zip(picked)
+      .map(|((category, cases), picked)| TriageCategory {
+        category,
+        failures: cases.len(),
+        cases: picked,
+      })
+      .collect(),
+  )
+}
+
+/// The environment of a bundle of tasks recorded in `recordings`
+pub fn environment(recordings: Option<&Path>) -> Environment {
+  let mut services: Vec<String> = recordings
+    .and_then(|dir| crate::replay::records(dir).ok())
+    .unwrap_or_default()
+    .into_iter()
+    .map(|record| record.service)
+    .collect();
+  services.sort();
+  services.dedup();
+  Environment {
+    capabilities: capabilities::capabilities(),
+    os: std::env::consts::OS.to_string(),
+    arch: std::env::consts::ARCH.to_string(),
+    services,
+    generated: Local::now().to_rfc3339(),
+  }
+}
+
+/// Write the bundle of `categories` to `path`, a `.tar.gz` holding `triage/index.json` and, per
+/// packaged task, `triage/<category>/<taskid>/` with its `input.zip`, its `cortex.log` or the
+/// `error.txt` of its failure
+pub fn write_bundle(path: &Path, categories: &[TriageCategory], environment: Environment) -> Result<(), Box<dyn Error>> {
+  if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+    fs::create_dir_all(parent)?;
+  }
+  let mut bundle = tar::Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+  let mtime = SystemTime::now()
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or(0);
+  let mut append = |name: String, contents: &[u8]| {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    bundle.append_data(&mut header, name, contents)
+  };
+  let index = TriageIndex {
+    environment,
+    categories: categories.to_vec(),
+  };
+  append(format!("{}/index.json", BUNDLE_DIR), &serde_json::to_vec_pretty(&index)?)?;
+  for category in categories {
+    for case in &category.cases {
+      let dir = format!("{}/{}/{}", BUNDLE_DIR, category.dir_name(), taskid_file_stem(&case.taskid));
+      if let Some(ref input) = case.input {
+        append(format!("{}/input.zip", dir), &fs::read(input)?)?;
+      }
+      if let Some(ref log) = case.log {
+        append(format!("{}/cortex.log", dir), log)?;
+      }
+      if let Some(ref error) = case.error {
+        append(format!("{}/error.txt", dir), error.as_bytes())?;
+      }
+    }
+  }
+  bundle.into_inner()?.finish()?;
+  Ok(())
+}
@@ -0,0 +1,210 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Automatic tuning of `message_size`, the size of the frames results are sent in.
+//!
+//! Each thread tries a ladder of frame sizes within the configured bounds on its first tasks,
+//! measuring how fast results are handed to the sink and how much memory the process holds
+//! meanwhile, then settles on the smallest frame size within 10% of the best throughput.
+//! Results are measured as sent on the wire, i.e. after compression.
+//!
+//! Alternatively, a worker with [`message_size_bounds`](crate::worker::Worker::message_size_bounds)
+//! picks the frame size of every result from its size, see [`FrameSizeBounds::for_result`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Tasks sent with each candidate frame size before moving on to the next one
+pub const SAMPLES_PER_CANDIDATE: usize = 2;
+
+/// Frames an adaptively framed result is split into, unless the bounds say otherwise
+pub const FRAMES_PER_RESULT: u64 = 64;
+
+/// The range of frame sizes to choose from, in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FrameSizeBounds {
+  /// Smallest frame size tried
+  pub min: usize,
+  /// Largest frame size tried
+  pub max: usize,
+}
+
+impl Default for FrameSizeBounds {
+  fn default() -> FrameSizeBounds {
+    FrameSizeBounds {
+      min: 64 * 1024,
+      max: 16 * 1024 * 1024,
+    }
+  }
+}
+
+impl FrameSizeBounds {
+  /// The frame size for a result of `result_bytes` bytes: a [`FRAMES_PER_RESULT`]th of it, within
+  /// the bounds, so that small results are not buffered in huge frames, nor large ones sent in
+  /// countless tiny ones
+  pub fn for_result(&self, result_bytes: u64) -> usize {
+    let size = usize::try_from(result_bytes / FRAMES_PER_RESULT).unwrap_or(usize::MAX);
+    size.clamp(self.min.max(1), self.max.max(1))
+  }
+}
+
+impl FromStr for FrameSizeBounds {
+  type Err = String;
+  /// Parses `MIN:MAX`, in bytes
+  fn from_str(bounds: &str) -> Result<FrameSizeBounds, String> {
+    let invalid = || format!("invalid bounds {:?}, expected MIN:MAX in bytes", bounds);
+    let (min, max) = bounds.split_once(':').ok_or_else(invalid)?;
+    let min: usize = min.trim().parse().map_err(|_| invalid())?;
+    let max: usize = max.trim().parse().map_err(|_| invalid())?;
+    if min == 0 || min > max {
+      return Err(invalid());
+    }
+    Ok(FrameSizeBounds { min, max })
+  }
+}
+
+impl TryFrom<String> for FrameSizeBounds {
+  type Error = String;
+  fn try_from(bounds: String) -> Result<FrameSizeBounds, String> { bounds.parse() }
+}
+
+impl From<FrameSizeBounds> for String {
+  fn from(bounds: FrameSizeBounds) -> String { bounds.to_string() }
+}
+
+impl fmt::Display for FrameSizeBounds {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}:{}", self.min, self.max) }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Sample {
+  tasks: usize,
+  bytes: u64,
+  elapsed: Duration,
+  peak_rss: Option<u64>,
+}
+
+impl Sample {
+  fn throughput(&self) -> f64 { self.bytes as f64 / self.elapsed.as_secs_f64().max(1e-6) }
+}
+
+/// Per-thread search for the best frame size
+#[derive(Clone, Debug)]
+pub struct FrameSizeTuner {
+  candidates: Vec<usize>,
+  samples: Vec<Sample>,
+  current: usize,
+  converged: bool,
+}
+
+impl FrameSizeTuner {
+  /// Start with the smallest frame size, quadrupling it up to `bounds.max`
+  pub fn new(bounds: FrameSizeBounds) -> FrameSizeTuner {
+    let mut candidates = vec![bounds.min.max(1)];
+    while let Some(&last) = candidates.last() {
+      if last >= bounds.max {
+        break;
+      }
+      candidates.push(last.saturating_mul(4).min(bounds.max));
+    }
+    FrameSizeTuner {
+      samples: vec![Sample::default(); candidates.len()],
+      candidates,
+      current: 0,
+      converged: false,
+    }
+  }
+
+  /// The frame size to use for the next task
+  pub fn message_size(&self) -> usize { self.candidates[self.current] }
+
+  /// Has the search settled on a frame size?
+  pub fn converged(&self) -> bool { self.converged }
+
+  /// Record that `bytes` were sent in `elapsed`, returning the new frame size if it changes
+  pub fn observe(&mut self, bytes: u64, elapsed: Duration) -> Option<usize> {
+    if self.converged {
+      return None;
+    }
+    let sample = &mut self.samples[self.current];
+    sample.tasks += 1;
+    sample.bytes += bytes;
+    sample.elapsed += elapsed;
+    sample.peak_rss = sample.peak_rss.max(resident_memory());
+    if sample.tasks < SAMPLES_PER_CANDIDATE {
+      return None;
+    }
+    if self.current + 1 < self.candidates.len() {
+      self.current += 1;
+      return Some(self.message_size());
+    }
+    // the smallest frames, and the least memory, that come close to the best throughput
+    let best = self.samples.iter().map(Sample::throughput).fold(0.0, f64::max);
+    let previous = self.current;
+    self.current = self
+      .samples
+      .iter()
+      .position(|sample| sample.throughput() >= 0.9 * best)
+      .unwrap_or(previous);
+    self.converged = true;
+    Some(self.message_size())
+  }
+}
+
+impl fmt::Display for FrameSizeTuner {
+  /// The measurements of every candidate frame size, for the logs
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (candidate, sample) in self.candidates.iter().zip(&self.samples) {
+      if sample.tasks > 0 {
+        write!(
+          f,
+          "[{} bytes: {:.1} MB/s, {} MB resident] ",
+          candidate,
+          sample.throughput() / 1_048_576.0,
+          sample.peak_rss.unwrap_or(0) / 1_048_576
+        )?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Resident memory of this process, where available
+pub fn resident_memory() -> Option<u64> {
+  let status = fs::read_to_string("/proc/self/status").ok()?;
+  let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+  let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+  Some(kilobytes * 1024)
+}
+
+static BOUNDS: Mutex<Option<FrameSizeBounds>> = Mutex::new(None);
+/// The frame size each thread currently uses, keyed by identity
+static EFFECTIVE: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// Tune the frame size of every thread started afterwards within `bounds`
+pub fn enable(bounds: FrameSizeBounds) { *BOUNDS.lock().unwrap() = Some(bounds); }
+
+/// The bounds frame sizes are tuned within, if tuning is enabled
+pub fn bounds() -> Option<FrameSizeBounds> { *BOUNDS.lock().unwrap() }
+
+/// A tuner for a new thread, if tuning is enabled
+pub fn tuner() -> Option<FrameSizeTuner> { BOUNDS.lock().unwrap().map(FrameSizeTuner::new) }
+
+/// Report the frame size currently used by the thread `identity`
+pub fn report(identity: &str, message_size: usize) {
+  EFFECTIVE.lock().unwrap().insert(identity.to_string(), message_size);
+}
+
+/// The frame size currently used by every tuned thread, keyed by identity
+pub fn effective() -> BTreeMap<String, usize> { EFFECTIVE.lock().unwrap().clone() }
@@ -0,0 +1,109 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Zero-downtime upgrades: on SIGUSR2 the worker drains like on a first SIGTERM, saves the state
+//! a successor needs into a file and replaces itself with the (possibly updated) binary at the
+//! same path, with the same arguments and thus the same configuration.
+//!
+//! The successor keeps the PID, so daemons keep their detachment and pid file. Journals and
+//! quarantine strikes are already on disk, the saved state carries the recently completed
+//! taskids, so that tasks CorTeX re-dispatches across the upgrade are still recognized.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::SIGUSR2;
+
+use crate::dedup;
+use crate::shutdown;
+
+/// Environment variable pointing the successor to the saved state
+pub const UPGRADE_STATE_VAR: &str = "PERICORTEX_UPGRADE_STATE";
+
+/// What an upgraded worker inherits from its predecessor
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UpgradeState {
+  /// Taskids completed most recently, oldest first
+  pub completed: Vec<String>,
+}
+
+fn flag() -> &'static Arc<AtomicBool> {
+  static FLAG: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+  FLAG.get_or_init(|| Arc::new(AtomicBool::new(false)))
+}
+
+/// Drain and upgrade on SIGUSR2
+pub fn on_signal() -> Result<(), Box<dyn Error>> {
+  signal_hook::flag::register(SIGUSR2, Arc::clone(flag()))?;
+  shutdown::request_on(SIGUSR2)
+}
+
+/// Was an upgrade requested?
+pub fn requested() -> bool { flag().load(Ordering::SeqCst) }
+
+/// Write the state of this process into `path`
+pub fn save(path: &Path) -> Result<(), Box<dyn Error>> {
+  let state = UpgradeState {
+    completed: dedup::completed(),
+  };
+  fs::write(path, serde_json::to_string(&state)?)?;
+  Ok(())
+}
+
+/// Adopt the state saved in `path`
+pub fn restore_from(path: &Path) -> Result<UpgradeState, Box<dyn Error>> {
+  let state: UpgradeState = serde_json::from_str(&fs::read_to_string(path)?)?;
+  for taskid in &state.completed {
+    dedup::begin(taskid);
+    dedup::complete(taskid, None);
+  }
+  Ok(state)
+}
+
+/// Adopt the state of the predecessor of this process, returning whether it is an upgrade
+pub fn restore() -> Result<bool, Box<dyn Error>> {
+  let path = match env::var_os(UPGRADE_STATE_VAR) {
+    Some(path) => PathBuf::from(path),
+    None => return Ok(false),
+  };
+  env::remove_var(UPGRADE_STATE_VAR);
+  let state = restore_from(&path)?;
+  fs::remove_file(&path).ok();
+  info!(
+    target: "pericortex",
+    "upgraded, remembering {} recently completed tasks", state.completed.len()
+  );
+  Ok(true)
+}
+
+/// Save the state of this drained process and replace it with the binary it was started from.
+/// Only returns if that fails
+#[cfg(unix)]
+pub fn exec() -> Result<(), Box<dyn Error>> {
+  use std::os::unix::process::CommandExt;
+  use std::process::Command;
+
+  let mut args = env::args_os();
+  let program = args.next().ok_or("no program name to execute")?;
+  let path = env::temp_dir().join(format!("pericortex-upgrade-{}.json", std::process::id()));
+  save(&path)?;
+  info!(target: "pericortex", "drained, upgrading to {:?}", program);
+  let error = Command::new(&program).args(args).env(UPGRADE_STATE_VAR, &path).exec();
+  fs::remove_file(&path).ok();
+  Err(From::from(format!("failed to execute {:?}: {}", program, error)))
+}
+
+/// Replace this process with the binary it was started from (unsupported on this platform)
+#[cfg(not(unix))]
+pub fn exec() -> Result<(), Box<dyn Error>> {
+  Err(From::from("upgrading in place is only supported on unix platforms"))
+}
@@ -0,0 +1,280 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A watchdog for wedged conversions, e.g. a converter stuck in a system call its timeout
+//! couldn't interrupt.
+//!
+//! Converter threads mark the progress of their task in flight as it goes through its stages.
+//! A thread which made no progress for the hang threshold is reported: its stage, the state of
+//! its OS thread and the converter processes it started are logged, it is marked dead for the
+//! `/healthz` probe, and the sink receives a `Fatal:workercrash` result for its task. With
+//! respawning on, the pool replaces the thread and leaves it behind; should the wedged thread
+//! come back after all, its result is discarded.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zmq::{Context, Socket};
+
+use crate::adaptor;
+use crate::crash;
+use crate::health;
+
+/// When a thread is considered wedged, and what to do about it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchdog {
+  /// Time without progress after which a thread is reported
+  pub hang_threshold: Duration,
+  /// Replace wedged threads with new ones
+  pub respawn: bool,
+}
+
+/// The task a converter thread is working on
+#[derive(Clone, Debug)]
+struct Activity {
+  identity: String,
+  result_identity: String,
+  service: String,
+  taskid: String,
+  sink: String,
+  stage: String,
+  started: Instant,
+  progressed: Instant,
+  os_thread: Option<String>,
+  reported: bool,
+}
+
+/// What is known about a wedged thread
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WedgeReport {
+  /// Identity of the converter thread
+  pub identity: String,
+  /// The task it is stuck on
+  pub taskid: String,
+  /// The last stage it reached
+  pub stage: String,
+  /// Time since its last progress
+  pub stalled: Duration,
+  /// State, kernel wait channel and current system call of its OS thread, where available
+  pub os_thread: Option<String>,
+  /// Process ids and command lines of the converters it started, where available
+  pub children: Vec<String>,
+}
+
+impl fmt::Display for WedgeReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "thread {} stalled for {} seconds on task {} while {}",
+      self.identity,
+      self.stalled.as_secs(),
+      self.taskid,
+      self.stage
+    )?;
+    if let Some(ref os_thread) = self.os_thread {
+      write!(f, "; {}", os_thread)?;
+    }
+    if !self.children.is_empty() {
+      write!(f, "; converters: {}", self.children.join(", "))?;
+    }
+    Ok(())
+  }
+}
+
+static WATCHDOG: Mutex<Option<Watchdog>> = Mutex::new(None);
+static ACTIVITIES: Mutex<BTreeMap<usize, Activity>> = Mutex::new(BTreeMap::new());
+static NEXT_ACTIVITY: AtomicUsize = AtomicUsize::new(0);
+/// Identities of the wedged threads awaiting a replacement
+static WEDGED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+thread_local! {
+  static CURRENT: RefCell<Option<usize>> = const { RefCell::new(None) };
+}
+
+/// Ends the activity of a thread when dropped
+pub struct ActivityGuard(Option<usize>);
+
+impl Drop for ActivityGuard {
+  fn drop(&mut self) {
+    if let Some(id) = self.0 {
+      ACTIVITIES.lock().unwrap().remove(&id);
+      let _ = CURRENT.try_with(|current| current.borrow_mut().take());
+    }
+  }
+}
+
+/// Watch the converter threads from now on, in a background thread
+pub fn enable(watchdog: Watchdog) {
+  let interval = (watchdog.hang_threshold / 4).clamp(Duration::from_millis(100), Duration::new(5, 0));
+  let started = WATCHDOG.lock().unwrap().replace(watchdog).is_some();
+  if started {
+    return;
+  }
+  thread::spawn(move || {
+    // sockets to the sinks of the wedged threads, kept for later reports
+    let context = Context::new();
+    let mut sinks: BTreeMap<String, Socket> = BTreeMap::new();
+    loop {
+      thread::sleep(interval);
+      for (activity, report) in inspect(Instant::now()) {
+        error!(target: &format!("{}:watchdog", activity.identity), "wedged: {}", report);
+        health::mark_dead(&activity.identity);
+        if let Err(e) = notify(&context, &mut sinks, &activity, &report) {
+          warn!(
+            target: &format!("{}:watchdog", activity.identity),
+            "failed to report task {} to the sink: {}", activity.taskid, e
+          );
+        }
+        if respawning() {
+          WEDGED.lock().unwrap().push(activity.identity);
+        }
+      }
+    }
+  });
+}
+
+/// Are wedged threads replaced?
+pub fn respawning() -> bool { WATCHDOG.lock().unwrap().as_ref().map(|watchdog| watchdog.respawn).unwrap_or(false) }
+
+/// Identities of the threads found wedged since the last call, to be replaced
+pub fn take_wedged() -> Vec<String> { std::mem::take(&mut *WEDGED.lock().unwrap()) }
+
+/// Mark task `taskid` of `service`, fetched under `result_identity` for the sink at `sink`,
+/// in flight on the converter thread `identity`
+pub fn begin(identity: &str, result_identity: &str, service: &str, taskid: &str, sink: &str) -> ActivityGuard {
+  if WATCHDOG.lock().unwrap().is_none() {
+    return ActivityGuard(None);
+  }
+  let id = NEXT_ACTIVITY.fetch_add(1, Ordering::SeqCst);
+  let now = Instant::now();
+  let activity = Activity {
+    identity: identity.to_string(),
+    result_identity: result_identity.to_string(),
+    service: service.to_string(),
+    taskid: taskid.to_string(),
+    sink: sink.to_string(),
+    stage: String::from("converting"),
+    started: now,
+    progressed: now,
+    // the link reads e.g. `1234/task/5678` on Linux
+    os_thread: fs::read_link("/proc/thread-self")
+      .ok()
+      .and_then(|link| link.file_name().map(|tid| tid.to_string_lossy().into_owned())),
+    reported: false,
+  };
+  ACTIVITIES.lock().unwrap().insert(id, activity);
+  CURRENT.with(|current| *current.borrow_mut() = Some(id));
+  ActivityGuard(Some(id))
+}
+
+/// The task in flight on this thread progressed to `stage`
+pub fn progress(stage: &str) {
+  let Some(id) = CURRENT.with(|current| *current.borrow()) else {
+    return;
+  };
+  if let Some(activity) = ACTIVITIES.lock().unwrap().get_mut(&id) {
+    activity.stage = stage.to_string();
+    activity.progressed = Instant::now();
+  }
+}
+
+/// Was the task in flight on this thread reported as wedged? Its result is then discarded
+pub fn abandoned() -> bool {
+  let Some(id) = CURRENT.with(|current| *current.borrow()) else {
+    return false;
+  };
+  ACTIVITIES.lock().unwrap().get(&id).map(|activity| activity.reported).unwrap_or(false)
+}
+
+/// The threads which made no progress for the hang threshold at `now`, reported only once
+fn inspect(now: Instant) -> Vec<(Activity, WedgeReport)> {
+  let Some(threshold) = WATCHDOG.lock().unwrap().as_ref().map(|watchdog| watchdog.hang_threshold) else {
+    return Vec::new();
+  };
+  let mut activities = ACTIVITIES.lock().unwrap();
+  activities
+    .values_mut()
+    .filter(|activity| !activity.reported && now.saturating_duration_since(activity.progressed) > threshold)
+    .map(|activity| {
+      activity.reported = true;
+      let report = WedgeReport {
+        identity: activity.identity.clone(),
+        taskid: activity.taskid.clone(),
+        stage: activity.stage.clone(),
+        stalled: now.saturating_duration_since(activity.progressed),
+        os_thread: activity.os_thread.as_deref().and_then(os_thread_state),
+        children: activity.os_thread.as_deref().map(child_processes).unwrap_or_default(),
+      };
+      (activity.clone(), report)
+    })
+    .collect()
+}
+
+/// State, wait channel and system call of the OS thread `tid` of this process, where available
+fn os_thread_state(tid: &str) -> Option<String> {
+  let dir = format!("/proc/self/task/{}", tid);
+  let stat = fs::read_to_string(format!("{}/stat", dir)).ok()?;
+  // the state follows the parenthesized command name
+  let state = stat.rsplit_once(") ")?.1.split_whitespace().next()?.to_string();
+  let mut description = format!("OS thread {} in state {}", tid, state);
+  if let Ok(wchan) = fs::read_to_string(format!("{}/wchan", dir)) {
+    if !wchan.is_empty() && wchan != "0" {
+      description.push_str(&format!(", waiting in {}", wchan.trim()));
+    }
+  }
+  if let Ok(syscall) = fs::read_to_string(format!("{}/syscall", dir)) {
+    if let Some(number) = syscall.split_whitespace().next() {
+      description.push_str(&format!(", system call {}", number));
+    }
+  }
+  Some(description)
+}
+
+/// Process ids and command lines of the children the OS thread `tid` started, where available
+fn child_processes(tid: &str) -> Vec<String> {
+  let children = fs::read_to_string(format!("/proc/self/task/{}/children", tid)).unwrap_or_default();
+  children
+    .split_whitespace()
+    .map(|pid| {
+      let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+      let cmdline = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+      format!("{} ({})", pid, cmdline.trim())
+    })
+    .collect()
+}
+
+/// Send the sink of `activity` a `Fatal:workercrash` result for its task
+fn notify(
+  context: &Context,
+  sinks: &mut BTreeMap<String, Socket>,
+  activity: &Activity,
+  report: &WedgeReport,
+) -> Result<(), Box<dyn Error>> {
+  if !sinks.contains_key(&activity.sink) {
+    let socket = context.socket(zmq::PUSH)?;
+    socket.set_ipv6(true)?;
+    socket.connect(&activity.sink)?;
+    // give the new connection a moment, the report is sent without blocking
+    thread::sleep(Duration::from_millis(100));
+    sinks.insert(activity.sink.clone(), socket);
+  }
+  crash::send_report(
+    &sinks[&activity.sink],
+    &activity.result_identity,
+    &activity.service,
+    &activity.taskid,
+    activity.started.elapsed(),
+    adaptor::wedged_archive(report)?,
+  )
+}
@@ -6,19 +6,41 @@
 // except according to those terms.
 
 //! base class automating dispatcher communication via ZMQ
+//!
+//! The [`Worker`] trait is the surface converters implement; the thread pool running them lives in
+//! the `runtime` module, the chunked IO with CorTeX in [`transfer`], and the retry, throttling and
+//! limit policies in [`policy`](crate::policy).
 
-use std::borrow::Cow;
 use std::error::Error;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::ops::Deref;
-use std::path::Path;
-use std::thread;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
 use std::time::Duration;
-use std::ffi::OsString;
 
 use tempdir::TempDir;
-use zmq::{Context, Message, Socket, SNDMORE};
+use tempfile::SpooledTempFile;
+use zmq::Socket;
+
+use crate::adaptor::{self, RejectReason};
+use crate::artifacts::Artifact;
+use crate::config::WorkerConfig;
+use crate::dedup::DedupPolicy;
+use crate::endpoint::{Endpoint, ReconnectionMonitor};
+use crate::error::WorkerError;
+use crate::filters::{self, FilterContext, OutputFilter};
+use crate::health;
+use crate::pressure::LoadLimits;
+use crate::protocol::{Compression, PayloadFormat, ResultStatus, TaskStats};
+use crate::retry::RetryPolicy;
+use crate::runtime;
+use crate::state::{self, WorkerState};
+use crate::transfer;
+use crate::tuning::FrameSizeBounds;
+
+pub use crate::policy::{convert_counting_retries, convert_with_retries, Conversion};
+pub use crate::transfer::{FrameChunker, ReceivedFrames, SPOOL_THRESHOLD};
 
 /// Generic requirements for CorTeX workers
 pub trait Worker: Clone + Send {
@@ -26,16 +48,33 @@ pub trait Worker: Clone + Send {
   fn convert(&self, _: &Path) -> Result<File, Box<dyn Error>>;
   /// Size of chunk for network communication, larger implies less IO, smaller implies less RAM use
   fn message_size(&self) -> usize;
+  /// Bounds within which the frame size of every result is picked from its size, in place of
+  /// the static `message_size`, if any
+  fn message_size_bounds(&self) -> Option<FrameSizeBounds> {
+    None
+  }
+  /// Size of the frames a result of `result_bytes` bytes is sent in
+  fn message_size_for(&self, result_bytes: u64) -> usize {
+    match self.message_size_bounds() {
+      Some(bounds) => bounds.for_result(result_bytes),
+      None => self.message_size(),
+    }
+  }
   /// Name of the service, as registered in CorTeX
   fn get_service(&self) -> &str;
-  /// URL to the CorTeX dispatcher
-  fn get_source_address(&self) -> Cow<str>;
-  /// URL to the CorTeX sink
-  fn get_sink_address(&self) -> Cow<str>;
+  /// Endpoint of the CorTeX dispatcher
+  fn get_source_address(&self) -> &Endpoint;
+  /// Endpoint of the CorTeX sink
+  fn get_sink_address(&self) -> &Endpoint;
   /// Simultaneous threads used for one worker each
   fn pool_size(&self) -> usize {
     1
   }
+  /// Threads receiving tasks from the dispatcher and sending results to the sink, each under its
+  /// own identity, by default one per conversion thread
+  fn io_threads(&self) -> usize {
+    self.pool_size()
+  }
   /// Sets a uniquely identifying string for this worker instance
   fn set_identity(&mut self, _identity: String) {
     unimplemented!()
@@ -44,188 +83,242 @@ pub trait Worker: Clone + Send {
   fn get_identity(&self) -> &str {
     unimplemented!()
   }
+  /// Address (e.g. `0.0.0.0:8080`) serving the `/healthz` and `/readyz` probe endpoints, if any
+  fn health_address(&self) -> Option<String> {
+    None
+  }
+  /// Host saturation limits, under which task fetches are paused on some of the threads
+  fn load_limits(&self) -> Option<LoadLimits> {
+    None
+  }
+  /// Path to a TOML configuration file, reloaded on SIGHUP
+  fn config_path(&self) -> Option<PathBuf> {
+    None
+  }
+  /// Apply the settings of a (re)loaded configuration file
+  fn configure(&mut self, _config: &WorkerConfig) {}
+  /// Pause after an empty input or a failed conversion
+  fn throttle(&self) -> Duration {
+    Duration::new(60, 0)
+  }
+  /// Which failed conversions to retry in place, and how often, before reporting the failure
+  fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy::default()
+  }
+  /// How task inputs are packaged: ZIP archives by default. Single-file inputs are written to
+  /// the scratch directory under their own extension and handed to `convert` without any
+  /// archive validation.
+  fn payload_format(&self) -> PayloadFormat {
+    PayloadFormat::Zip
+  }
+  /// Reject obviously unconvertible inputs before converting them. The default `convert_payload`
+  /// runs it on the extracted entry, or on the directory holding a single-file input; workers
+  /// overriding `convert_payload` call it themselves. Rejected tasks are answered with a
+  /// `cortex.log` stating the reason, without throttling.
+  fn validate_input(&self, _dir: &Path) -> Result<(), RejectReason> {
+    Ok(())
+  }
+  /// Should results be checked against the CorTeX conventions (a ZIP with a `cortex.log` at its
+  /// root) before sending them? Violations are replaced by a failure archive explaining them.
+  /// Services whose results are deliberately not ZIP archives turn this off.
+  fn verify_results(&self) -> bool {
+    true
+  }
+  /// Transformations of the output directory applied to every result before it is sent, by
+  /// default those registered with [`filters::register`]. Results must be ZIP archives.
+  fn output_filters(&self) -> Vec<Arc<dyn OutputFilter>> {
+    filters::registered()
+  }
+  /// What to do with tasks CorTeX re-dispatches while, or after, this process converted them
+  fn dedup_policy(&self) -> DedupPolicy {
+    DedupPolicy::Off
+  }
+  /// Compression negotiated for payloads on the wire, see the `protocol` module
+  fn compression(&self) -> Compression {
+    Compression::None
+  }
+  /// The worker's own version, reported to the dispatcher in the protocol handshake
+  fn get_version(&self) -> String {
+    String::from("unknown")
+  }
+  /// Format of the task inputs (e.g. `tex`), announced when registering with CorTeX
+  fn input_format(&self) -> Option<String> {
+    None
+  }
+  /// Format of the results (e.g. `html`), announced when registering with CorTeX
+  fn output_format(&self) -> Option<String> {
+    None
+  }
+  /// Probes the version of the underlying converter (docker image digest, `latexmlc --VERSION`, ...),
+  /// logged at startup, shown by `selftest` and reported in the protocol handshake
+  fn tool_version(&self) -> Result<String, WorkerError> {
+    Err(WorkerError::Deterministic(format!(
+      "{} has no converter version probe",
+      self.get_service()
+    )))
+  }
+  /// Prepares the conversion backend before the first task, e.g. pulls and verifies a docker image.
+  /// An error stops the worker before it fetches any task
+  fn warmup(&self) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+  /// Called as the payload frames of task `taskid` are received, with the bytes received so far
+  fn on_receive_progress(&self, _taskid: &str, _bytes_so_far: usize) {}
+  /// Called as the result frames of task `taskid` are sent, with the bytes sent so far
+  fn on_send_progress(&self, _taskid: &str, _bytes_so_far: usize) {}
+  /// Checks that the dispatcher's source and sink accept connections, before any thread is spawned,
+  /// so that a wrong or unreachable endpoint fails the worker once, with a single error.
+  /// Workers started ahead of their dispatcher may skip the probe
+  fn probe_endpoints(&self) -> Result<(), Box<dyn Error>> {
+    let timeout = Duration::new(2, 0);
+    for (name, address) in [("source", self.get_source_address()), ("sink", self.get_sink_address())] {
+      health::endpoint_reachable(address, timeout)
+        .map_err(|e| format!("dispatcher {} {} is unreachable: {}", name, address, e))?;
+    }
+    Ok(())
+  }
+  /// Checks that the conversion backend (docker image, latexmlc, ...) is usable, consulted by `/readyz`
+  fn readiness_probe(&self) -> Result<(), Box<dyn Error>> {
+    Ok(())
+  }
+  /// The runtime state of the worker process: what each thread is doing, the tasks it went
+  /// through and what holds it back, served on `/state` and consulted by `/healthz`
+  fn state_snapshot(&self) -> WorkerState {
+    state::snapshot()
+  }
+  /// Convert the payload of task `taskid` as received from CorTeX. By default the payload is
+  /// written to a ZIP in `scratch`, checked with `validate_input` and handed to `convert`; workers
+  /// that unpack their input anyway can extract straight from the payload instead.
+  fn convert_payload(
+    &self,
+    payload: &mut SpooledTempFile,
+    taskid: &str,
+    scratch: &TempDir,
+  ) -> Result<File, Box<dyn Error>> {
+    let input_filepath = scratch.path().join(self.payload_format().file_name(taskid));
+    let mut input_file = File::create(&input_filepath)?;
+    payload.seek(SeekFrom::Start(0))?;
+    io::copy(payload, &mut input_file)?;
+    drop(input_file);
+    match self.payload_format() {
+      // archives which don't extract are left for `convert` to report
+      PayloadFormat::Zip => match adaptor::extract_zip_to_tmpdir(&input_filepath, "cortex_validate") {
+        Ok(extracted) => self.validate_input(extracted.path())?,
+        Err(e) => debug!(
+          target: &format!("{}:validate", self.get_identity()),
+          "task {} not validated, its input doesn't extract: {}", taskid, e
+        ),
+      },
+      PayloadFormat::SingleFile { .. } => self.validate_input(scratch.path())?,
+    }
+    self.convert(&input_filepath)
+  }
+  /// Run `convert` on a local corpus ZIP at `path`, bypassing the dispatcher,
+  /// and write the result to `destination`. Returns the size of the result in bytes.
+  fn convert_local(&self, path: &Path, destination: &Path) -> Result<u64, Box<dyn Error>> {
+    // work on a scratch copy, as the dispatcher protocol would
+    let input_tmpdir = TempDir::new("cortex_local")?;
+    let file_name = path.file_name().ok_or("input path has no file name")?;
+    let input_filepath = input_tmpdir.path().join(file_name);
+    fs::copy(path, &input_filepath)?;
+
+    let context = FilterContext {
+      taskid: file_name.to_string_lossy().into_owned(),
+      service: self.get_service().to_string(),
+      identity: String::from("local"),
+    };
+    let mut converted_file = filters::apply(&self.output_filters(), self.convert(&input_filepath)?, &context)?;
+    let mut destination_file = File::create(destination)?;
+    let written = io::copy(&mut converted_file, &mut destination_file)?;
+    input_tmpdir.close()?;
+    Ok(written)
+  }
 
   /// sets up the worker process, with as many threads as requested
   fn start(&mut self, limit: Option<usize>) -> Result<(), Box<dyn Error>>
   where
     Self: 'static + Sized,
   {
-    let hostname = hostname::get().unwrap_or_else(|_| OsString::from("hostname")).into_string().unwrap();
-    match self.pool_size() {
-      1 => {
-        self.set_identity(format!("{}:engrafo:1", hostname));
-        self.start_single(limit)
-      }
-      n => {
-        let mut threads = Vec::new();
-        for thread in 1..=n {
-          let thread_str = if thread < 10 {
-            format!("0{}", thread)
-          } else {
-            thread.to_string()
-          };
-          let identity_single = format!("{}:engrafo:{}", hostname, thread_str);
-          let mut thread_self: Self = self.clone();
-          thread_self.set_identity(identity_single);
-          threads.push(thread::spawn(move || {
-            // TODO: Errors can not be shared between threads safely? What should be the robustness strategy here?
-            thread_self.start_single(limit).unwrap();
-          }));
-        }
-        for t in threads {
-          t.join().unwrap();
-        }
-        Ok(())
-      }
-    }
+    runtime::start(self, limit)
   }
-  /// main worker loop for a single thread, works in perpetuity or up to a specified `limit`
-  fn start_single(&self, limit: Option<usize>) -> Result<(), Box<dyn Error>> {
-    let mut work_counter = 0;
-    // Connect to a task ventilator
-    let context_source = Context::new();
-    let source = context_source.socket(zmq::DEALER).unwrap();
-    source.set_identity(self.get_identity().as_bytes()).unwrap();
-
-    assert!(source.connect(&self.get_source_address()).is_ok());
-    // Connect to a task sink
-    let context_sink = Context::new();
-    let sink = context_sink.socket(zmq::PUSH).unwrap();
-    assert!(sink.connect(&self.get_sink_address()).is_ok());
-    // Work in perpetuity
-    loop {
-      // Prepare a File for the input
-      let input_tmpdir = TempDir::new("cortex_task").unwrap();
-      let (file_result, input_filepath, input_size, taskid) =
-        self.receive_from_cortex(&input_tmpdir, &source);
-      let converted_result = if file_result.is_ok() {
-        self.convert(Path::new(&input_filepath))
-      } else {
-        file_result
-      };
-
-      self.respond_to_cortex(converted_result, input_size, &taskid, &sink);
-
-      input_tmpdir.close().unwrap();
-      work_counter += 1;
-      if let Some(upper_bound) = limit {
-        if work_counter >= upper_bound {
-          // Give enough time to complete the Final job.
-          thread::sleep(Duration::new(1, 0));
-          break;
-        }
-      }
-    }
-    Ok(())
+  /// main worker loop for a single thread, fetching tasks under this worker's identity and
+  /// converting them, works in perpetuity or up to a specified `limit`
+  fn start_single(&self, limit: Option<usize>) -> Result<(), Box<dyn Error>>
+  where
+    Self: 'static,
+  {
+    runtime::run_pool(vec![self.clone()], vec![self.clone()], limit)
   }
 
-  /// Receive from the source endpoint
+  /// Request a task from the source endpoint, with an optional `handshake` frame, and receive it
+  /// with its metadata, if the dispatcher sent any.
+  /// The request is repeated whenever the source reconnects, as the dispatcher may have changed.
+  /// Fails on socket errors, after which the runtime requests a task again
   fn receive_from_cortex(
     &self,
-    input_tmpdir: &TempDir,
     source: &Socket,
-  ) -> (Result<File, Box<dyn Error>>, String, usize, String) {
-    let mut taskid_msg = Message::new();
-    let mut recv_msg = Message::new();
-    source.send(self.get_service(), 0).unwrap();
-    source.recv(&mut taskid_msg, 0).unwrap();
-    let taskid = taskid_msg.as_str().unwrap();
-
-    let input_filepath = input_tmpdir.path().to_str().unwrap().to_string() + "/" + taskid + ".zip";
-
-    let mut file = File::create(input_filepath.clone()).unwrap();
-    let mut input_size = 0;
-    loop {
-      source.recv(&mut recv_msg, 0).unwrap();
-
-      if let Ok(written) = file.write(recv_msg.deref()) {
-        input_size += written;
-      }
-      if !source.get_rcvmore().unwrap() {
-        break;
-      }
-    }
-
-    let file_result = if input_size > 0 {
-      file.seek(SeekFrom::Start(0)).unwrap();
-      Ok(file)
-    } else {
-      Err(From::from("Input was empty.")) // No input, no conversion needed
-    };
-
-    info!(
-      target: &format!("{}:received", self.get_identity()),
-      "task {}, read {} bytes from CorTeX.", taskid, input_size
-    );
-    (file_result, input_filepath, input_size, taskid.to_string())
+    reconnections: &ReconnectionMonitor,
+    handshake: Option<&str>,
+  ) -> zmq::Result<ReceivedFrames> {
+    transfer::receive(self, source, reconnections, handshake)
   }
 
-  /// Respond to the sink endpoint
+  /// Respond to the sink endpoint, preceding the result with its `status` and `stats` where the
+  /// protocol version calls for them, and with its secondary `artifacts`
+  #[allow(clippy::too_many_arguments)]
   fn respond_to_cortex(
     &self,
     file_result: Result<File, Box<dyn Error>>,
+    status: Option<&ResultStatus>,
+    stats: Option<&TaskStats>,
+    artifacts: Vec<Artifact>,
     input_size: usize,
     taskid: &str,
     sink: &Socket,
   ) {
-    sink.send(self.get_identity(), SNDMORE).unwrap();
-    sink.send(self.get_service(), SNDMORE).unwrap();
-    sink.send(taskid, SNDMORE).unwrap();
-    match file_result {
-      Ok(mut converted_file) => {
-        let mut total_size = 0;
-        loop {
-          // Stream converted data via zmq
-          let message_size = self.message_size();
-          let mut data = vec![0; message_size];
-          let size = converted_file.read(&mut data).unwrap();
-          total_size += size;
-          data.truncate(size);
-          if size < message_size {
-            // If exhausted, send the last frame
-            sink.send(&data, 0).unwrap();
-            // And terminate
-            break;
-          } else {
-            // If more to go, send the frame and indicate there's more to come
-            sink.send(&data, SNDMORE).unwrap();
-          }
-        }
-        info!(
-          target: &format!("{}:completed", self.get_identity()),
-          " task {}, sent {} bytes back to CorTeX.", taskid, total_size
-        );
-      }
-      Err(e) => {
-        // Send an empty reply, so that cortex knows this is an aberrant task
-        sink.send(Vec::new(), 0).unwrap();
-        // If there was nothing to do
-        // throttle in case there is a temporary local issue, such as running out of available RAM, etc.
-        // but also to protect the server from DDoS-like behavior where we send broken requests at nauseam.
-        if input_size == 0 {
-          info!(
-            target: &format!("{}:result", self.get_identity()),
-            "Empty input. Throttling for a minute."
-          );
-        } else {
-          info!(
-            target: &format!("{}:result", self.get_identity()),
-            "Conversion came back empty: {:?}. Throttling for a minute.", e
-          );
-        }
-        thread::sleep(Duration::new(60, 0));
-      }
-    }
+    transfer::respond(self, file_result, status, stats, artifacts, input_size, taskid, sink)
   }
 }
 
+/// Run a converter's version `command`, returning the first line it prints
+pub fn probe_version(command: &mut Command) -> Result<String, WorkerError> {
+  let probed = command.output().map_err(|e| match e.kind() {
+    io::ErrorKind::NotFound => WorkerError::Deterministic(format!("{:?} is not installed", command)),
+    _ => WorkerError::Transient(format!("failed to run {:?}: {}", command, e)),
+  })?;
+  if !probed.status.success() {
+    return Err(WorkerError::Deterministic(format!(
+      "{:?} failed with {}: {}",
+      command,
+      probed.status,
+      String::from_utf8_lossy(&probed.stderr).trim()
+    )));
+  }
+  // some tools print their version banner on STDERR
+  let first_line = |output: &[u8]| {
+    String::from_utf8_lossy(output)
+      .lines()
+      .map(str::trim)
+      .find(|line| !line.is_empty())
+      .map(str::to_string)
+  };
+  first_line(&probed.stdout)
+    .or_else(|| first_line(&probed.stderr))
+    .ok_or_else(|| WorkerError::Deterministic(format!("{:?} printed no version", command)))
+}
+
+mod command;
+pub use command::{CommandSpec, CommandWorker};
+
 mod echo;
 pub use echo::EchoWorker;
 
+#[cfg(feature = "latexml")]
 mod tex_to_html;
+#[cfg(feature = "latexml")]
 pub use tex_to_html::TexToHtmlWorker;
 
 #[cfg(feature = "engrafo")]
 mod engrafo;
 #[cfg(feature = "engrafo")]
-pub use engrafo::EngrafoWorker;
+pub use engrafo::{EngrafoWorker, GpuAssignment};
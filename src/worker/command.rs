@@ -0,0 +1,220 @@
+use super::{probe_version, Worker};
+use crate::adaptor;
+use crate::config::WorkerConfig;
+use crate::endpoint::Endpoint;
+use crate::error::WorkerError;
+use crate::protocol::{Compression, PayloadFormat};
+use crate::tuning::FrameSizeBounds;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A job specification for a `CommandWorker`, usually read from a TOML file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandSpec {
+  /// Name of the service, as registered in CorTeX
+  pub service: String,
+  /// The executable to run for every task
+  pub program: String,
+  /// Arguments to the executable, where `{input}` is replaced by the path to the task's ZIP
+  /// and `{output}` by the path at which the result ZIP is expected
+  pub args: Vec<String>,
+  /// A command printing the converter's version, e.g. `["my-converter", "--version"]`
+  #[serde(default)]
+  pub version_probe: Vec<String>,
+  /// Format of the task inputs, announced when registering with CorTeX
+  pub input_format: Option<String>,
+  /// Format of the results, announced when registering with CorTeX
+  pub output_format: Option<String>,
+  /// Extension of single-file task inputs, e.g. `pdf`, which are then not wrapped in a ZIP
+  pub input_extension: Option<String>,
+}
+
+impl CommandSpec {
+  /// Read a job specification from a TOML file
+  pub fn load(path: &Path) -> Result<CommandSpec, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    toml::from_str(&contents).map_err(Into::into)
+  }
+}
+
+/// A worker running an arbitrary executable on every task, for tools which already follow
+/// the CorTeX conventions of a ZIP input and a ZIP output with a `cortex.log` at its root.
+#[derive(Clone, Debug)]
+pub struct CommandWorker {
+  /// the usual
+  pub service: String,
+  /// the usual
+  pub version: f32,
+  /// the usual
+  pub message_size: usize,
+  /// Bounds of a frame size picked per result from its size, replacing `message_size`
+  pub message_size_bounds: Option<FrameSizeBounds>,
+  /// the usual
+  pub source: Endpoint,
+  /// the usual
+  pub sink: Endpoint,
+  /// Allow for multiple parallel workers
+  pub pool_size: usize,
+  /// Threads receiving tasks and sending results, by default one per worker thread
+  pub io_threads: Option<usize>,
+  /// the usual
+  pub identity: String,
+  /// The executable to run for every task
+  pub program: String,
+  /// Arguments to the executable, with `{input}` and `{output}` placeholders
+  pub args: Vec<String>,
+  /// Command printing the converter's version, the program followed by its arguments
+  pub version_probe: Vec<String>,
+  /// Format of the task inputs
+  pub input_format: Option<String>,
+  /// Format of the results
+  pub output_format: Option<String>,
+  /// Compression of payloads on the wire
+  pub compression: Compression,
+  /// How the task inputs are packaged
+  pub payload_format: PayloadFormat,
+}
+impl Default for CommandWorker {
+  fn default() -> CommandWorker {
+    CommandWorker {
+      service: "command".to_string(),
+      version: 0.1,
+      message_size: 100_000,
+      message_size_bounds: None,
+      source: Endpoint::tcp("127.0.0.1", 51695),
+      sink: Endpoint::tcp("127.0.0.1", 51696),
+      pool_size: 1,
+      io_threads: None,
+      identity: String::new(),
+      program: "true".to_string(),
+      args: Vec::new(),
+      version_probe: Vec::new(),
+      input_format: None,
+      output_format: None,
+      compression: Compression::None,
+      payload_format: PayloadFormat::Zip,
+    }
+  }
+}
+impl From<CommandSpec> for CommandWorker {
+  fn from(spec: CommandSpec) -> CommandWorker {
+    CommandWorker {
+      service: spec.service,
+      program: spec.program,
+      args: spec.args,
+      version_probe: spec.version_probe,
+      input_format: spec.input_format,
+      output_format: spec.output_format,
+      payload_format: match spec.input_extension {
+        Some(extension) => PayloadFormat::SingleFile { extension },
+        None => PayloadFormat::Zip,
+      },
+      ..CommandWorker::default()
+    }
+  }
+}
+
+impl Worker for CommandWorker {
+  fn get_service(&self) -> &str {
+    &self.service
+  }
+  fn get_version(&self) -> String {
+    format!("{:?}", self.version)
+  }
+  fn input_format(&self) -> Option<String> {
+    self.input_format.clone()
+  }
+  fn output_format(&self) -> Option<String> {
+    self.output_format.clone()
+  }
+  fn payload_format(&self) -> PayloadFormat {
+    self.payload_format.clone()
+  }
+  fn tool_version(&self) -> Result<String, WorkerError> {
+    match self.version_probe.split_first() {
+      Some((program, args)) => probe_version(Command::new(program).args(args)),
+      None => Err(WorkerError::Deterministic(String::from(
+        "the job specification has no version_probe",
+      ))),
+    }
+  }
+  fn get_source_address(&self) -> &Endpoint {
+    &self.source
+  }
+  fn get_sink_address(&self) -> &Endpoint {
+    &self.sink
+  }
+  fn message_size(&self) -> usize {
+    self.message_size
+  }
+  fn message_size_bounds(&self) -> Option<FrameSizeBounds> {
+    self.message_size_bounds
+  }
+  fn pool_size(&self) -> usize {
+    self.pool_size
+  }
+  fn io_threads(&self) -> usize {
+    self.io_threads.unwrap_or(self.pool_size)
+  }
+  fn get_identity(&self) -> &str {
+    &self.identity
+  }
+  fn set_identity(&mut self, identity: String) {
+    self.identity = identity;
+  }
+  fn configure(&mut self, config: &WorkerConfig) {
+    if let Some(ref source) = config.source {
+      self.source = source.clone();
+    }
+    if let Some(ref sink) = config.sink {
+      self.sink = sink.clone();
+    }
+    if let Some(pool_size) = config.pool_size {
+      self.pool_size = pool_size;
+    }
+    if let Some(io_threads) = config.io_threads {
+      self.io_threads = Some(io_threads);
+    }
+    if let Some(message_size) = config.message_size {
+      self.message_size = message_size;
+    }
+    if let Some(bounds) = config.message_size_bounds {
+      self.message_size_bounds = Some(bounds);
+    }
+    if let Some(compression) = config.compression {
+      self.compression = compression;
+    }
+  }
+  fn compression(&self) -> Compression {
+    self.compression
+  }
+
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
+    // the result sits next to the input, and is cleaned up together with the task's directory
+    let destination_path: PathBuf = path.with_extension("result.zip");
+    let input = path.to_string_lossy();
+    let output = destination_path.to_string_lossy();
+    let args: Vec<String> = self
+      .args
+      .iter()
+      .map(|arg| arg.replace("{input}", &input).replace("{output}", &output))
+      .collect();
+    let cmd_result = Command::new(&self.program).args(&args).output()?;
+
+    if destination_path.exists() {
+      let result = File::open(destination_path)?;
+      adaptor::ensure_cortex_log(result, &adaptor::synthesize_log(&self.program, &cmd_result))
+    } else {
+      Err(From::from(format!(
+        "{} produced no output ({}): {}",
+        self.program,
+        cmd_result.status,
+        String::from_utf8_lossy(&cmd_result.stderr)
+      )))
+    }
+  }
+}
@@ -1,10 +1,19 @@
-use std::borrow::Cow;
 use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use super::Worker;
+use crate::clock;
+use crate::config::WorkerConfig;
+use crate::endpoint::Endpoint;
+use crate::error::WorkerError;
+use crate::tuning::FrameSizeBounds;
 
-/// An echo worker for testing
+/// An echo worker for testing, which can also misbehave on purpose to check how
+/// dispatchers cope with slow, failing and corrupted results
 #[derive(Clone, Debug)]
 pub struct EchoWorker {
   /// the usual
@@ -13,12 +22,26 @@ pub struct EchoWorker {
   pub version: f32,
   /// the usual
   pub message_size: usize,
+  /// Bounds of a frame size picked per result from its size, replacing `message_size`
+  pub message_size_bounds: Option<FrameSizeBounds>,
   /// the usual
-  pub source: String,
+  pub source: Endpoint,
   /// the usual
-  pub sink: String,
+  pub sink: Endpoint,
   /// the usual
   pub identity: String,
+  /// Pause before answering every task
+  pub delay: Duration,
+  /// Fail every Nth task, answering it with an empty result
+  pub fail_every: Option<usize>,
+  /// Answer with a generated result of this many bytes instead of the payload
+  pub payload_size: Option<usize>,
+  /// Flip the bits of the first frame of every Nth result
+  pub corrupt_every: Option<usize>,
+  /// Pause after an empty input or a failed task
+  pub throttle: Duration,
+  /// Tasks answered so far, by all threads
+  pub answered: Arc<AtomicUsize>,
 }
 impl Default for EchoWorker {
   fn default() -> EchoWorker {
@@ -26,9 +49,16 @@ impl Default for EchoWorker {
       service: "echo_service".to_string(),
       version: 0.1,
       message_size: 100_000,
-      source: "tcp://127.0.0.1:51695".to_string(),
-      sink: "tcp://127.0.0.1:51696".to_string(),
+      message_size_bounds: None,
+      source: Endpoint::tcp("127.0.0.1", 51695),
+      sink: Endpoint::tcp("127.0.0.1", 51696),
       identity: "echo worker".to_string(),
+      delay: Duration::ZERO,
+      fail_every: None,
+      payload_size: None,
+      corrupt_every: None,
+      throttle: Duration::new(60, 0),
+      answered: Arc::new(AtomicUsize::new(0)),
     }
   }
 }
@@ -36,18 +66,80 @@ impl Worker for EchoWorker {
   fn get_service(&self) -> &str {
     &self.service
   }
-  fn get_source_address(&self) -> Cow<str> {
-    Cow::Borrowed(&self.source)
+  fn get_version(&self) -> String {
+    format!("{:?}", self.version)
   }
-  fn get_sink_address(&self) -> Cow<str> {
-    Cow::Borrowed(&self.sink)
+  fn tool_version(&self) -> Result<String, WorkerError> {
+    Ok(format!("pericortex {}", env!("CARGO_PKG_VERSION")))
+  }
+  fn get_source_address(&self) -> &Endpoint {
+    &self.source
+  }
+  fn get_sink_address(&self) -> &Endpoint {
+    &self.sink
   }
   fn message_size(&self) -> usize {
     self.message_size
   }
+  fn message_size_bounds(&self) -> Option<FrameSizeBounds> {
+    self.message_size_bounds
+  }
+  fn configure(&mut self, config: &WorkerConfig) {
+    if let Some(ref source) = config.source {
+      self.source = source.clone();
+    }
+    if let Some(ref sink) = config.sink {
+      self.sink = sink.clone();
+    }
+    if let Some(message_size) = config.message_size {
+      self.message_size = message_size;
+    }
+    if let Some(bounds) = config.message_size_bounds {
+      self.message_size_bounds = Some(bounds);
+    }
+    if let Some(throttle) = config.throttle_duration() {
+      self.throttle = throttle;
+    }
+  }
+  fn throttle(&self) -> Duration {
+    self.throttle
+  }
+
+  fn verify_results(&self) -> bool {
+    // echoes any payload, ZIP or not
+    false
+  }
 
   fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
-    File::open(path).map_err(Into::into)
+    let task = self.answered.fetch_add(1, Ordering::SeqCst) + 1;
+    let every = |n: Option<usize>| n.map(|n| task.is_multiple_of(n.max(1))).unwrap_or(false);
+    if !self.delay.is_zero() {
+      clock::sleep(self.delay);
+    }
+    if every(self.fail_every) {
+      return Err(Box::new(WorkerError::Deterministic(format!("failing task {} on purpose", task))));
+    }
+    let mut result = match self.payload_size {
+      Some(size) => {
+        let mut generated = tempfile::tempfile()?;
+        io::copy(&mut io::repeat(b'e').take(size as u64), &mut generated)?;
+        generated.seek(SeekFrom::Start(0))?;
+        generated
+      }
+      None => File::open(path)?,
+    };
+    if every(self.corrupt_every) {
+      let mut frame = Vec::new();
+      (&mut result).take(self.message_size as u64).read_to_end(&mut frame)?;
+      let corrupted: Vec<u8> = frame.iter().map(|byte| !byte).collect();
+      // the payload was opened read-only, corrupt a copy
+      let mut copy = tempfile::tempfile()?;
+      copy.write_all(&corrupted)?;
+      io::copy(&mut result, &mut copy)?;
+      result = copy;
+    }
+    result.seek(SeekFrom::Start(0))?;
+    Ok(result)
   }
   fn set_identity(&mut self, identity: String) {
     self.identity = identity;
@@ -8,17 +8,31 @@
 
 //! a CorTeX worker for Engrafo, via a docker image
 
-use std::borrow::Cow;
+use std::cell::{OnceCell, RefCell};
 use std::env;
 use std::fs::File;
 use std::io::{Write};
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
 use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use tempdir::TempDir;
+use tempfile::SpooledTempFile;
 
-use super::Worker;
-use crate::adaptor;
+use super::{probe_version, Worker};
+use crate::adaptor::{self, RejectReason};
+use crate::analysis;
+use crate::config::WorkerConfig;
+use crate::endpoint::Endpoint;
+use crate::error::WorkerError;
+use crate::pressure::LoadLimits;
+use crate::retry::RetryPolicy;
+use crate::sanity::{self, SanityThresholds};
+use crate::tuning::FrameSizeBounds;
+use crate::scratch;
 
 /// An echo worker for testing
 #[derive(Clone, Debug)]
@@ -29,31 +43,109 @@ pub struct EngrafoWorker {
   pub version: f32,
   /// the usual
   pub message_size: usize,
+  /// Bounds of a frame size picked per result from its size, replacing `message_size`
+  pub message_size_bounds: Option<FrameSizeBounds>,
   /// the usual
-  pub source: String,
+  pub source: Endpoint,
   /// the usual
-  pub sink: String,
-  /// port to the source address
-  pub source_port: usize,
-  /// port to the sink address
-  pub sink_port: usize,
+  pub sink: Endpoint,
   /// Allow for multiple parallel workers
   pub pool_size: usize,
+  /// Threads receiving tasks and sending results, by default one per worker thread
+  pub io_threads: Option<usize>,
   /// A uniquely identifying string, usually `hostname:engrafo:threadid`
   pub identity: String,
+  /// Address for serving the `/healthz` and `/readyz` probe endpoints, if any
+  pub health_address: Option<String>,
+  /// Host saturation limits for adaptively pausing threads, if any
+  pub load_limits: Option<LoadLimits>,
+  /// TOML configuration file, reloaded on SIGHUP
+  pub config_path: Option<PathBuf>,
+  /// Pause after an empty input or a failed conversion
+  pub throttle: Duration,
+  /// Threads used to compress a result archive, Engrafo outputs carry many assets
+  pub compression_threads: usize,
+  /// Docker network of the container, e.g. `host` to reach a proxy on the host, or a user network
+  pub network_mode: Option<String>,
+  /// DNS servers of the container, e.g. an institutional resolver
+  pub dns: Vec<String>,
+  /// Environment of the container: `NAME=value`, or `NAME` to pass on the worker's own value,
+  /// e.g. `HTTPS_PROXY`
+  pub env: Vec<String>,
+  /// The docker image providing the Engrafo converter
+  pub image: String,
+  /// Digest (`sha256:...`) the Engrafo image is pinned to, pulled and verified at warmup
+  pub image_digest: Option<String>,
+  /// Keep a container running on every pool thread and `docker exec` Engrafo in it, recycling it
+  /// after this many tasks or a failed one, instead of starting a container per task
+  pub reuse_containers: Option<usize>,
+  /// GPUs made available to the containers, if any
+  pub gpus: Option<GpuAssignment>,
+  /// When an apparently successful conversion is reported as suspicious in `cortex.log`
+  pub sanity: SanityThresholds,
 }
+
+/// Which GPUs the containers of a worker may use
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuAssignment {
+  /// Every container sees all GPUs of the host, `all`
+  All,
+  /// Every pool thread is assigned one of these devices (ids or UUIDs) in turn, `0,1,2,3`
+  PerThread(Vec<String>),
+}
+
+impl FromStr for GpuAssignment {
+  type Err = String;
+  fn from_str(spec: &str) -> Result<GpuAssignment, String> {
+    if spec == "all" {
+      return Ok(GpuAssignment::All);
+    }
+    let devices: Vec<String> = spec.split(',').map(|device| device.trim().to_string()).collect();
+    if devices.iter().any(|device| device.is_empty()) {
+      Err(format!("invalid GPUs {:?}, expected all or a comma-separated list of device ids", spec))
+    } else {
+      Ok(GpuAssignment::PerThread(devices))
+    }
+  }
+}
+
+impl fmt::Display for GpuAssignment {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      GpuAssignment::All => f.write_str("all"),
+      GpuAssignment::PerThread(devices) => f.write_str(&devices.join(",")),
+    }
+  }
+}
+
+/// The docker image of the Engrafo release
+const ENGRAFO_IMAGE: &str = "arxivvanity/engrafo:2.0.0";
+
 impl Default for EngrafoWorker {
   fn default() -> EngrafoWorker {
     EngrafoWorker {
       service: "engrafo".to_string(),
       version: 2.0,
       message_size: 100_000,
-      source: "127.0.0.1".to_string(),
-      source_port: 51695,
-      sink: "127.0.0.1".to_string(),
-      sink_port: 51696,
+      message_size_bounds: None,
+      source: Endpoint::tcp("127.0.0.1", 51695),
+      sink: Endpoint::tcp("127.0.0.1", 51696),
       pool_size: 1,
+      io_threads: None,
       identity: "unknown:engrafo:1".to_string(),
+      health_address: None,
+      load_limits: None,
+      config_path: None,
+      throttle: Duration::new(60, 0),
+      compression_threads: 4,
+      network_mode: None,
+      dns: Vec::new(),
+      env: Vec::new(),
+      image: ENGRAFO_IMAGE.to_string(),
+      image_digest: None,
+      reuse_containers: None,
+      gpus: None,
+      sanity: SanityThresholds::default(),
     }
   }
 }
@@ -62,59 +154,329 @@ impl Worker for EngrafoWorker {
   fn get_service(&self) -> &str {
     &self.service
   }
-  fn get_source_address(&self) -> Cow<str> {
-    Cow::Owned(format!("tcp://{}:{}", self.source, self.source_port))
+  fn get_version(&self) -> String {
+    format!("{:?}", self.version)
   }
-  fn get_sink_address(&self) -> Cow<str> {
-    Cow::Owned(format!("tcp://{}:{}", self.sink, self.sink_port))
+  fn input_format(&self) -> Option<String> {
+    Some(String::from("tex"))
+  }
+  fn output_format(&self) -> Option<String> {
+    Some(String::from("html"))
+  }
+  fn tool_version(&self) -> Result<String, WorkerError> {
+    let digest = probe_version(
+      Command::new("docker")
+        .arg("image")
+        .arg("inspect")
+        .arg("--format")
+        .arg("{{if .RepoDigests}}{{index .RepoDigests 0}}{{else}}{{.Id}}{{end}}")
+        .arg(self.image()),
+    )?;
+    Ok(format!("{} ({})", self.image(), digest))
+  }
+  fn get_source_address(&self) -> &Endpoint {
+    &self.source
+  }
+  fn get_sink_address(&self) -> &Endpoint {
+    &self.sink
   }
   fn message_size(&self) -> usize {
     self.message_size
   }
+  fn message_size_bounds(&self) -> Option<FrameSizeBounds> {
+    self.message_size_bounds
+  }
   fn pool_size(&self) -> usize {
     self.pool_size
   }
+  fn io_threads(&self) -> usize {
+    self.io_threads.unwrap_or(self.pool_size)
+  }
   fn set_identity(&mut self, identity: String) {
     self.identity = identity;
   }
   fn get_identity(&self) -> &str {
     &self.identity
   }
+  fn health_address(&self) -> Option<String> {
+    self.health_address.clone()
+  }
+  fn load_limits(&self) -> Option<LoadLimits> {
+    self.load_limits.clone()
+  }
+  fn config_path(&self) -> Option<PathBuf> {
+    self.config_path.clone()
+  }
+  fn configure(&mut self, config: &WorkerConfig) {
+    if let Some(ref source) = config.source {
+      self.source = source.clone();
+    }
+    if let Some(ref sink) = config.sink {
+      self.sink = sink.clone();
+    }
+    if let Some(pool_size) = config.pool_size {
+      self.pool_size = pool_size;
+    }
+    if let Some(io_threads) = config.io_threads {
+      self.io_threads = Some(io_threads);
+    }
+    if let Some(message_size) = config.message_size {
+      self.message_size = message_size;
+    }
+    if let Some(bounds) = config.message_size_bounds {
+      self.message_size_bounds = Some(bounds);
+    }
+    if let Some(throttle) = config.throttle_duration() {
+      self.throttle = throttle;
+    }
+    if let Some(compression_threads) = config.compression_threads {
+      self.compression_threads = compression_threads;
+    }
+    if let Some(ref image) = config.image {
+      // the digest pinned the previous image, another one is pinned as `name@sha256:...`
+      if *image != self.image {
+        self.image = image.clone();
+        self.image_digest = None;
+      }
+    }
+  }
+  fn throttle(&self) -> Duration {
+    self.throttle
+  }
+  fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy {
+      attempts: 3,
+      backoff: Duration::new(10, 0),
+    }
+  }
+  fn validate_input(&self, dir: &Path) -> Result<(), RejectReason> {
+    adaptor::validate_tex_sources(dir)
+  }
+  fn warmup(&self) -> Result<(), Box<dyn Error>> {
+    if self.reuse_containers.is_some() {
+      remove_stale_containers();
+    }
+    let Some(ref digest) = self.image_digest else {
+      return Ok(());
+    };
+    if !is_digest(digest) {
+      return Err(From::from(format!("{:?} is not an image digest, expected sha256:<64 hex digits>", digest)));
+    }
+    let image = self.image();
+    if self.readiness_probe().is_err() {
+      info!(target: "engrafo", "pulling {}", image);
+      let pulled = Command::new("docker").arg("pull").arg(&image).output()?;
+      if !pulled.status.success() {
+        return Err(From::from(format!(
+          "failed to pull {}: {}",
+          image,
+          String::from_utf8_lossy(&pulled.stderr).trim()
+        )));
+      }
+    }
+    let inspected = Command::new("docker")
+      .arg("image")
+      .arg("inspect")
+      .arg("--format")
+      .arg("{{range .RepoDigests}}{{println .}}{{end}}")
+      .arg(&image)
+      .output()?;
+    let repo_digests = String::from_utf8_lossy(&inspected.stdout);
+    if inspected.status.success() && has_digest(&repo_digests, digest) {
+      Ok(())
+    } else {
+      Err(From::from(format!(
+        "refusing to run: the local {} doesn't carry the pinned digest {} (found {:?})",
+        image,
+        digest,
+        repo_digests.trim()
+      )))
+    }
+  }
+  fn readiness_probe(&self) -> Result<(), Box<dyn Error>> {
+    let inspected = Command::new("docker")
+      .arg("image")
+      .arg("inspect")
+      .arg(self.image())
+      .output()?;
+    if inspected.status.success() {
+      Ok(())
+    } else {
+      Err(From::from(format!("docker image {} is not available", self.image())))
+    }
+  }
 
-  fn convert(&self, path: &Path) -> Result<File, Box<Error>> {
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
     let input_tmpdir = adaptor::extract_zip_to_tmpdir(path, "engrafo_input")?;
-    let unpacked_dir_path = input_tmpdir.path().to_str().unwrap().to_string() + "/";
-    let destination_tmpdir = TempDir::new("engrafo_output").unwrap();
-    let destination_dir_path = destination_tmpdir.path().to_str().unwrap();
-    let tmp_dir_str = env::temp_dir().as_path().display().to_string();
-    let docker_input_path = unpacked_dir_path.replace(&tmp_dir_str, "/workdir");
-    let docker_output_path = destination_dir_path.replace(&tmp_dir_str, "/workdir");
-
-    let cmd_result = Command::new("docker")
-      .arg("run")
-      .arg("-m")
-      .arg("4g") // can be made customizeable based on architecture
-      .arg("-v")
-      .arg(format!("{}:/workdir", tmp_dir_str))
-      .arg("-w")
-      .arg("/workdir")
-      .arg("arxivvanity/engrafo:2.0.0")
-      .arg("engrafo")
-      .arg(docker_input_path)
-      .arg(docker_output_path)
-      .output()
-      .expect("failed to execute process engrafo docker process.");
+    self.convert_extracted(input_tmpdir)
+  }
+  fn convert_payload(
+    &self,
+    payload: &mut SpooledTempFile,
+    _taskid: &str,
+    _scratch: &TempDir,
+  ) -> Result<File, Box<dyn Error>> {
+    // Engrafo needs a directory, so skip the intermediate ZIP on disk
+    let input_tmpdir = adaptor::extract_zip_reader_to_tmpdir(payload, "engrafo_input")?;
+    self.convert_extracted(input_tmpdir)
+  }
+}
+
+impl EngrafoWorker {
+  /// The image the containers run: the configured image, or its pinned digest
+  pub fn image(&self) -> String {
+    match self.image_digest {
+      Some(ref digest) => pinned_image(&self.image, digest),
+      None => self.image.clone(),
+    }
+  }
+
+  /// The `docker run` options for the network, DNS and environment of the container
+  pub fn container_options(&self) -> Vec<String> {
+    let mut options = Vec::new();
+    if let Some(ref network_mode) = self.network_mode {
+      options.push(String::from("--network"));
+      options.push(network_mode.clone());
+    }
+    for server in &self.dns {
+      options.push(String::from("--dns"));
+      options.push(server.clone());
+    }
+    for variable in &self.env {
+      options.push(String::from("-e"));
+      options.push(variable.clone());
+    }
+    options
+  }
+
+  /// The `docker run` options giving the containers of the calling thread their GPUs
+  pub fn gpu_options(&self) -> Vec<String> {
+    let device = match self.gpus {
+      None => return Vec::new(),
+      Some(GpuAssignment::All) => String::from("all"),
+      Some(GpuAssignment::PerThread(ref devices)) if devices.is_empty() => return Vec::new(),
+      Some(GpuAssignment::PerThread(ref devices)) => {
+        let slot = GPU_SLOT.with(|slot| *slot.get_or_init(|| GPU_SLOTS_TAKEN.fetch_add(1, Ordering::SeqCst)));
+        format!("device={}", devices[slot % devices.len()])
+      }
+    };
+    vec![String::from("--gpus"), device]
+  }
+
+  /// The `docker run` options shared by per-task and long-lived containers
+  fn run_options(&self, tmp_dir: &Path) -> Vec<String> {
+    let mut options = vec![
+      String::from("-m"),
+      String::from("4g"), // can be made customizeable based on architecture
+      String::from("-v"),
+      format!("{}:/workdir", tmp_dir.display()),
+      String::from("-w"),
+      String::from("/workdir"),
+    ];
+    options.extend(self.container_options());
+    options.extend(self.gpu_options());
+    options
+  }
+
+  /// Run Engrafo in the long-lived container of this thread, starting one if needed
+  fn exec_in_container(
+    &self,
+    max_tasks: usize,
+    options: Vec<String>,
+    input: &str,
+    output: &str,
+  ) -> Result<Output, Box<dyn Error>> {
+    CONTAINER.with(|slot| -> Result<Output, Box<dyn Error>> {
+      let mut slot = slot.borrow_mut();
+      let image = self.image();
+      // e.g. a reloaded configuration changed the container's settings
+      let stale = |container: &ReusedContainer| container.image != image || container.options != options;
+      if slot.as_ref().map(stale).unwrap_or(false) {
+        *slot = None;
+      }
+      if slot.is_none() {
+        *slot = Some(ReusedContainer::start(image, options)?);
+      }
+      let container = slot.as_mut().unwrap();
+      let cmd_result = Command::new("docker")
+        .arg("exec")
+        .arg("-w")
+        .arg("/workdir")
+        .arg(&container.name)
+        .arg("engrafo")
+        .arg(input)
+        .arg(output)
+        .output()
+        .map_err(|e| WorkerError::Transient(format!("failed to run docker: {}", e)))?;
+      container.tasks += 1;
+      if !cmd_result.status.success() || container.tasks >= max_tasks {
+        // removed on drop
+        *slot = None;
+      }
+      // 126 and 127: the container couldn't run Engrafo, e.g. it died in between tasks
+      if let Some(code @ (126 | 127)) = cmd_result.status.code() {
+        return Err(Box::new(WorkerError::Transient(format!(
+          "docker exec exited with {}: {}",
+          code,
+          String::from_utf8_lossy(&cmd_result.stderr).trim()
+        ))));
+      }
+      Ok(cmd_result)
+    })
+  }
+
+  /// Run Engrafo on an extracted corpus entry, archiving its output with a `cortex.log`
+  fn convert_extracted(&self, input_tmpdir: TempDir) -> Result<File, Box<dyn Error>> {
+    self.validate_input(input_tmpdir.path())?;
+    let destination_tmpdir = scratch::tempdir("engrafo_output").unwrap();
+    let tmp_dir = env::temp_dir();
+    // bundles with several .tex files get their main file picked here, ties are left to Engrafo
+    let docker_input_path = match analysis::find_main_tex_file(input_tmpdir.path()) {
+      Ok(main) => container_path(&tmp_dir, &main)?.trim_end_matches('/').to_string(),
+      Err(ambiguity) => {
+        debug!(target: "engrafo", "{}", ambiguity);
+        container_path(&tmp_dir, input_tmpdir.path())?
+      }
+    };
+    let docker_output_path = container_path(&tmp_dir, destination_tmpdir.path())?;
+
+    let options = self.run_options(&tmp_dir);
+    let cmd_result = match self.reuse_containers {
+      Some(max_tasks) => {
+        self.exec_in_container(max_tasks, options, &docker_input_path, &docker_output_path)?
+      }
+      None => Command::new("docker")
+        .arg("run")
+        .args(options)
+        .arg(self.image())
+        .arg("engrafo")
+        .arg(docker_input_path)
+        .arg(docker_output_path)
+        .output()
+        .map_err(|e| WorkerError::Transient(format!("failed to run docker: {}", e)))?,
+    };
+    // 125: the docker daemon failed to start the container, 137: the container was OOM-killed
+    if let Some(code @ (125 | 137)) = cmd_result.status.code() {
+      return Err(Box::new(WorkerError::Transient(format!(
+        "docker exited with {}: {}",
+        code,
+        String::from_utf8_lossy(&cmd_result.stderr).trim()
+      ))));
+    }
 
     // Package the output -- cortex requires a single ZIP return,
     // with all logging information stored in a "cortex.log" file at the ZIP's root.
 
-    let log_name = format!("{}/cortex.log", destination_dir_path);
-    let cortex_log_path = Path::new(&log_name);
+    let cortex_log_path = destination_tmpdir.path().join("cortex.log");
     {
       // write log file and close it before archiving.
       let mut log_file = File::create(&cortex_log_path)?;
       log_file.write_all(&cmd_result.stderr)?;
       log_file.write_all(&cmd_result.stdout)?;
+      // Engrafo may exit cleanly with an empty or skeletal page
+      for message in sanity::check_conversion(input_tmpdir.path(), destination_tmpdir.path(), &self.sanity) {
+        writeln!(log_file, "{}", message)?;
+      }
     }
 
     // cleanup
@@ -125,6 +487,176 @@ impl Worker for EngrafoWorker {
     // succeeded.
     input_tmpdir.close().unwrap();
 
-    adaptor::archive_tmpdir_to_zip(destination_tmpdir).map_err(Into::into)
+    adaptor::archive_tmpdir_to_zip_parallel(destination_tmpdir, self.compression_threads)
+  }
+}
+
+/// GPU slots handed to pool threads so far
+static GPU_SLOTS_TAKEN: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+  /// The GPU slot of a pool thread, taken on its first conversion
+  static GPU_SLOT: OnceCell<usize> = const { OnceCell::new() };
+}
+
+/// Containers started by this process, for unique names
+static CONTAINERS_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Label of the long-lived containers, with the host of the process which started them
+const HOST_LABEL: &str = "pericortex.host";
+/// Label of the long-lived containers, with the pid of the process which started them
+const PID_LABEL: &str = "pericortex.pid";
+
+thread_local! {
+  /// The long-lived container of a pool thread, removed when the thread exits, or else by the
+  /// next process on the host, see [`remove_stale_containers`]
+  static CONTAINER: RefCell<Option<ReusedContainer>> = const { RefCell::new(None) };
+}
+
+/// A container idling between tasks, which are run in it with `docker exec`
+struct ReusedContainer {
+  name: String,
+  image: String,
+  options: Vec<String>,
+  tasks: usize,
+}
+
+impl ReusedContainer {
+  fn start(image: String, options: Vec<String>) -> Result<ReusedContainer, Box<dyn Error>> {
+    let name = format!(
+      "pericortex-engrafo-{}-{}",
+      process::id(),
+      CONTAINERS_STARTED.fetch_add(1, Ordering::SeqCst)
+    );
+    let started = Command::new("docker")
+      .arg("run")
+      .arg("-d")
+      .arg("--rm")
+      .arg("--name")
+      .arg(&name)
+      .arg("--label")
+      .arg(format!("{}={}", HOST_LABEL, host()))
+      .arg("--label")
+      .arg(format!("{}={}", PID_LABEL, process::id()))
+      .args(&options)
+      .arg("--entrypoint")
+      .arg("sleep")
+      .arg(&image)
+      .arg("infinity")
+      .output()
+      .map_err(|e| WorkerError::Transient(format!("failed to run docker: {}", e)))?;
+    if !started.status.success() {
+      return Err(Box::new(WorkerError::Transient(format!(
+        "failed to start container {}: {}",
+        name,
+        String::from_utf8_lossy(&started.stderr).trim()
+      ))));
+    }
+    Ok(ReusedContainer {
+      name,
+      image,
+      options,
+      tasks: 0,
+    })
+  }
+}
+
+impl Drop for ReusedContainer {
+  fn drop(&mut self) {
+    if let Err(e) = Command::new("docker").arg("rm").arg("-f").arg(&self.name).output() {
+      warn!(target: "engrafo", "failed to remove container {}: {}", self.name, e);
+    }
+  }
+}
+
+/// The host name the long-lived containers are labelled with
+fn host() -> String { hostname::get().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default() }
+
+/// Remove the long-lived containers this host's earlier processes left behind, as they are only
+/// removed by the threads which started them: those of processes which are gone, e.g. killed, and
+/// those of the process this one replaced by an upgrade, which keeps its pid
+fn remove_stale_containers() {
+  let listed = Command::new("docker")
+    .arg("ps")
+    .arg("--all")
+    .arg("--filter")
+    .arg(format!("label={}={}", HOST_LABEL, host()))
+    .arg("--format")
+    .arg(format!("{{{{.ID}}}} {{{{.Label \"{}\"}}}}", PID_LABEL))
+    .output();
+  let listed = match listed {
+    Ok(listed) if listed.status.success() => String::from_utf8_lossy(&listed.stdout).into_owned(),
+    Ok(listed) => {
+      let error = String::from_utf8_lossy(&listed.stderr).trim().to_string();
+      warn!(target: "engrafo", "failed to list stale containers: {}", error);
+      return;
+    }
+    Err(e) => {
+      warn!(target: "engrafo", "failed to list stale containers: {}", e);
+      return;
+    }
+  };
+  let stale: Vec<&str> = listed
+    .lines()
+    .filter_map(|line| line.split_once(' '))
+    .filter(|(_, pid)| match pid.trim().parse::<u32>() {
+      Ok(pid) => pid == process::id() || !running(pid),
+      Err(_) => false,
+    })
+    .map(|(id, _)| id)
+    .collect();
+  if stale.is_empty() {
+    return;
+  }
+  info!(target: "engrafo", "removing {} stale containers", stale.len());
+  if let Err(e) = Command::new("docker").arg("rm").arg("-f").args(&stale).output() {
+    warn!(target: "engrafo", "failed to remove stale containers: {}", e);
+  }
+}
+
+/// Is the process `pid` still running?
+fn running(pid: u32) -> bool {
+  // signal 0 only checks whether the process exists, EPERM means it does but isn't ours
+  let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+  result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The reference of `image` pinned to `digest`, e.g. `arxivvanity/engrafo@sha256:...`
+fn pinned_image(image: &str, digest: &str) -> String {
+  let name_start = image.rfind('/').map(|slash| slash + 1).unwrap_or(0);
+  let repository = match image[name_start..].find([':', '@']) {
+    Some(end) => &image[..name_start + end],
+    None => image,
+  };
+  format!("{}@{}", repository, digest)
+}
+
+/// Is `digest` among the `repository@digest` lines `docker image inspect` reported?
+fn has_digest(repo_digests: &str, digest: &str) -> bool {
+  repo_digests
+    .lines()
+    .any(|line| line.trim().rsplit_once('@').map(|(_, found)| found == digest).unwrap_or(false))
+}
+
+/// Is `digest` a well-formed image digest, `sha256:` followed by 64 hex digits?
+fn is_digest(digest: &str) -> bool {
+  digest
+    .strip_prefix("sha256:")
+    .map(|hex| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+    .unwrap_or(false)
+}
+
+/// The path of `host_path` inside the container, where the host's `mount` is mounted at `/workdir`.
+/// Container paths are always `/`-separated, also when the host runs Windows.
+fn container_path(mount: &Path, host_path: &Path) -> Result<String, Box<dyn Error>> {
+  let relative = host_path
+    .strip_prefix(mount)
+    .map_err(|_| format!("{} is not under {}", host_path.display(), mount.display()))?;
+  let mut path = String::from("/workdir");
+  for component in relative.components() {
+    path.push('/');
+    path.push_str(&component.as_os_str().to_string_lossy());
   }
+  path.push('/');
+  Ok(path)
 }
@@ -1,5 +1,11 @@
-use super::Worker;
-use std::borrow::Cow;
+use super::{probe_version, Worker};
+use crate::adaptor;
+use crate::analysis;
+use crate::config::WorkerConfig;
+use crate::encoding;
+use crate::endpoint::Endpoint;
+use crate::error::WorkerError;
+use crate::tuning::FrameSizeBounds;
 use std::env;
 use std::error::Error;
 use std::fs::File;
@@ -18,12 +24,16 @@ pub struct TexToHtmlWorker {
   pub version: f32,
   /// the usual
   pub message_size: usize,
+  /// Bounds of a frame size picked per result from its size, replacing `message_size`
+  pub message_size_bounds: Option<FrameSizeBounds>,
   /// the usual
-  pub source: String,
+  pub source: Endpoint,
   /// the usual
-  pub sink: String,
+  pub sink: Endpoint,
   ///  the usual
   pub identity: String,
+  /// Seconds latexmlc may spend on a document
+  pub timeout: u64,
 }
 impl Default for TexToHtmlWorker {
   fn default() -> TexToHtmlWorker {
@@ -31,9 +41,11 @@ impl Default for TexToHtmlWorker {
       service: "tex_to_html".to_string(),
       version: 0.1,
       message_size: 100_000,
-      source: "tcp://127.0.0.1:51695".to_string(),
-      sink: "tcp://127.0.0.1:51696".to_string(),
-      identity: String::new()
+      message_size_bounds: None,
+      source: Endpoint::tcp("127.0.0.1", 51695),
+      sink: Endpoint::tcp("127.0.0.1", 51696),
+      identity: String::new(),
+      timeout: 300,
     }
   }
 }
@@ -41,25 +53,76 @@ impl Worker for TexToHtmlWorker {
   fn get_service(&self) -> &str {
     &self.service
   }
-  fn get_source_address(&self) -> Cow<str> {
-    Cow::Borrowed(&self.source)
+  fn get_version(&self) -> String {
+    format!("{:?}", self.version)
   }
-  fn get_sink_address(&self) -> Cow<str> {
-    Cow::Borrowed(&self.sink)
+  fn input_format(&self) -> Option<String> {
+    Some(String::from("tex"))
+  }
+  fn output_format(&self) -> Option<String> {
+    Some(String::from("html"))
+  }
+  fn tool_version(&self) -> Result<String, WorkerError> {
+    probe_version(Command::new("latexmlc").arg("--VERSION"))
+  }
+  fn get_source_address(&self) -> &Endpoint {
+    &self.source
+  }
+  fn get_sink_address(&self) -> &Endpoint {
+    &self.sink
   }
   fn message_size(&self) -> usize {
     self.message_size
   }
+  fn message_size_bounds(&self) -> Option<FrameSizeBounds> {
+    self.message_size_bounds
+  }
+  fn configure(&mut self, config: &WorkerConfig) {
+    if let Some(ref source) = config.source {
+      self.source = source.clone();
+    }
+    if let Some(ref sink) = config.sink {
+      self.sink = sink.clone();
+    }
+    if let Some(message_size) = config.message_size {
+      self.message_size = message_size;
+    }
+    if let Some(bounds) = config.message_size_bounds {
+      self.message_size_bounds = Some(bounds);
+    }
+    if let Some(timeout) = config.timeout {
+      self.timeout = timeout;
+    }
+  }
   fn get_identity(&self) -> &str { &self.identity }
   fn set_identity(&mut self, identity: String) { self.identity = identity; }
+  fn readiness_probe(&self) -> Result<(), Box<dyn Error>> {
+    let probed = Command::new("latexmlc").arg("--VERSION").output()?;
+    if probed.status.success() {
+      Ok(())
+    } else {
+      Err(From::from("latexmlc --VERSION failed"))
+    }
+  }
 
   fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
-    let name = path.file_stem().unwrap().to_str().unwrap();
-    let destination_path = env::temp_dir().to_str().unwrap().to_string() + "/" + name + ".zip";
+    let name = path.file_stem().unwrap().to_string_lossy();
+    let destination_path = env::temp_dir().join(format!("{}.zip", name));
+    let encoding = encoding::detect_archive(path)?;
+    debug!(target: &format!("{}:encoding", self.identity), "sources of {} read as {}", name, encoding);
+    // point latexmlc at the main file ourselves, leaving only the ties to its own choice
+    let sources = adaptor::extract_zip_to_tmpdir(path, "tex_to_html_input")?;
+    let (whatsin, input) = match analysis::find_main_tex_file(sources.path()) {
+      Ok(main) => ("document", main),
+      Err(ambiguity) => {
+        debug!(target: &format!("{}:analysis", self.identity), "{}: {}", name, ambiguity);
+        ("archive", path.to_path_buf())
+      }
+    };
     // println!("Source {:?}", path);
-    Command::new("latexmlc")
+    let output = Command::new("latexmlc")
       .arg("--whatsin")
-      .arg("archive")
+      .arg(whatsin)
       .arg("--whatsout")
       .arg("archive")
       .arg("--format")
@@ -71,18 +134,19 @@ impl Worker for TexToHtmlWorker {
       .arg("[ids]latexml.sty")
       .arg("--nodefaultresources")
       .arg("--inputencoding")
-      .arg("iso-8859-1")
+      .arg(encoding.latexml_name())
       .arg("--timeout")
-      .arg("300")
+      .arg(self.timeout.to_string())
       .arg("--log")
       .arg("cortex.log")
       .arg("--destination")
       .arg(destination_path.clone())
-      .arg(path.to_string_lossy().to_string())
+      .arg(input.to_string_lossy().to_string())
       .output()
       .unwrap_or_else(|e| panic!("failed to execute process: {}", e));
 
     // println!("Dest: {:?}", destination_path);
-    File::open(destination_path).map_err(Into::into)
+    let result = File::open(destination_path)?;
+    adaptor::ensure_cortex_log(result, &adaptor::synthesize_log("latexmlc", &output))
   }
 }
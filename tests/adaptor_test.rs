@@ -0,0 +1,84 @@
+use pericortex::adaptor::{
+  archive_tmpdir_to_zip, archive_tmpdir_to_zip_parallel, ensure_cortex_log, extract_zip_reader_to_tmpdir,
+  synthesize_log, take_archiving,
+};
+use std::fs;
+use std::io::Read;
+use tempdir::TempDir;
+use zip::ZipArchive;
+
+fn assets() -> TempDir {
+  let dir = TempDir::new("adaptor_test").unwrap();
+  fs::create_dir_all(dir.path().join("images")).unwrap();
+  fs::write(dir.path().join("index.html"), "<p>paper</p>".repeat(1000)).unwrap();
+  fs::write(dir.path().join("cortex.log"), "Info:ok:done").unwrap();
+  for i in 0..25 {
+    fs::write(
+      dir.path().join("images").join(format!("figure{}.svg", i)),
+      format!("<svg id=\"{}\"/>", i).repeat(100),
+    )
+    .unwrap();
+  }
+  dir
+}
+
+fn contents(file: fs::File) -> Vec<(String, Vec<u8>)> {
+  let mut archive = ZipArchive::new(file).unwrap();
+  let mut entries = Vec::new();
+  for i in 0..archive.len() {
+    let mut entry = archive.by_index(i).unwrap();
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).unwrap();
+    entries.push((entry.name().to_string(), data));
+  }
+  entries.sort();
+  entries
+}
+
+#[test]
+fn parallel_archive_matches_serial() {
+  let serial = contents(archive_tmpdir_to_zip(assets()).unwrap());
+  let parallel = contents(archive_tmpdir_to_zip_parallel(assets(), 4).unwrap());
+  assert_eq!(serial.len(), 27);
+  assert!(serial.iter().any(|(name, _)| name == "images/figure7.svg"));
+  assert_eq!(serial, parallel);
+}
+
+#[test]
+#[cfg(unix)]
+fn missing_cortex_log_is_synthesized() {
+  let dir = TempDir::new("adaptor_test").unwrap();
+  fs::write(dir.path().join("index.html"), "<p>paper</p>").unwrap();
+  let result = archive_tmpdir_to_zip(dir).unwrap();
+  let output = std::process::Command::new("sh")
+    .arg("-c")
+    .arg("echo 'Warning:expected:foo missing' >&2; exit 3")
+    .output()
+    .unwrap();
+  let log = synthesize_log("sh", &output);
+  let entries = contents(ensure_cortex_log(result, &log).unwrap());
+
+  let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+  assert_eq!(names, vec!["cortex.log", "index.html"]);
+  let cortex_log = String::from_utf8(entries[0].1.clone()).unwrap();
+  assert!(cortex_log.starts_with("Warning:expected:foo missing\n"));
+  assert!(cortex_log.contains("Fatal:conversion:status sh failed"));
+}
+
+#[test]
+fn archiving_is_measured_per_thread() {
+  take_archiving();
+  let archive = archive_tmpdir_to_zip_parallel(assets(), 4).unwrap();
+  let size = archive.metadata().unwrap().len();
+  let extracted = extract_zip_reader_to_tmpdir(archive, "adaptor_test").unwrap();
+  assert!(extracted.path().join("images/figure7.svg").exists());
+
+  let totals = take_archiving();
+  assert_eq!(totals.archived.entries, 27);
+  assert_eq!(totals.archived.uncompressed_bytes, 12_000 + 12 + 10 * 1300 + 15 * 1400);
+  assert_eq!(totals.archived.compressed_bytes, size);
+  assert_eq!(totals.extracted.entries, 27);
+  assert_eq!(totals.extracted.uncompressed_bytes, totals.archived.uncompressed_bytes);
+  assert!(totals.extracted.compressed_bytes < totals.extracted.uncompressed_bytes);
+  assert_eq!(take_archiving().archived.entries, 0);
+}
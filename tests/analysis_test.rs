@@ -0,0 +1,53 @@
+use pericortex::analysis::{find_main_tex_file, Ambiguity};
+use std::fs;
+use tempdir::TempDir;
+
+fn bundle(files: &[(&str, &str)]) -> TempDir {
+  let dir = TempDir::new("analysis_test").unwrap();
+  for (name, contents) in files {
+    let path = dir.path().join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+  }
+  dir
+}
+
+#[test]
+fn main_file_has_the_documentclass() {
+  let dir = bundle(&[
+    ("paper.tex", "\\documentclass{article}\n\\begin{document}\\input{intro}\\end{document}"),
+    ("intro.tex", "\\section{Introduction} A much longer introduction. ".repeat(20).as_str()),
+    ("macros.tex", "% \\documentclass{article} is commented out\n\\newcommand{\\R}{\\mathbb{R}}"),
+  ]);
+  assert_eq!(find_main_tex_file(dir.path()).unwrap(), dir.path().join("paper.tex"));
+}
+
+#[test]
+fn included_files_are_not_main() {
+  // a standalone-compilable chapter is included by the book
+  let dir = bundle(&[
+    ("book.tex", "\\documentclass{book}\n\\include{chapters/one}\n\\input ./appendix.tex\n"),
+    (
+      "chapters/one.tex",
+      "\\documentclass{article}\n\\begin{document}A long chapter.\\end{document}\n".repeat(10).as_str(),
+    ),
+    ("appendix.tex", "\\documentclass{article}\nAppendix"),
+  ]);
+  assert_eq!(find_main_tex_file(dir.path()).unwrap(), dir.path().join("book.tex"));
+}
+
+#[test]
+fn size_decides_only_by_a_margin() {
+  let article = "\\documentclass{article}\n\\begin{document}Text\\end{document}\n";
+  let dir = bundle(&[("long.tex", article.repeat(3).as_str()), ("short.tex", article)]);
+  assert_eq!(find_main_tex_file(dir.path()).unwrap(), dir.path().join("long.tex"));
+
+  let dir = bundle(&[("a.tex", article), ("b.tex", article)]);
+  assert_eq!(
+    find_main_tex_file(dir.path()),
+    Err(Ambiguity::Tied(vec![dir.path().join("a.tex"), dir.path().join("b.tex")]))
+  );
+
+  let dir = bundle(&[("figure.png", "not tex")]);
+  assert_eq!(find_main_tex_file(dir.path()), Err(Ambiguity::NoTexFile));
+}
@@ -0,0 +1,18 @@
+use pericortex::announcements::{self, Announcement, Directive};
+
+#[test]
+fn announcements_steer_their_service_only() {
+  let pause: Announcement = "pause engrafo upgrading the image".parse().unwrap();
+  assert_eq!(pause.directive, Directive::Pause);
+  assert_eq!(pause.message, "upgrading the image");
+  assert_eq!(pause.to_string(), "pause engrafo upgrading the image");
+
+  announcements::apply(&pause, "tex_to_html");
+  assert!(!announcements::paused());
+  announcements::apply(&pause, "engrafo");
+  assert!(announcements::paused());
+  announcements::apply(&"resume *".parse().unwrap(), "engrafo");
+  assert!(!announcements::paused());
+
+  assert!("reboot engrafo".parse::<Announcement>().is_err());
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use pericortex::endpoint::Endpoint;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread;
+
+use pericortex::artifacts::{self, Artifact};
+use pericortex::dispatcher::Sink;
+use pericortex::protocol::{self, Outcome};
+use pericortex::worker::{EchoWorker, Worker};
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// Echoes every task, attaching a metrics artifact to its result, over a draft of it
+#[derive(Clone, Debug)]
+struct MeasuringWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for MeasuringWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn verify_results(&self) -> bool { false }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
+    artifacts::attach("metrics.json", file_with(b"{}"))?;
+    artifacts::attach("metrics.json", file_with(b"{\"pages\":1}"))?;
+    self.echo.convert(path)
+  }
+}
+
+fn file_with(contents: &[u8]) -> File {
+  let mut file = tempfile::tempfile().unwrap();
+  file.write_all(contents).unwrap();
+  file.seek(SeekFrom::Start(0)).unwrap();
+  file
+}
+
+#[test]
+fn artifacts_are_folded_into_the_result_archive() {
+  let mut result = tempfile::tempfile().unwrap();
+  {
+    let mut zip = ZipWriter::new(&mut result);
+    zip.start_file("cortex.log", FileOptions::default()).unwrap();
+    zip.finish().unwrap();
+  }
+  result.seek(SeekFrom::Start(0)).unwrap();
+
+  assert!(artifacts::attach("../report.pdf", file_with(b"%PDF")).is_err());
+  let report = Artifact {
+    name: String::from("report.pdf"),
+    file: file_with(b"%PDF"),
+  };
+  let folded = artifacts::fold(result, vec![report]).unwrap();
+
+  let mut archive = ZipArchive::new(folded).unwrap();
+  let mut names: Vec<&str> = archive.file_names().collect();
+  names.sort();
+  assert_eq!(names, ["artifacts/report.pdf", "cortex.log"]);
+  assert_eq!(archive.by_name("artifacts/report.pdf").unwrap().size(), 4);
+}
+
+#[test]
+fn artifacts_are_sent_in_frames_of_their_own() {
+  protocol::set_version(protocol::ARTIFACTS_VERSION).unwrap();
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"7", b"payload"], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+
+  let mut worker = MeasuringWorker {
+    echo: common::echo_worker(&source, &sink_address),
+  };
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let result = sink_thread.join().unwrap();
+  assert_eq!(result.outcome(), Outcome::Ok);
+  assert!(result.stats.is_some());
+  assert_eq!(result.payload, b"payload");
+  assert_eq!(result.artifacts["metrics.json"], b"{\"pages\":1}");
+}
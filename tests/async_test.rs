@@ -0,0 +1,116 @@
+#![cfg(feature = "async")]
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use pericortex::async_worker::{self, AsyncError, AsyncTask, AsyncWorker};
+use pericortex::dispatcher::Sink;
+use pericortex::endpoint::Endpoint;
+use pericortex::protocol::{self, Outcome};
+use zmq::SNDMORE;
+
+/// Answers every task with its payload after a pause, unless it mentions "hang"
+struct Sleeper {
+  source: Endpoint,
+  sink: Endpoint,
+  pause: Duration,
+  converting: Arc<AtomicUsize>,
+  most_converting: Arc<AtomicUsize>,
+}
+
+impl Sleeper {
+  fn new(source: &str, sink: &str, pause: Duration) -> Sleeper {
+    Sleeper {
+      source: source.parse().unwrap(),
+      sink: sink.parse().unwrap(),
+      pause,
+      converting: Arc::new(AtomicUsize::new(0)),
+      most_converting: Arc::new(AtomicUsize::new(0)),
+    }
+  }
+}
+
+impl AsyncWorker for Sleeper {
+  async fn convert(&self, task: AsyncTask) -> Result<Vec<u8>, AsyncError> {
+    let converting = self.converting.fetch_add(1, Ordering::SeqCst) + 1;
+    self.most_converting.fetch_max(converting, Ordering::SeqCst);
+    let pause = if task.payload == b"hang" {
+      Duration::new(3600, 0)
+    } else {
+      self.pause
+    };
+    tokio::time::sleep(pause).await;
+    self.converting.fetch_sub(1, Ordering::SeqCst);
+    Ok(task.payload)
+  }
+  fn get_service(&self) -> &str { "sleeper" }
+  fn get_source_address(&self) -> &Endpoint { &self.source }
+  fn get_sink_address(&self) -> &Endpoint { &self.sink }
+  fn concurrency(&self) -> usize { 4 }
+  fn timeout(&self) -> Duration { Duration::from_millis(500) }
+}
+
+/// A dispatcher answering every task request with one of `payloads`, in order
+fn ventilate(payloads: Vec<&'static str>) -> (thread::JoinHandle<Vec<Vec<u8>>>, String) {
+  let (ventilator, source) = common::dispatcher();
+  let handle = thread::spawn(move || {
+    let mut requests = Vec::new();
+    for (taskid, payload) in payloads.into_iter().enumerate() {
+      let request = ventilator.recv_multipart(0).unwrap();
+      ventilator.send(&request[0], SNDMORE).unwrap();
+      ventilator
+        .send_multipart([taskid.to_string().into_bytes(), payload.as_bytes().to_vec()], 0)
+        .unwrap();
+      requests.push(request[1].clone());
+    }
+    requests
+  });
+  (handle, source)
+}
+
+// a single test, as the protocol version is shared by the whole process
+#[test]
+fn tasks_are_converted_concurrently_within_their_timeout() {
+  protocol::set_version(protocol::STATS_VERSION).unwrap();
+  let (ventilator, source) = ventilate(vec!["a", "b", "c", "d", "e", "f", "g", "h"]);
+  let (sink, sink_address) = common::sink();
+  let sink = Sink::from_socket(sink);
+  let worker = Sleeper::new(&source, &sink_address, Duration::from_millis(200));
+  let most_converting = worker.most_converting.clone();
+  async_worker::start(worker, Some(8)).unwrap();
+
+  let requests = ventilator.join().unwrap();
+  assert!(requests.iter().all(|service| service == b"sleeper"));
+  let mut results: Vec<(String, Vec<u8>, Outcome)> = (0..8)
+    .map(|_| sink.recv_result().unwrap())
+    .map(|result| (result.taskid.clone(), result.payload.clone(), result.outcome()))
+    .collect();
+  results.sort();
+  let expected: Vec<(String, Vec<u8>, Outcome)> = ["a", "b", "c", "d", "e", "f", "g", "h"]
+    .iter()
+    .enumerate()
+    .map(|(taskid, payload)| (taskid.to_string(), payload.as_bytes().to_vec(), Outcome::Ok))
+    .collect();
+  assert_eq!(results, expected);
+  // the pauses overlapped, up to the concurrency of the worker
+  let most_converting = most_converting.load(Ordering::SeqCst);
+  assert!((2..=4).contains(&most_converting), "{} tasks at once", most_converting);
+
+  // a conversion running past the timeout is answered as a transient failure
+  let (ventilator, source) = ventilate(vec!["hang", "quick"]);
+  let (sink, sink_address) = common::sink();
+  let sink = Sink::from_socket(sink);
+  let worker = Sleeper::new(&source, &sink_address, Duration::ZERO);
+  async_worker::start(worker, Some(2)).unwrap();
+  ventilator.join().unwrap();
+  let mut results = [sink.recv_result().unwrap(), sink.recv_result().unwrap()];
+  results.sort_by(|a, b| a.taskid.cmp(&b.taskid));
+  assert_eq!(results[0].outcome(), Outcome::Fatal);
+  assert_eq!(results[0].status.as_ref().unwrap().reason.as_deref(), Some("transient"));
+  assert!(results[0].payload.is_empty());
+  assert_eq!(results[1].payload, b"quick");
+  assert_eq!(results[1].stats.as_ref().unwrap().output_bytes, 5);
+}
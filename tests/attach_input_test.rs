@@ -0,0 +1,48 @@
+mod common;
+
+use std::io::Cursor;
+use std::thread;
+use std::time::Duration;
+
+use pericortex::adaptor;
+use pericortex::dispatcher::Sink;
+use pericortex::policy::{self, InputAttachment};
+use pericortex::worker::Worker;
+use zip::ZipArchive;
+
+#[test]
+fn failure_reports_attach_the_selected_input_files() {
+  policy::set_attach_input(Some(InputAttachment {
+    extensions: vec![String::from("tex")],
+    max_bytes: Some(100),
+  }));
+  let input = common::zip_of(&[
+    ("paper.tex", "\\documentclass{article}"),
+    ("appendix.tex", &"%".repeat(200)),
+    ("figure.png", "PNG"),
+  ]);
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"1", &input], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+
+  let mut worker = common::echo_worker(&source, &sink_address);
+  worker.fail_every = Some(1);
+  worker.throttle = Duration::ZERO;
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let result = sink_thread.join().unwrap();
+
+  let mut report = ZipArchive::new(Cursor::new(result.payload.clone())).unwrap();
+  let mut names: Vec<&str> = report.file_names().collect();
+  names.sort();
+  assert_eq!(names, ["cortex.log", "input/paper.tex"]);
+  let log = adaptor::read_zip_entry(Cursor::new(result.payload), "cortex.log").unwrap().unwrap();
+  let log = String::from_utf8(log).unwrap();
+  assert!(log.starts_with("Fatal:conversion:failed"), "{}", log);
+  assert!(log.contains("Info:triage:omitted appendix.tex (200 bytes)"), "{}", log);
+  assert!(report.by_name("input/paper.tex").is_ok());
+}
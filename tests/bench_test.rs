@@ -0,0 +1,35 @@
+use std::fs;
+use std::path::Path;
+
+use pericortex::bench;
+use pericortex::worker::EchoWorker;
+
+#[test]
+fn every_sample_is_converted_every_iteration() {
+  let resources = Path::new("tests/resources");
+  let report = bench::bench(&EchoWorker::default(), resources, 3).unwrap();
+
+  assert_eq!(report.documents.len(), 1);
+  let document = &report.documents[0];
+  assert_eq!(document.entry, Path::new("1508.01222.zip"));
+  assert_eq!((document.latencies.len(), document.failures), (3, 0));
+  assert!(document.latencies.windows(2).all(|pair| pair[0] <= pair[1]));
+  // the echo worker answers with its input
+  let input_size = fs::metadata(resources.join("1508.01222.zip")).unwrap().len();
+  assert_eq!(document.output_size, Some(input_size));
+  assert_eq!(document.percentile(100.0), document.latencies.last().copied());
+  assert_eq!(report.percentile(50.0), document.percentile(50.0));
+  assert!(report.to_string().contains("1508.01222.zip\t"));
+}
+
+#[test]
+fn failed_iterations_are_counted_apart() {
+  let failing = EchoWorker {
+    fail_every: Some(2),
+    ..EchoWorker::default()
+  };
+  let report = bench::bench(&failing, Path::new("tests/resources"), 4).unwrap();
+  let document = &report.documents[0];
+  assert_eq!((document.latencies.len(), document.failures), (2, 2));
+  assert!(document.output_size.is_some());
+}
@@ -0,0 +1,16 @@
+use pericortex::capabilities::{capabilities, Capabilities};
+
+#[test]
+fn capabilities_match_the_build() {
+  let report = capabilities();
+  assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+  assert!(report.workers.contains(&"echo".to_string()));
+  assert_eq!(report.workers.contains(&"engrafo".to_string()), cfg!(feature = "engrafo"));
+  assert_eq!(report.integrations["object-storage"], cfg!(feature = "object-storage"));
+  assert_eq!(report.integrations["async"], cfg!(feature = "async"));
+  assert!(!report.integrations["python"]);
+  assert!(report.transports.contains(&"tcp".to_string()));
+
+  let json = serde_json::to_string(&report).unwrap();
+  assert_eq!(serde_json::from_str::<Capabilities>(&json).unwrap(), report);
+}
@@ -0,0 +1,76 @@
+use std::net::TcpListener;
+
+use tempdir::TempDir;
+
+use pericortex::check::{self, CheckReport, CheckStatus};
+use pericortex::endpoint::Endpoint;
+use pericortex::worker::EchoWorker;
+
+/// An endpoint accepting connections, for as long as the listener is held
+fn listening() -> (TcpListener, Endpoint) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let port = listener.local_addr().unwrap().port();
+  (listener, Endpoint::tcp("127.0.0.1", port))
+}
+
+fn status_of(report: &CheckReport, name: &str) -> CheckStatus {
+  report.outcomes.iter().find(|outcome| outcome.name == name).unwrap().status.clone()
+}
+
+#[test]
+fn a_reachable_dispatcher_and_writable_scratch_pass() {
+  let (_source_listener, source) = listening();
+  let (_sink_listener, sink) = listening();
+  let worker = EchoWorker {
+    source,
+    sink,
+    ..EchoWorker::default()
+  };
+  let scratch = TempDir::new("check_test").unwrap();
+  let report = check::diagnose_in(&worker, scratch.path(), 0);
+  assert!(report.passed(), "{}", report);
+  assert_eq!(status_of(&report, "dispatcher source"), CheckStatus::Pass);
+  assert_eq!(status_of(&report, "scratch space"), CheckStatus::Pass);
+  // the dispatcher can't tell, which doesn't fail the report
+  assert_eq!(status_of(&report, "service registration"), CheckStatus::Skip);
+}
+
+#[test]
+fn an_unreachable_endpoint_fails() {
+  let (_source_listener, source) = listening();
+  // nothing listens on a port which was just released
+  let (sink_listener, sink) = listening();
+  drop(sink_listener);
+  let worker = EchoWorker {
+    source,
+    sink,
+    ..EchoWorker::default()
+  };
+  let scratch = TempDir::new("check_test").unwrap();
+  let report = check::diagnose_in(&worker, scratch.path(), 0);
+  assert!(!report.passed());
+  assert_eq!(status_of(&report, "dispatcher source"), CheckStatus::Pass);
+  assert_eq!(status_of(&report, "dispatcher sink"), CheckStatus::Fail);
+  assert!(report.to_string().contains("[FAIL] dispatcher sink: "));
+}
+
+#[test]
+fn unwritable_or_small_scratch_space_fails() {
+  let (_source_listener, source) = listening();
+  let (_sink_listener, sink) = listening();
+  let worker = EchoWorker {
+    source,
+    sink,
+    ..EchoWorker::default()
+  };
+  let scratch = TempDir::new("check_test").unwrap();
+  let missing = scratch.path().join("missing");
+  let report = check::diagnose_in(&worker, &missing, 0);
+  assert!(!report.passed());
+  assert_eq!(status_of(&report, "scratch space"), CheckStatus::Fail);
+
+  let report = check::diagnose_in(&worker, scratch.path(), u64::MAX);
+  assert!(!report.passed());
+  let detail = &report.outcomes.iter().find(|outcome| outcome.name == "scratch space").unwrap().detail;
+  assert!(detail.contains("MB required"), "{}", detail);
+}
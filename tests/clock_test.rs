@@ -0,0 +1,69 @@
+use pericortex::clock::{self, MockClock};
+use pericortex::error::WorkerError;
+use pericortex::random;
+use pericortex::retry::RetryPolicy;
+use pericortex::worker::{convert_with_retries, EchoWorker, Worker};
+use rand::Rng;
+use pericortex::endpoint::Endpoint;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempdir::TempDir;
+
+/// Fails transiently a number of times before echoing
+#[derive(Clone, Debug)]
+struct FlakyWorker {
+  echo: EchoWorker,
+  failures: Arc<AtomicUsize>,
+}
+
+impl Worker for FlakyWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy {
+      attempts: 4,
+      backoff: Duration::from_secs(30),
+    }
+  }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
+    if self.failures.fetch_sub(1, Ordering::SeqCst) > 0 {
+      return Err(Box::new(WorkerError::Transient(String::from("docker daemon busy"))));
+    }
+    self.echo.convert(path)
+  }
+}
+
+#[test]
+fn backoff_runs_on_the_mock_clock() {
+  let mock = Arc::new(MockClock::default());
+  clock::install(mock.clone());
+  let worker = FlakyWorker {
+    echo: EchoWorker::default(),
+    failures: Arc::new(AtomicUsize::new(2)),
+  };
+  let scratch = TempDir::new("clock_test").unwrap();
+  let mut payload = tempfile::spooled_tempfile(1024);
+  std::io::Write::write_all(&mut payload, b"payload").unwrap();
+
+  let started = Instant::now();
+  let before = clock::now();
+  convert_with_retries(&worker, &mut payload, "1", &scratch).unwrap();
+  assert!(started.elapsed() < Duration::from_secs(5));
+  assert_eq!(mock.pauses(), vec![Duration::from_secs(30), Duration::from_secs(60)]);
+  assert_eq!(clock::now() - before, Duration::from_secs(90));
+}
+
+#[test]
+fn seeded_randomness_repeats() {
+  random::seed(7);
+  let first: Vec<u32> = (0..3).map(|_| random::rng().gen()).collect();
+  random::seed(7);
+  let second: Vec<u32> = (0..3).map(|_| random::rng().gen()).collect();
+  assert_eq!(first, second);
+}
@@ -0,0 +1,44 @@
+//! Test support: sockets bound to ports the operating system assigns, so that integration tests
+//! can run concurrently, and next to other services, without colliding on fixed ports, and the
+//! archives they exchange
+#![allow(dead_code)]
+
+use std::io::{Cursor, Write};
+
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use pericortex::worker::EchoWorker;
+
+/// A socket of `kind` bound to an ephemeral localhost port, with the endpoint to connect to it
+pub fn bind_ephemeral(kind: zmq::SocketType) -> (zmq::Socket, String) {
+  let socket = zmq::Context::new().socket(kind).unwrap();
+  socket.bind("tcp://127.0.0.1:*").unwrap();
+  let endpoint = socket.get_last_endpoint().unwrap().unwrap();
+  (socket, endpoint)
+}
+
+/// A mock dispatcher socket, handing out tasks to the workers connecting to its endpoint
+pub fn dispatcher() -> (zmq::Socket, String) { bind_ephemeral(zmq::ROUTER) }
+
+/// A mock sink socket, collecting the results sent to its endpoint
+pub fn sink() -> (zmq::Socket, String) { bind_ephemeral(zmq::PULL) }
+
+/// A ZIP archive of `files`, given as name and contents pairs
+pub fn zip_of(files: &[(&str, &str)]) -> Vec<u8> {
+  let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+  for (name, contents) in files {
+    zip.start_file(*name, FileOptions::default()).unwrap();
+    zip.write_all(contents.as_bytes()).unwrap();
+  }
+  zip.finish().unwrap().into_inner()
+}
+
+/// An echo worker fetching from `source` and responding to `sink`
+pub fn echo_worker(source: &str, sink: &str) -> EchoWorker {
+  EchoWorker {
+    source: source.parse().unwrap(),
+    sink: sink.parse().unwrap(),
+    ..EchoWorker::default()
+  }
+}
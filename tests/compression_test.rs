@@ -0,0 +1,124 @@
+mod common;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread;
+
+use tempfile::{spooled_tempfile, SpooledTempFile};
+
+use pericortex::dispatcher::{Sink, TaskResult};
+use pericortex::endpoint::Endpoint;
+use pericortex::protocol::{self, Compression};
+use pericortex::worker::{EchoWorker, Worker};
+
+/// An echo worker which negotiated zstd compression
+#[derive(Clone, Debug)]
+struct ZstdWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for ZstdWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn verify_results(&self) -> bool { false }
+  fn compression(&self) -> Compression { Compression::Zstd }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> { self.echo.convert(path) }
+}
+
+const REPORT: &[u8] = b"a text-heavy report, a text-heavy report, a text-heavy report, a text-heavy report";
+
+fn file_of(contents: &[u8]) -> File {
+  let mut file = tempfile::tempfile().unwrap();
+  file.write_all(contents).unwrap();
+  file.seek(SeekFrom::Start(0)).unwrap();
+  file
+}
+
+fn spooled_of(contents: &[u8]) -> SpooledTempFile {
+  let mut payload = spooled_tempfile(1024);
+  payload.write_all(contents).unwrap();
+  payload.seek(SeekFrom::Start(0)).unwrap();
+  payload
+}
+
+fn contents<R: Read>(mut reader: R) -> Vec<u8> {
+  let mut contents = Vec::new();
+  reader.read_to_end(&mut contents).unwrap();
+  contents
+}
+
+/// Convert `payload` as a single task with the worker `wrap` makes of an echo worker, returning
+/// the task request and the result sent to the sink
+fn exchange<W: Worker + 'static>(payload: Vec<u8>, wrap: impl FnOnce(EchoWorker) -> W) -> (Vec<Vec<u8>>, TaskResult) {
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"1", &payload[..]], 0).unwrap();
+    request
+  });
+  let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+  let mut worker = wrap(common::echo_worker(&source, &sink_address));
+  worker.start(Some(1)).unwrap();
+  (vent_thread.join().unwrap(), sink_thread.join().unwrap())
+}
+
+#[test]
+fn results_round_trip_through_zstd() {
+  let encoded = protocol::encode_result(file_of(REPORT), Compression::Zstd).unwrap();
+  let encoded = contents(encoded);
+  assert_eq!(encoded[..4], protocol::ZSTD_MAGIC);
+  let decoded = protocol::decode_payload(spooled_of(&encoded), Compression::Zstd).unwrap();
+  assert_eq!(contents(decoded), REPORT);
+}
+
+#[test]
+fn zip_archives_and_uncompressed_workers_are_sent_as_they_are() {
+  let archive = common::zip_of(&[("cortex.log", "")]);
+  assert_eq!(contents(protocol::encode_result(file_of(&archive), Compression::Zstd).unwrap()), archive);
+  assert_eq!(contents(protocol::encode_result(file_of(REPORT), Compression::None).unwrap()), REPORT);
+  assert!(contents(protocol::encode_result(file_of(b""), Compression::Zstd).unwrap()).is_empty());
+}
+
+#[test]
+fn payloads_are_only_decoded_once_zstd_was_negotiated() {
+  // e.g. a `.zst` input of a single-file service
+  let zst_input = zstd::encode_all(REPORT, 0).unwrap();
+  let received = protocol::decode_payload(spooled_of(&zst_input), Compression::None).unwrap();
+  assert_eq!(contents(received), zst_input);
+  // uncompressed payloads pass through a negotiated worker unchanged
+  let received = protocol::decode_payload(spooled_of(REPORT), Compression::Zstd).unwrap();
+  assert_eq!(contents(received), REPORT);
+}
+
+#[test]
+fn decompression_is_capped() {
+  let bomb = zstd::encode_all(io::repeat(0).take(1_000_000), 19).unwrap();
+  assert!(bomb.len() < 1_000);
+  let error = protocol::decode_payload_within(spooled_of(&bomb), Compression::Zstd, 100_000).unwrap_err();
+  assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+  // exactly at the cap is fine
+  let decoded = protocol::decode_payload_within(spooled_of(&bomb), Compression::Zstd, 1_000_000).unwrap();
+  assert_eq!(contents(decoded).len(), 1_000_000);
+}
+
+#[test]
+fn negotiated_workers_receive_and_send_zstd() {
+  let (request, result) = exchange(zstd::encode_all(REPORT, 0).unwrap(), |echo| ZstdWorker { echo });
+  assert!(request.iter().any(|frame| frame == protocol::ACCEPT_ZSTD.as_bytes()));
+  // echoed decompressed, and compressed again for the sink
+  assert_eq!(result.payload[..4], protocol::ZSTD_MAGIC);
+  assert_eq!(zstd::decode_all(&result.payload[..]).unwrap(), REPORT);
+
+  // without the negotiation, a zstd frame is an input like any other
+  let zst_input = zstd::encode_all(REPORT, 0).unwrap();
+  let (request, result) = exchange(zst_input.clone(), |echo| echo);
+  assert!(!request.iter().any(|frame| frame == protocol::ACCEPT_ZSTD.as_bytes()));
+  assert_eq!(result.payload, zst_input);
+}
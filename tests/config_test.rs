@@ -0,0 +1,68 @@
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use pericortex::config::{self, WorkerConfig, REDACTED};
+use pericortex::dispatcher::Sink;
+use pericortex::worker::{EchoWorker, Worker};
+
+#[test]
+fn effective_configurations_are_shown_with_secrets_redacted() {
+  std::env::set_var("AWS_REGION", "eu-central-1");
+  std::env::set_var("AWS_SECRET_ACCESS_KEY", "wJalrXUtnFEMI");
+  let mut worker = EchoWorker {
+    sink: "tcp://cortex.example.org:51696".parse().unwrap(),
+    ..EchoWorker::default()
+  };
+  let config: WorkerConfig = toml::from_str("message_size = 65536\nthrottle = 5").unwrap();
+  worker.configure(&config);
+  let dump = config::show(&worker, &config).unwrap();
+
+  // the worker's defaults fill in what the configuration leaves unset
+  let shown: toml::Table = toml::from_str(&dump).unwrap();
+  assert_eq!(shown["source"].as_str(), Some("tcp://127.0.0.1:51695"));
+  assert_eq!(shown["sink"].as_str(), Some("tcp://cortex.example.org:51696"));
+  assert_eq!(shown["message_size"].as_integer(), Some(65536));
+  assert_eq!(shown["throttle"].as_integer(), Some(5));
+  assert_eq!(shown["environment"]["AWS_REGION"].as_str(), Some("eu-central-1"));
+  assert_eq!(shown["environment"]["AWS_SECRET_ACCESS_KEY"].as_str(), Some(REDACTED));
+  assert!(!dump.contains("wJalrXUtnFEMI"));
+  assert!(config::is_secret("curve_secret_key"));
+}
+
+#[cfg(unix)]
+#[test]
+fn reloads_keep_the_overrides_of_the_installed_file() {
+  let dir = tempdir::TempDir::new("config_test").unwrap();
+  let path = dir.path().join("worker.toml");
+  fs::write(&path, "throttle = 1\nmessage_size = 65536").unwrap();
+  let overrides = WorkerConfig {
+    throttle: Some(0),
+    ..WorkerConfig::default()
+  };
+  let config = WorkerConfig::load(&path).unwrap().merged(&overrides);
+  config::install_from(path.clone(), config, overrides.clone());
+  assert_eq!(config::file(), Some((path.clone(), overrides)));
+
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    // the worker watches the file before it asks for a task
+    let request = ventilator.recv_multipart(0).unwrap();
+    fs::write(&path, "throttle = 1\nmessage_size = 131072").unwrap();
+    signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+    thread::sleep(Duration::from_secs(2));
+    ventilator.send_multipart([&request[0][..], b"1", b"payload"], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+  let mut worker = common::echo_worker(&source, &sink_address);
+  worker.start(Some(1)).unwrap();
+  vent_thread.join().unwrap();
+  assert_eq!(sink_thread.join().unwrap().taskid, "1");
+
+  let reloaded = config::current().unwrap();
+  assert_eq!(reloaded.message_size, Some(131072));
+  assert_eq!(reloaded.throttle, Some(0));
+}
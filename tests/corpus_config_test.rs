@@ -0,0 +1,70 @@
+#![cfg(feature = "latexml")]
+mod common;
+
+use std::thread;
+
+use pericortex::config::{ConfigResolver, WorkerConfig};
+use pericortex::protocol::{self, TaskMetadata};
+use pericortex::worker::{TexToHtmlWorker, Worker};
+use zmq::SNDMORE;
+
+fn metadata(corpus: &str) -> TaskMetadata {
+  TaskMetadata {
+    corpus: Some(corpus.to_string()),
+    ..TaskMetadata::default()
+  }
+}
+
+#[test]
+fn corpora_override_the_settings_of_their_tasks() {
+  let config: WorkerConfig = toml::from_str(
+    "message_size = 100000\n\
+     [corpora.longpapers]\n\
+     timeout = 1200\n\
+     [corpora.arxiv-2024]\n\
+     image = \"arxivvanity/engrafo:2.1.0\"\n",
+  )
+  .unwrap();
+  let resolver = ConfigResolver::new(config);
+  assert!(resolver.overrides(None).is_none());
+  assert!(resolver.overrides(Some(&metadata("zbmath"))).is_none());
+  assert!(resolver.overrides(Some(&TaskMetadata::default())).is_none());
+
+  let longpapers = resolver.config_for(Some(&metadata("longpapers")));
+  assert_eq!(longpapers.timeout, Some(1200));
+  assert_eq!(longpapers.message_size, Some(100_000));
+  let arxiv = resolver.config_for(Some(&metadata("arxiv-2024")));
+  assert_eq!(arxiv.image.as_deref(), Some("arxivvanity/engrafo:2.1.0"));
+  assert_eq!(arxiv.timeout, None);
+
+  let worker = TexToHtmlWorker::default();
+  assert_eq!(resolver.worker_for(&worker, Some(&metadata("longpapers"))).timeout, 1200);
+  assert_eq!(resolver.worker_for(&worker, Some(&metadata("zbmath"))).timeout, 300);
+}
+
+#[test]
+fn metadata_frames_are_not_part_of_the_payload() {
+  let frame = metadata("longpapers").frame();
+  assert_eq!(frame, "metadata:{\"corpus\":\"longpapers\"}");
+  assert_eq!(TaskMetadata::parse(frame.as_bytes()), Some(metadata("longpapers")));
+  assert!(TaskMetadata::parse(b"PK\x03\x04").is_none());
+
+  protocol::set_version(protocol::METADATA_VERSION).unwrap();
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator
+      .send_multipart([b"1".to_vec(), frame.into_bytes(), b"payload".to_vec()], 0)
+      .unwrap();
+  });
+  let sink_thread = thread::spawn(move || sink.recv_multipart(0).unwrap());
+
+  let mut worker = common::echo_worker(&source, &sink_address);
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let frames = sink_thread.join().unwrap();
+  assert_eq!(frames[2], b"1");
+  assert_eq!(frames.last().unwrap(), b"payload");
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use pericortex::endpoint::Endpoint;
+use std::error::Error;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use std::thread;
+
+use pericortex::adaptor;
+use pericortex::worker::{EchoWorker, Worker};
+use zmq::SNDMORE;
+
+/// Panics on every task
+#[derive(Clone, Debug)]
+struct CrashingWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for CrashingWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn convert(&self, _path: &Path) -> Result<File, Box<dyn Error>> { panic!("converter state corrupted") }
+}
+
+#[test]
+fn crashed_tasks_are_reported_to_the_sink() {
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator.send_multipart([b"13".to_vec(), b"payload".to_vec()], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || sink.recv_multipart(0).unwrap());
+
+  let mut worker = CrashingWorker {
+    echo: common::echo_worker(&source, &sink_address),
+  };
+  assert!(worker.start(Some(1)).is_err());
+  assert!(vent_thread.join().is_ok());
+  let frames = sink_thread.join().unwrap();
+
+  assert_eq!(frames[2], b"13");
+  let log = adaptor::read_zip_entry(Cursor::new(frames.last().unwrap()), "cortex.log").unwrap().unwrap();
+  let log = String::from_utf8(log).unwrap();
+  assert!(log.starts_with("Fatal:workercrash:panic converter state corrupted at tests/crash_test.rs:"), "{}", log);
+}
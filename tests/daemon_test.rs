@@ -0,0 +1,40 @@
+#![cfg(feature = "daemon")]
+use std::fs;
+use std::process;
+
+use tempdir::TempDir;
+
+use pericortex::daemon;
+
+#[test]
+fn pid_files_name_the_current_process() {
+  let dir = TempDir::new("daemon_test").unwrap();
+  let pid_file = dir.path().join("worker.pid");
+  daemon::write_pid_file(&pid_file).unwrap();
+  assert_eq!(fs::read_to_string(&pid_file).unwrap(), format!("{}\n", process::id()));
+
+  daemon::remove_pid_file(&pid_file);
+  assert!(!pid_file.exists());
+  // removing it twice is harmless
+  daemon::remove_pid_file(&pid_file);
+}
+
+#[test]
+fn stale_pid_files_are_replaced() {
+  let dir = TempDir::new("daemon_test").unwrap();
+  let pid_file = dir.path().join("worker.pid");
+  // left behind by a process which didn't exit cleanly
+  fs::write(&pid_file, "4194304\n").unwrap();
+  daemon::write_pid_file(&pid_file).unwrap();
+  assert_eq!(fs::read_to_string(&pid_file).unwrap().trim(), process::id().to_string());
+}
+
+#[test]
+fn pid_files_of_other_processes_are_kept() {
+  let dir = TempDir::new("daemon_test").unwrap();
+  let pid_file = dir.path().join("worker.pid");
+  // e.g. a replacement worker started with the same PID file
+  fs::write(&pid_file, "4194304\n").unwrap();
+  daemon::remove_pid_file(&pid_file);
+  assert_eq!(fs::read_to_string(&pid_file).unwrap(), "4194304\n");
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "tui")]
+use ratatui::backend::TestBackend;
+use ratatui::Terminal;
+
+use pericortex::dashboard::View;
+use pericortex::health;
+use pericortex::state::{self, Phase};
+
+#[test]
+fn dashboards_show_threads_throughput_and_log() {
+  let converter = health::register("host:echo:dashboard");
+  state::enter("host:echo:dashboard", Phase::Converting, Some("1508.01222"));
+  let mut view = View::new("echo_service");
+  view.sample(&state::snapshot());
+  state::record(false);
+  state::record(false);
+  view.sample(&state::snapshot());
+  assert_eq!(view.throughput, [2]);
+  view.record_line("[2026-10-16 12:00:00] \x1B[32mhost:echo:dashboard\t\x1B[0mconverted 1508.01222");
+
+  let mut terminal = Terminal::new(TestBackend::new(100, 24)).unwrap();
+  let snapshot = state::snapshot();
+  terminal.draw(|frame| view.draw(frame, &snapshot)).unwrap();
+  let screen: String = terminal
+    .backend()
+    .buffer()
+    .content()
+    .chunks(100)
+    .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>() + "\n")
+    .collect();
+  assert!(screen.contains("pericortex echo_service"));
+  assert!(screen.contains("host:echo:dashboard"));
+  assert!(screen.contains("converting"));
+  assert!(screen.contains("1508.01222"));
+  assert!(screen.contains("peak 2"));
+  assert!(screen.contains("host:echo:dashboard converted 1508.01222"));
+  assert!(!screen.contains("\x1B"));
+  converter.finish();
+}
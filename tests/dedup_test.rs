@@ -0,0 +1,43 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use pericortex::dedup::{self, Duplicate, DEDUP_CAPACITY};
+
+// a single test, as evicting tasks would forget those of any test running alongside
+#[test]
+fn duplicates_are_skipped_replayed_and_forgotten() {
+  // a redelivery of a task in flight is skipped
+  assert!(dedup::begin("in-flight").is_none());
+  assert!(matches!(dedup::begin("in-flight"), Some(Duplicate::InFlight)));
+
+  // a redelivery of a completed task replays the cached result
+  assert!(dedup::begin("completed").is_none());
+  let mut result = tempfile::tempfile().unwrap();
+  result.write_all(b"the converted document").unwrap();
+  result.seek(SeekFrom::Start(0)).unwrap();
+  dedup::complete("completed", Some(&mut result));
+  match dedup::begin("completed") {
+    Some(Duplicate::Completed(Some(mut cached))) => {
+      let mut replayed = String::new();
+      cached.read_to_string(&mut replayed).unwrap();
+      assert_eq!(replayed, "the converted document");
+    }
+    other => panic!("expected a cached result, got {:?}", other),
+  }
+  // a task completed without caching is still a duplicate
+  assert!(dedup::begin("uncached").is_none());
+  dedup::complete("uncached", None);
+  assert!(matches!(dedup::begin("uncached"), Some(Duplicate::Completed(None))));
+  assert_eq!(dedup::completed(), ["completed", "uncached"]);
+
+  // beyond the capacity, the oldest tasks are forgotten and convert anew
+  for i in 0..DEDUP_CAPACITY - 3 {
+    assert!(dedup::begin(&format!("filler-{}", i)).is_none());
+  }
+  assert!(matches!(dedup::begin("in-flight"), Some(Duplicate::InFlight)));
+  assert!(dedup::begin("evicting").is_none());
+  assert!(matches!(dedup::begin("completed"), Some(Duplicate::Completed(Some(_)))));
+  assert!(dedup::begin("one-more").is_none());
+  assert_eq!(dedup::completed(), ["uncached"]);
+  assert!(dedup::begin("in-flight").is_none());
+  assert!(dedup::begin("completed").is_none());
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "tools")]
+mod common;
+
+use std::fs;
+
+use tempdir::TempDir;
+
+use pericortex::diff::{self, normalize_html};
+use pericortex::logcodes::Severity;
+
+#[test]
+fn attribute_order_and_whitespace_normalize_away() {
+  assert_eq!(
+    normalize_html("<P  class='abstract'   ID=\"a1\">Some\n   text  </P><!-- generated -->"),
+    normalize_html("<p id=\"a1\" class=\"abstract\">Some text</p>")
+  );
+  assert_eq!(
+    normalize_html("<img alt=\"a  figure\" src=x.png />"),
+    ["<img alt=\"a figure\" src=\"x.png\"/>"]
+  );
+  assert!(diff::html_changes("<div b=\"2\" a=\"1\">\n  x\n</div>", "<div a='1' b='2'>x</div>").is_empty());
+}
+
+#[test]
+fn truncated_and_non_ascii_markup_is_tokenized() {
+  assert_eq!(normalize_html("<p>ok</p><é"), ["<p>", "ok", "</p>", "<é"]);
+  assert_eq!(normalize_html("<p>ok</p><a"), ["<p>", "ok", "</p>", "<a"]);
+  assert_eq!(normalize_html("<a title=\"x>"), ["<a title=\"x>"]);
+  assert_eq!(
+    normalize_html("<P Title='Ünïcode ✓'>Grüße,  世界</P>"),
+    ["<p title=\"Ünïcode ✓\">", "Grüße, 世界", "</p>"]
+  );
+}
+
+#[test]
+fn content_differences_are_reported() {
+  assert_eq!(
+    diff::html_changes("<p class=\"a\">old text</p>", "<p class=\"b\">old text</p>"),
+    ["- <p class=\"a\">", "+ <p class=\"b\">"]
+  );
+  assert_eq!(
+    diff::html_changes("<p>old text</p>", "<p>new text</p>"),
+    ["- old text", "+ new text"]
+  );
+}
+
+#[test]
+fn archives_are_compared_entry_by_entry() {
+  let dir = TempDir::new("diff_test").unwrap();
+  let old = dir.path().join("old.zip");
+  let new = dir.path().join("new.zip");
+  fs::write(
+    &old,
+    common::zip_of(&[
+      ("index.html", "<p class=\"x\" id=\"1\">Same</p>\n<p>Before</p>"),
+      ("layout.html", "<div  a=\"1\" b=\"2\">\n  Moved  around\n</div>"),
+      ("style.css", "p { margin: 0 }"),
+      ("cortex.log", "Info:conversion:done\n"),
+    ]),
+  )
+  .unwrap();
+  fs::write(
+    &new,
+    common::zip_of(&[
+      ("index.html", "<p id='1' class='x'>Same</p><p>After</p>"),
+      ("layout.html", "<div b=\"2\" a=\"1\">Moved around</div>"),
+      ("figure.png", "png"),
+      ("cortex.log", "Info:conversion:done\nWarning:missing_file:figure\n"),
+    ]),
+  )
+  .unwrap();
+
+  let diff = diff::diff_archives(&old, &new).unwrap();
+  assert!(!diff.is_identical());
+  assert_eq!(diff.added, ["figure.png"]);
+  assert_eq!(diff.removed, ["style.css"]);
+  // the layout only differs in attribute order and whitespace
+  let changed: Vec<&str> = diff.changed.iter().map(|entry| entry.name.as_str()).collect();
+  assert_eq!(changed, ["cortex.log", "index.html"]);
+  assert_eq!(diff.changed[1].changes, ["- Before", "+ After"]);
+  assert_eq!(diff.severity_deltas.get(&Severity::Warning), Some(&(0, 1)));
+  assert!(diff.to_string().contains("changed: index.html\n    - Before\n    + After\n"));
+
+  assert!(diff::diff_archives(&old, &old).unwrap().is_identical());
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use std::thread;
+
+use pericortex::dispatcher::{parse_result, Sink};
+use pericortex::error::WorkerError;
+use pericortex::protocol::{Outcome, ProtocolError, ResultStatus, TaskStats};
+use pericortex::worker::Worker;
+use zmq::SNDMORE;
+
+fn frames(frames: &[&[u8]]) -> Vec<Vec<u8>> { frames.iter().map(|frame| frame.to_vec()).collect() }
+
+#[test]
+fn sink_receives_typed_results() {
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator.send_multipart([b"7".to_vec(), b"payload".to_vec()], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+
+  let mut worker = common::echo_worker(&source, &sink_address);
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let result = sink_thread.join().unwrap();
+  assert!(result.identity.contains("echo_service"), "{}", result.identity);
+  assert_eq!(result.service, "echo_service");
+  assert_eq!(result.taskid, "7");
+  assert_eq!(result.payload, b"payload");
+  assert_eq!(result.status, None);
+  assert_eq!(result.outcome(), Outcome::Ok);
+}
+
+#[test]
+fn optional_frames_are_recognized() {
+  let status = ResultStatus::failed(&WorkerError::Deterministic("converter crashed".to_string()), 10);
+  let stats = TaskStats {
+    duration_ms: 1200,
+    ..TaskStats::default()
+  };
+  let failed = parse_result(frames(&[
+    b"host:echo:1",
+    b"echo_service",
+    b"3",
+    status.frame().as_bytes(),
+    stats.frame().as_bytes(),
+    b"",
+  ]))
+  .unwrap();
+  assert_eq!(failed.status, Some(status));
+  assert_eq!(failed.stats, Some(stats));
+  assert!(failed.payload.is_empty());
+  assert_eq!(failed.outcome(), Outcome::Fatal);
+
+  // a version 1 worker signals a failure with an empty payload only
+  let legacy = parse_result(frames(&[b"host:echo:1", b"echo_service", b"4", b""])).unwrap();
+  assert_eq!(legacy.outcome(), Outcome::Fatal);
+
+  let truncated = parse_result(frames(&[b"host:echo:1", b"echo_service"])).unwrap_err();
+  assert!(truncated.downcast_ref::<ProtocolError>().is_some());
+}
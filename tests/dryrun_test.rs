@@ -0,0 +1,43 @@
+mod common;
+
+use pericortex::dryrun;
+use pericortex::worker::Worker;
+use std::io::{Cursor, Read, Write};
+use std::thread;
+use zip::write::FileOptions;
+use zip::ZipArchive;
+use zmq::SNDMORE;
+
+#[test]
+fn dry_runs_extract_and_report_without_converting() {
+  let mut entry = Cursor::new(Vec::new());
+  {
+    let mut zip = zip::ZipWriter::new(&mut entry);
+    zip.start_file("paper.tex", FileOptions::default()).unwrap();
+    zip.write_all(b"\\documentclass{article}").unwrap();
+    zip.finish().unwrap();
+  }
+  let payload = entry.into_inner();
+
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator.send_multipart([b"1".to_vec(), payload], 0).unwrap();
+    request[1..].to_vec()
+  });
+  let sink_thread = thread::spawn(move || sink.recv_multipart(0).unwrap()[3..].concat());
+
+  dryrun::enable();
+  let mut worker = common::echo_worker(&source, &sink_address);
+  assert!(worker.start(Some(1)).is_ok());
+  let request = vent_thread.join().unwrap();
+  assert_eq!(request, vec![b"echo_service".to_vec(), b"mode:dry-run".to_vec()]);
+
+  let report = sink_thread.join().unwrap();
+  let mut archive = ZipArchive::new(Cursor::new(report)).unwrap();
+  let mut log = String::new();
+  archive.by_name("cortex.log").unwrap().read_to_string(&mut log).unwrap();
+  assert!(log.starts_with("Fatal:dry_run:not_converted received and extracted 1 files"), "{}", log);
+}
@@ -1,5 +1,10 @@
+mod common;
+
 use pericortex::worker::{EchoWorker, Worker};
+use std::fs;
+use std::io::Read;
 use std::thread;
+use tempdir::TempDir;
 use zmq::SNDMORE;
 
 #[test]
@@ -7,12 +12,9 @@ fn mock_round_trip() {
   // Let's get a minimal ZMQ ventilator/sink pair to test the worker
   let test_payload = "cortex peripherals - echo worker test".to_string();
   let sink_test_payload = test_payload.clone();
+  let (ventilator, ventilator_address) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
   let vent_thread = thread::spawn(move || {
-    let ventilator_context = zmq::Context::new();
-    let ventilator = ventilator_context.socket(zmq::ROUTER).unwrap();
-    let ventilator_address = "tcp://127.0.0.1:51695";
-    assert!(ventilator.bind(&ventilator_address).is_ok());
-
     // We expect one request
     let mut msg = zmq::Message::new();
     let mut identity = zmq::Message::new();
@@ -27,11 +29,6 @@ fn mock_round_trip() {
   });
 
   let sink_thread = thread::spawn(move || {
-    let sink_context = zmq::Context::new();
-    let sink = sink_context.socket(zmq::PULL).unwrap();
-    let sink_address = "tcp://127.0.0.1:51696";
-    assert!(sink.bind(&sink_address).is_ok());
-
     let mut id_msg = zmq::Message::new();
     sink.recv(&mut id_msg, 0).unwrap();
     let _identity = id_msg.as_str().unwrap();
@@ -53,10 +50,38 @@ fn mock_round_trip() {
   });
 
   // Start up an echo worker
-  let mut worker = EchoWorker::default();
+  let mut worker = common::echo_worker(&ventilator_address, &sink_address);
   // Perform a single echo task
   assert!(worker.start(Some(1)).is_ok());
 
   assert!(vent_thread.join().is_ok());
   assert!(sink_thread.join().is_ok());
 }
+
+#[test]
+fn echo_misbehaves_as_configured() {
+  let dir = TempDir::new("echo_test").unwrap();
+  let input = dir.path().join("task.zip");
+  fs::write(&input, "payload").unwrap();
+  let worker = EchoWorker {
+    message_size: 4,
+    fail_every: Some(2),
+    payload_size: Some(10),
+    corrupt_every: Some(3),
+    ..EchoWorker::default()
+  };
+  let answer = |worker: &EchoWorker| -> Option<Vec<u8>> {
+    let mut result = worker.convert(&input).ok()?;
+    let mut bytes = Vec::new();
+    result.read_to_end(&mut bytes).unwrap();
+    Some(bytes)
+  };
+
+  assert_eq!(answer(&worker), Some(b"eeeeeeeeee".to_vec()));
+  assert_eq!(answer(&worker), None);
+  let mut corrupted = vec![!b'e'; 4];
+  corrupted.extend(b"eeeeee");
+  assert_eq!(answer(&worker), Some(corrupted));
+  // clones share the count, as the threads of a pool do
+  assert_eq!(answer(&worker.clone()), None);
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use std::io::Cursor;
+use std::thread;
+use std::time::Duration;
+
+use pericortex::adaptor;
+use pericortex::dispatcher::Sink;
+use pericortex::policy::{self, EmptyInput};
+use pericortex::protocol::{self, Outcome};
+use pericortex::worker::Worker;
+use zmq::SNDMORE;
+
+#[test]
+fn empty_inputs_are_reported_apart_from_failures() {
+  protocol::set_version(protocol::EMPTY_INPUT_VERSION).unwrap();
+  for action in [EmptyInput::Report, EmptyInput::Skip] {
+    policy::set_empty_input(action, Some(Duration::ZERO));
+    let (ventilator, source) = common::dispatcher();
+    let (socket, sink_address) = common::sink();
+    let vent_thread = thread::spawn(move || {
+      let request = ventilator.recv_multipart(0).unwrap();
+      ventilator.send(&request[0], SNDMORE).unwrap();
+      ventilator.send_multipart([b"3".to_vec(), Vec::new()], 0).unwrap();
+    });
+    let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+
+    let mut worker = common::echo_worker(&source, &sink_address);
+    assert!(worker.start(Some(1)).is_ok());
+    assert!(vent_thread.join().is_ok());
+    let result = sink_thread.join().unwrap();
+    assert_eq!(result.outcome(), Outcome::Empty);
+    assert_eq!(result.status.unwrap().reason.as_deref(), Some("empty_input"));
+    match action {
+      EmptyInput::Report => {
+        let log = adaptor::read_zip_entry(Cursor::new(result.payload), "cortex.log").unwrap().unwrap();
+        assert!(String::from_utf8_lossy(&log).starts_with("Fatal:empty_input:empty"));
+      }
+      EmptyInput::Skip => assert!(result.payload.is_empty()),
+    }
+  }
+}
@@ -0,0 +1,73 @@
+use pericortex::encoding::{detect, detect_archive, transcode, transcode_dir, SourceEncoding};
+use std::fs::{self, File};
+use std::io::Write;
+use tempdir::TempDir;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+// "Привет, мир" in CP1251
+const CP1251_GREETING: &[u8] = b"\xCF\xF0\xE8\xE2\xE5\xF2, \xEC\xE8\xF0";
+
+#[test]
+fn encodings_are_detected() {
+  assert_eq!(detect(b"\\section{Intro}"), SourceEncoding::Utf8);
+  assert_eq!(detect("Erdős–Rényi".as_bytes()), SourceEncoding::Utf8);
+  assert_eq!(detect(b"\xEF\xBB\xBF\\section{Intro}"), SourceEncoding::Utf8Bom);
+  assert_eq!(detect(b"Erd\xF6s and R\xE9nyi, na\xEFve"), SourceEncoding::Latin1);
+  assert_eq!(detect(CP1251_GREETING), SourceEncoding::Cp1251);
+  // a declaration overrides the shape of the text
+  assert_eq!(
+    detect(b"\\usepackage[cp1251]{inputenc}\nJos\xE9"),
+    SourceEncoding::Cp1251
+  );
+  assert_eq!(
+    detect(b"\\usepackage[latin1]{inputenc}\n\xE4\xF6\xFC\xDF"),
+    SourceEncoding::Latin1
+  );
+}
+
+#[test]
+fn sources_are_transcoded_to_utf8() {
+  let (text, encoding) = transcode(b"\\usepackage[latin1]{inputenc}\nErd\xF6s");
+  assert_eq!(encoding, SourceEncoding::Latin1);
+  assert_eq!(text, "\\usepackage[utf8]{inputenc}\nErdös");
+
+  let (text, encoding) = transcode(CP1251_GREETING);
+  assert_eq!(encoding, SourceEncoding::Cp1251);
+  assert_eq!(text, "Привет, мир");
+
+  let (text, encoding) = transcode(b"\xEF\xBB\xBF\\inputencoding{utf8}");
+  assert_eq!(encoding, SourceEncoding::Utf8Bom);
+  assert_eq!(text, "\\inputencoding{utf8}");
+}
+
+#[test]
+fn directories_and_archives_are_transcoded() {
+  let dir = TempDir::new("encoding_test").unwrap();
+  fs::write(dir.path().join("main.tex"), b"\\input{ru}\nCaf\xE9").unwrap();
+  fs::write(dir.path().join("ru.tex"), CP1251_GREETING).unwrap();
+  fs::write(dir.path().join("plain.tex"), "already utf-8: é").unwrap();
+  fs::write(dir.path().join("figure.png"), b"\x89PNG\xFF\xFE").unwrap();
+
+  let archive_path = dir.path().join("sources.zip");
+  let mut archive = ZipWriter::new(File::create(&archive_path).unwrap());
+  for name in ["main.tex", "ru.tex", "plain.tex", "figure.png"] {
+    archive.start_file(name, FileOptions::default()).unwrap();
+    archive.write_all(&fs::read(dir.path().join(name)).unwrap()).unwrap();
+  }
+  archive.finish().unwrap();
+  // one latin-1 and one cp1251 source: a tie goes to latin-1
+  assert_eq!(detect_archive(&archive_path).unwrap(), SourceEncoding::Latin1);
+
+  let mut transcoded = transcode_dir(dir.path()).unwrap();
+  transcoded.sort();
+  assert_eq!(
+    transcoded,
+    vec![
+      (dir.path().join("main.tex"), SourceEncoding::Latin1),
+      (dir.path().join("ru.tex"), SourceEncoding::Cp1251),
+    ]
+  );
+  assert_eq!(fs::read_to_string(dir.path().join("ru.tex")).unwrap(), "Привет, мир");
+  assert_eq!(fs::read(dir.path().join("figure.png")).unwrap(), b"\x89PNG\xFF\xFE");
+}
@@ -0,0 +1,49 @@
+use std::net::TcpListener;
+use std::time::Instant;
+
+use pericortex::config::WorkerConfig;
+use pericortex::endpoint::{split_address, tcp_address, Endpoint};
+use pericortex::worker::{EchoWorker, Worker};
+
+#[test]
+fn addresses_round_trip() {
+  for (host, port) in [("127.0.0.1", 51695), ("2001:db8::1", 51696), ("cortex.example.org", 80)] {
+    let address = tcp_address(host, port);
+    assert_eq!(split_address(&address), Some((host.to_string(), port)));
+  }
+  assert_eq!(tcp_address("::1", 5), "tcp://[::1]:5");
+  assert_eq!(split_address("tcp://:5"), None);
+}
+
+#[test]
+fn endpoints_are_validated() {
+  let endpoint: Endpoint = "tcp://[2001:db8::1]:51695".parse().unwrap();
+  assert_eq!(endpoint, Endpoint::tcp("2001:db8::1", 51695));
+  assert_eq!(endpoint.to_string(), "tcp://[2001:db8::1]:51695");
+  assert_eq!(endpoint.host_port(), "[2001:db8::1]:51695");
+  let inproc: Endpoint = "inproc://normalize/tasks".parse().unwrap();
+  assert!(inproc.is_inproc());
+  assert_eq!(inproc.to_string(), "inproc://normalize/tasks");
+  for address in ["127.0.0.1:51695", "ipc://cortex", "tcp://127.0.0.1", "tcp://:51695", "tcp://host:0", "tcp://host:99999", "inproc://"] {
+    assert!(address.parse::<Endpoint>().is_err(), "{}", address);
+  }
+
+  let config: WorkerConfig = toml::from_str("sink = \"tcp://cortex.example.org:51696\"").unwrap();
+  assert_eq!(config.sink, Some(Endpoint::tcp("cortex.example.org", 51696)));
+  let error = toml::from_str::<WorkerConfig>("source = \"tcp://127.0.0.1:516950\"").unwrap_err();
+  assert!(error.to_string().contains("bad port"), "{}", error);
+}
+
+#[test]
+fn unreachable_dispatchers_fail_the_worker_before_it_starts() {
+  // nothing listens on a port released right away
+  let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+  let mut worker = EchoWorker {
+    source: Endpoint::tcp("127.0.0.1", port),
+    ..EchoWorker::default()
+  };
+  let started = Instant::now();
+  let error = worker.start(Some(1)).unwrap_err().to_string();
+  assert!(error.starts_with(&format!("dispatcher source tcp://127.0.0.1:{} is unreachable", port)), "{}", error);
+  assert!(started.elapsed().as_secs() < 5);
+}
@@ -1,6 +1,7 @@
 #![cfg(feature = "engrafo")]
 use std::io::Read;
 use std::path::Path;
+use std::process::Command;
 
 use pericortex::worker::{EngrafoWorker, Worker};
 
@@ -9,10 +10,106 @@ fn unit_engrafo_test() {
   let worker = EngrafoWorker::default();
   // test we can convert a test doc
   let test_input_path = Path::new("tests/resources/1508.01222.zip");
-  let converted = worker.convert(&test_input_path);
+  let converted = worker.convert(test_input_path);
   assert!(converted.is_ok());
   let mut zip_file = converted.unwrap();
   let mut contents = vec![];
   assert!(zip_file.read_to_end(&mut contents).is_ok());
   assert!(contents.len() > 1_000_000); // make sure we have a reasonably sized ZIP, as a basic sanity check
 }
+
+#[test]
+fn container_network_options() {
+  let worker = EngrafoWorker {
+    network_mode: Some("host".to_string()),
+    dns: vec!["10.0.0.2".to_string()],
+    env: vec!["HTTPS_PROXY".to_string(), "NO_PROXY=localhost".to_string()],
+    ..EngrafoWorker::default()
+  };
+  assert_eq!(
+    worker.container_options(),
+    vec!["--network", "host", "--dns", "10.0.0.2", "-e", "HTTPS_PROXY", "-e", "NO_PROXY=localhost"]
+  );
+  assert!(EngrafoWorker::default().container_options().is_empty());
+}
+
+#[test]
+fn pinned_images_are_referenced_by_digest() {
+  let digest = format!("sha256:{}", "ab".repeat(32));
+  let worker = EngrafoWorker {
+    image_digest: Some(digest.clone()),
+    ..EngrafoWorker::default()
+  };
+  assert_eq!(worker.image(), format!("arxivvanity/engrafo@{}", digest));
+  assert_eq!(EngrafoWorker::default().image(), "arxivvanity/engrafo:2.0.0");
+  let malformed = EngrafoWorker {
+    image_digest: Some("latest".to_string()),
+    ..EngrafoWorker::default()
+  };
+  assert!(malformed.warmup().is_err());
+}
+
+#[test]
+fn reused_containers_convert_consecutive_tasks() {
+  let worker = EngrafoWorker {
+    reuse_containers: Some(2),
+    ..EngrafoWorker::default()
+  };
+  let test_input_path = Path::new("tests/resources/1508.01222.zip");
+  for _ in 0..3 {
+    assert!(worker.convert(test_input_path).is_ok());
+  }
+}
+
+#[test]
+fn containers_left_behind_are_removed_at_warmup() {
+  // a container labelled as started by a process which is gone
+  let mut exited = Command::new("true").spawn().unwrap();
+  exited.wait().unwrap();
+  let host = hostname::get().unwrap().to_string_lossy().into_owned();
+  let pid_label = format!("pericortex.pid={}", exited.id());
+  let started = Command::new("docker")
+    .args(["run", "-d", "--label", &format!("pericortex.host={}", host), "--label", &pid_label])
+    .args(["--entrypoint", "sleep", &EngrafoWorker::default().image(), "infinity"])
+    .output()
+    .unwrap();
+  assert!(started.status.success());
+
+  let worker = EngrafoWorker {
+    reuse_containers: Some(2),
+    ..EngrafoWorker::default()
+  };
+  assert!(worker.warmup().is_ok());
+  let left = Command::new("docker")
+    .args(["ps", "--all", "--quiet", "--filter", &format!("label={}", pid_label)])
+    .output()
+    .unwrap();
+  assert!(left.stdout.is_empty());
+}
+
+#[test]
+fn pool_threads_take_turns_on_the_gpus() {
+  let worker = EngrafoWorker {
+    gpus: Some("0,1".parse().unwrap()),
+    ..EngrafoWorker::default()
+  };
+  let mut devices: Vec<Vec<String>> = (0..2)
+    .map(|_| {
+      let worker = worker.clone();
+      std::thread::spawn(move || (worker.gpu_options(), worker.gpu_options()))
+    })
+    .map(|thread| {
+      let (first, again) = thread.join().unwrap();
+      assert_eq!(first, again);
+      first
+    })
+    .collect();
+  devices.sort();
+  assert_eq!(devices, vec![vec!["--gpus", "device=0"], vec!["--gpus", "device=1"]]);
+  let all = EngrafoWorker {
+    gpus: Some("all".parse().unwrap()),
+    ..EngrafoWorker::default()
+  };
+  assert_eq!(all.gpu_options(), vec!["--gpus", "all"]);
+  assert!("0,,1".parse::<pericortex::worker::GpuAssignment>().is_err());
+}
@@ -0,0 +1,57 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use tempdir::TempDir;
+
+use pericortex::adaptor;
+use pericortex::filters::{self, AnonymizePaths, FilterContext, OutputFilter, StripLargeFiles};
+use pericortex::worker::{EchoWorker, Worker};
+
+struct Stamp;
+
+impl OutputFilter for Stamp {
+  fn name(&self) -> &str { "stamp" }
+
+  fn apply(&self, dir: &Path, context: &FilterContext) -> Result<(), Box<dyn Error>> {
+    fs::write(dir.join("stamp.txt"), format!("{} by {}", context.taskid, context.service))?;
+    Ok(())
+  }
+}
+
+#[test]
+fn filters_transform_the_output_before_archiving() {
+  let output = TempDir::new("filters_output").unwrap();
+  let scratch = env::temp_dir().join("cortex_task/main.tex");
+  fs::write(output.path().join("cortex.log"), format!("Warning:missing_file:{} not found\n", scratch.display())).unwrap();
+  fs::write(output.path().join("main.pdf"), vec![0u8; 2048]).unwrap();
+  fs::write(output.path().join("main.html"), "<p>ok</p>").unwrap();
+  let result = adaptor::archive_tmpdir_to_zip(output).unwrap();
+
+  let filters: Vec<Arc<dyn OutputFilter>> =
+    vec![Arc::new(AnonymizePaths::default()), Arc::new(StripLargeFiles { max_bytes: 1024 })];
+  let mut filtered = filters::apply(&filters, result, &FilterContext::default()).unwrap();
+  let destination = TempDir::new("filters_result").unwrap();
+  let path = destination.path().join("result.zip");
+  io::copy(&mut filtered, &mut fs::File::create(&path).unwrap()).unwrap();
+
+  let files: Vec<String> = adaptor::archive_manifest(&path).unwrap().into_keys().collect();
+  assert_eq!(files, vec!["cortex.log", "main.html"]);
+  let log = String::from_utf8(adaptor::read_archive_entry(&path, "cortex.log").unwrap().unwrap()).unwrap();
+  assert!(log.starts_with("Warning:missing_file:$TMPDIR/cortex_task/main.tex not found\n"), "{}", log);
+  assert!(log.contains("Info:strip_large_files:stripped main.pdf (2048 bytes)"), "{}", log);
+}
+
+#[test]
+fn registered_filters_apply_to_every_worker() {
+  filters::register(Arc::new(Stamp));
+  let worker = EchoWorker::default();
+  let destination = TempDir::new("filters_local").unwrap();
+  let path = destination.path().join("result.zip");
+  worker.convert_local(Path::new("tests/resources/1508.01222.zip"), &path).unwrap();
+  let stamp = adaptor::read_archive_entry(&path, "stamp.txt").unwrap().unwrap();
+  assert_eq!(stamp, b"1508.01222.zip by echo_service");
+}
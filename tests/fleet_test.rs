@@ -0,0 +1,55 @@
+#![cfg(feature = "tools")]
+use std::net::TcpListener;
+
+use pericortex::fleet::{self, Inventory, RemoteAction};
+use pericortex::health;
+use pericortex::state;
+use pericortex::worker::EchoWorker;
+
+const INVENTORY: &str = r#"
+[[host]]
+name = "gpu-01"
+address = "cortex@gpu-01.example.org"
+
+[[host.worker]]
+service = "engrafo"
+state = "STATE"
+command = "pericortex run engrafo --daemon --pid-file engrafo.pid --health 0.0.0.0:8080"
+pid_file = "engrafo.pid"
+
+[[host]]
+name = "gpu-02"
+
+[[host.worker]]
+service = "engrafo"
+state = "127.0.0.1:9"
+"#;
+
+#[test]
+fn fleet_states_are_polled_and_summarized() {
+  let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+  health::serve(&address, EchoWorker::default()).unwrap();
+  state::record(false);
+  let inventory: Inventory = toml::from_str(&INVENTORY.replace("STATE", &address)).unwrap();
+  assert_eq!(inventory.hosts[0].destination(), "cortex@gpu-01.example.org");
+  assert_eq!(inventory.hosts[1].destination(), "gpu-02");
+  assert_eq!(inventory.select(Some("gpu-02"), None).len(), 1);
+  assert!(inventory.select(None, Some("latexml")).is_empty());
+
+  let (_, first) = inventory.select(Some("gpu-01"), None)[0];
+  assert_eq!(
+    fleet::remote_command(first, &RemoteAction::Signal("USR2".to_string())).unwrap(),
+    "kill -USR2 \"$(cat 'engrafo.pid')\""
+  );
+  assert!(fleet::remote_command(first, &RemoteAction::Signal("TERM; reboot".to_string())).is_err());
+  let (_, second) = inventory.select(Some("gpu-02"), None)[0];
+  assert!(fleet::remote_command(second, &RemoteAction::Start).is_err());
+
+  let status = fleet::status(&inventory.select(None, None));
+  let polled = status.workers[0].state.as_ref().unwrap();
+  assert!(polled.completed >= 1);
+  assert!(status.workers[1].state.is_none() && status.workers[1].error.is_some());
+  let summary = status.to_string();
+  assert!(summary.contains("gpu-02               engrafo          unreachable"));
+  assert!(summary.contains("1 of 2 workers reachable"));
+}
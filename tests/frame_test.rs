@@ -0,0 +1,85 @@
+use pericortex::worker::FrameChunker;
+use std::io::{self, Read};
+
+/// A reader returning at most `step` bytes per call, and `Interrupted` every other call
+struct ShortReader {
+  data: Vec<u8>,
+  position: usize,
+  step: usize,
+  calls: usize,
+}
+
+impl ShortReader {
+  fn new(data: Vec<u8>, step: usize) -> Self {
+    ShortReader {
+      data,
+      position: 0,
+      step,
+      calls: 0,
+    }
+  }
+}
+
+impl Read for ShortReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.calls += 1;
+    if self.calls.is_multiple_of(2) {
+      return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+    }
+    let size = self.step.min(buf.len()).min(self.data.len() - self.position);
+    buf[..size].copy_from_slice(&self.data[self.position..self.position + size]);
+    self.position += size;
+    Ok(size)
+  }
+}
+
+fn chunk<R: Read>(reader: R, message_size: usize) -> Vec<(Vec<u8>, bool)> {
+  let mut chunker = FrameChunker::new(reader, message_size);
+  let mut frames = Vec::new();
+  while let Some((frame, last)) = chunker.next_frame().unwrap() {
+    frames.push((frame.to_vec(), last));
+  }
+  frames
+}
+
+#[test]
+fn short_reads_do_not_truncate() {
+  let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+  let frames = chunk(ShortReader::new(data.clone(), 7), 1000);
+
+  assert_eq!(frames.len(), 10);
+  for (frame, last) in &frames[..9] {
+    assert_eq!(frame.len(), 1000);
+    assert!(!last);
+  }
+  assert!(frames[9].1);
+  let joined: Vec<u8> = frames.into_iter().flat_map(|(frame, _)| frame).collect();
+  assert_eq!(joined, data);
+}
+
+#[test]
+fn exact_multiple_has_no_trailing_empty_frame() {
+  let frames = chunk(ShortReader::new(vec![1; 300], 64), 100);
+  let sizes: Vec<(usize, bool)> = frames.iter().map(|(f, last)| (f.len(), *last)).collect();
+  assert_eq!(sizes, vec![(100, false), (100, false), (100, true)]);
+}
+
+#[test]
+fn empty_input_is_a_single_empty_frame() {
+  let frames = chunk(ShortReader::new(Vec::new(), 3), 100);
+  assert_eq!(frames, vec![(Vec::new(), true)]);
+}
+
+#[test]
+fn a_single_full_frame_is_the_last_one() {
+  let frames = chunk(ShortReader::new(vec![2; 100], 30), 100);
+  assert_eq!(frames, vec![(vec![2; 100], true)]);
+}
+
+#[test]
+fn one_byte_past_a_frame_starts_the_next_one() {
+  let mut data = vec![3; 100];
+  data.push(4);
+  let frames = chunk(ShortReader::new(data, 100), 100);
+  assert_eq!(frames, vec![(vec![3; 100], false), (vec![4], true)]);
+}
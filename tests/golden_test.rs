@@ -0,0 +1,85 @@
+#![cfg(feature = "tools")]
+use std::fs;
+use std::path::Path;
+
+use pericortex::golden::{self, GoldenStatus, GoldenSummary};
+use pericortex::worker::EchoWorker;
+#[cfg(feature = "engrafo")]
+use pericortex::worker::EngrafoWorker;
+#[cfg(feature = "latexml")]
+use pericortex::worker::TexToHtmlWorker;
+use tempdir::TempDir;
+
+#[test]
+fn echo_matches_golden_summaries() {
+  let outcomes =
+    golden::check(&EchoWorker::default(), Path::new("tests/resources"), Path::new("tests/golden"), false)
+      .unwrap();
+  assert_eq!(outcomes.len(), 1);
+  assert_eq!(outcomes[0].status, GoldenStatus::Matches);
+}
+
+#[test]
+fn deviations_are_reported_until_updated() {
+  let golden_dir = TempDir::new("golden_test").unwrap();
+  let fixtures = Path::new("tests/resources");
+  let worker = EchoWorker::default();
+  let check = || golden::check(&worker, fixtures, golden_dir.path(), false).unwrap();
+  // a fixture without a golden summary fails until one is recorded
+  let missing = check();
+  assert_eq!(missing[0].status, GoldenStatus::Missing);
+  assert!(missing[0].is_regression());
+
+  let update = golden::check(&worker, fixtures, golden_dir.path(), true).unwrap();
+  assert_eq!(update[0].status, GoldenStatus::Updated);
+  assert_eq!(check()[0].status, GoldenStatus::Matches);
+
+  // pretend an earlier release produced an HTML page and a warning
+  let path = golden::golden_path(golden_dir.path(), "echo_service", Path::new("1508.01222.zip"));
+  let mut summary: GoldenSummary = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+  summary.files.push("ArXiv.html".to_string());
+  summary.html.insert("ArXiv.html".to_string(), vec!["<p>".to_string()]);
+  summary.severities.insert(pericortex::logcodes::Severity::Warning, 1);
+  fs::write(&path, serde_json::to_string(&summary).unwrap()).unwrap();
+
+  let outcomes = check();
+  assert!(outcomes[0].is_regression());
+  assert_eq!(
+    outcomes[0].status,
+    GoldenStatus::Changed(vec![
+      "removed: ArXiv.html".to_string(),
+      "cortex.log Warning: 1 -> 0".to_string()
+    ])
+  );
+}
+
+#[test]
+#[cfg(feature = "engrafo")]
+fn engrafo_matches_golden_summaries() {
+  let outcomes = golden::check(
+    &EngrafoWorker::default(),
+    Path::new("tests/resources"),
+    Path::new("tests/golden"),
+    false,
+  )
+  .unwrap();
+  for outcome in outcomes {
+    assert!(!outcome.is_regression(), "{}: {:?}", outcome.fixture.display(), outcome.status);
+  }
+}
+
+#[test]
+#[cfg(feature = "latexml")]
+#[ignore = "needs latexmlc on the PATH"]
+fn latexml_matches_golden_summaries() {
+  let outcomes = golden::check(
+    &TexToHtmlWorker::default(),
+    Path::new("tests/resources"),
+    Path::new("tests/golden"),
+    false,
+  )
+  .unwrap();
+  for outcome in outcomes {
+    assert!(!outcome.is_regression(), "{}: {:?}", outcome.fixture.display(), outcome.status);
+  }
+}
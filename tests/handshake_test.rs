@@ -0,0 +1,61 @@
+mod common;
+
+use std::thread;
+
+use pericortex::dispatcher::Sink;
+use pericortex::protocol::{self, Handshake};
+use pericortex::worker::Worker;
+
+/// Run a single task at protocol `version`, returning the task request and the result
+fn exchange(version: u32) -> (Vec<Vec<u8>>, pericortex::dispatcher::TaskResult) {
+  protocol::set_version(version).unwrap();
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"1", b"payload"], 0).unwrap();
+    request
+  });
+  let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+  let mut worker = common::echo_worker(&source, &sink_address);
+  worker.start(Some(1)).unwrap();
+  (vent_thread.join().unwrap(), sink_thread.join().unwrap())
+}
+
+fn handshake_of(request: &[Vec<u8>]) -> Option<Handshake> {
+  request.iter().find_map(|frame| {
+    let json = frame.strip_prefix(protocol::HANDSHAKE_PREFIX.as_bytes())?;
+    Some(serde_json::from_slice(json).unwrap())
+  })
+}
+
+// a single test, as the protocol version is shared by the whole process
+#[test]
+fn handshakes_announce_the_negotiated_version() {
+  // a dispatcher speaking the handshake version learns the versions of the worker
+  let (request, result) = exchange(protocol::HANDSHAKE_VERSION);
+  assert_eq!(request[1], b"echo_service");
+  let handshake = handshake_of(&request).expect("a handshake frame");
+  assert_eq!(handshake.protocol, protocol::HANDSHAKE_VERSION);
+  assert_eq!(handshake.pericortex, env!("CARGO_PKG_VERSION"));
+  assert_eq!(handshake.worker, common::echo_worker("tcp://127.0.0.1:1", "tcp://127.0.0.1:2").get_version());
+  assert_eq!(handshake.tool.as_deref(), Some(concat!("pericortex ", env!("CARGO_PKG_VERSION"))));
+  assert!(result.status.is_none());
+
+  // the latest version announces itself, and results carry their status
+  let (request, result) = exchange(protocol::LATEST_VERSION);
+  assert_eq!(handshake_of(&request).unwrap().protocol, protocol::LATEST_VERSION);
+  assert!(result.status.is_some() && result.stats.is_some());
+
+  // versions this release doesn't speak are refused, keeping the negotiated one
+  assert!(protocol::set_version(protocol::LATEST_VERSION + 1).is_err());
+  assert!(protocol::set_version(0).is_err());
+  assert_eq!(protocol::version(), protocol::LATEST_VERSION);
+
+  // falling back to the base version for an older dispatcher drops the extension frames
+  let (request, result) = exchange(protocol::BASE_VERSION);
+  assert_eq!(request.len(), 2);
+  assert!(handshake_of(&request).is_none());
+  assert!(result.status.is_none() && result.stats.is_none());
+  assert_eq!(result.payload, b"payload");
+}
@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use pericortex::endpoint::Endpoint;
+use pericortex::health;
+use pericortex::worker::{EchoWorker, Worker};
+
+/// An echo worker whose conversion backend is unavailable
+#[derive(Clone, Debug)]
+struct UnreadyWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for UnreadyWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> { self.echo.convert(path) }
+  fn readiness_probe(&self) -> Result<(), Box<dyn Error>> { Err(From::from("latexmlc is not installed")) }
+}
+
+/// Serve the probe endpoints of `worker` on an ephemeral port, returning its address
+fn serve<W: Worker + 'static>(worker: W) -> String {
+  let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+  health::serve(&address, worker).unwrap();
+  address
+}
+
+/// The response to a GET of `path` from the server on `address`
+fn get(address: &str, path: &str) -> String {
+  let mut stream = TcpStream::connect(address).unwrap();
+  write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+  let mut response = String::new();
+  stream.read_to_string(&mut response).unwrap();
+  response
+}
+
+/// A source endpoint accepting connections, for as long as the listener is held
+fn reachable_source() -> (TcpListener, Endpoint) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let port = listener.local_addr().unwrap().port();
+  (listener, Endpoint::tcp("127.0.0.1", port))
+}
+
+// a single test, as the threads' liveness is shared by the whole process
+#[test]
+fn liveness_follows_the_threads() {
+  let address = serve(EchoWorker::default());
+  let converter = health::register("health_test:echo:1");
+  assert!(get(&address, "/healthz").starts_with("HTTP/1.1 200 OK\r\n"));
+
+  drop(converter);
+  let response = get(&address, "/healthz");
+  assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+  assert!(response.ends_with("dead threads: health_test:echo:1"));
+  assert!(get(&address, "/metrics").starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+#[test]
+fn readiness_needs_the_dispatcher_and_the_backend() {
+  let (_listener, source) = reachable_source();
+  let ready = EchoWorker {
+    source: source.clone(),
+    ..EchoWorker::default()
+  };
+  let response = get(&serve(ready.clone()), "/readyz");
+  assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+  assert!(response.ends_with("\r\n\r\nready"));
+
+  let response = get(&serve(UnreadyWorker { echo: ready }), "/readyz");
+  assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+  assert!(response.ends_with("probe failed: latexmlc is not installed"));
+
+  // nothing listens on a port which was just released
+  let (listener, unreachable) = reachable_source();
+  drop(listener);
+  let response = get(
+    &serve(EchoWorker {
+      source: unreachable,
+      ..EchoWorker::default()
+    }),
+    "/readyz",
+  );
+  assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+  assert!(response.contains("dispatcher unreachable: "));
+}
+
+#[test]
+fn slow_clients_do_not_hold_back_probes() {
+  let (_listener, source) = reachable_source();
+  let address = serve(EchoWorker {
+    source,
+    ..EchoWorker::default()
+  });
+  // connected, but never sending its request
+  let _stalled = TcpStream::connect(&address).unwrap();
+  let probing = Instant::now();
+  assert!(get(&address, "/readyz").starts_with("HTTP/1.1 200 OK\r\n"));
+  assert!(probing.elapsed() < Duration::new(2, 0));
+}
+
+#[test]
+fn a_flood_of_clients_is_answered_by_a_few_threads() {
+  let (_listener, source) = reachable_source();
+  let address = serve(EchoWorker {
+    source,
+    ..EchoWorker::default()
+  });
+  let threads = || std::fs::read_dir("/proc/self/task").unwrap().count();
+  let before = threads();
+  let stalled: Vec<TcpStream> = (0..100).map(|_| TcpStream::connect(&address).unwrap()).collect();
+  std::thread::sleep(Duration::from_millis(200));
+  // with room for the responders of the tests running alongside
+  assert!(threads() < before + 40, "{} threads for 100 clients", threads() - before);
+
+  // the clients hanging up free the responders right away
+  drop(stalled);
+  let probing = Instant::now();
+  assert!(get(&address, "/readyz").starts_with("HTTP/1.1 200 OK\r\n"));
+  assert!(probing.elapsed() < Duration::new(2, 0));
+}
@@ -0,0 +1,22 @@
+use pericortex::identity::Identity;
+
+#[test]
+fn identities_carry_prefix_suffix_and_tag() {
+  let mut identity = Identity {
+    prefix: None,
+    hostname: "worker7".to_string(),
+    service: "engrafo".to_string(),
+    slot: 3,
+    pool_size: 16,
+    suffix: None,
+    uuid: None,
+  };
+  assert_eq!(identity.to_string(), "worker7:engrafo:03");
+  identity.pool_size = 1;
+  identity.slot = 1;
+  assert_eq!(identity.to_string(), "worker7:engrafo:1");
+  identity.prefix = Some("i-0abc".to_string());
+  identity.suffix = Some("eu-west".to_string());
+  identity.uuid = Some("5f2c9e01".to_string());
+  assert_eq!(identity.to_string(), "i-0abc:worker7:engrafo:1:eu-west:5f2c9e01");
+}
@@ -0,0 +1,67 @@
+#![cfg(feature = "tools")]
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tempdir::TempDir;
+
+use pericortex::adaptor;
+use pericortex::import::{self, ImportIndex, SourceKind, INDEX_FILE};
+
+fn tarball(path: &Path, files: &[(&str, &str)]) {
+  let mut builder = tar::Builder::new(GzEncoder::new(File::create(path).unwrap(), Compression::default()));
+  for (name, contents) in files {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+  }
+  builder.into_inner().unwrap().finish().unwrap();
+}
+
+#[test]
+fn raw_sources_become_corpus_entries() {
+  let raw = TempDir::new("import_raw").unwrap();
+  let corpus = TempDir::new("import_corpus").unwrap();
+  tarball(
+    &raw.path().join("1501.00001.tar.gz"),
+    &[("main.tex", "\\input{intro}"), ("sections/intro.tex", "Hello")],
+  );
+  let mut gzip = GzEncoder::new(File::create(raw.path().join("1501.00002.gz")).unwrap(), Compression::default());
+  gzip.write_all(b"\\documentclass{article}").unwrap();
+  gzip.finish().unwrap();
+  fs::write(raw.path().join("plain.tex"), "\\documentclass{article}").unwrap();
+  fs::create_dir_all(raw.path().join("pages/site/images")).unwrap();
+  fs::write(raw.path().join("pages/site/index.html"), "<html></html>").unwrap();
+  fs::write(raw.path().join("pages/site/images/logo.png"), [0u8; 4]).unwrap();
+  fs::write(raw.path().join("notes.txt"), "not a document").unwrap();
+  tarball(&raw.path().join("empty.tar.gz"), &[]);
+
+  let index = import::import(raw.path(), corpus.path()).unwrap();
+  let kinds: Vec<(&str, SourceKind, usize)> =
+    index.entries.iter().map(|entry| (entry.name.as_str(), entry.kind, entry.files)).collect();
+  assert_eq!(
+    kinds,
+    vec![
+      ("1501.00001", SourceKind::Tarball, 2),
+      ("1501.00002", SourceKind::Gzip, 1),
+      ("site", SourceKind::Bundle, 2),
+      ("plain", SourceKind::Tex, 1)
+    ]
+  );
+  assert_eq!(index.skipped.len(), 1);
+  assert_eq!(index.skipped[0].source, Path::new("empty.tar.gz"));
+
+  let entry = corpus.path().join("1501.00001/1501.00001.zip");
+  assert_eq!(
+    adaptor::read_archive_entry(&entry, "sections/intro.tex").unwrap(),
+    Some(b"Hello".to_vec())
+  );
+  assert!(adaptor::read_archive_entry(&corpus.path().join("1501.00002/1501.00002.zip"), "1501.00002.tex")
+    .unwrap()
+    .is_some());
+  assert_eq!(ImportIndex::load(&corpus.path().join(INDEX_FILE)).unwrap(), index);
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::Path;
+
+use tempdir::TempDir;
+
+use pericortex::config::WorkerConfig;
+use pericortex::endpoint::{self, Endpoint, MAX_IO_THREADS};
+use pericortex::worker::{EchoWorker, Worker};
+
+/// An echo worker with a configurable number of network threads
+#[derive(Clone, Debug)]
+struct NetworkedWorker {
+  echo: EchoWorker,
+  io_threads: usize,
+}
+
+impl Worker for NetworkedWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> { self.echo.convert(path) }
+  fn io_threads(&self) -> usize { self.io_threads }
+}
+
+#[test]
+fn network_contexts_get_the_configured_io_threads() {
+  for io_threads in [1, 3, MAX_IO_THREADS] {
+    let context = endpoint::network_context("tcp://127.0.0.1:51695", io_threads).unwrap();
+    assert_eq!(context.get_io_threads().unwrap(), io_threads as i32);
+  }
+  // inproc sockets only connect within the context shared by the process
+  let shared = endpoint::network_context("inproc://io_threads_test", 2).unwrap();
+  assert_eq!(shared.get_io_threads().unwrap(), endpoint::context("inproc://other").get_io_threads().unwrap());
+}
+
+#[test]
+fn invalid_io_threads_are_rejected() {
+  for io_threads in [0, MAX_IO_THREADS + 1] {
+    assert!(endpoint::validate_io_threads(io_threads).is_err());
+    assert!(endpoint::network_context("tcp://127.0.0.1:51695", io_threads).is_err());
+  }
+
+  let dir = TempDir::new("io_threads_test").unwrap();
+  let path = dir.path().join("worker.toml");
+  fs::write(&path, "io_threads = 0\n").unwrap();
+  let error = WorkerConfig::load(&path).unwrap_err();
+  assert!(error.to_string().contains("io_threads must be between 1 and"), "{}", error);
+  fs::write(&path, "io_threads = 2\n").unwrap();
+  assert_eq!(WorkerConfig::load(&path).unwrap().io_threads, Some(2));
+
+  // the worker refuses to start rather than connecting a pool without network threads
+  let (_ventilator, source) = common::dispatcher();
+  let (_sink, sink_address) = common::sink();
+  let mut worker = NetworkedWorker {
+    echo: common::echo_worker(&source, &sink_address),
+    io_threads: 0,
+  };
+  assert!(worker.start(Some(1)).unwrap_err().to_string().contains("io_threads"));
+}
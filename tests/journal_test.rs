@@ -0,0 +1,93 @@
+mod common;
+
+use pericortex::journal::{self, JournalEvent};
+use std::io::Cursor;
+use std::sync::Mutex;
+use std::time::Instant;
+use tempdir::TempDir;
+
+// the journal is shared by the whole process, one test journals at a time
+static JOURNALING: Mutex<()> = Mutex::new(());
+
+#[test]
+fn unfinished_tasks_are_in_progress_after_a_crash() {
+  let _journaling = JOURNALING.lock().unwrap();
+  let dir = TempDir::new("journal_test").unwrap();
+  let path = dir.path().join("run.journal");
+  journal::open(&path).unwrap();
+  assert!(!journal::started("1", "host:echo:1", 100, None));
+  assert!(!journal::started("2", "host:echo:2", 200, None));
+  journal::finished("1", "host:echo:1", 100, Instant::now(), Ok(150), None);
+  assert!(!journal::started("3", "host:echo:1", 300, None));
+  journal::finished("3", "host:echo:1", 300, Instant::now(), Err("timed out".to_string()), None);
+
+  // the worker crashed converting task 2, which a restarted worker is handed again
+  let entries = journal::read(&path).unwrap();
+  let unfinished: Vec<(&str, &str, usize)> = journal::in_progress(&entries)
+    .into_iter()
+    .map(|entry| (entry.taskid.as_str(), entry.identity.as_str(), entry.input_bytes))
+    .collect();
+  assert_eq!(unfinished, [("2", "host:echo:2", 200)]);
+  assert_eq!(entries[2].output_bytes, Some(150));
+  assert_eq!(entries[4].event, JournalEvent::Failed);
+  assert_eq!(entries[4].error.as_deref(), Some("timed out"));
+
+  journal::open(&path).unwrap();
+  assert!(journal::started("2", "host:echo:1", 200, None));
+  journal::finished("2", "host:echo:1", 200, Instant::now(), Ok(250), None);
+  let entries = journal::read(&path).unwrap();
+  // the crashed attempt stays on record, under the thread which crashed
+  let unfinished: Vec<&str> = journal::in_progress(&entries).iter().map(|entry| entry.identity.as_str()).collect();
+  assert_eq!(unfinished, ["host:echo:2"]);
+
+  assert!(journal::read(&dir.path().join("missing.journal")).is_err());
+}
+
+#[test]
+fn snapshots_pinpoint_the_changed_files() {
+  let _journaling = JOURNALING.lock().unwrap();
+  let dir = TempDir::new("journal_test").unwrap();
+  let mut journals = Vec::new();
+  for (run, html) in ["<p>one</p>", "<p>two</p>"].iter().enumerate() {
+    let path = dir.path().join(format!("run{}.journal", run));
+    journal::open(&path).unwrap();
+    journal::enable_snapshots();
+    let mut input = Cursor::new(common::zip_of(&[("paper.tex", "\\documentclass{article}")]));
+    let mut output = if run == 0 {
+      Cursor::new(common::zip_of(&[("cortex.log", "Info:ok"), ("paper.html", html), ("figure.svg", "<svg/>")]))
+    } else {
+      Cursor::new(common::zip_of(&[("cortex.log", "Info:ok"), ("paper.html", html), ("figure.png", "PNG")]))
+    };
+    let input_snapshot = journal::snapshot("42", &mut input).unwrap();
+    assert_eq!(input.position(), 0);
+    journal::started("42", "host:engrafo:1", 100, input_snapshot);
+    let output_snapshot = journal::snapshot("42", &mut output).unwrap();
+    journal::finished("42", "host:engrafo:1", 100, Instant::now(), Ok(200), output_snapshot);
+    journals.push(journal::read(&path).unwrap());
+  }
+
+  let entries = &journals[0];
+  assert_eq!(entries[0].event, JournalEvent::Started);
+  assert_eq!(entries[0].manifest.as_ref().unwrap()[0].path, "paper.tex");
+  assert_eq!(entries[0].manifest.as_ref().unwrap()[0].size, 23);
+  let output = entries[1].manifest.as_ref().unwrap();
+  let paths: Vec<&str> = output.iter().map(|entry| entry.path.as_str()).collect();
+  assert_eq!(paths, ["cortex.log", "figure.svg", "paper.html"]);
+
+  let before = journal::snapshots_by_task(&journals[0]);
+  let after = journal::snapshots_by_task(&journals[1]);
+  assert!(journal::manifest_changes(before["42"].input.unwrap(), after["42"].input.unwrap()).is_empty());
+  assert_eq!(
+    journal::manifest_changes(before["42"].output.unwrap(), after["42"].output.unwrap()),
+    [
+      "- figure.svg (6 bytes)",
+      "~ paper.html (10 -> 10 bytes)",
+      "+ figure.png (3 bytes)"
+    ]
+  );
+
+  // a payload which isn't an archive is snapshotted as a whole
+  let plain = journal::manifest(&mut Cursor::new(b"plain text".to_vec())).unwrap();
+  assert_eq!(plain.len(), 1);
+  assert_eq!((plain[0].path.as_str(), plain[0].size), ("", 10));
+}
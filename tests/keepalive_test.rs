@@ -0,0 +1,53 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use pericortex::dispatcher::{Sink, SinkMessage};
+use pericortex::progress;
+use pericortex::protocol;
+use pericortex::worker::{EchoWorker, Worker};
+use zmq::SNDMORE;
+
+#[test]
+fn long_result_sends_are_kept_alive() {
+  protocol::set_version(protocol::KEEPALIVE_VERSION).unwrap();
+  progress::set_interval(Duration::ZERO);
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator.send_multipart(["5", "payload"], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || {
+    let sink = Sink::from_socket(sink);
+    let mut keepalives = Vec::new();
+    let mut result = None;
+    // keepalives travel on a connection of their own, some may trail the result
+    while result.is_none() || sink.socket().poll(zmq::POLLIN, 500).unwrap() > 0 {
+      match sink.recv_message().unwrap() {
+        SinkMessage::Result(task_result) => result = Some(task_result),
+        SinkMessage::Keepalive { taskid, keepalive, .. } => {
+          assert_eq!(taskid, "5");
+          keepalives.push(keepalive.bytes_sent);
+        }
+      }
+    }
+    (result.unwrap(), keepalives)
+  });
+
+  let mut worker = EchoWorker {
+    message_size: 1000,
+    payload_size: Some(5000),
+    ..common::echo_worker(&source, &sink_address)
+  };
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let (result, keepalives) = sink_thread.join().unwrap();
+
+  assert_eq!(result.taskid, "5");
+  assert_eq!(result.payload.len(), 5000);
+  assert!(!keepalives.is_empty());
+  assert!(keepalives.iter().all(|bytes_sent| *bytes_sent > 0 && *bytes_sent <= 5000), "{:?}", keepalives);
+}
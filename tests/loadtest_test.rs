@@ -0,0 +1,71 @@
+#![cfg(feature = "tools")]
+mod common;
+
+use pericortex::loadtest::{self, LatencyDistribution, LoadTest};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use zmq::SNDMORE;
+
+#[test]
+fn latency_distributions_parse() {
+  assert_eq!(
+    "uniform:10:20".parse(),
+    Ok(LatencyDistribution::Uniform(Duration::from_millis(10), Duration::from_millis(20)))
+  );
+  assert_eq!("fixed:5".parse(), Ok(LatencyDistribution::Fixed(Duration::from_millis(5))));
+  assert!("exponential".parse::<LatencyDistribution>().is_err());
+}
+
+#[test]
+fn simulated_fleet_is_measured() {
+  let done = Arc::new(AtomicBool::new(false));
+  let serving = Arc::clone(&done);
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let dispatcher = thread::spawn(move || {
+    let mut dispatched = 0;
+    while !serving.load(Ordering::SeqCst) {
+      if ventilator.poll(zmq::POLLIN, 100).unwrap() == 0 {
+        continue;
+      }
+      let request = ventilator.recv_multipart(0).unwrap();
+      dispatched += 1;
+      ventilator.send(&request[0], SNDMORE).unwrap();
+      ventilator.send_multipart([dispatched.to_string().as_bytes(), b"payload"], 0).unwrap();
+    }
+    dispatched
+  });
+  let answered = Arc::clone(&done);
+  let sink = thread::spawn(move || {
+    let mut received = 0;
+    while !answered.load(Ordering::SeqCst) {
+      if sink.poll(zmq::POLLIN, 100).unwrap() > 0 {
+        assert_eq!(sink.recv_multipart(0).unwrap()[3], b"payload");
+        received += 1;
+      }
+    }
+    received
+  });
+
+  let report = loadtest::run(LoadTest {
+    source,
+    sink: sink_address,
+    service: "echo_service".to_string(),
+    workers: 8,
+    duration: Duration::from_secs(1),
+    latency: LatencyDistribution::Fixed(Duration::from_millis(10)),
+  })
+  .unwrap();
+  thread::sleep(Duration::from_millis(500));
+  done.store(true, Ordering::SeqCst);
+  let dispatched = dispatcher.join().unwrap();
+  let received = sink.join().unwrap();
+
+  assert_eq!(report.workers.len(), 8);
+  assert!(report.completed() > 8, "{}", report);
+  assert_eq!(report.completed(), received);
+  assert!(dispatched >= received);
+  assert!(report.fairness() > 0.5 && report.fairness() <= 1.0, "{}", report);
+}
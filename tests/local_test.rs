@@ -0,0 +1,41 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use pericortex::local::{BatchObserver, LocalRunner};
+use pericortex::report::Report;
+use pericortex::worker::EchoWorker;
+use tempdir::TempDir;
+
+/// Records what it observes
+#[derive(Default)]
+struct Tally {
+  planned: Mutex<usize>,
+  finished: Mutex<Vec<(String, bool)>>,
+}
+
+impl BatchObserver for Tally {
+  fn planned(&self, total: usize) { *self.planned.lock().unwrap() = total; }
+  fn finished(&self, _identity: &str, task: &str, error: Option<&str>) {
+    self.finished.lock().unwrap().push((task.to_string(), error.is_none()));
+  }
+}
+
+#[test]
+fn batch_runs_report_every_entry() {
+  let output = TempDir::new("local_test").unwrap();
+  let runner = LocalRunner {
+    worker: EchoWorker::default(),
+    input_dir: PathBuf::from("tests/resources"),
+    output_dir: output.path().to_path_buf(),
+  };
+  let tally = Arc::new(Tally::default());
+  let outcomes = runner.run_observed(tally.clone()).unwrap();
+  assert_eq!(*tally.planned.lock().unwrap(), outcomes.len());
+  assert_eq!(*tally.finished.lock().unwrap(), vec![("1508.01222.zip".to_string(), true)]);
+  assert!(output.path().join("summary.csv").exists());
+  let report: Report = serde_json::from_slice(&fs::read(output.path().join("report.json")).unwrap()).unwrap();
+  assert_eq!(report.summary.tasks, 1);
+  assert_eq!(report.tasks[0].task, "1508.01222.zip");
+  assert!(output.path().join("report.csv").exists());
+}
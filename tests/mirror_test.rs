@@ -0,0 +1,36 @@
+mod common;
+
+use std::thread;
+use std::time::Instant;
+
+use pericortex::dispatcher::Sink;
+use pericortex::mirror;
+use pericortex::protocol::Outcome;
+use pericortex::worker::Worker;
+
+#[test]
+fn mirrors_receive_a_copy_of_every_result() {
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let (mirror_socket, mirror_address) = common::sink();
+  // nothing listens there, the result is dropped for that mirror alone
+  mirror::enable(&[mirror_address, "tcp://127.0.0.1:1".to_string()]);
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"7", b"payload"], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+  let mirror_thread = thread::spawn(move || Sink::from_socket(mirror_socket).recv_result().unwrap());
+
+  let mut worker = common::echo_worker(&source, &sink_address);
+  let started = Instant::now();
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(started.elapsed() < mirror::MIRROR_SEND_TIMEOUT);
+  assert!(vent_thread.join().is_ok());
+  let result = sink_thread.join().unwrap();
+  let mirrored = mirror_thread.join().unwrap();
+  assert_eq!(result.outcome(), Outcome::Ok);
+  assert_eq!(mirrored.taskid, result.taskid);
+  assert_eq!(mirrored.identity, result.identity);
+  assert_eq!(mirrored.payload, b"payload");
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::thread;
+
+use tempdir::TempDir;
+
+use pericortex::mmap::{self, MappedFile};
+use pericortex::worker::{EchoWorker, Worker};
+
+const THRESHOLD: u64 = 1024 * 1024;
+const MESSAGE_SIZE: usize = 100_000;
+
+/// Send the result `worker` makes of `payload` as a single task, returning the payload frames
+/// the sink received
+fn send(payload: Vec<u8>, worker: impl FnOnce(EchoWorker) -> EchoWorker) -> Vec<Vec<u8>> {
+  mmap::set_threshold(THRESHOLD);
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"1", &payload[..]], 0).unwrap();
+  });
+  // the identity, service and taskid precede the payload frames
+  let sink_thread = thread::spawn(move || sink.recv_multipart(0).unwrap().split_off(3));
+  let mut worker = worker(EchoWorker {
+    message_size: MESSAGE_SIZE,
+    ..common::echo_worker(&source, &sink_address)
+  });
+  worker.start(Some(1)).unwrap();
+  vent_thread.join().unwrap();
+  sink_thread.join().unwrap()
+}
+
+fn frame_sizes(frames: &[Vec<u8>]) -> Vec<usize> { frames.iter().map(Vec::len).collect() }
+
+#[test]
+fn maps_hold_the_whole_file() {
+  let mut file = tempfile::tempfile().unwrap();
+  file.write_all(b"mapped contents").unwrap();
+  let mapped = MappedFile::map(&file).unwrap();
+  assert_eq!(&mapped[..], b"mapped contents");
+
+  // nothing to map
+  assert!(MappedFile::map(&tempfile::tempfile().unwrap()).is_err());
+}
+
+#[test]
+fn linked_files_are_not_mapped() {
+  // whoever else can open it could truncate it while it is mapped
+  let dir = TempDir::new("mmap_test").unwrap();
+  let path = dir.path().join("result.zip");
+  fs::write(&path, b"linked contents").unwrap();
+  assert!(MappedFile::map(&File::open(&path).unwrap()).is_err());
+
+  // once unlinked, only the descriptors held on it remain
+  let mut file = File::open(&path).unwrap();
+  fs::remove_file(&path).unwrap();
+  file.seek(SeekFrom::Start(0)).unwrap();
+  assert_eq!(&MappedFile::map(&file).unwrap()[..], b"linked contents");
+}
+
+#[test]
+fn results_above_the_threshold_are_sent_from_a_map() {
+  // generated into an anonymous temporary file, which is mapped
+  let size = 3 * THRESHOLD as usize + 1;
+  let frames = send(b"task".to_vec(), |echo| EchoWorker {
+    payload_size: Some(size),
+    ..echo
+  });
+  let mut expected = vec![MESSAGE_SIZE; size / MESSAGE_SIZE];
+  expected.push(size % MESSAGE_SIZE);
+  assert_eq!(frame_sizes(&frames), expected);
+  assert!(frames.concat().iter().all(|&byte| byte == b'e'));
+}
+
+#[test]
+fn results_below_the_threshold_are_sent_buffered() {
+  let size = THRESHOLD as usize - 1;
+  let frames = send(b"task".to_vec(), |echo| EchoWorker {
+    payload_size: Some(size),
+    ..echo
+  });
+  let mut expected = vec![MESSAGE_SIZE; size / MESSAGE_SIZE];
+  expected.push(size % MESSAGE_SIZE);
+  assert_eq!(frame_sizes(&frames), expected);
+  assert!(frames.concat().iter().all(|&byte| byte == b'e'));
+}
+
+#[test]
+fn unmappable_results_fall_back_to_buffered_reads() {
+  // echoed from the input file in the scratch directory, which is linked and so isn't mapped
+  let payload: Vec<u8> = (0..2 * THRESHOLD).map(|i| (i % 251) as u8).collect();
+  let frames = send(payload.clone(), |echo| echo);
+  assert_eq!(frames.len(), payload.len().div_ceil(MESSAGE_SIZE));
+  assert_eq!(frames.concat(), payload);
+}
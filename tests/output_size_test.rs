@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+
+use rand::Rng;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use pericortex::policy::{self, OutputSizeLimit, OversizedOutput};
+use pericortex::protocol::{Outcome, ResultStatus};
+
+/// A result with a small document and a large, incompressible figure
+fn oversized_result() -> File {
+  let mut figure = vec![0u8; 256 * 1024];
+  rand::thread_rng().fill(&mut figure[..]);
+  let mut result = tempfile::tempfile().unwrap();
+  {
+    let mut zip = ZipWriter::new(&mut result);
+    zip.start_file("index.html", FileOptions::default()).unwrap();
+    io::Write::write_all(&mut zip, b"<html><img src=\"figure.png\"/></html>").unwrap();
+    zip.start_file("figure.png", FileOptions::default()).unwrap();
+    io::Write::write_all(&mut zip, &figure).unwrap();
+    zip.start_file("cortex.log", FileOptions::default()).unwrap();
+    io::Write::write_all(&mut zip, b"Info:conversion:status engrafo completed\n").unwrap();
+    zip.finish().unwrap();
+  }
+  result.seek(SeekFrom::Start(0)).unwrap();
+  result
+}
+
+fn entries(result: &mut File) -> Vec<String> {
+  let names = ZipArchive::new(&mut *result).unwrap().file_names().map(String::from).collect();
+  result.seek(SeekFrom::Start(0)).unwrap();
+  names
+}
+
+#[test]
+fn oversized_outputs_follow_their_policy() {
+  let limit = |action| {
+    policy::set_output_size_limit(Some(OutputSizeLimit {
+      max_bytes: 64 * 1024,
+      action,
+    }))
+  };
+  // results within the limit are left alone
+  policy::set_output_size_limit(None);
+  let mut result = policy::limit_output_size(oversized_result(), "1", "test").unwrap();
+  assert_eq!(ResultStatus::of_result(&mut result).status, Outcome::Ok);
+
+  limit(OversizedOutput::Reject);
+  let mut rejected = policy::limit_output_size(oversized_result(), "1", "test").unwrap();
+  let status = ResultStatus::of_result(&mut rejected);
+  assert_eq!(status.status, Outcome::Fatal);
+  assert_eq!(status.reason.as_deref(), Some("output_size:exceeded"));
+  assert_eq!(entries(&mut rejected), ["cortex.log"]);
+
+  limit(OversizedOutput::TruncateAssets);
+  let mut truncated = policy::limit_output_size(oversized_result(), "1", "test").unwrap();
+  assert!(truncated.metadata().unwrap().len() <= 64 * 1024);
+  let status = ResultStatus::of_result(&mut truncated);
+  assert_eq!(status.status, Outcome::Warning);
+  assert_eq!(status.reason.as_deref(), Some("output_size:truncated"));
+  let mut names = entries(&mut truncated);
+  names.sort();
+  assert_eq!(names, ["cortex.log", "index.html"]);
+
+  limit(OversizedOutput::Divert);
+  let mut diverted = policy::limit_output_size(oversized_result(), "1", "test").unwrap();
+  assert!(policy::diverts(diverted.metadata().unwrap().len()));
+  assert_eq!(ResultStatus::of_result(&mut diverted).reason.as_deref(), Some("output_size:diverted"));
+  assert_eq!(entries(&mut diverted).len(), 3);
+  policy::set_output_size_limit(None);
+
+  assert_eq!("truncate-assets".parse(), Ok(OversizedOutput::TruncateAssets));
+  assert!("truncate".parse::<OversizedOutput>().is_err());
+}
@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use tempdir::TempDir;
+
+use pericortex::parallel;
+
+// a single test, as the pool is shared by the whole process
+#[test]
+fn files_are_converted_on_the_shared_pool() {
+  parallel::set_threads(2).unwrap();
+  assert!(parallel::set_threads(4).is_err());
+
+  let dir = TempDir::new("parallel_test").unwrap();
+  fs::create_dir(dir.path().join("figures")).unwrap();
+  fs::write(dir.path().join("paper.tex"), "\\documentclass{article}").unwrap();
+  fs::write(dir.path().join("figures/plot.svg"), "<svg/>").unwrap();
+  fs::write(dir.path().join("figures/broken.png"), "not a png").unwrap();
+
+  let outcomes = parallel::par_convert_files(dir.path(), |path: &Path| {
+    let thread = thread::current().name().unwrap_or_default().to_string();
+    assert!(thread.starts_with("pericortex-parallel-"), "converted on {}", thread);
+    if path.extension().is_some_and(|extension| extension == "png") {
+      return Err(format!("{} is corrupt", path.file_name().unwrap().to_string_lossy()));
+    }
+    Ok(fs::read(path).unwrap().len())
+  });
+
+  let outcomes: Vec<_> = outcomes
+    .into_iter()
+    .map(|(path, outcome)| (path.strip_prefix(dir.path()).unwrap().to_path_buf(), outcome))
+    .collect();
+  assert_eq!(
+    outcomes,
+    vec![
+      (Path::new("figures/broken.png").to_path_buf(), Err(String::from("broken.png is corrupt"))),
+      (Path::new("figures/plot.svg").to_path_buf(), Ok(6)),
+      (Path::new("paper.tex").to_path_buf(), Ok(23)),
+    ]
+  );
+}
@@ -0,0 +1,42 @@
+use std::fs;
+use std::io::{Cursor, Read};
+
+use tempdir::TempDir;
+use zip::ZipArchive;
+
+use pericortex::adaptor::{archive_tmpdir_to_zip, extract_zip_reader_to_tmpdir};
+
+/// A converter output with assets nested a few directories deep
+fn nested_output() -> TempDir {
+  let dir = TempDir::new("paths_test").unwrap();
+  let figures = dir.path().join("figures").join("chapter1");
+  fs::create_dir_all(&figures).unwrap();
+  fs::write(dir.path().join("index.html"), "<p>paper</p>").unwrap();
+  fs::write(figures.join("plot.svg"), "<svg/>").unwrap();
+  dir
+}
+
+#[test]
+fn archive_entry_names_use_forward_slashes() {
+  let archive = archive_tmpdir_to_zip(nested_output()).unwrap();
+  let archive = ZipArchive::new(archive).unwrap();
+  let mut names: Vec<&str> = archive.file_names().collect();
+  names.sort_unstable();
+  // ZIP entry names are '/'-separated whatever the host's path separator
+  assert_eq!(names, vec!["figures/chapter1/plot.svg", "index.html"]);
+}
+
+#[test]
+fn nested_entries_extract_to_joined_paths() {
+  let mut archive = archive_tmpdir_to_zip(nested_output()).unwrap();
+  let mut bytes = Vec::new();
+  archive.read_to_end(&mut bytes).unwrap();
+
+  let extracted = extract_zip_reader_to_tmpdir(Cursor::new(bytes), "paths_test").unwrap();
+  let plot = extracted.path().join("figures").join("chapter1").join("plot.svg");
+  assert_eq!(fs::read_to_string(plot).unwrap(), "<svg/>");
+  assert_eq!(
+    fs::read_to_string(extracted.path().join("index.html")).unwrap(),
+    "<p>paper</p>"
+  );
+}
@@ -0,0 +1,41 @@
+use pericortex::dryrun;
+use pericortex::protocol::PayloadFormat;
+use pericortex::worker::{CommandSpec, CommandWorker, Worker};
+use std::io::Write;
+use tempdir::TempDir;
+
+#[test]
+fn single_file_payloads_keep_their_extension() {
+  let pdf = PayloadFormat::SingleFile {
+    extension: "pdf".to_string(),
+  };
+  assert_eq!(PayloadFormat::Zip.file_name("7"), "7.zip");
+  assert_eq!(pdf.file_name("a/b"), "a%2Fb.pdf");
+
+  let spec: CommandSpec = toml::from_str(
+    r#"
+    service = "pdf_to_text"
+    program = "cp"
+    args = ["{input}", "{output}"]
+    input_extension = "pdf"
+    "#,
+  )
+  .unwrap();
+  let worker = CommandWorker::from(spec);
+  assert_eq!(worker.payload_format(), pdf);
+
+  let scratch = TempDir::new("payload_format_test").unwrap();
+  let mut payload = tempfile::spooled_tempfile(1024);
+  payload.write_all(b"%PDF-1.4").unwrap();
+  // a copied PDF is not a valid result, only the naming of the input matters here
+  let _ = worker.convert_payload(&mut payload, "7", &scratch);
+  let mut names: Vec<String> = std::fs::read_dir(scratch.path())
+    .unwrap()
+    .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+    .collect();
+  names.sort();
+  assert_eq!(names, ["7.pdf", "7.result.zip"]);
+
+  // a dry run doesn't expect an archive either
+  assert!(dryrun::rehearse(&worker, &mut payload).is_ok());
+}
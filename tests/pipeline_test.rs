@@ -0,0 +1,91 @@
+mod common;
+
+use pericortex::dispatcher::Sink;
+use pericortex::endpoint;
+use pericortex::pipeline::Handoff;
+use pericortex::stages::EarlyExit;
+use pericortex::worker::Worker;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn stages_hand_their_results_over_in_process() {
+  let (ventilator, ventilator_address) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let handoff = Handoff::bind("normalize-echo").unwrap();
+  assert_eq!(handoff.sink().to_string(), "inproc://normalize-echo/results");
+
+  let mut normalize = common::echo_worker(&ventilator_address, &handoff.sink().to_string());
+  normalize.service = String::from("normalize");
+  // every second task fails, and ends the pipeline
+  normalize.fail_every = Some(2);
+  normalize.throttle = Duration::ZERO;
+  let mut convert = common::echo_worker(&handoff.source().to_string(), &sink_address);
+  convert.service = String::from("convert");
+  let handoff = handoff.bypass(&sink_address.parse().unwrap(), EarlyExit::OnFatal).unwrap();
+  handoff.spawn();
+
+  let ventilator_thread = thread::spawn(move || {
+    for taskid in ["1", "2"] {
+      let request = ventilator.recv_multipart(0).unwrap();
+      assert_eq!(request[1], b"normalize");
+      ventilator.send_multipart([&request[0][..], taskid.as_bytes(), b"payload"], 0).unwrap();
+    }
+  });
+  let first_stage = thread::spawn(move || normalize.start(Some(2)).is_ok());
+  let second_stage = thread::spawn(move || convert.start(Some(1)).is_ok());
+
+  let sink = Sink::from_socket(sink);
+  let mut results = [sink.recv_result().unwrap(), sink.recv_result().unwrap()];
+  results.sort_by(|a, b| a.taskid.cmp(&b.taskid));
+  // the converted task went through both stages
+  assert_eq!((results[0].service.as_str(), results[0].payload.as_slice()), ("convert", &b"payload"[..]));
+  // the failed one was answered right after the first stage
+  assert_eq!(results[1].service, "normalize");
+  assert!(results[1].payload.is_empty());
+
+  assert!(ventilator_thread.join().is_ok());
+  assert!(first_stage.join().unwrap());
+  assert!(second_stage.join().unwrap());
+}
+
+#[test]
+fn a_slow_stage_holds_back_the_results_of_the_previous_one() {
+  let handoff = Handoff::bind("flood-trickle").unwrap();
+  let sink = handoff.sink().to_string();
+  let source = handoff.source().to_string();
+  handoff.spawn();
+  let upstream = endpoint::context(&sink).socket(zmq::PUSH).unwrap();
+  upstream.set_sndhwm(10).unwrap();
+  upstream.connect(&sink).unwrap();
+
+  // with nothing asked for downstream, the results back up into the upstream sink
+  let mut sent = 0;
+  let mut stalled = 0;
+  while sent < 5000 && stalled < 100 {
+    let frames = [b"upstream:1".to_vec(), b"flood".to_vec(), sent.to_string().into_bytes(), b"payload".to_vec()];
+    match upstream.send_multipart(frames, zmq::DONTWAIT) {
+      Ok(()) => {
+        sent += 1;
+        stalled = 0;
+      }
+      Err(zmq::Error::EAGAIN) => {
+        stalled += 1;
+        thread::sleep(Duration::from_millis(10));
+      }
+      Err(e) => panic!("failed to send a result: {}", e),
+    }
+  }
+  assert!(sent < 5000, "the handoff read all {} results", sent);
+
+  // and are handed over in order, one per request of the slow stage
+  let downstream = endpoint::context(&source).socket(zmq::DEALER).unwrap();
+  downstream.set_identity(b"trickle:1").unwrap();
+  downstream.connect(&source).unwrap();
+  for taskid in ["0", "1", "2"] {
+    downstream.send("trickle", 0).unwrap();
+    let task = downstream.recv_multipart(0).unwrap();
+    assert_eq!(task, [taskid.as_bytes(), b"payload"]);
+    thread::sleep(Duration::from_millis(100));
+  }
+}
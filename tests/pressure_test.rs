@@ -0,0 +1,49 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pericortex::config::WorkerConfig;
+use pericortex::pressure::{self, LoadLimits};
+use pericortex::worker::{EchoWorker, Worker};
+
+/// Wait up to a second for the controller to allow `expected` fetches
+fn allows(expected: usize) -> bool {
+  let deadline = Instant::now() + Duration::new(1, 0);
+  while Instant::now() < deadline {
+    if pressure::allowed_fetches() == Some(expected) {
+      return true;
+    }
+    thread::sleep(Duration::from_millis(5));
+  }
+  false
+}
+
+#[test]
+fn the_controller_follows_the_size_of_a_restarted_pool() {
+  let unsaturated = LoadLimits {
+    max_load_per_cpu: f64::MAX,
+    min_available_memory: 0,
+    poll_interval: Duration::from_millis(10),
+  };
+  pressure::control(unsaturated, 4);
+  assert!(allows(4));
+  // a reload shrinking the pool lowers the allowed fetches at once
+  pressure::set_pool_size(2);
+  assert_eq!(pressure::allowed_fetches(), Some(2));
+  // and growing it lets the controller allow more again
+  pressure::set_pool_size(6);
+  assert!(allows(6));
+  assert_eq!(pressure::pool_size(), 6);
+}
+
+#[test]
+fn the_sink_high_water_mark_bounds_the_largest_frames() {
+  pressure::limit_sink_queue(64 * 1024 * 1024);
+  let mut worker = EchoWorker::default();
+  let frames = pressure::sink_high_water_mark(&worker) as usize;
+  assert_eq!(frames, 64 * 1024 * 1024 / worker.message_size());
+  // results picking their frame size from their size may send frames up to the upper bound
+  let config: WorkerConfig = toml::from_str("message_size_bounds = \"1024:16777216\"").unwrap();
+  worker.configure(&config);
+  assert_eq!(pressure::largest_frame_size(&worker), 16 * 1024 * 1024);
+  assert_eq!(pressure::sink_high_water_mark(&worker), 4);
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use std::thread;
+
+use pericortex::dispatcher::Sink;
+use pericortex::protocol::{self, Outcome, ProbeReply, TaskMetadata};
+use pericortex::worker::Worker;
+
+#[test]
+fn probes_are_answered_without_a_conversion() {
+  protocol::set_version(protocol::PROBE_VERSION).unwrap();
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let probe = TaskMetadata {
+      probe: true,
+      ..TaskMetadata::default()
+    }
+    .frame();
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"probe-1", probe.as_bytes(), b""], 0).unwrap();
+    // the probe isn't counted towards the limit, a task still follows
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"2", b"payload"], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || {
+    let sink = Sink::from_socket(socket);
+    (sink.recv_result().unwrap(), sink.recv_result().unwrap())
+  });
+
+  let mut worker = common::echo_worker(&source, &sink_address);
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let (probe, task) = sink_thread.join().unwrap();
+  assert_eq!(probe.taskid, "probe-1");
+  assert_eq!(probe.outcome(), Outcome::Ok);
+  assert_eq!(probe.status.unwrap().reason.as_deref(), Some("probe"));
+  let reply: ProbeReply = serde_json::from_slice(&probe.payload).unwrap();
+  assert_eq!(reply.service, "echo_service");
+  assert_eq!(reply.converting, 0);
+  assert_eq!((task.taskid.as_str(), task.payload.as_slice()), ("2", &b"payload"[..]));
+}
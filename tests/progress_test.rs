@@ -0,0 +1,69 @@
+mod common;
+
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use pericortex::endpoint::Endpoint;
+use pericortex::progress::{self, TransferProgress};
+use pericortex::worker::{EchoWorker, Worker};
+use zmq::SNDMORE;
+
+/// Records the progress of its receives
+#[derive(Clone, Debug)]
+struct WatchedWorker {
+  echo: EchoWorker,
+  received: Arc<Mutex<Vec<usize>>>,
+}
+
+impl Worker for WatchedWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> { self.echo.convert(path) }
+  fn on_receive_progress(&self, taskid: &str, bytes_so_far: usize) {
+    assert_eq!(taskid, "7");
+    self.received.lock().unwrap().push(bytes_so_far);
+  }
+}
+
+#[test]
+fn receive_progress_is_reported_per_frame() {
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator
+      .send_multipart([b"7".to_vec(), vec![0; 1000], vec![1; 500], vec![2; 24]], 0)
+      .unwrap();
+  });
+  let sink_thread = thread::spawn(move || sink.recv_multipart(0).unwrap());
+
+  let received = Arc::new(Mutex::new(Vec::new()));
+  let mut worker = WatchedWorker {
+    echo: common::echo_worker(&source, &sink_address),
+    received: received.clone(),
+  };
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  assert_eq!(sink_thread.join().unwrap()[2], b"7");
+  assert_eq!(*received.lock().unwrap(), vec![1000, 1500, 1524]);
+}
+
+#[test]
+fn progress_is_logged_once_per_interval() {
+  progress::set_interval(Duration::from_millis(200));
+  let mut transfer = TransferProgress::new(String::from("test:sending"), "sent", "1");
+  assert!(!transfer.advance(100));
+  thread::sleep(Duration::from_millis(250));
+  assert!(transfer.advance(100));
+  assert!(!transfer.advance(100));
+  assert_eq!(transfer.bytes(), 300);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use pericortex::worker::Worker;
+use std::io::{Cursor, Read};
+use std::thread;
+use zip::ZipArchive;
+use zmq::SNDMORE;
+
+#[test]
+fn malformed_messages_are_reported_and_skipped() {
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    // 1. a taskid without payload frames, 2. a taskid which isn't UTF-8, 3. a proper task
+    let tasks: Vec<Vec<Vec<u8>>> = vec![
+      vec![b"1".to_vec()],
+      vec![vec![0xff, 0xfe], b"payload".to_vec()],
+      vec![b"3".to_vec(), b"payload".to_vec()],
+    ];
+    for frames in tasks {
+      let mut identity = zmq::Message::new();
+      let mut service = zmq::Message::new();
+      ventilator.recv(&mut identity, 0).unwrap();
+      ventilator.recv(&mut service, 0).unwrap();
+      assert_eq!(service.as_str(), Some("echo_service"));
+      ventilator.send(identity, SNDMORE).unwrap();
+      ventilator.send_multipart(frames, 0).unwrap();
+    }
+  });
+
+  let sink_thread = thread::spawn(move || {
+    let mut responses = Vec::new();
+    for _ in 0..3 {
+      let frames = sink.recv_multipart(0).unwrap();
+      responses.push((String::from_utf8_lossy(&frames[2]).into_owned(), frames[3..].concat()));
+    }
+    responses
+  });
+
+  let mut worker = common::echo_worker(&source, &sink_address);
+  // the malformed messages don't count towards the limit
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let responses = sink_thread.join().unwrap();
+
+  for (taskid, report) in &responses[..2] {
+    let mut archive = ZipArchive::new(Cursor::new(report)).unwrap();
+    let mut log = String::new();
+    archive.by_name("cortex.log").unwrap().read_to_string(&mut log).unwrap();
+    assert!(log.starts_with("Fatal:protocol:malformed_message"), "task {}: {}", taskid, log);
+  }
+  assert_eq!(responses[0].0, "1");
+  assert_eq!(responses[2], ("3".to_string(), b"payload".to_vec()));
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use std::io::{Cursor, Write};
+use std::thread;
+
+use pericortex::adaptor;
+use pericortex::config::WorkerConfig;
+use pericortex::provenance::{self, Provenance, PROVENANCE_FILE};
+use pericortex::worker::{CommandWorker, Worker};
+use zip::write::FileOptions;
+use zmq::SNDMORE;
+
+#[test]
+fn results_carry_their_provenance() {
+  let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+  zip.start_file("main.tex", FileOptions::default()).unwrap();
+  zip.write_all(b"\\documentclass{article}").unwrap();
+  let payload = zip.finish().unwrap().into_inner();
+  let input_sha256 = provenance::sha256(&mut Cursor::new(payload.clone())).unwrap();
+
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator.send_multipart([b"7".to_vec(), payload], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || sink.recv_multipart(0).unwrap());
+
+  let mut worker = CommandWorker {
+    service: "copy".to_string(),
+    source: source.parse().unwrap(),
+    sink: sink_address.parse().unwrap(),
+    program: "cp".to_string(),
+    args: vec!["{input}".to_string(), "{output}".to_string()],
+    ..CommandWorker::default()
+  };
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let frames = sink_thread.join().unwrap();
+
+  let result = Cursor::new(frames[3..].concat());
+  let record = adaptor::read_zip_entry(result, PROVENANCE_FILE).unwrap().unwrap();
+  let record: Provenance = serde_json::from_slice(&record).unwrap();
+  assert_eq!(record.taskid, "7");
+  assert_eq!(record.service, "copy");
+  assert_eq!(record.identity, String::from_utf8(frames[0].clone()).unwrap());
+  assert_eq!(record.pericortex, env!("CARGO_PKG_VERSION"));
+  assert_eq!(record.input_sha256, input_sha256);
+  assert_eq!(record.config_sha256, provenance::config_digest(&WorkerConfig::default()));
+  assert!(record.received <= record.completed);
+}
@@ -0,0 +1,23 @@
+use pericortex::quarantine;
+use tempdir::TempDir;
+
+#[test]
+fn interrupted_tasks_are_quarantined_across_restarts() {
+  let dir = TempDir::new("quarantine_test").unwrap();
+  let path = dir.path().join("worker.quarantine");
+
+  // two runs crash while converting "poison", each restart reloads the strikes
+  for _ in 0..2 {
+    quarantine::open(&path, 2).unwrap();
+    assert_eq!(quarantine::admit("poison"), Ok(()));
+  }
+  quarantine::open(&path, 2).unwrap();
+  assert_eq!(quarantine::admit("poison"), Err(2));
+
+  // completed conversions leave no strikes behind
+  assert_eq!(quarantine::admit("healthy"), Ok(()));
+  quarantine::release("healthy");
+  let strikes = quarantine::read(&path).unwrap();
+  assert_eq!(strikes.get("poison"), Some(&2));
+  assert!(!strikes.contains_key("healthy"));
+}
@@ -0,0 +1,41 @@
+use pericortex::quota::{self, Quota};
+use pericortex::shutdown;
+
+#[test]
+fn quotas_drain_the_worker() {
+  quota::enable(Quota {
+    max_tasks: Some(2),
+    ..Quota::default()
+  });
+  assert!(quota::admit_task());
+  assert!(quota::admit_task());
+  assert!(quota::exceeded().is_none());
+  assert!(!shutdown::requested());
+  assert!(!quota::admit_task());
+  assert_eq!(quota::exceeded().unwrap().to_string(), "quota reached: fetched 2 tasks");
+  assert!(shutdown::requested());
+
+  quota::enable(Quota {
+    max_failures: Some(2),
+    max_bytes: Some(10_000),
+    ..Quota::default()
+  });
+  assert!(quota::admit_task());
+  quota::record(true, 100, 0);
+  quota::record(false, 100, 1000);
+  assert!(quota::exceeded().is_none());
+  quota::record(true, 100, 0);
+  assert_eq!(quota::exceeded().unwrap().to_string(), "quota reached: 2 tasks failed");
+
+  quota::enable(Quota {
+    max_bytes: Some(10_000),
+    ..Quota::default()
+  });
+  quota::record(false, 4000, 4000);
+  assert!(quota::exceeded().is_none());
+  quota::record(false, 4000, 4000);
+  assert_eq!(
+    quota::exceeded().unwrap().to_string(),
+    "quota reached: transferred 16000 of 10000 bytes"
+  );
+}
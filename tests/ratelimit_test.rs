@@ -0,0 +1,37 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use pericortex::clock::{self, MockClock};
+use pericortex::ratelimit::{self, TokenBucket};
+
+#[test]
+fn tokens_are_spread_over_the_minute() {
+  let start = Instant::now();
+  let mut bucket = TokenBucket::new(30.0, start);
+  assert_eq!(bucket.take(start), Duration::ZERO);
+  assert_eq!(bucket.take(start), Duration::from_secs(2));
+  // the second token was reserved, the third is due two seconds after it
+  assert_eq!(bucket.take(start + Duration::from_secs(1)), Duration::from_secs(3));
+  // idle time doesn't accumulate into a burst
+  let later = start + Duration::from_secs(600);
+  assert_eq!(bucket.take(later), Duration::ZERO);
+  assert_eq!(bucket.take(later), Duration::from_secs(2));
+}
+
+#[test]
+fn fetches_wait_on_the_clock() {
+  let mock = Arc::new(MockClock::default());
+  clock::install(mock.clone());
+  ratelimit::set_max_tasks_per_minute(Some(6.0));
+  let before = clock::now();
+  for _ in 0..3 {
+    assert!(ratelimit::acquire("test"));
+  }
+  assert_eq!(clock::now() - before, Duration::from_secs(20));
+  assert!(mock.pauses().iter().all(|pause| *pause <= Duration::from_secs(1)));
+
+  ratelimit::set_max_tasks_per_minute(None);
+  let before = clock::now();
+  assert!(ratelimit::acquire("test"));
+  assert_eq!(clock::now(), before);
+}
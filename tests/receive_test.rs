@@ -0,0 +1,81 @@
+mod common;
+
+use std::io::{Cursor, Read};
+use std::thread;
+
+use pericortex::adaptor::{self, RejectReason};
+use pericortex::endpoint::ReconnectionMonitor;
+use pericortex::worker::{Worker, SPOOL_THRESHOLD};
+
+/// Receive `payload` as task `1` through the worker's `receive_from_cortex`
+fn receive(payload: Vec<u8>, identity: &str) -> pericortex::worker::ReceivedFrames {
+  let (ventilator, source_address) = common::dispatcher();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"1", &payload[..]], 0).unwrap();
+  });
+  let worker = common::echo_worker(&source_address, "tcp://127.0.0.1:1");
+  let context = zmq::Context::new();
+  let source = context.socket(zmq::DEALER).unwrap();
+  source.set_identity(identity.as_bytes()).unwrap();
+  source.connect(&source_address).unwrap();
+  let reconnections = ReconnectionMonitor::new(&context, &source, identity).unwrap();
+  let received = worker.receive_from_cortex(&source, &reconnections, None).unwrap();
+  vent_thread.join().unwrap();
+  received
+}
+
+#[test]
+fn small_payloads_are_received_in_memory() {
+  let (payload, input_size, taskid, metadata) = receive(b"a small payload".to_vec(), "small");
+  let mut payload = payload.unwrap();
+  assert!(!payload.is_rolled());
+  assert_eq!((input_size, taskid.as_str(), metadata), (15, "1", None));
+  let mut received = Vec::new();
+  payload.read_to_end(&mut received).unwrap();
+  assert_eq!(received, b"a small payload");
+}
+
+#[test]
+fn large_payloads_are_spooled_to_disk() {
+  let sent: Vec<u8> = (0..SPOOL_THRESHOLD + 1).map(|i| (i % 251) as u8).collect();
+  let (payload, input_size, _, _) = receive(sent.clone(), "large");
+  let mut payload = payload.unwrap();
+  assert!(payload.is_rolled());
+  assert_eq!(input_size, sent.len());
+  let mut received = Vec::new();
+  payload.read_to_end(&mut received).unwrap();
+  assert!(received == sent);
+}
+
+#[test]
+fn archives_extract_straight_from_a_reader() {
+  let archive = common::zip_of(&[("paper.tex", "\\documentclass{article}"), ("figures/plot.svg", "<svg/>")]);
+  let extracted = adaptor::extract_zip_reader_to_tmpdir(Cursor::new(archive), "receive_test").unwrap();
+  assert!(extracted.path().join("paper.tex").is_file());
+  assert_eq!(
+    std::fs::read_to_string(extracted.path().join("figures/plot.svg")).unwrap(),
+    "<svg/>"
+  );
+
+  match adaptor::extract_zip_reader_to_tmpdir(Cursor::new(b"%PDF-1.5".to_vec()), "receive_test") {
+    Err(e) => assert!(matches!(
+      e.downcast_ref::<RejectReason>(),
+      Some(RejectReason::UnsupportedFormat(_))
+    )),
+    Ok(_) => panic!("a PDF is not an archive"),
+  }
+}
+
+#[test]
+fn socket_errors_are_returned_rather_than_panicking() {
+  let worker = common::echo_worker("tcp://127.0.0.1:1", "tcp://127.0.0.1:1");
+  let context = zmq::Context::new();
+  // a PULL socket can't send, the task request already fails
+  let source = context.socket(zmq::PULL).unwrap();
+  let reconnections = ReconnectionMonitor::new(&context, &source, "unreceptive").unwrap();
+  match worker.receive_from_cortex(&source, &reconnections, None) {
+    Err(e) => assert_eq!(e, zmq::Error::ENOTSUP),
+    Ok(_) => panic!("a PULL socket requested a task"),
+  }
+}
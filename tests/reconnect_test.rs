@@ -0,0 +1,37 @@
+mod common;
+
+use pericortex::worker::Worker;
+use std::thread;
+use std::time::Duration;
+use zmq::SNDMORE;
+
+#[test]
+fn task_request_is_repeated_to_a_restarted_dispatcher() {
+  // the first dispatcher receives the request, then goes away without answering it
+  let (first, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let restarted = source.clone();
+  let vent_thread = thread::spawn(move || {
+    first.set_linger(0).unwrap();
+    first.recv_multipart(0).unwrap();
+    drop(first);
+    thread::sleep(Duration::from_millis(500));
+
+    // and comes back on the same port
+    let second = zmq::Context::new().socket(zmq::ROUTER).unwrap();
+    second.bind(&restarted).unwrap();
+    let request = second.recv_multipart(0).unwrap();
+    assert_eq!(request[1], b"echo_service");
+    second.send(&request[0], SNDMORE).unwrap();
+    second.send_multipart(["1", "payload"], 0).unwrap();
+  });
+
+  let sink_thread = thread::spawn(move || sink.recv_multipart(0).unwrap());
+
+  let mut worker = common::echo_worker(&source, &sink_address);
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let response = sink_thread.join().unwrap();
+  assert_eq!(response[2], b"1");
+  assert_eq!(response[3], b"payload");
+}
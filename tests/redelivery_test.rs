@@ -0,0 +1,67 @@
+mod common;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use std::thread;
+
+use tempdir::TempDir;
+
+use pericortex::adaptor;
+use pericortex::dedup::DedupPolicy;
+use pericortex::endpoint::Endpoint;
+use pericortex::quarantine;
+use pericortex::worker::{EchoWorker, Worker};
+
+/// Replays the results of redelivered tasks
+#[derive(Clone, Debug)]
+struct ReplayingWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for ReplayingWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> { self.echo.convert(path) }
+  fn dedup_policy(&self) -> DedupPolicy { DedupPolicy::Replay }
+}
+
+#[test]
+fn quarantined_tasks_are_answered_on_every_redelivery() {
+  let dir = TempDir::new("redelivery_test").unwrap();
+  let path = dir.path().join("worker.quarantine");
+  // an earlier run crashed while converting "poison"
+  quarantine::open(&path, 1).unwrap();
+  assert_eq!(quarantine::admit("poison"), Ok(()));
+  quarantine::open(&path, 1).unwrap();
+
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    for taskid in ["poison", "poison", "healthy"] {
+      let request = ventilator.recv_multipart(0).unwrap();
+      ventilator.send_multipart([&request[0][..], taskid.as_bytes(), b"payload"], 0).unwrap();
+    }
+  });
+  let sink_thread = thread::spawn(move || (0..3).map(|_| sink.recv_multipart(0).unwrap()).collect::<Vec<_>>());
+
+  let mut worker = ReplayingWorker {
+    echo: common::echo_worker(&source, &sink_address),
+  };
+  // neither the quarantined task nor its redelivery counts towards the limit
+  worker.start(Some(1)).unwrap();
+  vent_thread.join().unwrap();
+  let results = sink_thread.join().unwrap();
+  let taskids: Vec<&[u8]> = results.iter().map(|frames| &frames[2][..]).collect();
+  assert_eq!(taskids, [&b"poison"[..], b"poison", b"healthy"]);
+  // the redelivery is answered with the cached report, rather than dropped as in progress
+  for frames in &results[..2] {
+    let log = adaptor::read_zip_entry(Cursor::new(frames.last().unwrap()), "cortex.log").unwrap().unwrap();
+    assert!(String::from_utf8(log).unwrap().starts_with("Fatal:quarantined:"));
+  }
+}
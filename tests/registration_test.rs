@@ -0,0 +1,30 @@
+mod common;
+
+use pericortex::registration::{register, Registration};
+use pericortex::worker::EchoWorker;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn unknown_services_fail_registration() {
+  let (socket, address) = common::bind_ephemeral(zmq::REP);
+  let endpoint = thread::spawn(move || {
+    for reply in [
+      r#"{"status": "registered"}"#,
+      r#"{"status": "unknown_service", "message": "no such service"}"#,
+    ] {
+      let request = socket.recv_bytes(0).unwrap();
+      let registration: Registration = serde_json::from_slice(&request).unwrap();
+      assert_eq!(registration.service, "echo_service");
+      assert_eq!(registration.capacity, 1);
+      socket.send(reply, 0).unwrap();
+    }
+  });
+
+  let worker = EchoWorker::default();
+  let timeout = Duration::from_secs(5);
+  assert!(register(&address, &worker, timeout).is_ok());
+  let refused = register(&address, &worker, timeout).unwrap_err();
+  assert!(refused.to_string().contains("unknown_service"), "{}", refused);
+  endpoint.join().unwrap();
+}
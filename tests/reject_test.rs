@@ -0,0 +1,154 @@
+mod common;
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::path::Path;
+use std::thread;
+
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use pericortex::adaptor::{self, RejectReason};
+use pericortex::dispatcher::Sink;
+use pericortex::endpoint::Endpoint;
+use pericortex::protocol::{self, Outcome, PayloadFormat};
+use pericortex::worker::{EchoWorker, Worker};
+
+/// Requires a `.tex` source, which the runtime checks before converting, and extracts its inputs
+/// as the Engrafo worker does, echoing them back
+#[derive(Clone, Debug)]
+struct ValidatingWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for ValidatingWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
+    adaptor::extract_zip_to_tmpdir(path, "reject_test")?;
+    self.echo.convert(path)
+  }
+  fn validate_input(&self, dir: &Path) -> Result<(), RejectReason> { adaptor::validate_tex_sources(dir) }
+}
+
+/// Converts single PDF files, rejecting those without the PDF signature
+#[derive(Clone, Debug)]
+struct PdfWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for PdfWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn payload_format(&self) -> PayloadFormat { PayloadFormat::SingleFile { extension: String::from("pdf") } }
+  fn verify_results(&self) -> bool { false }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> { self.echo.convert(path) }
+  fn validate_input(&self, dir: &Path) -> Result<(), RejectReason> {
+    for entry in fs::read_dir(dir).map_err(|e| RejectReason::UnsupportedFormat(e.to_string()))? {
+      let path = entry.map_err(|e| RejectReason::UnsupportedFormat(e.to_string()))?.path();
+      let content = fs::read(&path).map_err(|e| RejectReason::UnsupportedFormat(e.to_string()))?;
+      if path.extension().is_some_and(|extension| extension == "pdf") && content.starts_with(b"%PDF") {
+        return Ok(());
+      }
+    }
+    Err(RejectReason::UnsupportedFormat(String::from("not a PDF")))
+  }
+}
+
+fn zip_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+  let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+  for (name, content) in entries {
+    zip.start_file(*name, FileOptions::default().compression_method(zip::CompressionMethod::Stored)).unwrap();
+    zip.write_all(content).unwrap();
+  }
+  zip.finish().unwrap().into_inner()
+}
+
+/// A ZIP whose entry is flagged as encrypted, in both its local and central headers
+fn encrypted_zip() -> Vec<u8> {
+  let mut archive = zip_of(&[("paper.tex", b"\\documentclass{article}")]);
+  archive[6] |= 1;
+  let central = archive.windows(4).position(|window| window == b"PK\x01\x02").unwrap();
+  archive[central + 8] |= 1;
+  archive
+}
+
+/// Convert `payload` as a single task, returning the result sent to the sink
+fn convert(payload: Vec<u8>) -> pericortex::dispatcher::TaskResult {
+  convert_with(payload, |echo| ValidatingWorker { echo })
+}
+
+/// Convert `payload` as a single task with the worker `wrap` makes of an echo worker
+fn convert_with<W: Worker + 'static>(
+  payload: Vec<u8>,
+  wrap: impl FnOnce(EchoWorker) -> W,
+) -> pericortex::dispatcher::TaskResult {
+  protocol::set_version(protocol::STATUS_VERSION).unwrap();
+  let (ventilator, source) = common::dispatcher();
+  let (socket, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send_multipart([&request[0][..], b"1", &payload[..]], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || Sink::from_socket(socket).recv_result().unwrap());
+  let mut worker = wrap(common::echo_worker(&source, &sink_address));
+  worker.start(Some(1)).unwrap();
+  vent_thread.join().unwrap();
+  sink_thread.join().unwrap()
+}
+
+fn reported(result: &pericortex::dispatcher::TaskResult) -> String {
+  let log = adaptor::read_zip_entry(Cursor::new(&result.payload), "cortex.log").unwrap().unwrap();
+  String::from_utf8(log).unwrap()
+}
+
+#[test]
+fn inputs_without_a_tex_source_are_rejected() {
+  let result = convert(zip_of(&[("README.txt", b"no sources here")]));
+  assert_eq!(result.outcome(), Outcome::Fatal);
+  assert_eq!(reported(&result), format!("Fatal:invalid_input:{}\n", RejectReason::NoTexSource));
+  assert!(reported(&result).starts_with("Fatal:invalid_input:no_tex_source "));
+}
+
+#[test]
+fn encrypted_inputs_are_rejected() {
+  let result = convert(encrypted_zip());
+  assert_eq!(result.outcome(), Outcome::Fatal);
+  assert_eq!(reported(&result), format!("Fatal:invalid_input:{}\n", RejectReason::Encrypted));
+}
+
+#[test]
+fn inputs_in_other_formats_are_rejected() {
+  let result = convert(b"%PDF-1.5 not an archive".to_vec());
+  assert_eq!(result.outcome(), Outcome::Fatal);
+  assert!(reported(&result).starts_with("Fatal:invalid_input:unsupported_format not a ZIP archive: "));
+}
+
+#[test]
+fn valid_inputs_are_converted() {
+  // echoed back, so shaped like a result
+  let input = zip_of(&[("paper.tex", b"\\documentclass{article}"), ("cortex.log", b"")]);
+  let result = convert(input);
+  assert_eq!(result.outcome(), Outcome::Ok);
+  assert!(adaptor::read_zip_entry(Cursor::new(&result.payload), "paper.tex").unwrap().is_some());
+}
+
+#[test]
+fn single_file_inputs_are_validated_before_converting() {
+  let result = convert_with(b"GIF89a not a document".to_vec(), |echo| PdfWorker { echo });
+  assert_eq!(result.outcome(), Outcome::Fatal);
+  assert_eq!(reported(&result), "Fatal:invalid_input:unsupported_format not a PDF\n");
+
+  let result = convert_with(b"%PDF-1.5 a document".to_vec(), |echo| PdfWorker { echo });
+  assert_eq!(result.outcome(), Outcome::Ok);
+  assert_eq!(result.payload, b"%PDF-1.5 a document");
+}
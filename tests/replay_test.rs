@@ -0,0 +1,77 @@
+mod common;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{Cursor, Seek, Write};
+use std::path::Path;
+
+use tempdir::TempDir;
+
+use pericortex::adaptor;
+use pericortex::endpoint::Endpoint;
+use pericortex::logcodes::Severity;
+use pericortex::replay::{self, ReplayVerdict};
+use pericortex::worker::{EchoWorker, Worker};
+
+/// Echoes its inputs, but fails on those without a `cortex.log`
+#[derive(Clone, Debug, Default)]
+struct MockWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for MockWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
+    match adaptor::read_archive_entry(path, "cortex.log")? {
+      Some(_) => self.echo.convert(path),
+      None => Err(From::from("no cortex.log to echo")),
+    }
+  }
+}
+
+/// Record a task as the runtime does, with its result if there is one
+fn record(taskid: &str, payload: &[u8], result: Option<&[u8]>) {
+  let stamp = replay::stamp(taskid, "echo_service", "test", payload.len());
+  replay::record_task(stamp, &mut Cursor::new(payload));
+  if let Some(result) = result {
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(result).unwrap();
+    file.rewind().unwrap();
+    replay::record_result(taskid, &mut file);
+  }
+}
+
+#[test]
+fn recorded_tasks_replay_against_their_results() {
+  let dir = TempDir::new("replay_test").unwrap();
+  replay::record_into(dir.path()).unwrap();
+  let unchanged = common::zip_of(&[("index.html", "<p>x</p>"), ("cortex.log", "Warning:missing_file:figure\n")]);
+  record("1", &unchanged, Some(&unchanged));
+  let upgraded = common::zip_of(&[("index.html", "<p>new</p>"), ("cortex.log", "")]);
+  record("2", &upgraded, Some(&common::zip_of(&[("index.html", "<p>old</p>"), ("cortex.log", "")])));
+  record("3", &upgraded, None);
+  record("4", &common::zip_of(&[("index.html", "<p>x</p>")]), None);
+  assert_eq!(replay::records(dir.path()).unwrap().len(), 4);
+
+  let outcomes = replay::replay(&MockWorker::default(), dir.path()).unwrap();
+  let verdicts: Vec<(&str, &ReplayVerdict)> =
+    outcomes.iter().map(|outcome| (outcome.record.taskid.as_str(), &outcome.verdict)).collect();
+  assert_eq!(
+    verdicts,
+    [
+      ("1", &ReplayVerdict::Identical),
+      ("2", &ReplayVerdict::Differs(vec!["index.html".to_string()])),
+      ("3", &ReplayVerdict::NoRecordedResult),
+      ("4", &ReplayVerdict::Failed("no cortex.log to echo".to_string())),
+    ]
+  );
+  assert_eq!(outcomes[0].result, Ok(unchanged.len() as u64));
+  assert_eq!(outcomes[0].log.severity, Some(Severity::Warning));
+  assert_eq!(outcomes[0].record.service, "echo_service");
+  assert!(outcomes[3].result.is_err());
+}
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use pericortex::logcodes::{self, Severity};
+use pericortex::report::{LogSummary, Report, TaskReport};
+
+fn summary(log: &str) -> LogSummary {
+  let messages = logcodes::parse(log);
+  LogSummary {
+    severity: logcodes::max_severity(&messages),
+    codes: logcodes::code_counts(&messages, Severity::Warning),
+  }
+}
+
+#[test]
+fn reports_aggregate_statuses_and_log_codes() {
+  let undefined = summary("Error:undefined:\\foo a\nError:undefined:\\foo b\nWarning:missing_file:x.sty\nInfo:note:y");
+  let clean = summary("Info:note:y");
+  let tasks = vec![
+    TaskReport::new("a.zip", Duration::from_millis(30), &Ok(100), &undefined),
+    TaskReport::new("b.zip", Duration::from_millis(10), &Ok(50), &clean),
+    TaskReport::new("c.zip", Duration::from_millis(20), &Err("timeout".to_string()), &LogSummary::default()),
+  ];
+  assert_eq!(tasks[0].status, "error");
+  assert_eq!(
+    tasks[0].top_codes,
+    vec![("Error:undefined:\\foo".to_string(), 2), ("Warning:missing_file:x.sty".to_string(), 1)]
+  );
+  assert_eq!((tasks[1].status.as_str(), tasks[2].status.as_str()), ("ok", "failed"));
+
+  let report = Report::new(tasks);
+  assert_eq!(report.summary.tasks, 3);
+  assert_eq!(report.summary.statuses["failed"], 1);
+  assert_eq!(report.summary.total_duration_ms, 60);
+  assert_eq!(report.summary.median_duration_ms, 20);
+  assert_eq!(report.summary.max_duration_ms, 30);
+  assert_eq!(report.summary.output_bytes, 150);
+  assert_eq!(report.summary.top_codes[0], ("Error:undefined:\\foo".to_string(), 1));
+}
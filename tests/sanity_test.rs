@@ -0,0 +1,50 @@
+use std::fs;
+
+use tempdir::TempDir;
+
+use pericortex::sanity::{self, SanityThresholds};
+
+#[test]
+fn source_equations_are_counted() {
+  let tex = "Let $x$ and $y$ be \\$5 apart, % $z$ is ignored\n\
+             \\begin{equation} x = y \\end{equation}\n\
+             $$a$$ \\[ b \\] \\( c \\) \\\\[2pt]\n\
+             \\begin{align*} d \\end{align*}";
+  assert_eq!(sanity::source_equations(tex), 7);
+}
+
+#[test]
+fn truncated_pages_keep_their_text() {
+  assert_eq!(sanity::body_text("<body><p>Théorème</p><é"), "Théorème");
+}
+
+#[test]
+fn skeletal_pages_are_reported() {
+  let thresholds = SanityThresholds::default();
+  let empty = "<html><head><style>p { color: red }</style></head><body><script>x()</script></body></html>";
+  let messages = sanity::assess(empty, 10, &thresholds);
+  assert!(messages.iter().any(|message| message.starts_with("Error:sanity:empty_body")));
+  assert!(messages.iter().any(|message| message.starts_with("Warning:sanity:short_output")));
+  assert!(messages.iter().any(|message| message.starts_with("Error:sanity:missing_math")));
+
+  let text = "word ".repeat(500);
+  let math = "<math><mi>x</mi></math>".repeat(4);
+  let page = format!("<html><body><p>{}</p>{}</body></html>", text, math);
+  assert_eq!(
+    sanity::assess(&page, 10, &thresholds),
+    vec!["Warning:sanity:missing_math 4 math elements rendered for 10 source equations"]
+  );
+  assert!(sanity::assess(&page, 6, &thresholds).is_empty());
+}
+
+#[test]
+fn conversions_without_a_page_are_errors() {
+  let input = TempDir::new("sanity_input").unwrap();
+  let output = TempDir::new("sanity_output").unwrap();
+  fs::write(input.path().join("main.tex"), "$x$").unwrap();
+  fs::write(output.path().join("cortex.log"), "").unwrap();
+  assert_eq!(
+    sanity::check_conversion(input.path(), output.path(), &SanityThresholds::default()),
+    vec!["Error:sanity:no_html the conversion produced no HTML page"]
+  );
+}
@@ -0,0 +1,39 @@
+use chrono::NaiveTime;
+
+use pericortex::config::WorkerConfig;
+use pericortex::schedule::{self, QuietWindow};
+
+fn at(hour: u32, minute: u32) -> NaiveTime { NaiveTime::from_hms_opt(hour, minute, 0).unwrap() }
+
+#[test]
+fn quiet_windows_may_wrap_around_midnight() {
+  let office: QuietWindow = "08:00-20:00".parse().unwrap();
+  assert!(office.contains(at(8, 0)));
+  assert!(office.contains(at(19, 59)));
+  assert!(!office.contains(at(20, 0)));
+  assert!(!office.contains(at(3, 0)));
+
+  let night: QuietWindow = "22:00-06:00".parse().unwrap();
+  assert!(night.contains(at(23, 30)));
+  assert!(night.contains(at(5, 0)));
+  assert!(!night.contains(at(12, 0)));
+  assert_eq!(night.to_string(), "22:00-06:00");
+
+  assert!("08:00".parse::<QuietWindow>().is_err());
+  assert!("8am-8pm".parse::<QuietWindow>().is_err());
+  assert!("08:00-08:00".parse::<QuietWindow>().is_err());
+}
+
+#[test]
+fn quiet_hours_are_configurable() {
+  let config: WorkerConfig = toml::from_str("quiet_hours = [\"08:00-12:00\", \"13:00-20:00\"]").unwrap();
+  let windows = config.quiet_hours.unwrap();
+  assert_eq!(windows.len(), 2);
+  assert!(toml::from_str::<WorkerConfig>("quiet_hours = [\"noon\"]").is_err());
+
+  schedule::set_quiet_hours(windows);
+  assert_eq!(schedule::quiet_at(at(9, 0)).map(|window| window.to_string()).as_deref(), Some("08:00-12:00"));
+  assert!(schedule::quiet_at(at(12, 30)).is_none());
+  schedule::set_quiet_hours(Vec::new());
+  assert!(schedule::quiet_at(at(9, 0)).is_none());
+}
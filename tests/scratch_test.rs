@@ -0,0 +1,35 @@
+use pericortex::scratch;
+use pericortex::state::{self, Phase};
+use std::fs;
+
+#[test]
+fn leftover_scratch_directories_are_swept_oldest_first() {
+  state::register("host:echo:1");
+  let leftovers: Vec<_> = ["1", "2"]
+    .iter()
+    .map(|taskid| {
+      let _task = scratch::begin("host:echo:1", taskid);
+      let dir = scratch::tempdir("cortex_task").unwrap().into_path();
+      fs::write(dir.join("payload.zip"), vec![0; 1000]).unwrap();
+      dir
+    })
+    .collect();
+  // the thread moved on to task 3, which is still being converted
+  state::enter("host:echo:1", Phase::Converting, Some("3"));
+  let _task = scratch::begin("host:echo:1", "3");
+  let live = scratch::tempdir("cortex_task").unwrap();
+  fs::write(live.path().join("payload.zip"), vec![0; 1000]).unwrap();
+
+  // within the budget, nothing is deleted
+  assert!(scratch::sweep(3000).deleted.is_empty());
+  // over it, the oldest leftover goes first
+  let report = scratch::sweep(2000);
+  assert_eq!(report.used_bytes, 3000);
+  assert_eq!(report.deleted, [leftovers[0].clone()]);
+  assert_eq!(report.freed_bytes, 1000);
+  // the directories of tasks in flight are never deleted
+  let report = scratch::sweep(0);
+  assert_eq!(report.deleted, [leftovers[1].clone()]);
+  assert!(live.path().exists());
+  assert!(!leftovers[1].exists());
+}
@@ -0,0 +1,42 @@
+use std::fs;
+
+use tempdir::TempDir;
+
+use pericortex::secrets::{self, SecretProvider, Secrets};
+
+#[test]
+fn secret_providers_are_parsed_from_their_spec() {
+  assert_eq!("env".parse::<Secrets>(), Ok(Secrets::Env));
+  assert_eq!("file:/etc/secrets".parse::<Secrets>(), Ok(Secrets::Files("/etc/secrets".into())));
+  let command: Secrets = "command:vault-get --field value".parse().unwrap();
+  assert_eq!(command, Secrets::Command("vault-get --field value".to_string()));
+  assert_eq!(command.to_string(), "command:vault-get --field value");
+  assert!("file:".parse::<Secrets>().is_err());
+  assert!("vault".parse::<Secrets>().is_err());
+}
+
+#[test]
+fn secrets_come_from_the_installed_provider() {
+  let dir = TempDir::new("secrets_test").unwrap();
+  let files = Secrets::Files(dir.path().to_path_buf());
+  assert_eq!(files.secret("AWS_ACCESS_KEY_ID").unwrap(), None);
+  let path = dir.path().join("AWS_SECRET_ACCESS_KEY");
+  fs::write(&path, "s3cr3t\n").unwrap();
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(files.secret("AWS_SECRET_ACCESS_KEY").is_err());
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+  }
+  assert_eq!(files.secret("AWS_SECRET_ACCESS_KEY").unwrap().as_deref(), Some("s3cr3t"));
+
+  let command = Secrets::Command("echo from-vault".to_string());
+  assert_eq!(command.secret("A; B").unwrap().as_deref(), Some("from-vault A; B"));
+  assert!(Secrets::Command("false".to_string()).secret("A").is_err());
+
+  secrets::install(Box::new(files));
+  assert_eq!(secrets::require("AWS_SECRET_ACCESS_KEY").unwrap(), "s3cr3t");
+  let missing = secrets::require("AWS_ACCESS_KEY_ID").unwrap_err();
+  assert_eq!(missing.to_string(), "AWS_ACCESS_KEY_ID is not set");
+}
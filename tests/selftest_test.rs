@@ -0,0 +1,23 @@
+use pericortex::check::CheckStatus;
+use pericortex::selftest::selftest;
+use pericortex::worker::EchoWorker;
+
+#[test]
+fn echo_passes_selftest() {
+  let report = selftest(&EchoWorker::default()).unwrap();
+  assert!(report.passed(), "{}", report);
+  let stages: Vec<(&str, CheckStatus)> = report
+    .stages
+    .iter()
+    .map(|stage| (stage.name, stage.status.clone()))
+    .collect();
+  assert_eq!(
+    stages,
+    vec![
+      ("tool version", CheckStatus::Pass),
+      ("fixture", CheckStatus::Pass),
+      ("convert", CheckStatus::Pass),
+      ("validate", CheckStatus::Skip)
+    ]
+  );
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "signing")]
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+
+use tempdir::TempDir;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use pericortex::adaptor;
+use pericortex::signing::{self, SIGNATURE_FILE};
+
+#[test]
+fn signed_results_verify_until_changed() {
+  let dir = TempDir::new("signing_test").unwrap();
+  let key_path = dir.path().join("fleet.key");
+  let key = signing::generate_key();
+  signing::save_key(&key_path, &key).unwrap();
+  assert!(signing::save_key(&key_path, &key).is_err());
+  let loaded = signing::load_key(&key_path).unwrap();
+  assert_eq!(signing::public_key(&loaded), signing::public_key(&key));
+  let trusted = signing::parse_public_key(&signing::public_key(&key)).unwrap();
+  let stranger = signing::parse_public_key(&signing::public_key(&signing::generate_key())).unwrap();
+
+  let mut result = tempfile::tempfile().unwrap();
+  {
+    let mut zip = ZipWriter::new(&mut result);
+    zip.start_file("index.html", FileOptions::default()).unwrap();
+    io::Write::write_all(&mut zip, b"<html/>").unwrap();
+    zip.start_file("cortex.log", FileOptions::default()).unwrap();
+    zip.finish().unwrap();
+  }
+  result.seek(SeekFrom::Start(0)).unwrap();
+  signing::enable(loaded);
+  let mut signed = signing::sign(result, "42").unwrap();
+  let signed_path = dir.path().join("42.zip");
+  io::copy(&mut signed, &mut File::create(&signed_path).unwrap()).unwrap();
+
+  let signature = signing::verify(&signed_path, &[trusted]).unwrap();
+  assert_eq!(signature.taskid, "42");
+  assert_eq!(signature.files.keys().collect::<Vec<_>>(), ["cortex.log", "index.html"]);
+  assert!(adaptor::read_archive_entry(&signed_path, SIGNATURE_FILE).unwrap().is_some());
+  assert!(signing::verify(&signed_path, &[stranger]).is_err());
+
+  let tampered = adaptor::upsert_zip_entry(File::open(&signed_path).unwrap(), "index.html", b"<html>forged</html>");
+  let tampered_path = dir.path().join("tampered.zip");
+  io::copy(&mut tampered.unwrap(), &mut File::create(&tampered_path).unwrap()).unwrap();
+  let error = signing::verify(&tampered_path, &[trusted]).unwrap_err();
+  assert_eq!(error.to_string(), "changed since signed: index.html");
+  fs::remove_file(&tampered_path).unwrap();
+}
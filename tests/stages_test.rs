@@ -0,0 +1,26 @@
+use pericortex::stages::EarlyExit;
+
+const CLEAN: &str = "Info:ok:done\nWarning:undefined:\\foo in paragraph 3";
+const BROKEN: &str = "Error:undefined:\\bar in equation 1\nError:expected:} at line 20\nWarning:font:missing";
+const FAILED: &str = "Error:undefined:\\bar\nFatal:timeout:exceeded 300 seconds";
+
+#[test]
+fn later_stages_run_unless_the_policy_exits_early() {
+  for policy in [EarlyExit::OnFatal, EarlyExit::OnError] {
+    let decision = policy.decide("latexml", CLEAN);
+    assert!(decision.proceed);
+    assert_eq!(decision.message, None);
+    assert!(!policy.decide("latexml", FAILED).proceed);
+  }
+
+  let continued = EarlyExit::OnFatal.decide("latexml", BROKEN);
+  assert!(continued.proceed);
+  assert!(continued.message.unwrap().starts_with("Info:stages:continued latexml reported 2 errors"));
+
+  let skipped = EarlyExit::OnError.decide("latexml", BROKEN);
+  assert!(!skipped.proceed);
+  assert!(skipped.message.unwrap().starts_with("Error:stages:skipped latexml reported 2 errors"));
+
+  assert_eq!("error".parse::<EarlyExit>(), Ok(EarlyExit::OnError));
+  assert!("sometimes".parse::<EarlyExit>().is_err());
+}
@@ -0,0 +1,55 @@
+use pericortex::health;
+use pericortex::logcodes;
+use pericortex::state::{self, Phase};
+use pericortex::worker::{EchoWorker, Worker};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+#[test]
+fn state_snapshots_follow_the_threads() {
+  let converter = health::register("host:echo:1");
+  let fetcher = health::register("host:echo:1:fetch");
+  state::enter("host:echo:1", Phase::Converting, Some("42"));
+  state::record(false);
+  state::record(true);
+
+  let snapshot = EchoWorker::default().state_snapshot();
+  let thread = snapshot.threads.iter().find(|thread| thread.identity == "host:echo:1").unwrap();
+  assert_eq!((thread.phase, thread.taskid.as_deref()), (Phase::Converting, Some("42")));
+  assert_eq!(snapshot.count(Phase::Idle), 1);
+  assert_eq!((snapshot.completed, snapshot.failed), (1, 1));
+  assert_eq!(snapshot.backpressure.allowed_fetches, None);
+  assert!(snapshot.dead_threads().is_empty());
+
+  // a thread dropping its liveness without finishing died
+  fetcher.finish();
+  drop(converter);
+  let snapshot = state::snapshot();
+  assert_eq!(snapshot.threads.len(), 1);
+  assert_eq!(snapshot.dead_threads(), ["host:echo:1"]);
+
+  // ... which the probe endpoints report from the same snapshot
+  let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+  health::serve(&address, EchoWorker::default()).unwrap();
+  let get = |path: &str| {
+    let mut stream = TcpStream::connect(&address).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+  };
+  assert!(get("/healthz").contains("503 Service Unavailable"));
+  let response = get("/state");
+  assert!(response.contains("application/json"));
+  assert!(response.contains(r#""identity":"host:echo:1","alive":false,"phase":"converting","taskid":"42""#));
+}
+
+#[test]
+fn log_categories_are_tallied_per_result() {
+  state::record_log(&logcodes::parse("Error:undefined:\\foo\nError:undefined:\\bar\nWarning:missing_file:x.sty"));
+  state::record_log(&logcodes::parse("Error:undefined:\\foo\nInfo:note:fine"));
+  assert_eq!(
+    state::snapshot().log_categories,
+    vec![("Error:undefined".to_string(), 2), ("Warning:missing_file".to_string(), 1)]
+  );
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use std::io;
+use std::thread;
+
+use pericortex::error::WorkerError;
+use pericortex::protocol::{self, Outcome, ResultStatus, TaskStats};
+use pericortex::retry::RetryPolicy;
+use pericortex::worker::Worker;
+use zmq::SNDMORE;
+
+#[test]
+fn results_are_preceded_by_their_status_and_stats() {
+  protocol::set_version(protocol::STATS_VERSION).unwrap();
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let payload = common::zip_of(&[(
+    "cortex.log",
+    "Warning:expected:foo missing\nError:undefined:\\bar\nError:undefined:\\baz\n",
+  )]);
+  let expected = payload.clone();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator.send_multipart([b"1".to_vec(), payload], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || sink.recv_multipart(0).unwrap());
+
+  let mut worker = common::echo_worker(&source, &sink_address);
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let frames = sink_thread.join().unwrap();
+
+  assert_eq!(frames[2], b"1");
+  let status = ResultStatus::parse(&frames[3]).unwrap();
+  assert_eq!(status.status, Outcome::Error);
+  assert_eq!(status.reason.as_deref(), Some("undefined:\\bar"));
+  let stats = TaskStats::parse(&frames[4]).unwrap();
+  assert_eq!(stats.output_bytes, expected.len() as u64);
+  assert_eq!(stats.retries, 0);
+  assert_eq!(frames[5..].concat(), expected);
+
+  let missing = io::Error::new(io::ErrorKind::NotFound, "no such file");
+  let failed = ResultStatus::failed(&missing, 0);
+  assert_eq!(ResultStatus::parse(failed.frame().as_bytes()), Some(failed));
+  // failures are classified by their type, whatever their message
+  let transient = WorkerError::Transient("docker daemon unavailable".to_string());
+  assert_eq!(ResultStatus::failed(&transient, 10).reason.as_deref(), Some("transient"));
+  let deterministic = WorkerError::Deterministic("transient failure: in the log only".to_string());
+  assert_eq!(ResultStatus::failed(&deterministic, 10).reason.as_deref(), Some("no_result"));
+  assert_eq!(ResultStatus::failed(&missing, 10).reason.as_deref(), Some("no_result"));
+  // whatever is retried is reported as transient once the retries are used up
+  let full_disk = io::Error::new(io::ErrorKind::StorageFull, "no space left on device");
+  assert!(RetryPolicy::default().is_retryable(&full_disk));
+  assert_eq!(ResultStatus::failed(&full_disk, 10).reason.as_deref(), Some("transient"));
+  assert!(ResultStatus::parse(b"payload").is_none());
+}
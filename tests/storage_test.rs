@@ -0,0 +1,96 @@
+#![cfg(feature = "object-storage")]
+use pericortex::protocol::{self, ObjectManifest};
+use pericortex::storage::{self, checksum, ObjectStore};
+use std::io::{BufRead, BufReader, Read, Seek, Write};
+use std::net::TcpListener;
+use std::thread;
+
+#[test]
+fn uploads_are_signed_and_described_by_a_manifest() {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let server = thread::spawn(move || {
+    let (stream, _) = listener.accept().unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut head = Vec::new();
+    loop {
+      let mut line = String::new();
+      reader.read_line(&mut line).unwrap();
+      if line.trim().is_empty() {
+        break;
+      }
+      head.push(line.trim().to_string());
+    }
+    let length: usize = head
+      .iter()
+      .find_map(|line| line.strip_prefix("Content-Length: ").or_else(|| line.strip_prefix("content-length: ")))
+      .unwrap()
+      .parse()
+      .unwrap();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    (&stream).write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+    (head, body)
+  });
+
+  let store = ObjectStore {
+    endpoint: format!("http://127.0.0.1:{}", port),
+    bucket: "results".to_string(),
+    region: "us-east-1".to_string(),
+    access_key: "minio".to_string(),
+    secret_key: "minio123".to_string(),
+  };
+  let mut archive = tempfile::tempfile().unwrap();
+  archive.write_all(&b"PK\x03\x04 large result".repeat(1000)).unwrap();
+  let expected = checksum(&mut archive).unwrap();
+  let manifest = store.upload("engrafo/task%2F1.zip", &mut archive).unwrap();
+  let (head, body) = server.join().unwrap();
+
+  assert_eq!(head[0], "PUT /results/engrafo/task%252F1.zip HTTP/1.1");
+  assert!(head.iter().any(|line| line.starts_with("Authorization: AWS4-HMAC-SHA256 Credential=minio/")));
+  assert_eq!(body.len() as u64, manifest.size);
+  assert_eq!((manifest.sha256.clone(), manifest.size), expected);
+  assert_eq!(manifest.url, format!("http://127.0.0.1:{}/results/engrafo/task%252F1.zip", port));
+  assert_eq!(ObjectManifest::parse(manifest.frame().as_bytes()), Some(manifest));
+}
+
+#[test]
+fn payload_manifests_are_downloaded_and_verified() {
+  let payload = b"PK\x03\x04 task payload".repeat(100);
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let served = payload.clone();
+  let server = thread::spawn(move || {
+    // the first download is truncated, the retry gets the complete payload
+    for body in [&served[..10], &served[..]] {
+      let (stream, _) = listener.accept().unwrap();
+      let mut reader = BufReader::new(stream.try_clone().unwrap());
+      let mut line = String::new();
+      while reader.read_line(&mut line).unwrap() > 2 {
+        line.clear();
+      }
+      let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len());
+      (&stream).write_all(head.as_bytes()).unwrap();
+      (&stream).write_all(body).unwrap();
+    }
+  });
+
+  let mut file = tempfile::tempfile().unwrap();
+  file.write_all(&payload).unwrap();
+  let (sha256, size) = checksum(&mut file).unwrap();
+  let manifest = ObjectManifest {
+    url: format!("http://127.0.0.1:{}/tasks/1.zip", port),
+    sha256,
+    size,
+  };
+  let mut frame = tempfile::spooled_tempfile(1024);
+  frame.write_all(manifest.frame().as_bytes()).unwrap();
+  frame.rewind().unwrap();
+  protocol::set_version(protocol::MANIFEST_VERSION).unwrap();
+  let mut downloaded = storage::resolve(frame, "test").unwrap();
+  server.join().unwrap();
+
+  let mut contents = Vec::new();
+  downloaded.read_to_end(&mut contents).unwrap();
+  assert_eq!(contents, payload);
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "tools")]
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use tempdir::TempDir;
+
+use pericortex::import::{self, INDEX_FILE};
+use pericortex::submit::{self, Submission, SubmissionReply, SubmitOptions};
+
+/// A mock ventilator answering `replies` submissions, returning the headers it received
+fn ventilator(replies: Vec<&'static str>) -> (thread::JoinHandle<Vec<Submission>>, String) {
+  let (socket, endpoint) = common::bind_ephemeral(zmq::REP);
+  let handle = thread::spawn(move || {
+    let mut received = Vec::new();
+    for status in replies {
+      let frames = socket.recv_multipart(0).unwrap();
+      let submission: Submission = serde_json::from_slice(&frames[0]).unwrap();
+      assert_eq!(submission.bytes, frames[1].len() as u64);
+      received.push(submission);
+      let reply = SubmissionReply {
+        status: status.to_string(),
+        message: None,
+      };
+      socket.send(serde_json::to_string(&reply).unwrap().as_bytes(), 0).unwrap();
+    }
+    received
+  });
+  (handle, endpoint)
+}
+
+#[test]
+fn interrupted_submissions_resume_with_unacknowledged_entries() {
+  let raw = TempDir::new("submit_raw").unwrap();
+  let corpus = TempDir::new("submit_corpus").unwrap();
+  for name in ["a", "b", "c"] {
+    fs::write(raw.path().join(format!("{}.tex", name)), "\\documentclass{article}").unwrap();
+  }
+  import::import(raw.path(), corpus.path()).unwrap();
+  let manifest = corpus.path().join(INDEX_FILE);
+
+  let (handle, ventilator_address) = ventilator(vec!["accepted", "unknown_corpus", "accepted"]);
+  let options = SubmitOptions {
+    ventilator: ventilator_address,
+    corpus: "sandbox".to_string(),
+    rate: Some(50.0),
+    timeout: Duration::from_secs(10),
+  };
+  let report = submit::submit(&manifest, &options).unwrap();
+  assert_eq!(report.submitted, 2);
+  assert_eq!(report.rejected, vec![("b".to_string(), "unknown_corpus".to_string())]);
+  let received = handle.join().unwrap();
+  assert!(received.iter().all(|submission| submission.corpus == "sandbox"));
+
+  let (handle, ventilator_address) = ventilator(vec!["accepted"]);
+  let options = SubmitOptions {
+    ventilator: ventilator_address,
+    ..options
+  };
+  let report = submit::submit(&manifest, &options).unwrap();
+  assert_eq!((report.submitted, report.resumed), (1, 2));
+  assert_eq!(handle.join().unwrap()[0].entry, "b");
+  assert_eq!(submit::acknowledged(&manifest).unwrap().len(), 3);
+}
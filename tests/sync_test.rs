@@ -0,0 +1,100 @@
+//! Concurrency tests of the coordination primitives. With `RUSTFLAGS="--cfg loom"` every
+//! interleaving is explored, otherwise each test is repeated on real threads.
+use std::time::Duration;
+
+use pericortex::sync::{Arc, AtomicUsize, Ordering, Permits, ShutdownSignal, TaskCounter};
+
+#[cfg(loom)]
+use loom::{model, thread};
+#[cfg(not(loom))]
+use std::thread;
+
+#[cfg(not(loom))]
+fn model<F: Fn() + Sync + Send + 'static>(f: F) {
+  for _ in 0..200 {
+    f();
+  }
+}
+
+#[test]
+fn drain_waits_for_the_task_in_conversion() {
+  model(|| {
+    let tasks = TaskCounter::new(ShutdownSignal::new());
+    let started = Arc::new(AtomicUsize::new(0));
+    let converted = Arc::new(AtomicUsize::new(0));
+    let converter = {
+      let (tasks, started, converted) = (tasks.clone(), Arc::clone(&started), Arc::clone(&converted));
+      thread::spawn(move || {
+        if let Some(_task) = tasks.begin() {
+          started.fetch_add(1, Ordering::SeqCst);
+          converted.fetch_add(1, Ordering::SeqCst);
+        }
+      })
+    };
+    assert_eq!(tasks.drain(None), 0);
+    // a task is either refused, or completed before the drain returns
+    assert_eq!(started.load(Ordering::SeqCst), converted.load(Ordering::SeqCst));
+    assert!(tasks.begin().is_none());
+    converter.join().unwrap();
+  });
+}
+
+#[test]
+fn concurrent_shutdowns_refuse_all_later_tasks() {
+  model(|| {
+    let shutdown = ShutdownSignal::new();
+    let tasks = TaskCounter::new(shutdown.clone());
+    let requester = {
+      let tasks = tasks.clone();
+      thread::spawn(move || tasks.drain(None))
+    };
+    let converter = {
+      let tasks = tasks.clone();
+      thread::spawn(move || tasks.begin().is_some())
+    };
+    // a signal handler raises the flag while the pool drains
+    shutdown.request();
+    assert_eq!(requester.join().unwrap(), 0);
+    converter.join().unwrap();
+    assert!(shutdown.requested());
+    assert!(tasks.begin().is_none());
+    assert_eq!(tasks.in_flight(), 0);
+  });
+}
+
+#[test]
+fn permits_bound_the_active_threads() {
+  model(|| {
+    let permits = Arc::new(Permits::new(1));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..2)
+      .map(|_| {
+        let (permits, peak) = (Arc::clone(&permits), Arc::clone(&peak));
+        thread::spawn(move || {
+          let _permit = permits.acquire(&ShutdownSignal::new(), Duration::from_millis(10)).unwrap();
+          peak.fetch_max(permits.active(), Ordering::SeqCst);
+        })
+      })
+      .collect();
+    for thread in threads {
+      thread.join().unwrap();
+    }
+    assert_eq!(peak.load(Ordering::SeqCst), 1);
+    assert_eq!(permits.active(), 0);
+  });
+}
+
+#[test]
+#[cfg(not(loom))]
+fn shutdown_releases_threads_waiting_for_a_permit() {
+  let permits = Arc::new(Permits::new(0));
+  let shutdown = ShutdownSignal::new();
+  let waiting = {
+    let (permits, shutdown) = (Arc::clone(&permits), shutdown.clone());
+    thread::spawn(move || permits.acquire(&shutdown, Duration::from_millis(10)).is_none())
+  };
+  shutdown.request();
+  assert!(waiting.join().unwrap());
+  permits.set_allowed(1);
+  assert!(permits.acquire(&shutdown, Duration::from_millis(10)).is_some());
+}
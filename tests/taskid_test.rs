@@ -0,0 +1,56 @@
+use pericortex::protocol::taskid_file_stem;
+use pericortex::worker::{EchoWorker, Worker};
+use std::io::{Read, Write};
+use tempdir::TempDir;
+
+const HOSTILE_TASKIDS: [&str; 6] = ["../../escape", "a/b", "..\\escape", "..", "/etc/passwd", "%2e%2e"];
+
+#[test]
+fn hostile_taskids_stay_in_one_path_component() {
+  for taskid in HOSTILE_TASKIDS {
+    let stem = taskid_file_stem(taskid);
+    assert!(!stem.contains(['/', '\\', '.']), "{:?} became {:?}", taskid, stem);
+    assert!(!stem.is_empty());
+  }
+  assert_eq!(taskid_file_stem("12345"), "12345");
+  // distinct taskids never collide
+  assert_ne!(taskid_file_stem("%2e"), taskid_file_stem("."));
+}
+
+#[test]
+fn very_long_taskids_fit_in_a_file_name() {
+  let long = format!("{}{}", "../".repeat(1000), "a".repeat(5000));
+  let stem = taskid_file_stem(&long);
+  assert!(!stem.contains(['/', '\\', '.']), "{:?} became {:?}", long, stem);
+  assert!(stem.len() + ".result.zip".len() <= 255, "{} bytes", stem.len());
+  // taskids sharing a long prefix are told apart by the hash of the whole taskid
+  assert_ne!(stem, taskid_file_stem(&format!("{}b", long)));
+  // a shortened stem taken as a taskid isn't shortened to itself
+  let shortened = taskid_file_stem(&"a".repeat(300));
+  assert_eq!(shortened.len(), stem.len());
+  assert_ne!(taskid_file_stem(&shortened), shortened);
+
+  let scratch = TempDir::new("taskid_test").unwrap();
+  let mut payload = tempfile::spooled_tempfile(1024);
+  payload.write_all(b"payload").unwrap();
+  assert!(EchoWorker::default().convert_payload(&mut payload, &long, &scratch).is_ok());
+}
+
+#[test]
+fn hostile_taskids_do_not_escape_the_scratch_directory() {
+  let outer = TempDir::new("taskid_test").unwrap();
+  let scratch = TempDir::new_in(outer.path(), "scratch").unwrap();
+  for taskid in HOSTILE_TASKIDS {
+    let mut payload = tempfile::spooled_tempfile(1024);
+    payload.write_all(b"payload").unwrap();
+    let mut result = EchoWorker::default()
+      .convert_payload(&mut payload, taskid, &scratch)
+      .unwrap();
+    let mut echoed = String::new();
+    result.read_to_string(&mut echoed).unwrap();
+    assert_eq!(echoed, "payload");
+  }
+  // nothing but the scratch directory was created next to it
+  assert_eq!(std::fs::read_dir(outer.path()).unwrap().count(), 1);
+  assert_eq!(std::fs::read_dir(scratch.path()).unwrap().count(), HOSTILE_TASKIDS.len());
+}
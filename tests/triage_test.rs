@@ -0,0 +1,114 @@
+#![cfg(feature = "tools")]
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use flate2::read::GzDecoder;
+use tempdir::TempDir;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use pericortex::journal;
+use pericortex::triage::{self, TriageIndex, TriageOptions, FAILED_CATEGORY};
+
+fn finished(taskid: &str, input_bytes: usize, error: Option<&str>) {
+  let outcome = error.map_or(Ok(100), |error| Err(error.to_string()));
+  journal::finished(taskid, "worker:1", input_bytes, Instant::now(), outcome, None);
+}
+
+fn record(dir: &Path, taskid: &str, input_bytes: usize, log: Option<&str>) {
+  fs::write(dir.join(format!("{}.zip", taskid)), vec![b'x'; input_bytes]).unwrap();
+  if let Some(log) = log {
+    let mut zip = ZipWriter::new(File::create(dir.join(format!("{}.result.zip", taskid))).unwrap());
+    zip.start_file("cortex.log", FileOptions::default()).unwrap();
+    zip.write_all(log.as_bytes()).unwrap();
+    zip.finish().unwrap();
+  }
+}
+
+#[test]
+fn failed_tasks_are_bundled_by_log_category() {
+  let dir = TempDir::new("triage_test").unwrap();
+  let recordings = dir.path().join("recorded");
+  fs::create_dir(&recordings).unwrap();
+  record(&recordings, "1", 300, Some("Warning:missing_file:x.sty\nError:undefined:\\foo"));
+  record(&recordings, "2", 100, Some("Error:undefined:\\bar"));
+  record(&recordings, "3", 200, Some("Error:undefined:\\baz\nFatal:timeout:latexml"));
+  record(&recordings, "4", 50, None);
+  record(&recordings, "5", 50, Some("Warning:missing_file:x.sty"));
+  let journal = dir.path().join("run.journal");
+  journal::open(&journal).unwrap();
+  finished("1", 300, None);
+  finished("2", 100, None);
+  finished("3", 200, None);
+  // failed, then failed again after a restart
+  finished("4", 50, Some("out of memory"));
+  finished("4", 50, Some("killed"));
+  finished("5", 50, None);
+
+  let options = TriageOptions {
+    per_category: 1,
+    ..TriageOptions::default()
+  };
+  let categories = triage::collect(&journal, Some(&recordings), options).unwrap();
+  let summary: Vec<(&str, usize, Vec<&str>)> = categories
+    .iter()
+    .map(|category| {
+      let cases = category.cases.iter().map(|case| case.taskid.as_str()).collect();
+      (category.category.as_str(), category.failures, cases)
+    })
+    .collect();
+  assert_eq!(
+    summary,
+    [("Error:undefined", 2, vec!["2"]), ("Fatal:timeout", 1, vec!["3"]), (FAILED_CATEGORY, 1, vec!["4"])]
+  );
+  assert_eq!(categories[2].cases[0].error.as_deref(), Some("killed"));
+
+  // too small a budget for the inputs of the logged failures
+  let options = TriageOptions {
+    per_category: 1,
+    max_bytes: 60,
+  };
+  let tight = triage::collect(&journal, Some(&recordings), options).unwrap();
+  let packaged: usize = tight.iter().map(|category| category.cases.len()).sum();
+  assert_eq!(packaged, 1);
+
+  let bundle = dir.path().join("triage.tar.gz");
+  triage::write_bundle(&bundle, &categories, triage::environment(Some(&recordings))).unwrap();
+  let mut archive = tar::Archive::new(GzDecoder::new(File::open(&bundle).unwrap()));
+  let mut names = Vec::new();
+  let mut index = None;
+  for entry in archive.entries().unwrap() {
+    let mut entry = entry.unwrap();
+    let name = entry.path().unwrap().to_string_lossy().to_string();
+    if name == "triage/index.json" {
+      let mut json = String::new();
+      entry.read_to_string(&mut json).unwrap();
+      index = Some(serde_json::from_str::<TriageIndex>(&json).unwrap());
+    }
+    names.push(name);
+  }
+  names.sort();
+  assert_eq!(
+    names,
+    [
+      "triage/Error_undefined/2/cortex.log",
+      "triage/Error_undefined/2/input.zip",
+      "triage/Fatal_timeout/3/cortex.log",
+      "triage/Fatal_timeout/3/input.zip",
+      "triage/failed/4/error.txt",
+      "triage/failed/4/input.zip",
+      "triage/index.json",
+    ]
+  );
+  let index = index.unwrap();
+  let failures: Vec<(String, usize)> = index
+    .categories
+    .iter()
+    .map(|category| (category.category.clone(), category.failures))
+    .collect();
+  assert_eq!(failures[0], ("Error:undefined".to_string(), 2));
+  assert_eq!(index.categories[2].cases[0].input_bytes, 50);
+  assert!(index.environment.services.is_empty());
+}
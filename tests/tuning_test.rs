@@ -0,0 +1,37 @@
+use pericortex::config::WorkerConfig;
+use pericortex::tuning::{FrameSizeBounds, FrameSizeTuner};
+use pericortex::worker::{EchoWorker, Worker};
+use std::time::Duration;
+
+#[test]
+fn tuner_settles_on_the_smallest_fast_frame_size() {
+  let bounds: FrameSizeBounds = "1024:16384".parse().unwrap();
+  let mut tuner = FrameSizeTuner::new(bounds);
+  // 1KB frames are slow, 4KB and 16KB frames are about as fast as each other
+  let mut sizes = vec![tuner.message_size()];
+  for millis in [400, 400, 100, 100, 95, 95] {
+    if let Some(size) = tuner.observe(1_000_000, Duration::from_millis(millis)) {
+      sizes.push(size);
+    }
+  }
+  assert_eq!(sizes, vec![1024, 4096, 16384, 4096]);
+  assert!(tuner.converged());
+  assert_eq!(tuner.observe(1_000_000, Duration::from_millis(1)), None);
+  assert!("4096:1024".parse::<FrameSizeBounds>().is_err());
+}
+
+#[test]
+fn frame_size_follows_the_result_size_within_bounds() {
+  let bounds: FrameSizeBounds = "1024:16384".parse().unwrap();
+  assert_eq!(bounds.for_result(0), 1024);
+  assert_eq!(bounds.for_result(64 * 4096), 4096);
+  assert_eq!(bounds.for_result(u64::MAX), 16384);
+
+  let mut worker = EchoWorker::default();
+  assert_eq!(worker.message_size_for(64 * 4096), worker.message_size());
+  let config: WorkerConfig = toml::from_str("message_size_bounds = \"1024:16384\"").unwrap();
+  worker.configure(&config);
+  assert_eq!(worker.message_size_bounds(), Some(bounds));
+  assert_eq!(worker.message_size_for(64 * 4096), 4096);
+  assert_eq!(toml::to_string(&config).unwrap().trim(), "message_size_bounds = \"1024:16384\"");
+}
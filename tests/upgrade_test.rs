@@ -0,0 +1,18 @@
+use pericortex::dedup::{self, Duplicate};
+use pericortex::upgrade;
+use tempdir::TempDir;
+
+#[test]
+fn completed_tasks_survive_an_upgrade() {
+  dedup::begin("upgrade-1");
+  dedup::complete("upgrade-1", None);
+  dedup::begin("upgrade-2");
+  let dir = TempDir::new("upgrade_test").unwrap();
+  let path = dir.path().join("state.json");
+  upgrade::save(&path).unwrap();
+
+  let state = upgrade::restore_from(&path).unwrap();
+  // the task still in flight is not handed over
+  assert_eq!(state.completed, vec!["upgrade-1".to_string()]);
+  assert!(matches!(dedup::begin("upgrade-1"), Some(Duplicate::Completed(None))));
+}
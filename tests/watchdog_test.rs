@@ -0,0 +1,68 @@
+mod common;
+
+use pericortex::endpoint::Endpoint;
+use std::error::Error;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use pericortex::adaptor;
+use pericortex::watchdog::{self, Watchdog};
+use pericortex::worker::{EchoWorker, Worker};
+use zmq::SNDMORE;
+
+/// Stalls on every task, then echoes it
+#[derive(Clone, Debug)]
+struct StallingWorker {
+  echo: EchoWorker,
+}
+
+impl Worker for StallingWorker {
+  fn get_service(&self) -> &str { self.echo.get_service() }
+  fn get_source_address(&self) -> &Endpoint { self.echo.get_source_address() }
+  fn get_sink_address(&self) -> &Endpoint { self.echo.get_sink_address() }
+  fn message_size(&self) -> usize { self.echo.message_size() }
+  fn get_identity(&self) -> &str { self.echo.get_identity() }
+  fn set_identity(&mut self, identity: String) { self.echo.set_identity(identity) }
+  fn convert(&self, path: &Path) -> Result<File, Box<dyn Error>> {
+    thread::sleep(Duration::new(2, 0));
+    self.echo.convert(path)
+  }
+}
+
+#[test]
+fn wedged_tasks_are_reported_once() {
+  watchdog::enable(Watchdog {
+    hang_threshold: Duration::from_millis(500),
+    respawn: false,
+  });
+  let (ventilator, source) = common::dispatcher();
+  let (sink, sink_address) = common::sink();
+  let vent_thread = thread::spawn(move || {
+    let request = ventilator.recv_multipart(0).unwrap();
+    ventilator.send(&request[0], SNDMORE).unwrap();
+    ventilator.send_multipart([b"21".to_vec(), b"payload".to_vec()], 0).unwrap();
+  });
+  let sink_thread = thread::spawn(move || {
+    let report = sink.recv_multipart(0).unwrap();
+    // the result of the wedged conversion is discarded once it completes
+    let more = sink.poll(zmq::POLLIN, 3000).unwrap();
+    (report, more)
+  });
+
+  let mut worker = StallingWorker {
+    echo: common::echo_worker(&source, &sink_address),
+  };
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(vent_thread.join().is_ok());
+  let (frames, more) = sink_thread.join().unwrap();
+
+  assert_eq!(frames[2], b"21");
+  let log = adaptor::read_zip_entry(Cursor::new(frames.last().unwrap()), "cortex.log").unwrap().unwrap();
+  let log = String::from_utf8(log).unwrap();
+  assert!(log.starts_with("Fatal:workercrash:wedged thread "), "{}", log);
+  assert!(log.contains("on task 21 while converting"), "{}", log);
+  assert_eq!(more, 0);
+}